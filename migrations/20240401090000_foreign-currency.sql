@@ -0,0 +1,19 @@
+ALTER TABLE account ADD COLUMN currency TEXT(3); /* ISO 4217 code, NULL for the base currency */
+ALTER TABLE batch ADD COLUMN generated_by TEXT; /* NULL for batches posted by a user */
+ALTER TABLE entry ADD COLUMN currency_amount INTEGER; /* amount in the account currency, for foreign currency accounts */
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (390, 'Unrealised Exchange Rate Gains', 'system');
+
+CREATE TABLE exchange_rate
+(
+    currency TEXT(3) NOT NULL,
+    date     DATE NOT NULL,
+    rate     REAL NOT NULL, /* units of base currency per unit of foreign currency */
+    PRIMARY KEY (currency, date)
+);
+
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('baseCurrency', 'GBP');
+
+/* System Accounts */
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('unrealisedExchangeAccount', 390);
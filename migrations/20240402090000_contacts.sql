@@ -0,0 +1,15 @@
+ALTER TABLE entry ADD COLUMN contact_id INTEGER;
+
+CREATE TABLE contact
+(
+    id                 INTEGER PRIMARY KEY,
+    kind               TEXT NOT NULL,
+    name               TEXT(140) NOT NULL,
+    address            TEXT,
+    postcode           TEXT(16),
+    country            TEXT(2), /* ISO 3166-1 alpha-2 code */
+    email              TEXT,
+    tax_id             TEXT(32),
+    control_account_id INTEGER,
+    archived           BOOLEAN DEFAULT FALSE
+);
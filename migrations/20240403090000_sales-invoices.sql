@@ -0,0 +1,56 @@
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (120, 'Trade Debtors', 'currentAsset');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (270, 'Output VAT', 'currentLiability');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (400, 'Sales', 'revenue');
+
+CREATE TABLE tax_code
+(
+    code TEXT PRIMARY KEY,
+    name TEXT(140) NOT NULL,
+    rate INTEGER NOT NULL /* basis points */
+);
+
+INSERT INTO tax_code (code, name, rate) VALUES ('STANDARD', 'Standard rate', 2000);
+INSERT INTO tax_code (code, name, rate) VALUES ('REDUCED', 'Reduced rate', 500);
+INSERT INTO tax_code (code, name, rate) VALUES ('ZERO', 'Zero rate', 0);
+INSERT INTO tax_code (code, name, rate) VALUES ('EXEMPT', 'Exempt', 0);
+
+CREATE TABLE invoice
+(
+    id         INTEGER PRIMARY KEY,
+    number     TEXT UNIQUE, /* assigned on approval */
+    state      TEXT NOT NULL,
+    contact_id INTEGER NOT NULL,
+    date       DATE NOT NULL,
+    due_date   DATE NOT NULL,
+    reference  TEXT(140),
+    journal_id INTEGER
+);
+
+CREATE TABLE invoice_line
+(
+    id          INTEGER PRIMARY KEY,
+    invoice_id  INTEGER NOT NULL,
+    description TEXT(140) NOT NULL,
+    quantity    REAL NOT NULL,
+    unit_price  INTEGER NOT NULL,
+    tax_code    TEXT NOT NULL,
+    account_id  INTEGER NOT NULL,
+    net_amount  INTEGER NOT NULL,
+    tax_amount  INTEGER NOT NULL
+);
+
+/* Sales Invoicing */
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('invoicePrefix', 'INV-');
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('nextInvoiceNumber', 1);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('debtorsControlAccount', 120);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('salesAccount', 400);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('outputVatAccount', 270);
+
+/* Skip the account numbers taken above */
+UPDATE settings SET intValue = 121 WHERE name = 'nextAccountCurrentAsset' AND intValue < 121;
+UPDATE settings SET intValue = 401 WHERE name = 'nextAccountRevenue' AND intValue < 401;
@@ -0,0 +1,12 @@
+/* invoice or creditNote */
+ALTER TABLE invoice ADD COLUMN kind TEXT NOT NULL DEFAULT 'invoice';
+
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('entityAddress', NULL);
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('entityRegisteredNumber', NULL);
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('entityVatNumber', NULL);
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('entityEmail', NULL);
+
+/* Sales Invoicing */
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('creditNotePrefix', 'CN-');
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('nextCreditNoteNumber', 1);
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('invoiceFooter', NULL);
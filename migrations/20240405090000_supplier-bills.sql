@@ -0,0 +1,65 @@
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (260, 'Trade Creditors', 'currentLiability');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (271, 'Input VAT', 'currentLiability');
+
+CREATE TABLE bill
+(
+    id                 INTEGER PRIMARY KEY,
+    contact_id         INTEGER NOT NULL,
+    supplier_reference TEXT(140),
+    date               DATE NOT NULL,
+    due_date           DATE NOT NULL,
+    journal_id         INTEGER NOT NULL
+);
+
+CREATE TABLE bill_line
+(
+    id          INTEGER PRIMARY KEY,
+    bill_id     INTEGER NOT NULL,
+    description TEXT(140) NOT NULL,
+    tax_code    TEXT NOT NULL,
+    account_id  INTEGER NOT NULL,
+    net_amount  INTEGER NOT NULL,
+    tax_amount  INTEGER NOT NULL
+);
+
+CREATE TABLE bill_payment
+(
+    id         INTEGER PRIMARY KEY,
+    bill_id    INTEGER NOT NULL,
+    journal_id INTEGER NOT NULL,
+    amount     INTEGER NOT NULL
+);
+
+CREATE TABLE payment_run
+(
+    id              INTEGER PRIMARY KEY,
+    state           TEXT NOT NULL,
+    due_by          DATE NOT NULL,
+    payment_date    DATE NOT NULL,
+    cash_account_id INTEGER NOT NULL,
+    batch_id        INTEGER
+);
+
+CREATE TABLE payment_run_line
+(
+    id             INTEGER PRIMARY KEY,
+    payment_run_id INTEGER NOT NULL,
+    bill_id        INTEGER NOT NULL,
+    amount         INTEGER NOT NULL
+);
+
+/* Purchase Ledger */
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('creditorsControlAccount', 260);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('inputVatAccount', 271);
+
+/* Expense accounts are split into direct 500-549 and indirect 550-599 */
+INSERT OR IGNORE INTO settings (name, intValue)
+SELECT 'nextAccountDirectExpense', intValue FROM settings WHERE name = 'nextAccountExpense';
+INSERT OR IGNORE INTO settings (name, intValue)
+SELECT 'nextAccountIndirectExpense', MAX(intValue, 550) FROM settings WHERE name = 'nextAccountExpense';
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('nextAccountDirectExpense', 500);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('nextAccountIndirectExpense', 550);
+DELETE FROM settings WHERE name = 'nextAccountExpense';
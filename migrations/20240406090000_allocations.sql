@@ -0,0 +1,14 @@
+/* Settlement of an invoice or a bill by a receipt or payment journal */
+CREATE TABLE allocation
+(
+    id         INTEGER PRIMARY KEY,
+    journal_id INTEGER NOT NULL,
+    invoice_id INTEGER,
+    bill_id    INTEGER,
+    amount     INTEGER NOT NULL
+);
+
+INSERT INTO allocation (journal_id, bill_id, amount)
+SELECT journal_id, bill_id, amount FROM bill_payment ORDER BY id;
+
+DROP TABLE bill_payment;
@@ -0,0 +1,5 @@
+/* The date of the allocation, for ageing. Existing allocations take the date of their journal */
+ALTER TABLE allocation ADD COLUMN date TEXT NOT NULL DEFAULT '';
+UPDATE allocation
+SET date = COALESCE((SELECT batch.date FROM journal JOIN batch ON batch.id = journal.batch_id
+            WHERE journal.id = allocation.journal_id), '');
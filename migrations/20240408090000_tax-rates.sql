@@ -0,0 +1,36 @@
+ALTER TABLE entry ADD COLUMN tax_code TEXT;
+
+/* Rates move to tax_rate so that they can change over time */
+ALTER TABLE tax_code ADD COLUMN treatment TEXT NOT NULL DEFAULT 'taxable';
+
+CREATE TABLE tax_rate
+(
+    code           TEXT NOT NULL,
+    effective_from DATE NOT NULL,
+    rate           INTEGER NOT NULL, /* basis points */
+    PRIMARY KEY (code, effective_from)
+);
+
+UPDATE tax_code SET treatment = 'exempt' WHERE code = 'EXEMPT';
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('STANDARD', '1991-04-01', 1750);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('STANDARD', '2008-12-01', 1500);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('STANDARD', '2010-01-01', 1750);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('STANDARD', '2011-01-04', 2000);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('REDUCED', '1997-09-01', 500);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('ZERO', '1973-04-01', 0);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('EXEMPT', '1973-04-01', 0);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('OUTSIDE_SCOPE', '1973-04-01', 0);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('REVERSE_CHARGE', '1991-04-01', 1750);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('REVERSE_CHARGE', '2008-12-01', 1500);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('REVERSE_CHARGE', '2010-01-01', 1750);
+INSERT INTO tax_rate (code, effective_from, rate) VALUES ('REVERSE_CHARGE', '2011-01-04', 2000);
+
+/* Codes added since the schema was created keep their single rate */
+INSERT INTO tax_rate (code, effective_from, rate)
+SELECT code, '1973-04-01', rate FROM tax_code
+WHERE code NOT IN ('STANDARD', 'REDUCED', 'ZERO', 'EXEMPT', 'OUTSIDE_SCOPE', 'REVERSE_CHARGE');
+
+ALTER TABLE tax_code DROP COLUMN rate;
+
+INSERT OR IGNORE INTO tax_code (code, name, treatment) VALUES ('OUTSIDE_SCOPE', 'Outside the scope of VAT', 'outsideScope');
+INSERT OR IGNORE INTO tax_code (code, name, treatment) VALUES ('REVERSE_CHARGE', 'Reverse charge', 'reverseCharge');
@@ -0,0 +1,23 @@
+CREATE TABLE vat_return
+(
+    id                 INTEGER PRIMARY KEY,
+    period_key         TEXT NOT NULL UNIQUE,
+    start_date         DATE NOT NULL,
+    end_date           DATE NOT NULL,
+    scheme             TEXT NOT NULL,
+    box1               INTEGER NOT NULL,
+    box2               INTEGER NOT NULL,
+    box3               INTEGER NOT NULL,
+    box4               INTEGER NOT NULL,
+    box5               INTEGER NOT NULL,
+    box6               INTEGER NOT NULL,
+    box7               INTEGER NOT NULL,
+    box8               INTEGER NOT NULL,
+    box9               INTEGER NOT NULL,
+    processing_date    TEXT NOT NULL,
+    form_bundle_number TEXT NOT NULL
+);
+
+/* VAT returns */
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('vatScheme', 'accrual');
+INSERT OR IGNORE INTO settings (name, strValue) VALUES ('vatLockDate', NULL);
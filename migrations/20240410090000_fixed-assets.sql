@@ -0,0 +1,50 @@
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (180, 'Fixed Assets at Cost', 'nonCurrentAsset');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (181, 'Accumulated Depreciation', 'nonCurrentAsset');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (480, 'Gain or Loss on Disposal of Fixed Assets', 'otherIncome');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (550, 'Depreciation', 'indirectExpense');
+
+CREATE TABLE fixed_asset
+(
+    id                        INTEGER PRIMARY KEY,
+    name                      TEXT(140) NOT NULL,
+    cost_account_id           INTEGER NOT NULL,
+    accumulated_account_id    INTEGER NOT NULL,
+    depreciation_account_id   INTEGER NOT NULL,
+    cost                      INTEGER NOT NULL,
+    residual_value            INTEGER NOT NULL,
+    acquisition_date          DATE NOT NULL,
+    useful_life_months        INTEGER NOT NULL,
+    method                    TEXT NOT NULL,
+    rate                      INTEGER, /* basis points a year, for the reducing balance method */
+    disposal_date             DATE,
+    disposal_proceeds         INTEGER,
+    disposal_journal_id       INTEGER
+);
+
+CREATE TABLE depreciation
+(
+    id         INTEGER PRIMARY KEY,
+    asset_id   INTEGER NOT NULL,
+    month      TEXT(7) NOT NULL, /* YYYY-MM */
+    amount     INTEGER NOT NULL,
+    journal_id INTEGER NOT NULL,
+    UNIQUE (asset_id, month)
+);
+
+/* Fixed Assets */
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('fixedAssetCostAccount', 180);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('accumulatedDepreciationAccount', 181);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('depreciationAccount', 550);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('assetDisposalAccount', 480);
+
+/* Skip the account numbers taken above */
+UPDATE settings SET intValue = 182 WHERE name = 'nextAccountNonCurrentAsset' AND intValue < 182;
+UPDATE settings SET intValue = 481 WHERE name = 'nextAccountOtherIncome' AND intValue < 481;
+UPDATE settings SET intValue = 551 WHERE name = 'nextAccountIndirectExpense' AND intValue < 551;
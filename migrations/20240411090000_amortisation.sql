@@ -0,0 +1,32 @@
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (130, 'Prepayments', 'prepayments');
+
+INSERT OR IGNORE INTO account (id, name, type)
+VALUES (230, 'Accruals', 'currentLiability');
+
+CREATE TABLE amortisation_schedule
+(
+    id                 INTEGER PRIMARY KEY,
+    kind               TEXT NOT NULL,
+    description        TEXT(140) NOT NULL,
+    total              INTEGER NOT NULL,
+    start_date         DATE NOT NULL,
+    periods            INTEGER NOT NULL,
+    expense_account_id INTEGER NOT NULL,
+    balance_account_id INTEGER NOT NULL /* the prepayment or accrual account */
+);
+
+CREATE TABLE amortisation
+(
+    id          INTEGER PRIMARY KEY,
+    schedule_id INTEGER NOT NULL,
+    period      INTEGER NOT NULL, /* 1 for the first period of the schedule */
+    amount      INTEGER NOT NULL,
+    journal_id  INTEGER NOT NULL,
+    UNIQUE (schedule_id, period)
+);
+
+/* Prepayments and Accruals */
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('prepaymentsAccount', 130);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('accrualsAccount', 230);
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('nextAccountPrepayments', 131);
@@ -0,0 +1,29 @@
+CREATE TABLE recurring_journal
+(
+    id                     INTEGER PRIMARY KEY,
+    unstructured_narrative TEXT(120) NOT NULL,
+    recurrence             TEXT NOT NULL,
+    day                    INTEGER, /* day of the month, for the monthlyOnDay recurrence */
+    start_date             DATE NOT NULL,
+    end_date               DATE
+);
+
+CREATE TABLE recurring_journal_entry
+(
+    id                   INTEGER PRIMARY KEY,
+    recurring_journal_id INTEGER NOT NULL,
+    account_id           INTEGER NOT NULL,
+    amount               INTEGER NOT NULL,
+    currency_amount      INTEGER,
+    contact_id           INTEGER,
+    tax_code             TEXT
+);
+
+CREATE TABLE recurring_journal_posting
+(
+    id                   INTEGER PRIMARY KEY,
+    recurring_journal_id INTEGER NOT NULL,
+    date                 DATE NOT NULL, /* the date of the occurrence posted */
+    journal_id           INTEGER NOT NULL,
+    UNIQUE (recurring_journal_id, date)
+);
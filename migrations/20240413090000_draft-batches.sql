@@ -0,0 +1,31 @@
+CREATE TABLE draft_batch
+(
+    id          INTEGER PRIMARY KEY,
+    date        DATE, /* the date to post the batch on, or the date it is approved */
+    state       TEXT NOT NULL,
+    created_by  TEXT NOT NULL,
+    reviewed_by TEXT,
+    review_note TEXT(140),
+    batch_id    INTEGER /* the batch posted when the draft is approved */
+);
+
+CREATE TABLE draft_journal
+(
+    id                     INTEGER PRIMARY KEY,
+    draft_batch_id         INTEGER NOT NULL,
+    unstructured_narrative TEXT(140) NOT NULL
+);
+
+CREATE TABLE draft_entry
+(
+    id               INTEGER PRIMARY KEY,
+    draft_journal_id INTEGER NOT NULL,
+    account_id       INTEGER NOT NULL,
+    amount           INTEGER NOT NULL,
+    currency_amount  INTEGER,
+    contact_id       INTEGER,
+    tax_code         TEXT
+);
+
+/* Journals over this amount must be posted as drafts and approved by a second user, if set */
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('journalApprovalThreshold', NULL);
@@ -0,0 +1,21 @@
+/* The user who created each account, batch and journal, NULL for accounts created by the schema */
+ALTER TABLE account ADD COLUMN created_by INTEGER;
+ALTER TABLE batch ADD COLUMN created_by INTEGER; /* the user who posted the batch, or whose request generated it */
+ALTER TABLE journal ADD COLUMN created_by INTEGER;
+
+CREATE TABLE user
+(
+    id         INTEGER PRIMARY KEY,
+    name       TEXT(64) NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE api_token
+(
+    id          INTEGER PRIMARY KEY,
+    user_id     INTEGER NOT NULL,
+    token_hash  TEXT(64) NOT NULL UNIQUE, /* hex SHA-256 of the token, which is not stored */
+    description TEXT(140),
+    created_at  DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked     BOOLEAN NOT NULL DEFAULT FALSE
+);
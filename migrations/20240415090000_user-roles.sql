@@ -0,0 +1,3 @@
+/* viewer, bookkeeper, approver or admin. Users created before roles keep the access they had */
+ALTER TABLE user ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer';
+UPDATE user SET role = 'admin';
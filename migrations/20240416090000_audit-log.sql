@@ -0,0 +1,23 @@
+/* Append-only: the triggers below stop events being changed or removed */
+CREATE TABLE audit_event
+(
+    id         INTEGER PRIMARY KEY,
+    user_id    INTEGER, /* NULL for changes made by the server itself, such as creating the first user */
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    operation  TEXT     NOT NULL,
+    subject    TEXT, /* the ID or name of what was changed */
+    before     TEXT, /* JSON */
+    after      TEXT  /* JSON */
+);
+
+CREATE TRIGGER audit_event_no_update
+    BEFORE UPDATE ON audit_event
+BEGIN
+    SELECT RAISE(ABORT, 'audit events cannot be changed');
+END;
+
+CREATE TRIGGER audit_event_no_delete
+    BEFORE DELETE ON audit_event
+BEGIN
+    SELECT RAISE(ABORT, 'audit events cannot be removed');
+END;
@@ -0,0 +1,15 @@
+/* The entities hosted by the server, and the users granted access to each. Only used in the server database */
+CREATE TABLE entity
+(
+    id           INTEGER PRIMARY KEY,
+    slug         TEXT(32) NOT NULL UNIQUE,
+    database_url TEXT     NOT NULL,
+    created_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE entity_grant
+(
+    entity_id INTEGER NOT NULL,
+    user_id   INTEGER NOT NULL,
+    PRIMARY KEY (entity_id, user_id)
+);
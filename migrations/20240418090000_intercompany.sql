@@ -0,0 +1,16 @@
+/* For intercompany accounts, the slug of the entity the balance is held with */
+ALTER TABLE account ADD COLUMN counterparty TEXT(32);
+
+/* Journals posted in the books of two entities under a shared reference. Only used in the server database */
+CREATE TABLE intercompany_journal
+(
+    id              INTEGER PRIMARY KEY,
+    reference       TEXT(32) NOT NULL UNIQUE,
+    date            DATE     NOT NULL,
+    amount          INTEGER  NOT NULL, /* the amount owed to the first entity by the second */
+    from_entity_id  INTEGER  NOT NULL,
+    from_journal_id INTEGER  NOT NULL,
+    to_entity_id    INTEGER  NOT NULL,
+    to_journal_id   INTEGER  NOT NULL,
+    created_by      INTEGER  NOT NULL
+);
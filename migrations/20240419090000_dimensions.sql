@@ -0,0 +1,26 @@
+/* Analytical dimensions, such as project or department, and the values entries may be tagged with */
+CREATE TABLE dimension
+(
+    name        TEXT(32) PRIMARY KEY,
+    description TEXT(140)
+);
+
+CREATE TABLE dimension_value
+(
+    dimension   TEXT(32) NOT NULL,
+    value       TEXT(32) NOT NULL,
+    description TEXT(140),
+    PRIMARY KEY (dimension, value)
+);
+
+/* At most one value of each dimension per entry */
+CREATE TABLE entry_tag
+(
+    entry_id  INTEGER  NOT NULL,
+    dimension TEXT(32) NOT NULL,
+    value     TEXT(32) NOT NULL,
+    PRIMARY KEY (entry_id, dimension)
+);
+
+ALTER TABLE recurring_journal_entry ADD COLUMN tags TEXT; /* JSON object of dimension tags */
+ALTER TABLE draft_entry ADD COLUMN tags TEXT; /* JSON object of dimension tags */
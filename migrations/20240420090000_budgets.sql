@@ -0,0 +1,12 @@
+/* Budgeted balance of a profit and loss account for a month, optionally for one dimension value */
+CREATE TABLE budget
+(
+    account_id INTEGER  NOT NULL,
+    month      DATE     NOT NULL, /* the first day of the month */
+    dimension  TEXT(32),
+    value      TEXT(32),
+    amount     INTEGER  NOT NULL
+);
+
+/* The month the fiscal year starts in, 1 for January, for budgets and year to date reports */
+INSERT OR IGNORE INTO settings (name, intValue) VALUES ('fiscalYearStartMonth', 1);
@@ -0,0 +1,7 @@
+/* Maker-checker compares users by ID, as names need not be unique over time */
+ALTER TABLE draft_batch ADD COLUMN created_by_user_id INTEGER;
+ALTER TABLE draft_batch ADD COLUMN reviewed_by_user_id INTEGER;
+
+UPDATE draft_batch SET created_by_user_id = (SELECT id FROM user WHERE user.name = draft_batch.created_by);
+UPDATE draft_batch SET reviewed_by_user_id = (SELECT id FROM user WHERE user.name = draft_batch.reviewed_by)
+WHERE reviewed_by IS NOT NULL;
@@ -0,0 +1,2 @@
+/* Output VAT is seeded as 270, within the range current liabilities are numbered from */
+UPDATE settings SET intValue = 271 WHERE name = 'nextAccountCurrentLiability' AND intValue < 271;
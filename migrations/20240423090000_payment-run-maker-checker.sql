@@ -0,0 +1,13 @@
+/* Input VAT is recoverable, so it is an asset rather than a liability */
+UPDATE account SET type = 'currentAsset' WHERE id = 271 AND type = 'currentLiability';
+
+/* Trade Creditors and Input VAT are seeded as 260 and 271, within the range current liabilities are numbered from */
+UPDATE settings SET intValue = 272 WHERE name = 'nextAccountCurrentLiability' AND intValue < 272;
+
+/* A payment run must be confirmed by a different user to the one who proposed it */
+ALTER TABLE payment_run ADD COLUMN created_by INTEGER;
+
+UPDATE payment_run SET created_by = (
+    SELECT user_id FROM audit_event
+    WHERE operation = 'paymentRunNew' AND subject = CAST(payment_run.id AS TEXT)
+);
@@ -0,0 +1,2 @@
+/* Accounts holding money for clients, which are sent statements in bulk */
+ALTER TABLE account ADD COLUMN client BOOLEAN NOT NULL DEFAULT FALSE;
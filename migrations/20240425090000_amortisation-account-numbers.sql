@@ -0,0 +1,4 @@
+/* Prepayments and Accruals are seeded as 130 and 230, within the ranges current assets and current liabilities are
+   numbered from */
+UPDATE settings SET intValue = 131 WHERE name = 'nextAccountCurrentAsset' AND intValue < 131;
+UPDATE settings SET intValue = 231 WHERE name = 'nextAccountCurrentLiability' AND intValue < 231;
@@ -0,0 +1,16 @@
+/* Recurring journal narratives may be as long as the narratives of the journals they post */
+CREATE TABLE recurring_journal_new
+(
+    id                     INTEGER PRIMARY KEY,
+    unstructured_narrative TEXT(140) NOT NULL,
+    recurrence             TEXT NOT NULL,
+    day                    INTEGER, /* day of the month, for the monthlyOnDay recurrence */
+    start_date             DATE NOT NULL,
+    end_date               DATE
+);
+
+INSERT INTO recurring_journal_new (id, unstructured_narrative, recurrence, day, start_date, end_date)
+SELECT id, unstructured_narrative, recurrence, day, start_date, end_date FROM recurring_journal;
+
+DROP TABLE recurring_journal;
+ALTER TABLE recurring_journal_new RENAME TO recurring_journal;
@@ -0,0 +1,230 @@
+//! Aged debtors and aged creditors.
+//!
+//! The amount outstanding on each open item as at a date is placed in a bucket by the number of
+//! days past its due date, and the buckets are totalled per contact. Only allocations dated on or
+//! before the report date are taken into account, so part-settled documents age by their
+//! remaining balance. Cash on account is not yet due, and ages from the date it was received or
+//! paid.
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteExecutor;
+
+use crate::{allocations, db};
+use crate::contacts::ContactKind;
+
+/// The sales or purchase ledger.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AgedLedger {
+    /// Open items for customers, reported as aged debtors.
+    Receivables,
+    /// Open items for suppliers, reported as aged creditors.
+    Payables,
+}
+
+impl AgedLedger {
+    pub fn title(&self) -> &'static str {
+        match self {
+            AgedLedger::Receivables => "Aged debtors",
+            AgedLedger::Payables => "Aged creditors",
+        }
+    }
+}
+
+/// Amounts outstanding by age.
+#[derive(Clone, Default, Serialize)]
+pub struct AgedBalance {
+    /// Not yet due.
+    pub current: i64,
+    /// 1 to 30 days past due.
+    pub days_30: i64,
+    /// 31 to 60 days past due.
+    pub days_60: i64,
+    /// 61 to 90 days past due.
+    pub days_90: i64,
+    /// More than 90 days past due.
+    pub days_over_90: i64,
+    pub total: i64,
+}
+
+impl AgedBalance {
+    fn add(&mut self, days_overdue: i64, amount: i64) {
+        match days_overdue {
+            i64::MIN..=0 => self.current += amount,
+            1..=30 => self.days_30 += amount,
+            31..=60 => self.days_60 += amount,
+            61..=90 => self.days_90 += amount,
+            _ => self.days_over_90 += amount,
+        }
+        self.total += amount;
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct AgedContact {
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub balance: AgedBalance,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AgedReport {
+    pub ledger: AgedLedger,
+    pub as_at: String,
+    pub contacts: Vec<AgedContact>,
+    pub total: AgedBalance,
+}
+
+pub async fn aged_report(e: impl SqliteExecutor<'_>, ledger: AgedLedger, as_at: NaiveDate) -> Result<AgedReport> {
+    let as_at_str = as_at.to_string();
+    let (kind, sign) = match ledger {
+        AgedLedger::Receivables => (ContactKind::Customer, 1),
+        AgedLedger::Payables => (ContactKind::Supplier, -1),
+    };
+    let items = db::open_item_query(e, None, Some(as_at_str.as_str())).await?
+        .into_iter()
+        .filter(|i| i.contact_kind == kind);
+    let mut contacts: Vec<AgedContact> = Vec::new();
+    let mut total = AgedBalance::default();
+    for item in items {
+        let due_date = NaiveDate::parse_from_str(item.due_date.as_str(), "%Y-%m-%d")?;
+        let days_overdue = (as_at - due_date).num_days();
+        let outstanding = sign * allocations::outstanding(item.amount, item.allocated);
+        if contacts.last().map(|c| c.contact_id) != Some(item.contact_id) {
+            contacts.push(AgedContact {
+                contact_id: item.contact_id,
+                contact_name: item.contact_name,
+                balance: AgedBalance::default(),
+            });
+        }
+        contacts.last_mut().unwrap().balance.add(days_overdue, outstanding);
+        total.add(days_overdue, outstanding);
+    }
+    Ok(AgedReport {
+        ledger,
+        as_at: as_at_str,
+        contacts,
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Pool, Sqlite};
+
+    use super::*;
+    use crate::{contacts, entities, invoicing, ledger, purchases};
+    use crate::contacts::ContactData;
+    use crate::invoicing::{InvoiceData, InvoiceKind, InvoiceLineData};
+    use crate::ledger::AccountType;
+    use crate::purchases::{BillData, BillLineData};
+    use crate::users::{Role, User};
+
+    fn bookkeeper() -> User {
+        User {
+            user_id: 1,
+            name: "bookkeeper".to_string(),
+            role: Role::Bookkeeper,
+        }
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    async fn contact(e: &Pool<Sqlite>, kind: ContactKind, name: &str) -> i64 {
+        contacts::contact_new(e, &ContactData {
+            kind,
+            name: name.to_string(),
+            address: None,
+            postcode: None,
+            country: None,
+            email: None,
+            tax_id: None,
+            control_account_id: None,
+        }, &bookkeeper()).await.unwrap()
+    }
+
+    /// Approve an invoice for 100.00 plus standard rate VAT, returning its ID.
+    async fn invoice(e: &Pool<Sqlite>, contact_id: i64, date_str: &str, due_date: &str) -> i64 {
+        let data = InvoiceData {
+            kind: InvoiceKind::Invoice,
+            contact_id,
+            date: date(date_str),
+            due_date: date(due_date),
+            reference: None,
+            lines: vec![InvoiceLineData {
+                description: "Consulting".to_string(),
+                quantity: 1.0,
+                unit_price: 10000,
+                tax_code: "STANDARD".to_string(),
+                account_id: None,
+            }],
+        };
+        let invoice_id = invoicing::invoice_new(e, &data, &bookkeeper()).await.unwrap();
+        invoicing::invoice_approve(e, invoice_id, &bookkeeper()).await.unwrap();
+        invoice_id
+    }
+
+    #[actix_web::test]
+    async fn aged_report_buckets_debtors_by_days_past_due() {
+        let db = entities::memory_connect().await;
+        let customer = contact(&db, ContactKind::Customer, "Customer").await;
+        let current = invoice(&db, customer, "2024-06-20", "2024-07-01").await;
+        let days_30 = invoice(&db, customer, "2024-05-15", "2024-06-15").await;
+        invoice(&db, customer, "2024-04-15", "2024-05-15").await;
+        invoice(&db, customer, "2024-03-15", "2024-04-15").await;
+        invoice(&db, customer, "2024-01-01", "2024-02-01").await;
+        invoicing::invoice_receive(&db, days_30, 2000, date("2024-06-20"), 100, &bookkeeper()).await.unwrap();
+        // Received after the report date, so still outstanding as at it.
+        invoicing::invoice_receive(&db, current, 12000, date("2024-07-05"), 100, &bookkeeper()).await.unwrap();
+
+        let report = aged_report(&db, AgedLedger::Receivables, date("2024-07-01")).await.unwrap();
+        assert_eq!(report.contacts.len(), 1);
+        let balance = &report.contacts[0].balance;
+        assert_eq!((balance.current, balance.days_30, balance.days_60, balance.days_90, balance.days_over_90),
+                   (12000, 10000, 12000, 12000, 12000));
+        assert_eq!(balance.total, 58000);
+        assert_eq!(report.total.total, 58000);
+
+        let report = aged_report(&db, AgedLedger::Receivables, date("2024-07-05")).await.unwrap();
+        assert_eq!(report.total.current, 0);
+    }
+
+    #[actix_web::test]
+    async fn aged_report_totals_creditors_per_supplier() {
+        let db = entities::memory_connect().await;
+        let expense_account = ledger::account_new(&db, None, &"Rent".to_string(), &AccountType::IndirectExpense,
+                                                  None, None, &User { role: Role::Admin, ..bookkeeper() })
+            .await
+            .unwrap();
+        let customer = contact(&db, ContactKind::Customer, "Customer").await;
+        invoice(&db, customer, "2024-06-01", "2024-06-30").await;
+        for (name, due_date) in [("Landlord", "2024-06-01"), ("Utility", "2024-07-15")] {
+            let contact_id = contact(&db, ContactKind::Supplier, name).await;
+            let bill = BillData {
+                contact_id,
+                supplier_reference: None,
+                date: date("2024-05-01"),
+                due_date: date(due_date),
+                lines: vec![BillLineData {
+                    description: "Rent".to_string(),
+                    account_id: expense_account,
+                    net_amount: 5000,
+                    tax_code: "ZERO".to_string(),
+                    tax_amount: None,
+                }],
+            };
+            purchases::bill_new(&db, &bill, &bookkeeper()).await.unwrap();
+        }
+
+        let report = aged_report(&db, AgedLedger::Payables, date("2024-07-01")).await.unwrap();
+        let names: Vec<&str> = report.contacts.iter().map(|c| c.contact_name.as_str()).collect();
+        assert_eq!(names, vec!["Landlord", "Utility"]);
+        assert_eq!(report.contacts[0].balance.days_30, 5000);
+        assert_eq!(report.contacts[1].balance.current, 5000);
+        assert_eq!(report.total.total, 10000);
+    }
+}
@@ -0,0 +1,345 @@
+//! Settlement of invoices and bills by receipts and payments.
+//!
+//! Each allocation records that part of a receipt or payment journal settles an invoice, credit
+//! note or bill, so that the amount outstanding on each document, and its age, is known.
+//!
+//! Every journal with entries for a contact is an open item until it is fully allocated. Cash
+//! received or paid without a document stays on account until it is allocated, and a credit note
+//! can be allocated against the invoice it corrects.
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{contacts, db, invoicing, ledger, purchases, vat};
+use crate::contacts::ContactKind;
+use crate::error::Error::InstructionError;
+use crate::invoicing::InvoiceState;
+use crate::ledger::{BatchMetadata, Journal, JournalEntry};
+use crate::users::{Role, User};
+
+/// An invoice, credit note or bill that can be settled.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Document {
+    Invoice(i64),
+    Bill(i64),
+}
+
+impl Document {
+    pub fn invoice_id(&self) -> Option<i64> {
+        match self {
+            Document::Invoice(id) => Some(*id),
+            Document::Bill(_) => None,
+        }
+    }
+
+    pub fn bill_id(&self) -> Option<i64> {
+        match self {
+            Document::Invoice(_) => None,
+            Document::Bill(id) => Some(*id),
+        }
+    }
+}
+
+/// A receipt or payment settling `amount` of a document, with the journal that posts it.
+pub struct Settlement {
+    pub document: Document,
+    pub amount: i64,
+    pub journal: Journal,
+}
+
+/// The signed amount of an open item still to be allocated.
+pub fn outstanding(amount: i64, allocated: i64) -> i64 {
+    amount - amount.signum() * allocated
+}
+
+/// A journal for a contact that is not fully allocated.
+#[derive(Clone, Serialize)]
+pub struct OpenItem {
+    pub journal_id: i64,
+    pub date: String,
+    pub due_date: String,
+    /// The invoice, credit note or bill posted by the journal, if any.
+    pub document: Option<Document>,
+    pub narrative: String,
+    /// The total of the journal's entries for the contact, positive for a debit.
+    pub amount: i64,
+    pub allocated: i64,
+    pub outstanding: i64,
+}
+
+impl From<db::OpenItemResult> for OpenItem {
+    fn from(r: db::OpenItemResult) -> Self {
+        OpenItem {
+            journal_id: r.journal_id,
+            date: r.date,
+            due_date: r.due_date,
+            document: r.invoice_id.map(Document::Invoice).or(r.bill_id.map(Document::Bill)),
+            narrative: r.narrative,
+            amount: r.amount,
+            allocated: r.allocated,
+            outstanding: outstanding(r.amount, r.allocated),
+        }
+    }
+}
+
+/// List the open items for a contact, optionally as at a date.
+pub async fn open_items(e: impl SqliteExecutor<'_>, contact_id: i64, as_at: Option<NaiveDate>) -> Result<Vec<OpenItem>> {
+    let as_at = as_at.map(|d| d.to_string());
+    Ok(db::open_item_query(e, Some(contact_id), as_at.as_deref()).await?
+        .into_iter()
+        .map(OpenItem::from)
+        .collect())
+}
+
+#[derive(Clone, Serialize)]
+pub struct Allocation {
+    pub allocation_id: i64,
+    pub journal_id: i64,
+    pub document: Document,
+    pub amount: i64,
+    pub date: String,
+}
+
+/// List the allocations made to or from the journals for a contact.
+pub async fn allocation_list(e: impl SqliteExecutor<'_>, contact_id: i64) -> Result<Vec<Allocation>> {
+    Ok(db::allocation_list_query(e, contact_id).await?
+        .into_iter()
+        .map(|r| Allocation {
+            allocation_id: r.allocation_id,
+            journal_id: r.journal_id,
+            document: r.invoice_id.map(Document::Invoice).or(r.bill_id.map(Document::Bill)).unwrap(),
+            amount: r.amount,
+            date: r.date,
+        })
+        .collect())
+}
+
+/// Allocate `amount` of a journal for a contact, such as cash on account or a credit note, to an
+/// invoice or bill for the same contact.
+///
+/// The allocation is dated `date`, or the later of the journal and the document dates if not
+/// given. Returns the allocation ID.
+pub async fn allocation_new(e: &Pool<Sqlite>, journal_id: i64, document: Document, amount: i64,
+                            date: Option<NaiveDate>, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    if amount <= 0 {
+        bail!(InstructionError("allocation amount must be positive".to_string()));
+    }
+    let (document_journal_id, contact_id) = match document {
+        Document::Invoice(invoice_id) => {
+            let invoice = invoicing::invoice_detail(e, invoice_id).await?;
+            if invoice.state != InvoiceState::Approved {
+                bail!(InstructionError(format!("invoice {invoice_id} is not approved")));
+            }
+            (invoice.journal_id.unwrap(), invoice.contact_id)
+        }
+        Document::Bill(bill_id) => {
+            let bill = purchases::bill_detail(e, bill_id).await?;
+            (bill.journal_id, bill.contact_id)
+        }
+    };
+    if document_journal_id == journal_id {
+        bail!(InstructionError("a document cannot be allocated to itself".to_string()));
+    }
+    let items = open_items(e, contact_id, None).await?;
+    let Some(document_item) = items.iter().find(|i| i.journal_id == document_journal_id) else {
+        bail!(InstructionError("document is already fully allocated".to_string()));
+    };
+    let Some(item) = items.iter().find(|i| i.journal_id == journal_id) else {
+        bail!(InstructionError(format!("journal {journal_id} has nothing to allocate for contact {contact_id}")));
+    };
+    if item.outstanding.signum() == document_item.outstanding.signum() {
+        bail!(InstructionError("journal and document must be a debit and a credit".to_string()));
+    }
+    let available = item.outstanding.abs().min(document_item.outstanding.abs());
+    if amount > available {
+        bail!(InstructionError(format!("allocation must be between 1 and {available}")));
+    }
+    let earliest = item.date.clone().max(document_item.date.clone());
+    let date = date.map(|d| d.to_string()).unwrap_or(earliest.clone());
+    if date < earliest {
+        bail!(InstructionError(format!("allocation cannot be dated before {earliest}")));
+    }
+    vat::lock_check(e, date.as_str()).await?;
+    Ok(db::allocation_new_tx(e, journal_id, &document, amount, date.as_str(), user.user_id).await?)
+}
+
+/// Delete an allocation, unless it is dated in a submitted VAT period.
+pub async fn allocation_delete(e: &Pool<Sqlite>, allocation_id: i64, user: &User) -> Result<()> {
+    user.require(Role::Bookkeeper)?;
+    let Some(date) = db::allocation_date_query(e, allocation_id).await? else {
+        bail!(InstructionError(format!("allocation {allocation_id} does not exist")));
+    };
+    vat::lock_check(e, date.as_str()).await?;
+    match db::allocation_delete_tx(e, allocation_id, user.user_id).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("allocation {allocation_id} does not exist"))),
+        result => Ok(result?),
+    }
+}
+
+/// Record cash received from a customer, or paid to a supplier, that is not yet allocated to a
+/// document, returning the journal ID.
+pub async fn cash_on_account(e: &Pool<Sqlite>, contact_id: i64, amount: i64, date: NaiveDate,
+                             cash_account_id: i64, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    purchases::cash_account_validate(e, cash_account_id).await?;
+    if amount <= 0 {
+        bail!(InstructionError("amount must be positive".to_string()));
+    }
+    let contact = contacts::contact_detail(e, contact_id).await?;
+    let (control_account, sign, description, generated_by) = match contact.kind {
+        ContactKind::Customer => (invoicing::debtors_account(e, contact_id).await?, 1, "Receipt", "receiptOnAccount"),
+        ContactKind::Supplier => (purchases::creditors_account(e, contact_id).await?, -1, "Payment", "paymentOnAccount"),
+    };
+    let journal = Journal {
+        unstructured_narrative: ledger::narrative_truncate(format!("{description} on account {}", contact.name)),
+        entries: vec![
+            JournalEntry {
+                account: cash_account_id,
+                amount: sign * amount,
+                ..Default::default()
+            },
+            JournalEntry {
+                account: control_account,
+                amount: -sign * amount,
+                contact: Some(contact_id),
+                ..Default::default()
+            },
+        ],
+    };
+    let metadata = BatchMetadata {
+        date: Some(date.to_string()),
+        generated_by: Some(generated_by.to_string()),
+        created_by: Some(user.user_id),
+    };
+    Ok(ledger::batch_new(e, &metadata, vec![journal]).await?.1[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contacts::ContactData;
+    use crate::entities;
+    use crate::error::Error;
+    use crate::invoicing::{InvoiceData, InvoiceKind, InvoiceLineData};
+    use crate::settings;
+
+    fn bookkeeper() -> User {
+        User {
+            user_id: 1,
+            name: "bookkeeper".to_string(),
+            role: Role::Bookkeeper,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 4, day).unwrap()
+    }
+
+    /// Set up a customer with an approved invoice for 120.00 and 50.00 received on account,
+    /// returning the customer, invoice and receipt journal IDs.
+    async fn customer_with_receipt(e: &Pool<Sqlite>) -> (i64, i64, i64) {
+        let contact_id = contacts::contact_new(e, &ContactData {
+            kind: ContactKind::Customer,
+            name: "Customer".to_string(),
+            address: None,
+            postcode: None,
+            country: None,
+            email: None,
+            tax_id: None,
+            control_account_id: None,
+        }, &bookkeeper()).await.unwrap();
+        let invoice = InvoiceData {
+            kind: InvoiceKind::Invoice,
+            contact_id,
+            date: date(1),
+            due_date: date(30),
+            reference: None,
+            lines: vec![InvoiceLineData {
+                description: "Consulting".to_string(),
+                quantity: 1.0,
+                unit_price: 10000,
+                tax_code: "STANDARD".to_string(),
+                account_id: None,
+            }],
+        };
+        let invoice_id = invoicing::invoice_new(e, &invoice, &bookkeeper()).await.unwrap();
+        invoicing::invoice_approve(e, invoice_id, &bookkeeper()).await.unwrap();
+        let journal_id = cash_on_account(e, contact_id, 5000, date(5), 100, &bookkeeper()).await.unwrap();
+        (contact_id, invoice_id, journal_id)
+    }
+
+    async fn invoice_outstanding(e: &Pool<Sqlite>, invoice_id: i64) -> i64 {
+        invoicing::invoice_detail(e, invoice_id).await.unwrap().outstanding
+    }
+
+    #[actix_web::test]
+    async fn allocation_new_settles_invoice_from_cash_on_account() {
+        let db = entities::memory_connect().await;
+        let (contact_id, invoice_id, journal_id) = customer_with_receipt(&db).await;
+        assert_eq!(open_items(&db, contact_id, None).await.unwrap().len(), 2);
+
+        let allocation_id = allocation_new(&db, journal_id, Document::Invoice(invoice_id), 5000, None, &bookkeeper())
+            .await
+            .unwrap();
+        assert_eq!(invoice_outstanding(&db, invoice_id).await, 7000);
+        let items = open_items(&db, contact_id, None).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].document, items[0].outstanding), (Some(Document::Invoice(invoice_id)), 7000));
+        let allocations = allocation_list(&db, contact_id).await.unwrap();
+        assert_eq!(allocations.len(), 1);
+        assert_eq!((allocations[0].allocation_id, allocations[0].date.as_str()), (allocation_id, "2024-04-05"));
+    }
+
+    #[actix_web::test]
+    async fn allocation_new_refuses_over_allocation() {
+        let db = entities::memory_connect().await;
+        let (_, invoice_id, journal_id) = customer_with_receipt(&db).await;
+        let document = Document::Invoice(invoice_id);
+        assert!(is_instruction_error(allocation_new(&db, journal_id, document, 5001, None, &bookkeeper()).await));
+        assert!(is_instruction_error(allocation_new(&db, journal_id, document, 0, None, &bookkeeper()).await));
+        assert!(is_instruction_error(allocation_new(&db, journal_id, document, 1000, Some(date(4)), &bookkeeper())
+            .await));
+
+        allocation_new(&db, journal_id, document, 3000, None, &bookkeeper()).await.unwrap();
+        assert!(is_instruction_error(allocation_new(&db, journal_id, document, 2001, None, &bookkeeper()).await));
+        allocation_new(&db, journal_id, document, 2000, None, &bookkeeper()).await.unwrap();
+        assert!(is_instruction_error(allocation_new(&db, journal_id, document, 1, None, &bookkeeper()).await));
+        assert_eq!(invoice_outstanding(&db, invoice_id).await, 7000);
+    }
+
+    #[actix_web::test]
+    async fn allocation_delete_reopens_the_invoice() {
+        let db = entities::memory_connect().await;
+        let (contact_id, invoice_id, journal_id) = customer_with_receipt(&db).await;
+        let allocation_id = allocation_new(&db, journal_id, Document::Invoice(invoice_id), 5000, None, &bookkeeper())
+            .await
+            .unwrap();
+        allocation_delete(&db, allocation_id, &bookkeeper()).await.unwrap();
+        assert_eq!(invoice_outstanding(&db, invoice_id).await, 12000);
+        assert_eq!(open_items(&db, contact_id, None).await.unwrap().len(), 2);
+        assert!(allocation_list(&db, contact_id).await.unwrap().is_empty());
+        assert!(is_instruction_error(allocation_delete(&db, allocation_id, &bookkeeper()).await));
+    }
+
+    #[actix_web::test]
+    async fn allocation_delete_refuses_allocations_in_a_locked_period() {
+        let db = entities::memory_connect().await;
+        let (_, invoice_id, journal_id) = customer_with_receipt(&db).await;
+        let allocation_id = allocation_new(&db, journal_id, Document::Invoice(invoice_id), 5000, None, &bookkeeper())
+            .await
+            .unwrap();
+        let mut connection = db.acquire().await.unwrap();
+        settings::set_settings_str(&mut connection, "vatLockDate", "2024-04-05", None).await;
+        drop(connection);
+        assert!(is_instruction_error(allocation_delete(&db, allocation_id, &bookkeeper()).await));
+        assert_eq!(invoice_outstanding(&db, invoice_id).await, 7000);
+    }
+}
@@ -0,0 +1,405 @@
+//! Prepayment and accrual amortisation schedules.
+//!
+//! A schedule spreads a total evenly over a number of monthly periods from a start date, posting
+//! one journal at the end of each period when [`amortisation_run`] is run for it.
+//!
+//! - A prepayment releases the amount paid in advance from the prepayment account to the expense
+//!   account each period.
+//! - An accrual charges the expense each period against the accrual account, and reverses the
+//!   charge on the first day of the following period, so that the supplier's bill can be posted
+//!   to the expense account as usual when it arrives.
+//!
+//! The balance account defaults to the `prepaymentsAccount` or `accrualsAccount` setting.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::{Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, fx, ledger, settings};
+use crate::error::Error::InstructionError;
+use crate::ledger::{AccountType, BatchMetadata, Journal, JournalEntry};
+use crate::users::{Role, User};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum ScheduleKind {
+    /// An expense paid in advance, released from the prepayment account.
+    Prepayment,
+    /// An expense incurred but not yet billed, charged to the accrual account and reversed.
+    Accrual,
+}
+
+/// The details of an amortisation schedule, as given when the schedule is created.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ScheduleData {
+    pub kind: ScheduleKind,
+    pub description: String,
+    pub total: i64,
+    /// A date in the first period.
+    pub start_date: NaiveDate,
+    /// The number of monthly periods.
+    pub periods: i64,
+    pub expense_account_id: i64,
+    /// The prepayment or accrual account.
+    pub balance_account_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SchedulePeriod {
+    /// The period, counting from 1.
+    pub period: i64,
+    /// The last day of the period, which the journal is dated.
+    pub date: String,
+    pub amount: i64,
+    /// The journal the period was posted in, or [`None`] if it has not been posted yet.
+    pub journal_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Schedule {
+    pub schedule_id: i64,
+    pub kind: ScheduleKind,
+    pub description: String,
+    pub total: i64,
+    pub start_date: String,
+    pub periods: i64,
+    pub expense_account_id: i64,
+    pub balance_account_id: i64,
+    pub amortised: i64,
+    pub remaining: i64,
+    pub periods_posted: i64,
+    /// Every period of the schedule, posted or not. Only filled in by [`schedule_detail`].
+    pub lines: Vec<SchedulePeriod>,
+}
+
+impl From<db::ScheduleResult> for Schedule {
+    fn from(r: db::ScheduleResult) -> Self {
+        Schedule {
+            schedule_id: r.schedule_id,
+            kind: r.kind,
+            description: r.description,
+            total: r.total,
+            start_date: r.start_date,
+            periods: r.periods,
+            expense_account_id: r.expense_account_id,
+            balance_account_id: r.balance_account_id,
+            amortised: r.amortised,
+            remaining: r.total - r.amortised,
+            periods_posted: r.periods_posted,
+            lines: Vec::new(),
+        }
+    }
+}
+
+impl Schedule {
+    /// The amount for a period, counting from 1. The total is spread evenly, with any remainder
+    /// falling in the later periods.
+    fn amount(&self, period: i64) -> i64 {
+        self.total * period / self.periods - self.total * (period - 1) / self.periods
+    }
+
+    /// The last day of a period, counting from 1.
+    fn date(&self, period: i64) -> NaiveDate {
+        let start = NaiveDate::parse_from_str(self.start_date.as_str(), "%Y-%m-%d").unwrap();
+        fx::period_end(start + Months::new((period - 1) as u32))
+    }
+
+    /// The journal posting the amount for a period.
+    fn journal(&self, period: i64) -> Journal {
+        let amount = self.amount(period);
+        Journal {
+            unstructured_narrative: ledger::narrative_truncate(
+                format!("{} {period}/{}", self.description, self.periods)),
+            entries: vec![
+                JournalEntry {
+                    account: self.expense_account_id,
+                    amount,
+                    ..Default::default()
+                },
+                JournalEntry {
+                    account: self.balance_account_id,
+                    amount: -amount,
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    /// The journal reversing an accrual for a period.
+    fn reversal(&self, period: i64) -> Journal {
+        let mut journal = self.journal(period);
+        journal.unstructured_narrative = ledger::narrative_truncate(
+            format!("Reversal of {} {period}/{}", self.description, self.periods));
+        for entry in &mut journal.entries {
+            entry.amount = -entry.amount;
+        }
+        journal
+    }
+}
+
+/// A period posted by an amortisation run.
+pub struct AmortisationCharge {
+    pub schedule_id: i64,
+    pub period: i64,
+    pub amount: i64,
+}
+
+/// A batch posted by an amortisation run.
+pub struct AmortisationBatch {
+    pub metadata: BatchMetadata,
+    pub journals: Vec<Journal>,
+    /// The period posted by each journal, in the same order, or empty for a batch of reversals.
+    pub charges: Vec<AmortisationCharge>,
+}
+
+/// Create an amortisation schedule, returning the schedule ID.
+pub async fn schedule_new(e: &Pool<Sqlite>, schedule: &ScheduleData, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    if schedule.description.is_empty() || schedule.description.len() > 120 {
+        bail!(InstructionError("schedule description must be 1-120 chars".to_string()));
+    }
+    if schedule.total <= 0 {
+        bail!(InstructionError("total must be positive".to_string()));
+    }
+    if !(1..=600).contains(&schedule.periods) {
+        bail!(InstructionError("schedule must have 1-600 periods".to_string()));
+    }
+    match db::account_type_query(e, schedule.expense_account_id).await? {
+        Some(AccountType::DirectExpense) | Some(AccountType::IndirectExpense) => {}
+        _ => bail!(InstructionError(format!("account {} is not an expense account", schedule.expense_account_id))),
+    }
+    let mut schedule = schedule.clone();
+    let (setting, account_type, description) = match schedule.kind {
+        ScheduleKind::Prepayment => ("prepaymentsAccount", AccountType::Prepayments, "prepayments"),
+        ScheduleKind::Accrual => ("accrualsAccount", AccountType::CurrentLiability, "current liability"),
+    };
+    if schedule.balance_account_id.is_none() {
+        schedule.balance_account_id = Some(settings::get_settings_int(e, setting).await.unwrap());
+    }
+    let balance_account_id = schedule.balance_account_id.unwrap();
+    if db::account_type_query(e, balance_account_id).await? != Some(account_type) {
+        bail!(InstructionError(format!("account {balance_account_id} is not a {description} account")));
+    }
+    Ok(db::amortisation_schedule_new_tx(e, &schedule, user.user_id).await?)
+}
+
+/// Get a schedule with each of its periods.
+pub async fn schedule_detail(e: &Pool<Sqlite>, schedule_id: i64) -> Result<Schedule> {
+    let Some(result) = db::amortisation_schedule_query(e, schedule_id).await? else {
+        bail!(InstructionError(format!("amortisation schedule {schedule_id} does not exist")));
+    };
+    let mut schedule = Schedule::from(result);
+    let posted: BTreeMap<i64, db::AmortisationResult> = db::amortisation_query(e, schedule_id).await?
+        .into_iter()
+        .map(|r| (r.period, r))
+        .collect();
+    schedule.lines = (1..=schedule.periods)
+        .map(|period| SchedulePeriod {
+            period,
+            date: schedule.date(period).to_string(),
+            amount: posted.get(&period).map(|r| r.amount).unwrap_or_else(|| schedule.amount(period)),
+            journal_id: posted.get(&period).map(|r| r.journal_id),
+        })
+        .collect();
+    Ok(schedule)
+}
+
+/// List schedules, optionally only those with periods still to post.
+pub async fn schedule_list(e: impl SqliteExecutor<'_>, active: bool) -> Result<Vec<Schedule>> {
+    Ok(db::amortisation_schedule_list_query(e, active).await?
+        .into_iter()
+        .map(Schedule::from)
+        .collect())
+}
+
+/// The batches posted by [`amortisation_run`].
+pub struct AmortisationRun {
+    pub batch_ids: Vec<i64>,
+    pub journal_count: usize,
+    pub total: i64,
+}
+
+/// Post every period of every schedule that ends on or before the end of the month containing
+/// `period_end` and has not already been posted.
+///
+/// Periods are posted in one batch per period end date, with accrual reversals in a further batch
+/// dated the day after. Returns [`None`] if there was nothing to post.
+pub async fn amortisation_run(e: &Pool<Sqlite>, period_end: NaiveDate, user: &User) -> Result<Option<AmortisationRun>> {
+    user.require(Role::Bookkeeper)?;
+    let period_end = fx::period_end(period_end);
+    let mut charges: BTreeMap<NaiveDate, (Vec<Journal>, Vec<AmortisationCharge>)> = BTreeMap::new();
+    let mut reversals: BTreeMap<NaiveDate, Vec<Journal>> = BTreeMap::new();
+    let mut total = 0;
+    for schedule in schedule_list(e, true).await? {
+        let posted: Vec<i64> = db::amortisation_query(e, schedule.schedule_id).await?
+            .into_iter()
+            .map(|r| r.period)
+            .collect();
+        for period in (1..=schedule.periods).filter(|p| !posted.contains(p)) {
+            let date = schedule.date(period);
+            if date > period_end {
+                break;
+            }
+            let amount = schedule.amount(period);
+            if amount == 0 {
+                continue;
+            }
+            total += amount;
+            let (journals, period_charges) = charges.entry(date).or_default();
+            journals.push(schedule.journal(period));
+            period_charges.push(AmortisationCharge {
+                schedule_id: schedule.schedule_id,
+                period,
+                amount,
+            });
+            if schedule.kind == ScheduleKind::Accrual {
+                reversals.entry(fx::next_period_start(date)).or_default().push(schedule.reversal(period));
+            }
+        }
+    }
+    if charges.is_empty() {
+        return Ok(None);
+    }
+    let journal_count = charges.values().map(|(j, _)| j.len()).sum();
+    let mut batches: Vec<AmortisationBatch> = Vec::new();
+    for (date, (journals, charges)) in charges {
+        ledger::journals_validate(e, &journals).await?;
+        batches.push(AmortisationBatch {
+            metadata: BatchMetadata {
+                date: Some(date.to_string()),
+                generated_by: Some("amortisation".to_string()),
+                created_by: Some(user.user_id),
+            },
+            journals,
+            charges,
+        });
+    }
+    for (date, journals) in reversals {
+        ledger::journals_validate(e, &journals).await?;
+        batches.push(AmortisationBatch {
+            metadata: BatchMetadata {
+                date: Some(date.to_string()),
+                generated_by: Some("accrualReversal".to_string()),
+                created_by: Some(user.user_id),
+            },
+            journals,
+            charges: Vec::new(),
+        });
+    }
+    let batch_ids = db::amortisation_run_tx(e, batches).await?;
+    Ok(Some(AmortisationRun {
+        batch_ids,
+        journal_count,
+        total,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn user(role: Role) -> User {
+        User {
+            user_id: 1,
+            name: "user".to_string(),
+            role,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    async fn expense_account(e: &Pool<Sqlite>) -> i64 {
+        ledger::account_new(e, None, &"Insurance".to_string(), &AccountType::IndirectExpense, None, None,
+                            &user(Role::Admin)).await.unwrap()
+    }
+
+    fn schedule(kind: ScheduleKind, total: i64, start_date: &str, expense_account_id: i64) -> ScheduleData {
+        ScheduleData {
+            kind,
+            description: "Insurance".to_string(),
+            total,
+            start_date: date(start_date),
+            periods: 3,
+            expense_account_id,
+            balance_account_id: None,
+        }
+    }
+
+    async fn balance(e: &Pool<Sqlite>, account_id: i64, as_at: &str) -> i64 {
+        ledger::account_list(e, date(as_at), None).await.unwrap()
+            .into_iter()
+            .find(|a| a.account_id == account_id)
+            .unwrap()
+            .account_balance
+    }
+
+    #[actix_web::test]
+    async fn amortisation_run_releases_prepayments_once_per_period() {
+        let db = entities::memory_connect().await;
+        let expenses = expense_account(&db).await;
+        let data = schedule(ScheduleKind::Prepayment, 1000, "2024-01-15", expenses);
+        let schedule_id = schedule_new(&db, &data, &user(Role::Bookkeeper)).await.unwrap();
+
+        let run = amortisation_run(&db, date("2024-02-10"), &user(Role::Bookkeeper)).await.unwrap().unwrap();
+        assert_eq!((run.batch_ids.len(), run.journal_count, run.total), (2, 2, 666));
+        assert!(amortisation_run(&db, date("2024-02-29"), &user(Role::Bookkeeper)).await.unwrap().is_none());
+        assert_eq!(balance(&db, expenses, "2024-12-31").await, 666);
+        assert_eq!(balance(&db, 130, "2024-12-31").await, -666);
+
+        let schedule = schedule_detail(&db, schedule_id).await.unwrap();
+        assert_eq!((schedule.amortised, schedule.remaining, schedule.periods_posted), (666, 334, 2));
+        let lines: Vec<(&str, i64, bool)> = schedule.lines.iter()
+            .map(|l| (l.date.as_str(), l.amount, l.journal_id.is_some()))
+            .collect();
+        assert_eq!(lines, vec![("2024-01-31", 333, true), ("2024-02-29", 333, true), ("2024-03-31", 334, false)]);
+    }
+
+    #[actix_web::test]
+    async fn amortisation_run_reverses_accruals_the_next_day() {
+        let db = entities::memory_connect().await;
+        let expenses = expense_account(&db).await;
+        let data = schedule(ScheduleKind::Accrual, 900, "2024-01-01", expenses);
+        schedule_new(&db, &data, &user(Role::Bookkeeper)).await.unwrap();
+
+        let run = amortisation_run(&db, date("2024-01-31"), &user(Role::Bookkeeper)).await.unwrap().unwrap();
+        assert_eq!((run.batch_ids.len(), run.journal_count, run.total), (2, 1, 300));
+        assert_eq!(balance(&db, expenses, "2024-01-31").await, 300);
+        assert_eq!(balance(&db, 230, "2024-01-31").await, -300);
+        assert_eq!(balance(&db, expenses, "2024-02-01").await, 0);
+        assert_eq!(balance(&db, 230, "2024-02-01").await, 0);
+    }
+
+    #[actix_web::test]
+    async fn schedule_new_checks_the_accounts() {
+        let db = entities::memory_connect().await;
+        let expenses = expense_account(&db).await;
+        let bookkeeper = user(Role::Bookkeeper);
+        let mut data = schedule(ScheduleKind::Prepayment, 1000, "2024-01-01", expenses);
+        data.balance_account_id = Some(230);
+        assert!(is_instruction_error(schedule_new(&db, &data, &bookkeeper).await));
+        let data = schedule(ScheduleKind::Accrual, 1000, "2024-01-01", 100);
+        assert!(is_instruction_error(schedule_new(&db, &data, &bookkeeper).await));
+        let mut data = schedule(ScheduleKind::Accrual, 1000, "2024-01-01", expenses);
+        data.periods = 0;
+        assert!(is_instruction_error(schedule_new(&db, &data, &bookkeeper).await));
+    }
+
+    #[actix_web::test]
+    async fn account_new_numbers_current_assets_after_seeded_accounts() {
+        let db = entities::memory_connect().await;
+        let account_id = ledger::account_new(&db, None, &"Deposits".to_string(), &AccountType::CurrentAsset, None,
+                                             None, &user(Role::Admin)).await.unwrap();
+        assert_eq!(account_id, 131);
+    }
+}
@@ -0,0 +1,536 @@
+//! The fixed asset register and depreciation.
+//!
+//! Each asset records its cost, residual value, useful life and depreciation method, along with
+//! the accounts its cost, accumulated depreciation and depreciation charge are posted to. These
+//! default to the `fixedAssetCostAccount`, `accumulatedDepreciationAccount` and
+//! `depreciationAccount` settings.
+//!
+//! # Depreciation
+//!
+//! Depreciation is charged monthly from the month the asset is acquired. [`depreciation_run`]
+//! posts the charge for every month up to the end of the period that has not yet been posted, so
+//! it can be run again for the same period without posting twice. The last month of the useful
+//! life writes the asset down to its residual value.
+//!
+//! # Disposal
+//!
+//! [`fixed_asset_dispose`] removes the cost and accumulated depreciation of the asset and posts
+//! the difference between the proceeds and the net book value to the `assetDisposalAccount`
+//! setting.
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, fx, ledger, purchases, settings};
+use crate::error::Error::InstructionError;
+use crate::ledger::{AccountType, BatchMetadata, Journal, JournalEntry};
+use crate::users::{Role, User};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum DepreciationMethod {
+    /// The depreciable amount is charged evenly over the useful life.
+    #[default]
+    StraightLine,
+    /// A fixed annual rate of the net book value is charged, with the remainder charged in the
+    /// last month of the useful life.
+    ReducingBalance,
+}
+
+/// The details of a fixed asset, as given when the asset is added to the register.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FixedAssetData {
+    pub name: String,
+    pub cost: i64,
+    #[serde(default)]
+    pub residual_value: i64,
+    pub acquisition_date: NaiveDate,
+    pub useful_life_months: i64,
+    #[serde(default)]
+    pub method: DepreciationMethod,
+    /// The annual rate in basis points, for the reducing balance method.
+    pub rate: Option<i64>,
+    pub cost_account_id: Option<i64>,
+    pub accumulated_account_id: Option<i64>,
+    pub depreciation_account_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DepreciationLine {
+    /// The month, as `YYYY-MM`.
+    pub month: String,
+    pub amount: i64,
+    /// The net book value at the end of the month.
+    pub net_book_value: i64,
+    /// The journal the charge was posted in, or [`None`] if it is projected.
+    pub journal_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct FixedAsset {
+    pub asset_id: i64,
+    pub name: String,
+    pub cost_account_id: i64,
+    pub accumulated_account_id: i64,
+    pub depreciation_account_id: i64,
+    pub cost: i64,
+    pub residual_value: i64,
+    pub acquisition_date: String,
+    pub useful_life_months: i64,
+    pub method: DepreciationMethod,
+    pub rate: Option<i64>,
+    pub accumulated_depreciation: i64,
+    pub net_book_value: i64,
+    pub disposal_date: Option<String>,
+    pub disposal_proceeds: Option<i64>,
+    pub disposal_journal_id: Option<i64>,
+    /// The depreciation posted followed by the projected charges for the rest of the useful life.
+    /// Only filled in by [`fixed_asset_detail`].
+    pub schedule: Vec<DepreciationLine>,
+}
+
+impl From<db::FixedAssetResult> for FixedAsset {
+    fn from(r: db::FixedAssetResult) -> Self {
+        FixedAsset {
+            asset_id: r.asset_id,
+            name: r.name,
+            cost_account_id: r.cost_account_id,
+            accumulated_account_id: r.accumulated_account_id,
+            depreciation_account_id: r.depreciation_account_id,
+            cost: r.cost,
+            residual_value: r.residual_value,
+            acquisition_date: r.acquisition_date,
+            useful_life_months: r.useful_life_months,
+            method: r.method,
+            rate: r.rate,
+            accumulated_depreciation: r.accumulated,
+            net_book_value: r.cost - r.accumulated,
+            disposal_date: r.disposal_date,
+            disposal_proceeds: r.disposal_proceeds,
+            disposal_journal_id: r.disposal_journal_id,
+            schedule: Vec::new(),
+        }
+    }
+}
+
+impl FixedAsset {
+    /// The index of `month` in the useful life, counting the month of acquisition as 0.
+    fn month_index(&self, month: &str) -> i64 {
+        month_number(month) - month_number(&self.acquisition_date[..7])
+    }
+
+    /// The month at `index` in the useful life, as `YYYY-MM`.
+    fn month(&self, index: i64) -> String {
+        let n = month_number(&self.acquisition_date[..7]) + index;
+        format!("{:04}-{:02}", n.div_euclid(12), n.rem_euclid(12) + 1)
+    }
+
+    /// The depreciation charge for the month at `index`, given the net book value at the start of
+    /// the month.
+    fn charge(&self, index: i64, net_book_value: i64) -> i64 {
+        let remaining = (net_book_value - self.residual_value).max(0);
+        if index < 0 || index >= self.useful_life_months {
+            return 0;
+        }
+        if index == self.useful_life_months - 1 {
+            return remaining;
+        }
+        let charge = match self.method {
+            DepreciationMethod::StraightLine => {
+                let depreciable = self.cost - self.residual_value;
+                depreciable * (index + 1) / self.useful_life_months - depreciable * index / self.useful_life_months
+            }
+            DepreciationMethod::ReducingBalance => {
+                (net_book_value as f64 * self.rate.unwrap_or(0) as f64 / 120000.0).round() as i64
+            }
+        };
+        charge.min(remaining)
+    }
+
+    /// The charges for each month from `from` to `to` inclusive, as `(index, amount)`, given the
+    /// net book value at the start of `from`.
+    fn charges(&self, from: i64, to: i64, mut net_book_value: i64) -> Vec<(i64, i64)> {
+        (from.max(0)..=to.min(self.useful_life_months - 1))
+            .map(|index| {
+                let charge = self.charge(index, net_book_value);
+                net_book_value -= charge;
+                (index, charge)
+            })
+            .collect()
+    }
+}
+
+/// The number of months since year 0 of a `YYYY-MM` month.
+fn month_number(month: &str) -> i64 {
+    let year: i64 = month[..4].parse().unwrap();
+    let month: i64 = month[5..7].parse().unwrap();
+    year * 12 + month - 1
+}
+
+async fn account_validate(e: impl SqliteExecutor<'_>, account_id: i64, account_types: &[AccountType], description: &str)
+                          -> Result<()> {
+    match db::account_type_query(e, account_id).await? {
+        Some(account_type) if account_types.contains(&account_type) => Ok(()),
+        Some(_) => bail!(InstructionError(format!("account {account_id} is not {description} account"))),
+        None => bail!(InstructionError(format!("account {account_id} does not exist"))),
+    }
+}
+
+/// Add an asset to the register, returning the asset ID.
+///
+/// The asset's cost is expected to have been posted already, for example by a bill to the cost
+/// account.
+pub async fn fixed_asset_new(e: &Pool<Sqlite>, asset: &FixedAssetData, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    if asset.name.is_empty() || asset.name.len() > 140 {
+        bail!(InstructionError("asset name must be 1-140 chars".to_string()));
+    }
+    if asset.cost <= 0 {
+        bail!(InstructionError("cost must be positive".to_string()));
+    }
+    if asset.residual_value < 0 || asset.residual_value > asset.cost {
+        bail!(InstructionError("residual value must be between zero and the cost".to_string()));
+    }
+    if !(1..=1200).contains(&asset.useful_life_months) {
+        bail!(InstructionError("useful life must be 1-1200 months".to_string()));
+    }
+    match (asset.method, asset.rate) {
+        (DepreciationMethod::ReducingBalance, Some(rate)) if (1..=10000).contains(&rate) => {}
+        (DepreciationMethod::ReducingBalance, _) => {
+            bail!(InstructionError("reducing balance rate must be 1-10000 basis points".to_string()));
+        }
+        (DepreciationMethod::StraightLine, Some(_)) => {
+            bail!(InstructionError("rate only applies to the reducing balance method".to_string()));
+        }
+        (DepreciationMethod::StraightLine, None) => {}
+    }
+    let mut asset = asset.clone();
+    if asset.cost_account_id.is_none() {
+        asset.cost_account_id = Some(settings::get_settings_int(e, "fixedAssetCostAccount").await.unwrap());
+    }
+    if asset.accumulated_account_id.is_none() {
+        asset.accumulated_account_id = Some(settings::get_settings_int(e, "accumulatedDepreciationAccount").await.unwrap());
+    }
+    if asset.depreciation_account_id.is_none() {
+        asset.depreciation_account_id = Some(settings::get_settings_int(e, "depreciationAccount").await.unwrap());
+    }
+    let non_current = [AccountType::NonCurrentAsset];
+    account_validate(e, asset.cost_account_id.unwrap(), &non_current, "a non-current asset").await?;
+    account_validate(e, asset.accumulated_account_id.unwrap(), &non_current, "a non-current asset").await?;
+    account_validate(e, asset.depreciation_account_id.unwrap(),
+                     &[AccountType::DirectExpense, AccountType::IndirectExpense], "an expense").await?;
+    Ok(db::fixed_asset_new_tx(e, &asset, user.user_id).await?)
+}
+
+/// Get an asset with its depreciation schedule.
+pub async fn fixed_asset_detail(e: &Pool<Sqlite>, asset_id: i64) -> Result<FixedAsset> {
+    let Some(result) = db::fixed_asset_query(e, asset_id).await? else {
+        bail!(InstructionError(format!("fixed asset {asset_id} does not exist")));
+    };
+    let mut asset = FixedAsset::from(result);
+    let mut net_book_value = asset.cost;
+    let mut next_index = 0;
+    for r in db::depreciation_query(e, asset_id).await? {
+        net_book_value -= r.amount;
+        next_index = asset.month_index(&r.month) + 1;
+        asset.schedule.push(DepreciationLine {
+            month: r.month,
+            amount: r.amount,
+            net_book_value,
+            journal_id: Some(r.journal_id),
+        });
+    }
+    if asset.disposal_date.is_none() {
+        for (index, amount) in asset.charges(next_index, asset.useful_life_months - 1, net_book_value) {
+            net_book_value -= amount;
+            asset.schedule.push(DepreciationLine {
+                month: asset.month(index),
+                amount,
+                net_book_value,
+                journal_id: None,
+            });
+        }
+    }
+    Ok(asset)
+}
+
+/// List the assets in the register, optionally excluding those disposed of.
+pub async fn fixed_asset_list(e: impl SqliteExecutor<'_>, active: bool) -> Result<Vec<FixedAsset>> {
+    Ok(db::fixed_asset_list_query(e, active).await?
+        .into_iter()
+        .map(FixedAsset::from)
+        .collect())
+}
+
+/// A month's depreciation of an asset, posted by [`depreciation_run`].
+pub struct DepreciationCharge {
+    pub asset_id: i64,
+    /// The month, as `YYYY-MM`.
+    pub month: String,
+    pub amount: i64,
+}
+
+/// The batch posted by [`depreciation_run`].
+pub struct DepreciationRun {
+    pub batch_id: i64,
+    pub journal_count: usize,
+    pub total: i64,
+}
+
+/// Post depreciation for every month up to the end of the month containing `period_end` that has
+/// not already been posted, with one journal per asset dated at the end of the month.
+///
+/// Returns [`None`] if there was nothing to post, in which case no batch is posted.
+pub async fn depreciation_run(e: &Pool<Sqlite>, period_end: NaiveDate, user: &User) -> Result<Option<DepreciationRun>> {
+    user.require(Role::Bookkeeper)?;
+    let period_end = fx::period_end(period_end);
+    let end_month = format!("{:04}-{:02}", period_end.year(), period_end.month());
+    let mut journals: Vec<(Journal, Vec<DepreciationCharge>)> = Vec::new();
+    let mut total = 0;
+    for asset in fixed_asset_list(e, true).await? {
+        let last_month = db::depreciation_query(e, asset.asset_id).await?.pop().map(|r| r.month);
+        let from = last_month.map(|m| asset.month_index(&m) + 1).unwrap_or(0);
+        let charges: Vec<DepreciationCharge> = asset.charges(from, asset.month_index(&end_month), asset.net_book_value)
+            .into_iter()
+            .filter(|(_, amount)| *amount != 0)
+            .map(|(index, amount)| DepreciationCharge {
+                asset_id: asset.asset_id,
+                month: asset.month(index),
+                amount,
+            })
+            .collect();
+        let amount: i64 = charges.iter().map(|c| c.amount).sum();
+        if amount == 0 {
+            continue;
+        }
+        total += amount;
+        let journal = Journal {
+            unstructured_narrative: ledger::narrative_truncate(format!("Depreciation of {} to {end_month}", asset.name)),
+            entries: vec![
+                JournalEntry {
+                    account: asset.depreciation_account_id,
+                    amount,
+                    ..Default::default()
+                },
+                JournalEntry {
+                    account: asset.accumulated_account_id,
+                    amount: -amount,
+                    ..Default::default()
+                },
+            ],
+        };
+        ledger::journals_validate(e, std::slice::from_ref(&journal)).await?;
+        journals.push((journal, charges));
+    }
+    if journals.is_empty() {
+        return Ok(None);
+    }
+    let journal_count = journals.len();
+    let metadata = BatchMetadata {
+        date: Some(period_end.to_string()),
+        generated_by: Some("depreciation".to_string()),
+        created_by: Some(user.user_id),
+    };
+    let batch_id = db::depreciation_run_tx(e, &metadata, journals).await?;
+    Ok(Some(DepreciationRun {
+        batch_id,
+        journal_count,
+        total,
+    }))
+}
+
+/// Dispose of an asset on `date`, posting the gain or loss against its net book value, and
+/// return the journal ID.
+///
+/// Any proceeds are received into `cash_account_id`. Depreciation should be run up to the month
+/// before the disposal first, as none is charged for the month of disposal or later.
+pub async fn fixed_asset_dispose(e: &Pool<Sqlite>, asset_id: i64, date: NaiveDate, proceeds: i64,
+                                 cash_account_id: Option<i64>, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    let asset = fixed_asset_detail(e, asset_id).await?;
+    if asset.disposal_date.is_some() {
+        bail!(InstructionError(format!("fixed asset {asset_id} has already been disposed of")));
+    }
+    if date.to_string() < asset.acquisition_date {
+        bail!(InstructionError("asset cannot be disposed of before it was acquired".to_string()));
+    }
+    let month = format!("{:04}-{:02}", date.year(), date.month());
+    if asset.schedule.iter().any(|l| l.journal_id.is_some() && l.month >= month) {
+        bail!(InstructionError(format!("depreciation has been posted for {month} or later")));
+    }
+    if proceeds < 0 {
+        bail!(InstructionError("proceeds cannot be negative".to_string()));
+    }
+    let disposal_account = settings::get_settings_int(e, "assetDisposalAccount").await.unwrap();
+    let mut entries = vec![
+        JournalEntry {
+            account: asset.accumulated_account_id,
+            amount: asset.accumulated_depreciation,
+            ..Default::default()
+        },
+        JournalEntry {
+            account: asset.cost_account_id,
+            amount: -asset.cost,
+            ..Default::default()
+        },
+    ];
+    if proceeds > 0 {
+        let Some(cash_account_id) = cash_account_id else {
+            bail!(InstructionError("a cash account is needed for the proceeds".to_string()));
+        };
+        purchases::cash_account_validate(e, cash_account_id).await?;
+        entries.push(JournalEntry {
+            account: cash_account_id,
+            amount: proceeds,
+            ..Default::default()
+        });
+    }
+    let gain = proceeds - asset.net_book_value;
+    if gain != 0 {
+        entries.push(JournalEntry {
+            account: disposal_account,
+            amount: -gain,
+            ..Default::default()
+        });
+    }
+    let journal = Journal {
+        unstructured_narrative: ledger::narrative_truncate(format!("Disposal of {}", asset.name)),
+        entries: entries.into_iter().filter(|e| e.amount != 0).collect(),
+    };
+    ledger::journals_validate(e, std::slice::from_ref(&journal)).await?;
+    let date = date.to_string();
+    let metadata = BatchMetadata {
+        date: Some(date.clone()),
+        generated_by: Some("assetDisposal".to_string()),
+        created_by: Some(user.user_id),
+    };
+    match db::fixed_asset_dispose_tx(e, asset_id, date.as_str(), proceeds, &metadata, journal).await {
+        Err(sqlx::Error::RowNotFound) => {
+            bail!(InstructionError(format!("fixed asset {asset_id} has already been disposed of")))
+        }
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn bookkeeper() -> User {
+        User {
+            user_id: 1,
+            name: "bookkeeper".to_string(),
+            role: Role::Bookkeeper,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn asset(cost: i64, residual_value: i64, useful_life_months: i64, method: DepreciationMethod, rate: Option<i64>)
+             -> FixedAssetData {
+        FixedAssetData {
+            name: "Van".to_string(),
+            cost,
+            residual_value,
+            acquisition_date: date("2024-01-15"),
+            useful_life_months,
+            method,
+            rate,
+            cost_account_id: None,
+            accumulated_account_id: None,
+            depreciation_account_id: None,
+        }
+    }
+
+    async fn balance(e: &Pool<Sqlite>, account_id: i64) -> i64 {
+        ledger::account_detail(e, account_id).await.unwrap().balance
+    }
+
+    fn amounts(asset: &FixedAsset) -> Vec<i64> {
+        asset.schedule.iter().map(|l| l.amount).collect()
+    }
+
+    #[actix_web::test]
+    async fn depreciation_run_posts_each_month_once() {
+        let db = entities::memory_connect().await;
+        let data = asset(12000, 0, 12, DepreciationMethod::StraightLine, None);
+        let asset_id = fixed_asset_new(&db, &data, &bookkeeper()).await.unwrap();
+
+        let run = depreciation_run(&db, date("2024-03-15"), &bookkeeper()).await.unwrap().unwrap();
+        assert_eq!((run.journal_count, run.total), (1, 3000));
+        assert!(depreciation_run(&db, date("2024-03-31"), &bookkeeper()).await.unwrap().is_none());
+        assert_eq!(balance(&db, 550).await, 3000);
+        assert_eq!(balance(&db, 181).await, -3000);
+
+        let asset = fixed_asset_detail(&db, asset_id).await.unwrap();
+        assert_eq!((asset.accumulated_depreciation, asset.net_book_value), (3000, 9000));
+        assert_eq!(asset.schedule.len(), 12);
+        assert_eq!(asset.schedule.iter().filter(|l| l.journal_id.is_some()).count(), 3);
+        assert_eq!(asset.schedule.last().map(|l| (l.month.as_str(), l.net_book_value)), Some(("2024-12", 0)));
+    }
+
+    #[actix_web::test]
+    async fn fixed_asset_detail_writes_down_to_the_residual_value() {
+        let db = entities::memory_connect().await;
+        let data = asset(10000, 1000, 7, DepreciationMethod::StraightLine, None);
+        let straight_line = fixed_asset_detail(&db, fixed_asset_new(&db, &data, &bookkeeper()).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(amounts(&straight_line), vec![1285, 1286, 1286, 1285, 1286, 1286, 1286]);
+        assert_eq!(straight_line.schedule.last().unwrap().net_book_value, 1000);
+
+        let data = asset(100000, 0, 3, DepreciationMethod::ReducingBalance, Some(2400));
+        let reducing_balance = fixed_asset_detail(&db, fixed_asset_new(&db, &data, &bookkeeper()).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(amounts(&reducing_balance), vec![2000, 1960, 96040]);
+    }
+
+    #[actix_web::test]
+    async fn fixed_asset_new_validates_the_method() {
+        let db = entities::memory_connect().await;
+        let data = asset(10000, 0, 12, DepreciationMethod::ReducingBalance, None);
+        assert!(is_instruction_error(fixed_asset_new(&db, &data, &bookkeeper()).await));
+        let data = asset(10000, 0, 12, DepreciationMethod::StraightLine, Some(2000));
+        assert!(is_instruction_error(fixed_asset_new(&db, &data, &bookkeeper()).await));
+        let data = asset(10000, 10001, 12, DepreciationMethod::StraightLine, None);
+        assert!(is_instruction_error(fixed_asset_new(&db, &data, &bookkeeper()).await));
+        let mut data = asset(10000, 0, 12, DepreciationMethod::StraightLine, None);
+        data.depreciation_account_id = Some(100);
+        assert!(is_instruction_error(fixed_asset_new(&db, &data, &bookkeeper()).await));
+    }
+
+    #[actix_web::test]
+    async fn fixed_asset_dispose_posts_the_gain_on_net_book_value() {
+        let db = entities::memory_connect().await;
+        let data = asset(12000, 0, 12, DepreciationMethod::StraightLine, None);
+        let asset_id = fixed_asset_new(&db, &data, &bookkeeper()).await.unwrap();
+        depreciation_run(&db, date("2024-03-31"), &bookkeeper()).await.unwrap();
+
+        assert!(is_instruction_error(fixed_asset_dispose(&db, asset_id, date("2024-03-10"), 0, None, &bookkeeper())
+            .await));
+        assert!(is_instruction_error(fixed_asset_dispose(&db, asset_id, date("2024-04-10"), 10000, None,
+                                                         &bookkeeper()).await));
+        fixed_asset_dispose(&db, asset_id, date("2024-04-10"), 10000, Some(100), &bookkeeper()).await.unwrap();
+        assert_eq!(balance(&db, 480).await, -1000);
+        assert_eq!(balance(&db, 181).await, 0);
+        assert_eq!(balance(&db, 100).await, 10000);
+        assert!(is_instruction_error(fixed_asset_dispose(&db, asset_id, date("2024-04-11"), 0, None, &bookkeeper())
+            .await));
+
+        let asset = fixed_asset_detail(&db, asset_id).await.unwrap();
+        assert_eq!(asset.disposal_date.as_deref(), Some("2024-04-10"));
+        assert_eq!(asset.schedule.len(), 3);
+        assert!(depreciation_run(&db, date("2024-06-30"), &bookkeeper()).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,130 @@
+//! The audit log.
+//!
+//! Every change to the books, their settings and their users records an audit event in the same
+//! transaction as the change, so there is an event if and only if the change was made. An event
+//! records who made the change, when, what kind of change it was and what it changed, as JSON of
+//! the subject before and after the change. Events cannot be changed or removed once written.
+//!
+//! Changes to the ledger itself are recorded as [`AuditOperation::BatchNew`] events, with the
+//! journals posted. Processes that post batches, such as depreciation runs, are identified by the
+//! `generated_by` field of the batch.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, users};
+use crate::error::Error::InstructionError;
+use crate::users::{Role, User};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum AuditOperation {
+    SettingSet,
+    AccountNew,
+    AccountClientSet,
+    AccountArchive,
+    BatchNew,
+    ExchangeRateSet,
+    ContactNew,
+    TaxCodeNew,
+    TaxRateSet,
+    InvoiceNew,
+    InvoiceUpdate,
+    InvoiceApprove,
+    BillNew,
+    AllocationNew,
+    AllocationDelete,
+    PaymentRunNew,
+    PaymentRunConfirm,
+    VatReturnSubmit,
+    FixedAssetNew,
+    FixedAssetDispose,
+    AmortisationScheduleNew,
+    RecurringJournalNew,
+    DraftNew,
+    DraftSubmit,
+    DraftApprove,
+    DraftReject,
+    UserNew,
+    UserRoleSet,
+    TokenNew,
+    TokenRevoke,
+    EntityNew,
+    EntityGrant,
+    EntityRevoke,
+    IntercompanyJournalNew,
+    DimensionNew,
+    DimensionValueNew,
+    BudgetSet,
+}
+
+/// Which events to return from [`audit_events`]. Every field is optional.
+#[derive(Clone, Deserialize)]
+pub struct AuditFilter {
+    pub user_id: Option<i64>,
+    pub operation: Option<AuditOperation>,
+    /// The ID or name of what was changed, such as an account ID or a setting name.
+    pub subject: Option<String>,
+    /// The first and last dates of the events, inclusive.
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// The maximum number of events, most recent first. Defaults to 100.
+    pub limit: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AuditEvent {
+    pub audit_event_id: i64,
+    /// The user who made the change, or [`None`] if it was made by the server itself.
+    pub user_id: Option<i64>,
+    pub user_name: Option<String>,
+    pub created_at: String,
+    pub operation: AuditOperation,
+    pub subject: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+impl From<db::AuditEventResult> for AuditEvent {
+    fn from(r: db::AuditEventResult) -> Self {
+        AuditEvent {
+            audit_event_id: r.audit_event_id,
+            user_id: r.user_id,
+            user_name: None,
+            created_at: r.created_at,
+            operation: r.operation,
+            subject: r.subject,
+            before: r.before.and_then(|j| serde_json::from_str(j.as_str()).ok()),
+            after: r.after.and_then(|j| serde_json::from_str(j.as_str()).ok()),
+        }
+    }
+}
+
+/// Find the audit events matching a filter, most recent first. Only admins may read the audit log.
+///
+/// The log is read from `e`, which is the server database or the database of an entity, and the
+/// names of the users who made the changes from the server database.
+pub async fn audit_events(e: impl SqliteExecutor<'_>, server: &Pool<Sqlite>, filter: &AuditFilter, user: &User)
+                          -> Result<Vec<AuditEvent>> {
+    user.require(Role::Admin)?;
+    let limit = filter.limit.unwrap_or(100);
+    if !(1..=10000).contains(&limit) {
+        bail!(InstructionError("limit must be 1-10000".to_string()));
+    }
+    let names: HashMap<i64, String> = users::user_list(server, user).await?
+        .into_iter()
+        .map(|u| (u.user_id, u.name))
+        .collect();
+    Ok(db::audit_event_query(e, filter, limit).await?
+        .into_iter()
+        .map(AuditEvent::from)
+        .map(|mut event| {
+            event.user_name = event.user_id.and_then(|id| names.get(&id).cloned());
+            event
+        })
+        .collect())
+}
@@ -0,0 +1,281 @@
+//! Budgets for the profit and loss accounts.
+//!
+//! A budget is set for a fiscal year, as an amount for each month, for an account as a whole or
+//! for the entries of an account tagged with each value of a dimension. An account may not be
+//! budgeted both ways in the same year, so that its budget is the sum of its budget lines. Like
+//! balances, budgeted amounts are positive for debits and negative for credits, so budgeted
+//! income is negative.
+//!
+//! The fiscal year starts in the month given by the `fiscalYearStartMonth` setting, and is named
+//! by the calendar year it starts in.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+use crate::{db, dimensions, settings};
+use crate::dimensions::{Tag, Tags};
+use crate::error::Error::InstructionError;
+use crate::users::{Role, User};
+
+/// The number of months in a fiscal year, and so of amounts in a budget line.
+const MONTHS: usize = 12;
+
+/// The budget of an account, or of the entries of an account tagged with a dimension value.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BudgetLine {
+    pub account: i64,
+    #[serde(default)]
+    pub tag: Option<Tag>,
+    /// The amount for each month of the fiscal year, in order.
+    pub amounts: Vec<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Budget {
+    pub fiscal_year: i32,
+    /// The first day of each month of the fiscal year.
+    pub months: Vec<String>,
+    pub lines: Vec<BudgetLine>,
+}
+
+/// The first day of a fiscal year.
+pub async fn fiscal_year_start(e: &Pool<Sqlite>, fiscal_year: i32) -> Result<NaiveDate> {
+    let month = settings::get_settings_int(e, "fiscalYearStartMonth").await.unwrap_or(1);
+    let Some(start) = NaiveDate::from_ymd_opt(fiscal_year, month as u32, 1) else {
+        bail!(InstructionError(format!("fiscal year {fiscal_year} is out of range")));
+    };
+    Ok(start)
+}
+
+/// The first day of the fiscal year a date falls in.
+pub async fn year_start(e: &Pool<Sqlite>, date: NaiveDate) -> Result<NaiveDate> {
+    let start = fiscal_year_start(e, date.year()).await?;
+    if start > date {
+        return fiscal_year_start(e, date.year() - 1).await;
+    }
+    Ok(start)
+}
+
+/// The first day of each month of a fiscal year.
+async fn fiscal_year_months(e: &Pool<Sqlite>, fiscal_year: i32) -> Result<Vec<String>> {
+    let start = fiscal_year_start(e, fiscal_year).await?;
+    Ok((0..MONTHS as u32)
+        .map(|i| (start + Months::new(i)).to_string())
+        .collect())
+}
+
+/// The budget for a fiscal year.
+pub async fn budget_get(e: &Pool<Sqlite>, fiscal_year: i32) -> Result<Budget> {
+    let months = fiscal_year_months(e, fiscal_year).await?;
+    let mut lines: Vec<BudgetLine> = Vec::new();
+    for r in db::budget_query(e, &months[0], &months[MONTHS - 1]).await? {
+        let tag = r.dimension.zip(r.value).map(|(dimension, value)| Tag { dimension, value });
+        let month = months.iter().position(|m| *m == r.month).unwrap();
+        let line = match lines.last_mut() {
+            Some(line) if line.account == r.account_id && line.tag == tag => line,
+            _ => {
+                lines.push(BudgetLine { account: r.account_id, tag, amounts: vec![0; MONTHS] });
+                lines.last_mut().unwrap()
+            }
+        };
+        line.amounts[month] = r.amount;
+    }
+    Ok(Budget { fiscal_year, months, lines })
+}
+
+/// Set the budget of each account, or account and dimension value, in `lines` for a fiscal year,
+/// replacing any budget it already has. The budgets of other accounts are left as they are.
+pub async fn budget_set(e: &Pool<Sqlite>, fiscal_year: i32, lines: &[BudgetLine], user: &User) -> Result<()> {
+    user.require(Role::Bookkeeper)?;
+    if lines.is_empty() {
+        bail!(InstructionError("no budget lines".to_string()));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        if line.amounts.len() != MONTHS {
+            bail!(InstructionError(format!("budget for account {} must have {MONTHS} monthly amounts",
+                line.account)));
+        }
+        match db::account_type_query(e, line.account).await? {
+            None => bail!(InstructionError(format!("account {} does not exist", line.account))),
+            Some(account_type) if !account_type.is_profit_and_loss() => {
+                bail!(InstructionError(format!("account {} is not a profit and loss account", line.account)))
+            }
+            Some(_) => {}
+        }
+        if lines[..i].iter().any(|l| l.account == line.account && l.tag == line.tag) {
+            bail!(InstructionError(format!("account {} is budgeted twice", line.account)));
+        }
+    }
+    let tags: Vec<Tags> = lines
+        .iter()
+        .filter_map(|l| l.tag.as_ref().map(|t| Tags::from([(t.dimension.clone(), t.value.clone())])))
+        .collect();
+    dimensions::tags_validate(e, tags.iter()).await?;
+
+    // Check no account is budgeted both as a whole and by dimension, or by two dimensions.
+    let existing = budget_get(e, fiscal_year).await?;
+    let mut dimensions: BTreeMap<i64, Option<&str>> = BTreeMap::new();
+    let replaced = |l: &BudgetLine| lines.iter().any(|n| n.account == l.account && n.tag == l.tag);
+    for line in existing.lines.iter().filter(|l| !replaced(l)).chain(lines) {
+        let dimension = line.tag.as_ref().map(|t| t.dimension.as_str());
+        match dimensions.insert(line.account, dimension) {
+            Some(other) if other != dimension => bail!(InstructionError(format!(
+                "account {} is budgeted by {} and by {} in {fiscal_year}", line.account,
+                other.unwrap_or("account"), dimension.unwrap_or("account")))),
+            _ => {}
+        }
+    }
+    Ok(db::budget_set_tx(e, fiscal_year.to_string().as_str(), &existing.months, lines, user.user_id).await?)
+}
+
+/// Set budgets for a fiscal year from CSV, returning the number of budget lines set.
+///
+/// The first row is a header, and is skipped. Each other row has the account, the dimension and
+/// value, which are both empty for a budget of the whole account, then the amount for each month
+/// of the fiscal year, where an empty amount is zero.
+pub async fn budget_import(e: &Pool<Sqlite>, fiscal_year: i32, csv: &str, user: &User) -> Result<usize> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv.as_bytes());
+    let mut lines: Vec<BudgetLine> = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let row = i + 2;
+        let Ok(record) = record else {
+            bail!(InstructionError(format!("row {row} is not valid CSV")));
+        };
+        if record.len() != MONTHS + 3 {
+            bail!(InstructionError(format!("row {row} must have {} columns", MONTHS + 3)));
+        }
+        let Ok(account) = record[0].parse::<i64>() else {
+            bail!(InstructionError(format!("row {row} has an invalid account")));
+        };
+        let tag = match (&record[1], &record[2]) {
+            ("", "") => None,
+            ("", _) | (_, "") => bail!(InstructionError(format!("row {row} must have both a dimension and a value"))),
+            (dimension, value) => Some(Tag { dimension: dimension.to_string(), value: value.to_string() }),
+        };
+        let mut amounts: Vec<i64> = Vec::new();
+        for amount in record.iter().skip(3) {
+            match amount {
+                "" => amounts.push(0),
+                amount => match amount.parse::<i64>() {
+                    Ok(amount) => amounts.push(amount),
+                    Err(_) => bail!(InstructionError(format!("row {row} has an invalid amount {amount}"))),
+                },
+            }
+        }
+        lines.push(BudgetLine { account, tag, amounts });
+    }
+    budget_set(e, fiscal_year, &lines, user).await?;
+    Ok(lines.len())
+}
+
+/// The total budget of every budgeted account for the months from `from` to `to`, both the first
+/// day of a month, optionally only the budgets for a dimension value.
+pub async fn budget_totals(e: &Pool<Sqlite>, from: NaiveDate, to: NaiveDate, tag: Option<&Tag>)
+                           -> Result<BTreeMap<i64, i64>> {
+    Ok(db::budget_total_query(e, from.to_string().as_str(), to.to_string().as_str(), tag).await?
+        .into_iter()
+        .map(|r| (r.account_id, r.amount))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn bookkeeper() -> User {
+        User {
+            user_id: 1,
+            name: "bookkeeper".to_string(),
+            role: Role::Bookkeeper,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    const HEADER: &str = "account,dimension,value,apr,may,jun,jul,aug,sep,oct,nov,dec,jan,feb,mar\n";
+
+    /// An entity whose fiscal year starts in April.
+    async fn entity() -> Pool<Sqlite> {
+        let db = entities::memory_connect().await;
+        let mut connection = db.acquire().await.unwrap();
+        settings::set_settings_int(&mut connection, "fiscalYearStartMonth", 4, None).await;
+        drop(connection);
+        db
+    }
+
+    fn amounts(budget: &Budget, account: i64) -> Vec<i64> {
+        budget.lines.iter().find(|l| l.account == account).unwrap().amounts.clone()
+    }
+
+    #[actix_web::test]
+    async fn budget_import_sets_each_month_of_the_fiscal_year() {
+        let db = entity().await;
+        let csv = format!("{HEADER}550,,,100,100,100,100,100,100,100,100,100,100,100,100\n\
+                           400,,,-500,,,-500,,,-500,,,-500,,\n");
+        assert_eq!(budget_import(&db, 2024, csv.as_str(), &bookkeeper()).await.unwrap(), 2);
+
+        let budget = budget_get(&db, 2024).await.unwrap();
+        assert_eq!((budget.months[0].as_str(), budget.months[11].as_str()), ("2024-04-01", "2025-03-01"));
+        assert_eq!(amounts(&budget, 550), vec![100; 12]);
+        assert_eq!(amounts(&budget, 400), vec![-500, 0, 0, -500, 0, 0, -500, 0, 0, -500, 0, 0]);
+
+        let from = NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+        let totals = budget_totals(&db, from, to, None).await.unwrap();
+        assert_eq!((totals[&550], totals[&400]), (400, -1000));
+    }
+
+    #[actix_web::test]
+    async fn budget_import_replaces_the_lines_imported_again() {
+        let db = entity().await;
+        let csv = format!("{HEADER}550,,,100,100,100,100,100,100,100,100,100,100,100,100\n\
+                           400,,,-500,-500,-500,-500,-500,-500,-500,-500,-500,-500,-500,-500\n");
+        budget_import(&db, 2024, csv.as_str(), &bookkeeper()).await.unwrap();
+        let csv = format!("{HEADER}550,,,200,200,200,200,200,200,200,200,200,200,200,200\n");
+        budget_import(&db, 2024, csv.as_str(), &bookkeeper()).await.unwrap();
+
+        let budget = budget_get(&db, 2024).await.unwrap();
+        assert_eq!(budget.lines.len(), 2);
+        assert_eq!(amounts(&budget, 550), vec![200; 12]);
+        assert_eq!(amounts(&budget, 400), vec![-500; 12]);
+        assert!(budget_get(&db, 2025).await.unwrap().lines.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn budget_import_rejects_invalid_rows() {
+        let db = entity().await;
+        let months = ",1,1,1,1,1,1,1,1,1,1,1,1";
+        for row in ["550,,,1,1".to_string(), "x,,".to_string() + months, "550,,,x,1,1,1,1,1,1,1,1,1,1,1".to_string(),
+                    "100,,".to_string() + months, "550,project,".to_string() + months,
+                    "550,project,alpha".to_string() + months] {
+            let csv = format!("{HEADER}{row}\n");
+            assert!(is_instruction_error(budget_import(&db, 2024, csv.as_str(), &bookkeeper()).await), "{row}");
+        }
+        assert!(budget_get(&db, 2024).await.unwrap().lines.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn budget_set_refuses_budgeting_an_account_as_a_whole_and_by_dimension() {
+        let db = entity().await;
+        let admin = User { role: Role::Admin, ..bookkeeper() };
+        dimensions::dimension_new(&db, "project", None, &admin).await.unwrap();
+        dimensions::dimension_value_new(&db, "project", "alpha", None, &admin).await.unwrap();
+        let whole = BudgetLine { account: 550, tag: None, amounts: vec![100; 12] };
+        budget_set(&db, 2024, &[whole], &bookkeeper()).await.unwrap();
+
+        let tag = Tag { dimension: "project".to_string(), value: "alpha".to_string() };
+        let by_project = BudgetLine { account: 550, tag: Some(tag), amounts: vec![50; 12] };
+        assert!(is_instruction_error(budget_set(&db, 2024, std::slice::from_ref(&by_project), &bookkeeper()).await));
+        budget_set(&db, 2025, &[by_project], &bookkeeper()).await.unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//! Consolidated reports across a group of entities.
+//!
+//! Each report totals the accounts of every entity in the group by account type, then eliminates
+//! what the entities owe each other and what they have charged each other:
+//!
+//! * the balances of intercompany accounts held with another entity in the group;
+//! * the entries of intercompany journals between entities in the group that were posted to profit
+//!   and loss accounts.
+//!
+//! Amounts are balances, debits positive and credits negative, as in the other reports. If the
+//! eliminations do not cancel out, for example because one side of a balance was posted without
+//! the other, the balance sheet shows the difference on an `IntercompanyDifference` line, and
+//! [`crate::intercompany::intercompany_mismatches`] shows where it arises.
+//!
+//! Each report may be compared with other periods, or other dates for a balance sheet, for which
+//! the consolidated amounts are shown.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+
+use crate::db;
+use crate::entities::EntityDb;
+use crate::error::Error::InstructionError;
+use crate::ledger::AccountType;
+use crate::reports::Period;
+
+/// The line for the profit and loss accumulated on the balance sheet.
+const RETAINED_PROFIT: &str = "RetainedProfit";
+/// The line for eliminations that do not cancel out on the balance sheet.
+const INTERCOMPANY_DIFFERENCE: &str = "IntercompanyDifference";
+
+const BALANCE_SHEET_LINES: [&str; 11] = ["Cash", "CurrentAsset", "Inventory", "Prepayments", "NonCurrentAsset",
+    "CurrentLiability", "NonCurrentLiability", "Equity", "System", RETAINED_PROFIT, INTERCOMPANY_DIFFERENCE];
+const PROFIT_AND_LOSS_LINES: [&str; 4] = ["Revenue", "OtherIncome", "DirectExpense", "IndirectExpense"];
+
+#[derive(Clone, Serialize)]
+pub struct ConsolidatedLine {
+    /// The account type the line totals, or `RetainedProfit` or `IntercompanyDifference`.
+    pub line: String,
+    /// The total of each entity, in the order of [`ConsolidatedReport::entities`].
+    pub entities: Vec<i64>,
+    pub eliminations: i64,
+    pub consolidated: i64,
+    /// The consolidated amount for each comparison, in the order of
+    /// [`ConsolidatedReport::comparisons`].
+    pub comparatives: Vec<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ConsolidatedReport {
+    /// The slugs of the entities in the group.
+    pub entities: Vec<String>,
+    /// The first date of the period, or [`None`] for a balance sheet.
+    pub from: Option<String>,
+    /// The last date of the period, or the date of a balance sheet.
+    pub to: String,
+    pub lines: Vec<ConsolidatedLine>,
+    /// The consolidated net assets for a balance sheet, or profit for a profit and loss account,
+    /// positive for a profit.
+    pub total: i64,
+    pub comparisons: Vec<Period>,
+    pub comparative_totals: Vec<i64>,
+}
+
+impl ConsolidatedReport {
+    /// Add the consolidated amounts of the report for a comparison.
+    fn append(&mut self, other: ConsolidatedReport) {
+        self.comparisons.push(Period { from: other.from, to: other.to });
+        for (line, other) in self.lines.iter_mut().zip(other.lines) {
+            line.comparatives.push(other.consolidated);
+        }
+        self.comparative_totals.push(other.total);
+    }
+}
+
+/// The line an account type is reported on, or [`None`] if it is not part of the report.
+fn report_line(account_type: &AccountType, balance_sheet: bool) -> Option<String> {
+    match (balance_sheet, account_type.is_profit_and_loss()) {
+        (true, true) => Some(RETAINED_PROFIT.to_string()),
+        (true, false) | (false, true) => Some(format!("{account_type:?}")),
+        (false, false) => None,
+    }
+}
+
+async fn consolidate(server: &Pool<Sqlite>, group: &[EntityDb], from: Option<&str>, to: &str, balance_sheet: bool)
+                     -> Result<ConsolidatedReport> {
+    let names: &[&str] = if balance_sheet { &BALANCE_SHEET_LINES } else { &PROFIT_AND_LOSS_LINES };
+    let mut lines: BTreeMap<String, ConsolidatedLine> = names
+        .iter()
+        .map(|name| (name.to_string(), ConsolidatedLine {
+            line: name.to_string(),
+            entities: vec![0; group.len()],
+            eliminations: 0,
+            consolidated: 0,
+            comparatives: Vec::new(),
+        }))
+        .collect();
+    let in_group = |slug: &str| group.iter().any(|entity| entity.slug == slug);
+
+    for (i, entity) in group.iter().enumerate() {
+        for account in db::account_balance_query(&entity.db, from, Some(to), None).await? {
+            let Some(line) = report_line(&account.account_type, balance_sheet) else {
+                continue;
+            };
+            let line = lines.get_mut(&line).unwrap();
+            line.entities[i] += account.balance;
+            if account.counterparty.as_deref().is_some_and(in_group) {
+                line.eliminations -= account.balance;
+            }
+        }
+    }
+
+    for journal in db::intercompany_journal_list_query(server, from, Some(to)).await? {
+        if !in_group(journal.from_entity.as_str()) || !in_group(journal.to_entity.as_str()) {
+            continue;
+        }
+        for (slug, journal_id) in [(&journal.from_entity, journal.from_journal_id),
+            (&journal.to_entity, journal.to_journal_id)] {
+            let entity = group.iter().find(|entity| &entity.slug == slug).unwrap();
+            for entry in db::journal_entry_query(&entity.db, journal_id).await? {
+                if !entry.account_type.is_profit_and_loss() {
+                    continue;
+                }
+                if let Some(line) = report_line(&entry.account_type, balance_sheet) {
+                    lines.get_mut(&line).unwrap().eliminations -= entry.amount;
+                }
+            }
+        }
+    }
+
+    if balance_sheet {
+        let unmatched: i64 = lines.values().map(|line| line.eliminations).sum();
+        lines.get_mut(INTERCOMPANY_DIFFERENCE).unwrap().eliminations = -unmatched;
+    }
+    let mut lines: Vec<ConsolidatedLine> = names
+        .iter()
+        .map(|name| lines.remove(*name).unwrap())
+        .collect();
+    for line in &mut lines {
+        line.consolidated = line.entities.iter().sum::<i64>() + line.eliminations;
+    }
+    let total = if balance_sheet {
+        lines.iter()
+            .filter(|line| matches!(line.line.as_str(), "Cash" | "CurrentAsset" | "Inventory" | "Prepayments"
+                | "NonCurrentAsset" | "CurrentLiability" | "NonCurrentLiability"))
+            .map(|line| line.consolidated)
+            .sum()
+    } else {
+        -lines.iter().map(|line| line.consolidated).sum::<i64>()
+    };
+    Ok(ConsolidatedReport {
+        entities: group.iter().map(|entity| entity.slug.clone()).collect(),
+        from: from.map(String::from),
+        to: to.to_string(),
+        lines,
+        total,
+        comparisons: Vec::new(),
+        comparative_totals: Vec::new(),
+    })
+}
+
+/// The consolidated balance sheet of a group of entities as at a date, and as at each of the
+/// dates it is compared with.
+pub async fn consolidated_balance_sheet(server: &Pool<Sqlite>, group: &[EntityDb], as_at: NaiveDate,
+                                        comparisons: &[NaiveDate]) -> Result<ConsolidatedReport> {
+    let mut report = consolidate(server, group, None, as_at.to_string().as_str(), true).await?;
+    for date in comparisons {
+        report.append(consolidate(server, group, None, date.to_string().as_str(), true).await?);
+    }
+    Ok(report)
+}
+
+/// The consolidated profit and loss account of a group of entities for a period, and for each of
+/// the periods it is compared with.
+pub async fn consolidated_profit_and_loss(server: &Pool<Sqlite>, group: &[EntityDb], from: NaiveDate, to: NaiveDate,
+                                          comparisons: &[(NaiveDate, NaiveDate)]) -> Result<ConsolidatedReport> {
+    if from > to {
+        bail!(InstructionError("period ends before it starts".to_string()));
+    }
+    let mut report = consolidate(server, group, Some(from.to_string().as_str()), to.to_string().as_str(), false)
+        .await?;
+    for (from, to) in comparisons {
+        report.append(consolidate(server, group, Some(from.to_string().as_str()), to.to_string().as_str(), false)
+            .await?);
+    }
+    Ok(report)
+}
@@ -0,0 +1,141 @@
+//! Customers and suppliers.
+//!
+//! Journal entries may reference a contact so that the balance of a single control account, such
+//! as trade debtors or client money held, can be broken down by contact.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::db;
+use crate::error::Error::InstructionError;
+use crate::ledger::AccountType;
+use crate::users::{Role, User};
+
+/// The kind of contact.
+#[derive(Clone, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum ContactKind {
+    /// A contact that the entity sells to.
+    Customer,
+    /// A contact that the entity buys from.
+    Supplier,
+}
+
+/// The details of a contact, as given when the contact is created.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ContactData {
+    pub kind: ContactKind,
+    pub name: String,
+    pub address: Option<String>,
+    pub postcode: Option<String>,
+    /// ISO 3166-1 alpha-2 country code.
+    pub country: Option<String>,
+    pub email: Option<String>,
+    /// VAT registration number or other tax identifier.
+    pub tax_id: Option<String>,
+    /// The account that entries for this contact are normally posted to.
+    pub control_account_id: Option<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Contact {
+    pub contact_id: i64,
+    pub kind: ContactKind,
+    pub name: String,
+    pub address: Option<String>,
+    pub postcode: Option<String>,
+    pub country: Option<String>,
+    pub email: Option<String>,
+    pub tax_id: Option<String>,
+    pub control_account_id: Option<i64>,
+    pub balance: i64,
+}
+
+impl From<db::ContactResult> for Contact {
+    fn from(r: db::ContactResult) -> Self {
+        Contact {
+            contact_id: r.contact_id,
+            kind: r.kind,
+            name: r.name,
+            address: r.address,
+            postcode: r.postcode,
+            country: r.country,
+            email: r.email,
+            tax_id: r.tax_id,
+            control_account_id: r.control_account_id,
+            balance: r.balance,
+        }
+    }
+}
+
+pub async fn contact_new(e: &Pool<Sqlite>, contact: &ContactData, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    if contact.name.is_empty() || contact.name.len() > 140 {
+        bail!(InstructionError("contact name must be 1-140 chars".to_string()));
+    }
+    if let Some(country) = &contact.country {
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+            bail!(InstructionError("country is not an ISO 3166-1 alpha-2 code".to_string()));
+        }
+    }
+    if contact.tax_id.as_ref().is_some_and(|t| t.len() > 32) {
+        bail!(InstructionError("tax id over 32 chars".to_string()));
+    }
+    if let Some(account_id) = contact.control_account_id {
+        match db::account_type_query(e, account_id).await? {
+            Some(AccountType::CurrentAsset) | Some(AccountType::CurrentLiability) => {}
+            Some(_) => bail!(InstructionError("control account must be a current asset or liability".to_string())),
+            None => bail!(InstructionError(format!("account {account_id} does not exist"))),
+        }
+    }
+    Ok(db::contact_new_tx(e, contact, user.user_id).await?)
+}
+
+pub async fn contact_detail(e: impl SqliteExecutor<'_>, contact_id: i64) -> Result<Contact> {
+    match db::contact_query(e, contact_id).await? {
+        Some(r) => Ok(r.into()),
+        None => bail!(InstructionError(format!("contact {contact_id} does not exist"))),
+    }
+}
+
+pub async fn contact_list(e: impl SqliteExecutor<'_>, kind: Option<&ContactKind>) -> Result<Vec<Contact>> {
+    Ok(db::contact_list_query(e, kind).await?
+        .into_iter()
+        .map(Contact::from)
+        .collect())
+}
+
+/// Check that entries reference only contacts that exist.
+pub async fn contacts_validate(e: impl SqliteExecutor<'_>, contact_ids: impl Iterator<Item=i64>) -> Result<()> {
+    let contact_ids: Vec<i64> = contact_ids.collect();
+    if contact_ids.is_empty() {
+        return Ok(());
+    }
+    let existing = db::contact_id_query(e).await?;
+    if let Some(contact_id) = contact_ids.into_iter().find(|c| !existing.contains(c)) {
+        bail!(InstructionError(format!("contact {contact_id} does not exist")));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Serialize)]
+pub struct ContactBalance {
+    pub contact_id: Option<i64>,
+    pub contact_name: Option<String>,
+    pub balance: i64,
+}
+
+/// Break down the balance of an account by contact.
+///
+/// Entries that do not reference a contact are grouped together with a `contact_id` of [`None`].
+pub async fn account_contact_balances(e: impl SqliteExecutor<'_>, account_id: i64) -> Result<Vec<ContactBalance>> {
+    Ok(db::account_contact_balance_query(e, account_id).await?
+        .into_iter()
+        .map(|r| ContactBalance {
+            contact_id: r.contact_id,
+            contact_name: r.contact_name,
+            balance: r.balance,
+        })
+        .collect())
+}
@@ -16,10 +16,26 @@
 //!   <https://github.com/launchbadge/sqlx/issues/419>.
 
 use serde::Serialize;
+use serde_json::{json, Value};
 use sqlx::{Error, Pool, Sqlite, SqliteConnection, SqliteExecutor};
 
-use crate::ledger::{AccountType, Journal};
+use crate::allocations::{Document, Settlement};
+use crate::audit::{AuditFilter, AuditOperation};
+use crate::amortisation::{AmortisationBatch, ScheduleData, ScheduleKind};
+use crate::assets::{DepreciationCharge, DepreciationMethod, FixedAssetData};
+use crate::budgets::BudgetLine;
+use crate::contacts::{ContactData, ContactKind};
+use crate::dimensions::{Tag, Tags};
+use crate::drafts::{DraftData, DraftJournal, DraftState};
+use crate::intercompany::IntercompanyJournalRecord;
+use crate::invoicing::{InvoiceHeader, InvoiceKind, InvoiceLine, InvoiceState};
+use crate::ledger::{AccountType, BatchMetadata, EntryData, Journal, JournalEntry};
+use crate::purchases::{BillHeader, BillLine, PaymentRunState};
+use crate::recurring::{Recurrence, RecurringJournalData, RecurringOccurrence};
 use crate::settings;
+use crate::tax::TaxTreatment;
+use crate::users::Role;
+use crate::vat::VatScheme;
 
 
 /// The result of an [`account_detail_query`].
@@ -69,7 +85,13 @@ pub struct AccountSummaryResult {
     pub timestamp: String,
 }
 
-pub async fn account_list_query(e: impl SqliteExecutor<'_>) -> Result<Vec<AccountSummaryResult>, Error> {
+/// List the accounts with their balances from the entries in batches dated on or before `as_at`,
+/// which may be [`None`] for no limit, optionally only from the entries tagged with a dimension
+/// value.
+pub async fn account_list_query(e: impl SqliteExecutor<'_>, as_at: Option<&str>, tag: Option<&Tag>)
+                                -> Result<Vec<AccountSummaryResult>, Error> {
+    let dimension = tag.map(|t| t.dimension.as_str());
+    let value = tag.map(|t| t.value.as_str());
     sqlx::query_as!(AccountSummaryResult,
         r#"SELECT account.id AS "account_id!", name AS "account_name!", type AS "account_type!: AccountType",
         IFNULL(b.balance, 0) AS "balance!: i64", CURRENT_TIMESTAMP AS "timestamp!"
@@ -77,14 +99,28 @@ pub async fn account_list_query(e: impl SqliteExecutor<'_>) -> Result<Vec<Accoun
             LEFT JOIN (
                 SELECT account_id, SUM(amount) AS balance
                 FROM entry
+                    JOIN journal ON entry.journal_id = journal.id
+                    JOIN batch ON journal.batch_id = batch.id
+                WHERE (?1 IS NULL OR batch.date <= ?1)
+                    AND (?2 IS NULL OR EXISTS (SELECT 1 FROM entry_tag
+                        WHERE entry_id = entry.id AND dimension = ?2 AND value = ?3))
                 GROUP BY account_id
-            ) b ON account.id = b.account_id;"#)
+            ) b ON account.id = b.account_id;"#, as_at, dimension, value)
         .fetch_all(e)
         .await
 }
 
+/// Find the type of an account, or [`None`] if the account does not exist.
+pub async fn account_type_query(e: impl SqliteExecutor<'_>, account_id: i64) -> Result<Option<AccountType>, Error> {
+    Ok(sqlx::query!(r#"SELECT type AS "account_type!: AccountType" FROM account WHERE id=?;"#, account_id)
+        .fetch_optional(e)
+        .await?
+        .map(|r| r.account_type))
+}
+
 pub async fn account_new_tx(db: &Pool<Sqlite>, account_id: Option<i64>, account_name: &String,
-                            account_type: &AccountType) -> Result<i64, Error> {
+                            account_type: &AccountType, currency: Option<&str>, counterparty: Option<&str>,
+                            created_by: i64) -> Result<i64, Error> {
     let mut transaction = db.begin().await?;
     let next_id_setting_name = format!("nextAccount{account_type:?}");
     let this_account_id = match account_id {
@@ -94,29 +130,165 @@ pub async fn account_new_tx(db: &Pool<Sqlite>, account_id: Option<i64>, account_
         }
     };
     sqlx::query!(
-        "INSERT INTO account (id, name, type) VALUES (?, ?, ?)",
-        this_account_id, account_name, account_type
+        "INSERT INTO account (id, name, type, currency, counterparty, created_by) VALUES (?, ?, ?, ?, ?, ?)",
+        this_account_id, account_name, account_type, currency, counterparty, created_by
     )
         .execute(&mut *transaction)
         .await?;
     if account_id.is_none() {
-        settings::set_settings_int(&mut *transaction, next_id_setting_name.as_str(), this_account_id + 1).await;
+        settings::set_settings_int(&mut transaction, next_id_setting_name.as_str(), this_account_id + 1,
+                                   Some(created_by)).await;
     }
+    let after = json!({"account_name": account_name, "account_type": account_type, "currency": currency,
+        "counterparty": counterparty});
+    audit_event_new(&mut transaction, Some(created_by), AuditOperation::AccountNew, this_account_id.to_string().as_str(),
+                    None, Some(after)).await?;
     transaction.commit().await?;
     Ok(this_account_id)
 }
 
-pub async fn batch_new(e: &mut SqliteConnection) -> Result<i64, Error> {
-    sqlx::query!("INSERT INTO batch (date) VALUES (DATE('NOW'));").execute(&mut *e).await?;
+/// Mark an account as held for a client, or not.
+///
+/// Fails with [`Error::RowNotFound`] if the account does not exist.
+pub async fn account_client_set_tx(db: &Pool<Sqlite>, account_id: i64, client: bool, actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let before = sqlx::query!(r#"SELECT client AS "client!: bool" FROM account WHERE id=?;"#, account_id)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or(Error::RowNotFound)?
+        .client;
+    sqlx::query!("UPDATE account SET client=? WHERE id=?;", client, account_id)
+        .execute(&mut *transaction)
+        .await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::AccountClientSet, account_id.to_string().as_str(),
+                    Some(json!({"client": before})), Some(json!({"client": client}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// List the IDs of the client accounts of a type.
+pub async fn client_account_list_query(e: impl SqliteExecutor<'_>, account_type: &AccountType)
+                                       -> Result<Vec<i64>, Error> {
+    Ok(sqlx::query!(r#"SELECT id AS "account_id!" FROM account WHERE client AND type=? ORDER BY id;"#, account_type)
+        .fetch_all(e)
+        .await?
+        .into_iter()
+        .map(|r| r.account_id)
+        .collect())
+}
+
+/// The result of an [`account_balance_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct AccountBalanceResult {
+    pub account_id: i64,
+    pub account_name: String,
+    pub account_type: AccountType,
+    /// The entity an intercompany account holds balances with.
+    pub counterparty: Option<String>,
+    pub balance: i64,
+}
+
+/// Find the balance of every account from the entries in batches dated between `from` and `to`
+/// inclusive, either of which may be [`None`] for no limit, and optionally only the entries
+/// tagged with a dimension value.
+pub async fn account_balance_query(e: impl SqliteExecutor<'_>, from: Option<&str>, to: Option<&str>,
+                                   tag: Option<&Tag>) -> Result<Vec<AccountBalanceResult>, Error> {
+    let dimension = tag.map(|t| t.dimension.as_str());
+    let value = tag.map(|t| t.value.as_str());
+    sqlx::query_as!(AccountBalanceResult,
+        r#"SELECT account.id AS "account_id!", name AS "account_name!", type AS "account_type!: AccountType",
+        counterparty, IFNULL(b.balance, 0) AS "balance!: i64"
+        FROM account
+            LEFT JOIN (
+                SELECT account_id, SUM(amount) AS balance
+                FROM entry
+                    JOIN journal ON entry.journal_id = journal.id
+                    JOIN batch ON journal.batch_id = batch.id
+                WHERE (?1 IS NULL OR batch.date >= ?1) AND (?2 IS NULL OR batch.date <= ?2)
+                    AND (?3 IS NULL OR EXISTS (SELECT 1 FROM entry_tag
+                        WHERE entry_id = entry.id AND dimension = ?3 AND value = ?4))
+                GROUP BY account_id
+            ) b ON account.id = b.account_id
+        ORDER BY account.id;"#, from, to, dimension, value)
+        .fetch_all(e)
+        .await
+}
+
+/// The result of an [`account_dimension_balance_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct AccountDimensionBalanceResult {
+    pub account_id: i64,
+    /// The value of the dimension, or [`None`] for entries not tagged with the dimension.
+    pub value: Option<String>,
+    pub balance: i64,
+}
+
+/// Find the balance of every account by the values of a dimension, from the entries in batches
+/// dated between `from` and `to` inclusive, and optionally only the entries tagged with a value
+/// of another dimension. Accounts without entries are left out.
+pub async fn account_dimension_balance_query(e: impl SqliteExecutor<'_>, from: Option<&str>, to: Option<&str>,
+                                             dimension: &str, tag: Option<&Tag>)
+                                             -> Result<Vec<AccountDimensionBalanceResult>, Error> {
+    let tag_dimension = tag.map(|t| t.dimension.as_str());
+    let tag_value = tag.map(|t| t.value.as_str());
+    sqlx::query_as!(AccountDimensionBalanceResult,
+        r#"SELECT entry.account_id AS "account_id!", entry_tag.value AS "value?", SUM(amount) AS "balance!: i64"
+        FROM entry
+            JOIN journal ON entry.journal_id = journal.id
+            JOIN batch ON journal.batch_id = batch.id
+            LEFT JOIN entry_tag ON entry_tag.entry_id = entry.id AND entry_tag.dimension = ?3
+        WHERE (?1 IS NULL OR batch.date >= ?1) AND (?2 IS NULL OR batch.date <= ?2)
+            AND (?4 IS NULL OR EXISTS (SELECT 1 FROM entry_tag t
+                WHERE t.entry_id = entry.id AND t.dimension = ?4 AND t.value = ?5))
+        GROUP BY entry.account_id, entry_tag.value
+        ORDER BY entry.account_id, entry_tag.value;"#, from, to, dimension, tag_dimension, tag_value)
+        .fetch_all(e)
+        .await
+}
+
+/// Find the intercompany account held with an entity, the lowest numbered if there are several.
+pub async fn intercompany_account_query(e: impl SqliteExecutor<'_>, counterparty: &str) -> Result<Option<i64>, Error> {
+    Ok(sqlx::query!(r#"SELECT id AS "account_id!" FROM account WHERE counterparty=? ORDER BY id LIMIT 1;"#,
+        counterparty)
+        .fetch_optional(e)
+        .await?
+        .map(|r| r.account_id))
+}
+
+/// The result of a [`journal_entry_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct JournalEntryResult {
+    pub account_type: AccountType,
+    pub amount: i64,
+}
+
+/// Find the entries of a journal.
+pub async fn journal_entry_query(e: impl SqliteExecutor<'_>, journal_id: i64)
+                                 -> Result<Vec<JournalEntryResult>, Error> {
+    sqlx::query_as!(JournalEntryResult,
+        r#"SELECT account.type AS "account_type!: AccountType", amount
+        FROM entry
+            JOIN account ON entry.account_id = account.id
+        WHERE journal_id=?
+        ORDER BY entry.id;"#, journal_id)
+        .fetch_all(e)
+        .await
+}
+
+pub async fn batch_new(e: &mut SqliteConnection, metadata: &BatchMetadata) -> Result<i64, Error> {
+    sqlx::query!("INSERT INTO batch (date, generated_by, created_by) VALUES (IFNULL(?, DATE('NOW')), ?, ?);",
+        metadata.date, metadata.generated_by, metadata.created_by)
+        .execute(&mut *e).await?;
     Ok(sqlx::query!(r#"SELECT last_insert_rowid() AS "batch_id: i64";"#)
         .fetch_one(&mut *e)
         .await?
         .batch_id)
 }
 
-pub async fn journal_new(e: &mut SqliteConnection, batch_id: i64, unstructured_narrative: String) -> Result<i64, Error> {
-    sqlx::query!("INSERT INTO journal (batch_id, unstructured_narrative) VALUES (?, ?)",
-        batch_id, unstructured_narrative)
+pub async fn journal_new(e: &mut SqliteConnection, batch_id: i64, unstructured_narrative: String,
+                         created_by: Option<i64>) -> Result<i64, Error> {
+    sqlx::query!("INSERT INTO journal (batch_id, unstructured_narrative, created_by) VALUES (?, ?, ?)",
+        batch_id, unstructured_narrative, created_by)
         .execute(&mut *e).await?;
     Ok(sqlx::query!(r#"SELECT last_insert_rowid() AS "journal_id: i64";"#)
         .fetch_one(&mut *e)
@@ -124,28 +296,1946 @@ pub async fn journal_new(e: &mut SqliteConnection, batch_id: i64, unstructured_n
         .journal_id)
 }
 
-pub async fn journal_entry_new(e: &mut SqliteConnection, journal_id: i64, account_id: i64, amount: i64) -> Result<i64, Error> {
-    sqlx::query!("INSERT INTO entry (journal_id, account_id, amount) VALUES (?, ?, ?);",
-            journal_id, account_id, amount)
+pub async fn journal_entry_new(e: &mut SqliteConnection, journal_id: i64, entry: &JournalEntry) -> Result<i64, Error> {
+    sqlx::query!(
+            "INSERT INTO entry (journal_id, account_id, amount, currency_amount, contact_id, tax_code)
+            VALUES (?, ?, ?, ?, ?, ?);",
+            journal_id, entry.account, entry.amount, entry.currency_amount, entry.contact, entry.tax_code)
         .execute(&mut *e)
         .await?;
-    Ok(sqlx::query!(r#"SELECT last_insert_rowid() AS "entry_id: i64";"#)
+    let entry_id = sqlx::query!(r#"SELECT last_insert_rowid() AS "entry_id!: i64";"#)
         .fetch_one(&mut *e)
         .await?
-        .entry_id)
+        .entry_id;
+    for (dimension, value) in &entry.tags {
+        sqlx::query!("INSERT INTO entry_tag (entry_id, dimension, value) VALUES (?, ?, ?);", entry_id, dimension, value)
+            .execute(&mut *e)
+            .await?;
+    }
+    Ok(entry_id)
+}
+
+/// A journal entry stored for posting later, with its tags as JSON.
+#[derive(sqlx::FromRow, Debug)]
+struct EntryDataResult {
+    account: i64,
+    amount: i64,
+    currency_amount: Option<i64>,
+    contact: Option<i64>,
+    tax_code: Option<String>,
+    tags: Option<String>,
+}
+
+impl From<EntryDataResult> for EntryData {
+    fn from(r: EntryDataResult) -> Self {
+        EntryData {
+            account: r.account,
+            amount: r.amount,
+            currency_amount: r.currency_amount,
+            contact: r.contact,
+            tax_code: r.tax_code,
+            tags: r.tags.and_then(|j| serde_json::from_str(j.as_str()).ok()).unwrap_or_default(),
+        }
+    }
+}
+
+/// The tags of an entry stored for posting later, as JSON, or [`None`] if it has none.
+fn tags_json(tags: &Tags) -> Option<String> {
+    (!tags.is_empty()).then(|| json!(tags).to_string())
+}
+
+pub async fn dimension_new_tx(db: &Pool<Sqlite>, name: &str, description: Option<&str>, actor: i64)
+                              -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    sqlx::query!("INSERT INTO dimension (name, description) VALUES (?, ?);", name, description)
+        .execute(&mut *transaction)
+        .await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::DimensionNew, name, None,
+                    Some(json!({"description": description}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn dimension_value_new_tx(db: &Pool<Sqlite>, dimension: &str, value: &str, description: Option<&str>,
+                                    actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    sqlx::query!("INSERT INTO dimension_value (dimension, value, description) VALUES (?, ?, ?);",
+        dimension, value, description)
+        .execute(&mut *transaction)
+        .await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::DimensionValueNew, dimension, None,
+                    Some(json!({"value": value, "description": description}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The result of a [`dimension_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct DimensionResult {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+pub async fn dimension_list_query(e: impl SqliteExecutor<'_>) -> Result<Vec<DimensionResult>, Error> {
+    sqlx::query_as!(DimensionResult,
+        r#"SELECT name AS "name!", description FROM dimension ORDER BY name;"#)
+        .fetch_all(e)
+        .await
+}
+
+/// The result of a [`dimension_value_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct DimensionValueResult {
+    pub dimension: String,
+    pub value: String,
+    pub description: Option<String>,
+}
+
+/// List the values of every dimension, or of one dimension.
+pub async fn dimension_value_query(e: impl SqliteExecutor<'_>, dimension: Option<&str>)
+                                   -> Result<Vec<DimensionValueResult>, Error> {
+    sqlx::query_as!(DimensionValueResult,
+        r#"SELECT dimension AS "dimension!", value AS "value!", description FROM dimension_value
+        WHERE ?1 IS NULL OR dimension = ?1
+        ORDER BY dimension, value;"#, dimension)
+        .fetch_all(e)
+        .await
 }
 
-pub async fn batch_new_tx(db: &Pool<Sqlite>, journals: Vec<Journal>) -> Result<(i64, Vec<i64>), Error> {
+/// Replace the budget of each account, or account and dimension value, in `lines` for `months`,
+/// given as the first day of each month, recording the change in the audit log.
+pub async fn budget_set_tx(db: &Pool<Sqlite>, fiscal_year: &str, months: &[String], lines: &[BudgetLine], actor: i64)
+                           -> Result<(), Error> {
     let mut transaction = db.begin().await?;
-    let batch_id = batch_new(&mut *transaction).await?;
+    let first = months.first().map(String::as_str);
+    let last = months.last().map(String::as_str);
+    let mut before: Vec<Value> = Vec::new();
+    for line in lines {
+        let dimension = line.tag.as_ref().map(|t| t.dimension.as_str());
+        let value = line.tag.as_ref().map(|t| t.value.as_str());
+        let amounts: Vec<i64> = sqlx::query!(
+            r#"SELECT amount AS "amount!: i64" FROM budget
+            WHERE account_id=? AND dimension IS ? AND value IS ? AND month >= ? AND month <= ?
+            ORDER BY month;"#, line.account, dimension, value, first, last)
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .map(|r| r.amount)
+            .collect();
+        if !amounts.is_empty() {
+            before.push(json!({"account": line.account, "tag": line.tag, "amounts": amounts}));
+        }
+        sqlx::query!("DELETE FROM budget
+            WHERE account_id=? AND dimension IS ? AND value IS ? AND month >= ? AND month <= ?;",
+            line.account, dimension, value, first, last)
+            .execute(&mut *transaction)
+            .await?;
+        for (month, amount) in months.iter().zip(&line.amounts) {
+            sqlx::query!("INSERT INTO budget (account_id, month, dimension, value, amount) VALUES (?, ?, ?, ?, ?);",
+                line.account, month, dimension, value, amount)
+                .execute(&mut *transaction)
+                .await?;
+        }
+    }
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::BudgetSet, fiscal_year,
+                    (!before.is_empty()).then(|| json!(before)), Some(json!(lines))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The result of a [`budget_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct BudgetResult {
+    pub account_id: i64,
+    pub month: String,
+    pub dimension: Option<String>,
+    pub value: Option<String>,
+    pub amount: i64,
+}
+
+/// Find the budgeted amounts for the months from `first` to `last` inclusive.
+pub async fn budget_query(e: impl SqliteExecutor<'_>, first: &str, last: &str) -> Result<Vec<BudgetResult>, Error> {
+    sqlx::query_as!(BudgetResult,
+        r#"SELECT account_id AS "account_id!", month AS "month!: String", dimension, value, amount AS "amount!: i64"
+        FROM budget
+        WHERE month >= ? AND month <= ?
+        ORDER BY account_id, dimension, value, month;"#, first, last)
+        .fetch_all(e)
+        .await
+}
+
+/// The result of a [`budget_total_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct BudgetTotalResult {
+    pub account_id: i64,
+    pub amount: i64,
+}
+
+/// Find the total budget of every budgeted account for the months from `first` to `last`
+/// inclusive, optionally only the budgets for a dimension value.
+pub async fn budget_total_query(e: impl SqliteExecutor<'_>, first: &str, last: &str, tag: Option<&Tag>)
+                                -> Result<Vec<BudgetTotalResult>, Error> {
+    let dimension = tag.map(|t| t.dimension.as_str());
+    let value = tag.map(|t| t.value.as_str());
+    sqlx::query_as!(BudgetTotalResult,
+        r#"SELECT account_id AS "account_id!", SUM(amount) AS "amount!: i64"
+        FROM budget
+        WHERE month >= ?1 AND month <= ?2 AND (?3 IS NULL OR (dimension = ?3 AND value = ?4))
+        GROUP BY account_id
+        ORDER BY account_id;"#, first, last, dimension, value)
+        .fetch_all(e)
+        .await
+}
+
+/// Insert a batch and its journals using an existing connection or transaction, recording the
+/// batch in the audit log.
+pub async fn batch_insert(e: &mut SqliteConnection, metadata: &BatchMetadata, journals: Vec<Journal>)
+                          -> Result<(i64, Vec<i64>), Error> {
+    let batch_id = batch_new(&mut *e, metadata).await?;
+    let after = json!({"metadata": metadata, "journals": &journals});
+    audit_event_new(&mut *e, metadata.created_by, AuditOperation::BatchNew, batch_id.to_string().as_str(), None,
+                    Some(after)).await?;
     let mut journal_ids: Vec<i64> = Vec::new();
     for journal in journals {
-        let journal_id = journal_new(&mut *transaction, batch_id, journal.unstructured_narrative).await?;
-        for entry in journal.entries {
-            journal_entry_new(&mut *transaction, journal_id, entry.account, entry.amount).await?;
+        let journal_id = journal_new(&mut *e, batch_id, journal.unstructured_narrative, metadata.created_by).await?;
+        for entry in &journal.entries {
+            journal_entry_new(&mut *e, journal_id, entry).await?;
         }
         journal_ids.push(journal_id);
     }
-    transaction.commit().await?;
     Ok((batch_id, journal_ids))
 }
+
+pub async fn batch_new_tx(db: &Pool<Sqlite>, metadata: &BatchMetadata, journals: Vec<Journal>)
+                          -> Result<(i64, Vec<i64>), Error> {
+    let mut transaction = db.begin().await?;
+    let result = batch_insert(&mut transaction, metadata, journals).await?;
+    transaction.commit().await?;
+    Ok(result)
+}
+
+/// Post several batches in a single transaction, returning the batch IDs in the order given.
+pub async fn batches_new_tx(db: &Pool<Sqlite>, batches: Vec<(BatchMetadata, Vec<Journal>)>) -> Result<Vec<i64>, Error> {
+    let mut transaction = db.begin().await?;
+    let mut batch_ids: Vec<i64> = Vec::new();
+    for (metadata, journals) in batches {
+        batch_ids.push(batch_insert(&mut transaction, &metadata, journals).await?.0);
+    }
+    transaction.commit().await?;
+    Ok(batch_ids)
+}
+
+pub async fn exchange_rate_set_tx(db: &Pool<Sqlite>, currency: &str, date: &str, rate: f64, actor: i64)
+                                  -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let before = sqlx::query!(r#"SELECT rate AS "rate!: f64" FROM exchange_rate WHERE currency=? AND date=?;"#,
+        currency, date)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map(|r| json!({"currency": currency, "date": date, "rate": r.rate}));
+    sqlx::query!("INSERT OR REPLACE INTO exchange_rate (currency, date, rate) VALUES (?, ?, ?);",
+        currency, date, rate)
+        .execute(&mut *transaction)
+        .await?;
+    let after = json!({"currency": currency, "date": date, "rate": rate});
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::ExchangeRateSet, currency, before, Some(after))
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Find the most recent exchange rate for `currency` on or before `date`.
+pub async fn exchange_rate_query(e: impl SqliteExecutor<'_>, currency: &str, date: &str) -> Result<Option<f64>, Error> {
+    Ok(sqlx::query!(
+        r#"SELECT rate AS "rate!: f64" FROM exchange_rate WHERE currency=? AND date <= ? ORDER BY date DESC LIMIT 1;"#,
+        currency, date)
+        .fetch_optional(e)
+        .await?
+        .map(|r| r.rate))
+}
+
+/// The inner result of [`foreign_account_balance_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct ForeignAccountBalanceResult {
+    /// The account ID.
+    pub account_id: i64,
+    /// The account type.
+    pub account_type: AccountType,
+    /// The account currency.
+    pub currency: String,
+    /// The balance of the account in the base currency.
+    pub balance: i64,
+    /// The balance of the account in the account currency.
+    pub currency_balance: i64,
+}
+
+/// Find the balances of all accounts held in a currency other than `base_currency` as at `date`.
+pub async fn foreign_account_balance_query(e: impl SqliteExecutor<'_>, base_currency: &str, date: &str)
+                                           -> Result<Vec<ForeignAccountBalanceResult>, Error> {
+    sqlx::query_as!(ForeignAccountBalanceResult,
+        r#"SELECT account.id AS "account_id!", type AS "account_type!: AccountType", currency AS "currency!",
+        IFNULL(b.balance, 0) AS "balance!: i64", IFNULL(b.currency_balance, 0) AS "currency_balance!: i64"
+        FROM account
+            LEFT JOIN (
+                SELECT account_id, SUM(amount) AS balance, SUM(IFNULL(currency_amount, 0)) AS currency_balance
+                FROM entry
+                    JOIN journal ON entry.journal_id = journal.id
+                    JOIN batch ON journal.batch_id = batch.id
+                WHERE batch.date <= ?
+                GROUP BY account_id
+            ) b ON account.id = b.account_id
+        WHERE currency IS NOT NULL AND currency != ?;"#, date, base_currency)
+        .fetch_all(e)
+        .await
+}
+
+/// Find the IDs of all accounts held in a currency other than the `baseCurrency` setting.
+pub async fn foreign_account_query(e: impl SqliteExecutor<'_>) -> Result<Vec<i64>, Error> {
+    sqlx::query_scalar!(
+        r#"SELECT id AS "id!" FROM account
+        WHERE currency IS NOT NULL
+            AND currency != IFNULL((SELECT strValue FROM settings WHERE name = 'baseCurrency'), '');"#)
+        .fetch_all(e)
+        .await
+}
+
+/// Find the IDs of all archived accounts.
+pub async fn archived_account_query(e: impl SqliteExecutor<'_>) -> Result<Vec<i64>, Error> {
+    sqlx::query_scalar!(r#"SELECT id AS "id!" FROM account WHERE archived;"#)
+        .fetch_all(e)
+        .await
+}
+
+/// Archive an account.
+///
+/// Fails with [`Error::RowNotFound`] if the account does not exist or is already archived.
+pub async fn account_archive_tx(db: &Pool<Sqlite>, account_id: i64, actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let updated = sqlx::query!("UPDATE account SET archived=TRUE WHERE id=? AND archived IS NOT TRUE;", account_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if updated == 0 {
+        return Err(Error::RowNotFound);
+    }
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::AccountArchive, account_id.to_string().as_str(),
+                    Some(json!({"archived": false})), Some(json!({"archived": true}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn contact_new_tx(db: &Pool<Sqlite>, contact: &ContactData, actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let contact_id = sqlx::query!(
+        "INSERT INTO contact (kind, name, address, postcode, country, email, tax_id, control_account_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?);",
+        contact.kind, contact.name, contact.address, contact.postcode, contact.country, contact.email,
+        contact.tax_id, contact.control_account_id)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::ContactNew, contact_id.to_string().as_str(), None,
+                    Some(json!(contact))).await?;
+    transaction.commit().await?;
+    Ok(contact_id)
+}
+
+/// The result of a [`contact_query`] or [`contact_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct ContactResult {
+    pub contact_id: i64,
+    pub kind: ContactKind,
+    pub name: String,
+    pub address: Option<String>,
+    pub postcode: Option<String>,
+    pub country: Option<String>,
+    pub email: Option<String>,
+    pub tax_id: Option<String>,
+    pub control_account_id: Option<i64>,
+    /// The balance of all entries referencing the contact.
+    /// Positive values mean the contact has a debit balance.
+    /// Negative values mean the contact has a credit balance.
+    pub balance: i64,
+}
+
+pub async fn contact_query(e: impl SqliteExecutor<'_>, contact_id: i64) -> Result<Option<ContactResult>, Error> {
+    sqlx::query_as!(ContactResult,
+        r#"SELECT contact.id AS "contact_id!", kind AS "kind!: ContactKind", name AS "name!", address, postcode,
+        country, email, tax_id, control_account_id,
+        (SELECT IFNULL(SUM(amount), 0) FROM entry WHERE contact_id = contact.id) AS "balance!: i64"
+        FROM contact WHERE id=?;"#, contact_id)
+        .fetch_optional(e)
+        .await
+}
+
+pub async fn contact_list_query(e: impl SqliteExecutor<'_>, kind: Option<&ContactKind>) -> Result<Vec<ContactResult>, Error> {
+    sqlx::query_as!(ContactResult,
+        r#"SELECT contact.id AS "contact_id!", kind AS "kind!: ContactKind", name AS "name!", address, postcode,
+        country, email, tax_id, control_account_id,
+        (SELECT IFNULL(SUM(amount), 0) FROM entry WHERE contact_id = contact.id) AS "balance!: i64"
+        FROM contact WHERE archived = FALSE AND (?1 IS NULL OR kind = ?1) ORDER BY name;"#, kind)
+        .fetch_all(e)
+        .await
+}
+
+/// Find the IDs of all contacts, including archived contacts.
+pub async fn contact_id_query(e: impl SqliteExecutor<'_>) -> Result<Vec<i64>, Error> {
+    sqlx::query_scalar!(r#"SELECT id AS "id!" FROM contact;"#)
+        .fetch_all(e)
+        .await
+}
+
+/// The inner result of [`account_contact_balance_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct AccountContactBalanceResult {
+    /// The contact ID, or [`None`] for entries that do not reference a contact.
+    pub contact_id: Option<i64>,
+    /// The contact name, or [`None`] for entries that do not reference a contact.
+    pub contact_name: Option<String>,
+    /// The balance of entries to the account referencing the contact.
+    pub balance: i64,
+}
+
+/// Break down the balance of an account by the contact referenced on each entry.
+pub async fn account_contact_balance_query(e: impl SqliteExecutor<'_>, account_id: i64)
+                                           -> Result<Vec<AccountContactBalanceResult>, Error> {
+    sqlx::query_as!(AccountContactBalanceResult,
+        r#"SELECT entry.contact_id AS "contact_id?: i64", contact.name AS "contact_name?: String",
+        SUM(amount) AS "balance!: i64"
+        FROM entry
+            LEFT JOIN contact ON entry.contact_id = contact.id
+        WHERE account_id=?
+        GROUP BY entry.contact_id
+        ORDER BY contact.name;"#, account_id)
+        .fetch_all(e)
+        .await
+}
+
+/// The result of a [`tax_code_query`] or [`tax_code_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct TaxCodeResult {
+    pub code: String,
+    pub name: String,
+    pub treatment: TaxTreatment,
+    /// The rate in basis points effective on the date queried, if any.
+    pub rate: Option<i64>,
+}
+
+pub async fn tax_code_query(e: impl SqliteExecutor<'_>, code: &str, date: &str) -> Result<Option<TaxCodeResult>, Error> {
+    sqlx::query_as!(TaxCodeResult,
+        r#"SELECT code AS "code!", name AS "name!", treatment AS "treatment!: TaxTreatment",
+        (SELECT rate FROM tax_rate WHERE tax_rate.code = tax_code.code AND effective_from <= ?2
+            ORDER BY effective_from DESC LIMIT 1) AS "rate?: i64"
+        FROM tax_code WHERE code=?1;"#, code, date)
+        .fetch_optional(e)
+        .await
+}
+
+pub async fn tax_code_list_query(e: impl SqliteExecutor<'_>, date: &str) -> Result<Vec<TaxCodeResult>, Error> {
+    sqlx::query_as!(TaxCodeResult,
+        r#"SELECT code AS "code!", name AS "name!", treatment AS "treatment!: TaxTreatment",
+        (SELECT rate FROM tax_rate WHERE tax_rate.code = tax_code.code AND effective_from <= ?1
+            ORDER BY effective_from DESC LIMIT 1) AS "rate?: i64"
+        FROM tax_code ORDER BY code;"#, date)
+        .fetch_all(e)
+        .await
+}
+
+pub async fn tax_code_new_tx(db: &Pool<Sqlite>, code: &str, name: &str, treatment: &TaxTreatment, actor: i64)
+                             -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    sqlx::query!("INSERT INTO tax_code (code, name, treatment) VALUES (?, ?, ?);", code, name, treatment)
+        .execute(&mut *transaction)
+        .await?;
+    let after = json!({"name": name, "treatment": treatment});
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::TaxCodeNew, code, None, Some(after)).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Set the rate of a tax code from a date, replacing any rate already set from that date.
+pub async fn tax_rate_set_tx(db: &Pool<Sqlite>, code: &str, effective_from: &str, rate: i64, actor: i64)
+                             -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let before = sqlx::query!("SELECT rate FROM tax_rate WHERE code=? AND effective_from=?;", code, effective_from)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map(|r| json!({"effective_from": effective_from, "rate": r.rate}));
+    sqlx::query!("INSERT OR REPLACE INTO tax_rate (code, effective_from, rate) VALUES (?, ?, ?);",
+        code, effective_from, rate)
+        .execute(&mut *transaction)
+        .await?;
+    let after = json!({"effective_from": effective_from, "rate": rate});
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::TaxRateSet, code, before, Some(after)).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn invoice_lines_insert(e: &mut SqliteConnection, invoice_id: i64, lines: &[InvoiceLine]) -> Result<(), Error> {
+    for line in lines {
+        sqlx::query!(
+            "INSERT INTO invoice_line (invoice_id, description, quantity, unit_price, tax_code, account_id,
+            net_amount, tax_amount) VALUES (?, ?, ?, ?, ?, ?, ?, ?);",
+            invoice_id, line.description, line.quantity, line.unit_price, line.tax_code, line.account_id,
+            line.net_amount, line.tax_amount)
+            .execute(&mut *e)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn invoice_new_tx(db: &Pool<Sqlite>, header: &InvoiceHeader, lines: &[InvoiceLine], actor: i64)
+                            -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let invoice_id = sqlx::query!(
+        "INSERT INTO invoice (kind, state, contact_id, date, due_date, reference) VALUES (?, ?, ?, ?, ?, ?);",
+        header.kind, InvoiceState::Draft, header.contact_id, header.date, header.due_date, header.reference)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    invoice_lines_insert(&mut transaction, invoice_id, lines).await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::InvoiceNew, invoice_id.to_string().as_str(), None,
+                    Some(json!({"header": header, "lines": lines}))).await?;
+    transaction.commit().await?;
+    Ok(invoice_id)
+}
+
+/// Replace the header and lines of a draft invoice.
+///
+/// Returns [`Error::RowNotFound`] if the invoice does not exist or is not a draft.
+pub async fn invoice_update_tx(db: &Pool<Sqlite>, invoice_id: i64, header: &InvoiceHeader, lines: &[InvoiceLine],
+                               actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let before = json!({
+        "header": invoice_query(&mut *transaction, invoice_id).await?,
+        "lines": invoice_line_query(&mut *transaction, invoice_id).await?,
+    });
+    let updated = sqlx::query!(
+        "UPDATE invoice SET kind=?, contact_id=?, date=?, due_date=?, reference=? WHERE id=? AND state=?;",
+        header.kind, header.contact_id, header.date, header.due_date, header.reference, invoice_id, InvoiceState::Draft)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if updated != 1 {
+        return Err(Error::RowNotFound);
+    }
+    sqlx::query!("DELETE FROM invoice_line WHERE invoice_id=?;", invoice_id)
+        .execute(&mut *transaction)
+        .await?;
+    invoice_lines_insert(&mut transaction, invoice_id, lines).await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::InvoiceUpdate, invoice_id.to_string().as_str(),
+                    Some(before), Some(json!({"header": header, "lines": lines}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Mark a draft invoice as approved and post its journal in a single transaction, assigning the
+/// next number from the `number_setting` setting, with `prefix`. The journal is made by `journal`
+/// from the number assigned.
+///
+/// Returns the number assigned, or [`Error::RowNotFound`] if the invoice does not exist or is not
+/// a draft.
+pub async fn invoice_approve_tx(db: &Pool<Sqlite>, invoice_id: i64, prefix: &str, number_setting: &str,
+                                metadata: &BatchMetadata, journal: impl FnOnce(&str) -> Journal, actor: i64)
+                                -> Result<String, Error> {
+    let mut transaction = db.begin().await?;
+    let updated = sqlx::query!("UPDATE invoice SET state=? WHERE id=? AND state=?;",
+        InvoiceState::Approved, invoice_id, InvoiceState::Draft)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if updated != 1 {
+        return Err(Error::RowNotFound);
+    }
+    let invoice_number = settings::get_settings_int(&mut *transaction, number_setting).await.unwrap();
+    let number = format!("{prefix}{invoice_number:05}");
+    let (_, journal_ids) = batch_insert(&mut transaction, metadata, vec![journal(number.as_str())]).await?;
+    sqlx::query!("UPDATE invoice SET number=?, journal_id=? WHERE id=?;", number, journal_ids[0], invoice_id)
+        .execute(&mut *transaction)
+        .await?;
+    settings::set_settings_int(&mut transaction, number_setting, invoice_number + 1, Some(actor)).await;
+    let after = json!({"state": InvoiceState::Approved, "number": number, "journal_id": journal_ids[0]});
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::InvoiceApprove, invoice_id.to_string().as_str(),
+                    Some(json!({"state": InvoiceState::Draft})), Some(after)).await?;
+    transaction.commit().await?;
+    Ok(number)
+}
+
+/// The result of an [`invoice_query`] or [`invoice_list_query`].
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct InvoiceResult {
+    pub invoice_id: i64,
+    pub kind: InvoiceKind,
+    pub number: Option<String>,
+    pub state: InvoiceState,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub date: String,
+    pub due_date: String,
+    pub reference: Option<String>,
+    pub journal_id: Option<i64>,
+    pub net_total: i64,
+    pub tax_total: i64,
+    /// The total of all receipts and credits allocated to the invoice.
+    pub allocated: i64,
+}
+
+pub async fn invoice_query(e: impl SqliteExecutor<'_>, invoice_id: i64) -> Result<Option<InvoiceResult>, Error> {
+    sqlx::query_as!(InvoiceResult,
+        r#"SELECT invoice.id AS "invoice_id!", invoice.kind AS "kind!: InvoiceKind", number,
+        state AS "state!: InvoiceState",
+        contact_id AS "contact_id!", contact.name AS "contact_name!", date AS "date!: String", due_date AS "due_date!: String",
+        reference, journal_id,
+        (SELECT IFNULL(SUM(net_amount), 0) FROM invoice_line WHERE invoice_id = invoice.id) AS "net_total!: i64",
+        (SELECT IFNULL(SUM(tax_amount), 0) FROM invoice_line WHERE invoice_id = invoice.id) AS "tax_total!: i64",
+        (SELECT IFNULL(SUM(amount), 0) FROM allocation
+            WHERE invoice_id = invoice.id OR journal_id = invoice.journal_id) AS "allocated!: i64"
+        FROM invoice
+            JOIN contact ON invoice.contact_id = contact.id
+        WHERE invoice.id=?;"#, invoice_id)
+        .fetch_optional(e)
+        .await
+}
+
+pub async fn invoice_list_query(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, state: Option<&InvoiceState>)
+                                -> Result<Vec<InvoiceResult>, Error> {
+    sqlx::query_as!(InvoiceResult,
+        r#"SELECT invoice.id AS "invoice_id!", invoice.kind AS "kind!: InvoiceKind", number,
+        state AS "state!: InvoiceState",
+        contact_id AS "contact_id!", contact.name AS "contact_name!", date AS "date!: String", due_date AS "due_date!: String",
+        reference, journal_id,
+        (SELECT IFNULL(SUM(net_amount), 0) FROM invoice_line WHERE invoice_id = invoice.id) AS "net_total!: i64",
+        (SELECT IFNULL(SUM(tax_amount), 0) FROM invoice_line WHERE invoice_id = invoice.id) AS "tax_total!: i64",
+        (SELECT IFNULL(SUM(amount), 0) FROM allocation
+            WHERE invoice_id = invoice.id OR journal_id = invoice.journal_id) AS "allocated!: i64"
+        FROM invoice
+            JOIN contact ON invoice.contact_id = contact.id
+        WHERE (?1 IS NULL OR contact_id = ?1) AND (?2 IS NULL OR state = ?2)
+        ORDER BY date, invoice.id;"#, contact_id, state)
+        .fetch_all(e)
+        .await
+}
+
+pub async fn invoice_line_query(e: impl SqliteExecutor<'_>, invoice_id: i64) -> Result<Vec<InvoiceLine>, Error> {
+    sqlx::query_as!(InvoiceLine,
+        r#"SELECT description AS "description!", quantity AS "quantity!: f64", unit_price AS "unit_price!: i64",
+        tax_code AS "tax_code!", account_id AS "account_id!: i64", net_amount AS "net_amount!: i64",
+        tax_amount AS "tax_amount!: i64"
+        FROM invoice_line WHERE invoice_id=? ORDER BY id;"#, invoice_id)
+        .fetch_all(e)
+        .await
+}
+
+/// Post the journal for a bill and record the bill, in a single transaction.
+pub async fn bill_new_tx(db: &Pool<Sqlite>, header: &BillHeader, lines: &[BillLine], metadata: &BatchMetadata,
+                         journal: Journal) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let (_, journal_ids) = batch_insert(&mut transaction, metadata, vec![journal]).await?;
+    let bill_id = sqlx::query!(
+        "INSERT INTO bill (contact_id, supplier_reference, date, due_date, journal_id) VALUES (?, ?, ?, ?, ?);",
+        header.contact_id, header.supplier_reference, header.date, header.due_date, journal_ids[0])
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    for line in lines {
+        sqlx::query!(
+            "INSERT INTO bill_line (bill_id, description, tax_code, account_id, net_amount, tax_amount)
+            VALUES (?, ?, ?, ?, ?, ?);",
+            bill_id, line.description, line.tax_code, line.account_id, line.net_amount, line.tax_amount)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    let after = json!({"header": header, "lines": lines, "journal_id": journal_ids[0]});
+    audit_event_new(&mut transaction, metadata.created_by, AuditOperation::BillNew, bill_id.to_string().as_str(), None,
+                    Some(after)).await?;
+    transaction.commit().await?;
+    Ok(bill_id)
+}
+
+/// The result of a [`bill_query`] or [`bill_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct BillResult {
+    pub bill_id: i64,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub supplier_reference: Option<String>,
+    pub date: String,
+    pub due_date: String,
+    pub journal_id: i64,
+    pub net_total: i64,
+    pub tax_total: i64,
+    /// The total of all payments made against the bill.
+    pub paid: i64,
+}
+
+pub async fn bill_query(e: impl SqliteExecutor<'_>, bill_id: i64) -> Result<Option<BillResult>, Error> {
+    sqlx::query_as!(BillResult,
+        r#"SELECT bill.id AS "bill_id!", contact_id AS "contact_id!", contact.name AS "contact_name!",
+        supplier_reference, date AS "date!: String", due_date AS "due_date!: String", journal_id AS "journal_id!",
+        (SELECT IFNULL(SUM(net_amount), 0) FROM bill_line WHERE bill_id = bill.id) AS "net_total!: i64",
+        (SELECT IFNULL(SUM(tax_amount), 0) FROM bill_line WHERE bill_id = bill.id) AS "tax_total!: i64",
+        (SELECT IFNULL(SUM(amount), 0) FROM allocation
+            WHERE bill_id = bill.id OR journal_id = bill.journal_id) AS "paid!: i64"
+        FROM bill
+            JOIN contact ON bill.contact_id = contact.id
+        WHERE bill.id=?;"#, bill_id)
+        .fetch_optional(e)
+        .await
+}
+
+/// List bills, optionally only those for a contact, those with an amount outstanding, or those
+/// due on or before a date.
+pub async fn bill_list_query(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, outstanding: bool,
+                             due_by: Option<&str>) -> Result<Vec<BillResult>, Error> {
+    sqlx::query_as!(BillResult,
+        r#"SELECT bill_id AS "bill_id!", contact_id AS "contact_id!", contact_name AS "contact_name!",
+        supplier_reference, date AS "date!: String", due_date AS "due_date!: String", journal_id AS "journal_id!",
+        net_total AS "net_total!: i64", tax_total AS "tax_total!: i64", paid AS "paid!: i64"
+        FROM (
+            SELECT bill.id AS bill_id, contact_id, contact.name AS contact_name, supplier_reference, date, due_date,
+            journal_id,
+            (SELECT IFNULL(SUM(net_amount), 0) FROM bill_line WHERE bill_id = bill.id) AS net_total,
+            (SELECT IFNULL(SUM(tax_amount), 0) FROM bill_line WHERE bill_id = bill.id) AS tax_total,
+            (SELECT IFNULL(SUM(amount), 0) FROM allocation
+                WHERE bill_id = bill.id OR journal_id = bill.journal_id) AS paid
+            FROM bill
+                JOIN contact ON bill.contact_id = contact.id
+        )
+        WHERE (?1 IS NULL OR contact_id = ?1) AND (?2 = FALSE OR net_total + tax_total != paid)
+            AND (?3 IS NULL OR due_date <= ?3)
+        ORDER BY due_date, bill_id;"#, contact_id, outstanding, due_by)
+        .fetch_all(e)
+        .await
+}
+
+pub async fn bill_line_query(e: impl SqliteExecutor<'_>, bill_id: i64) -> Result<Vec<BillLine>, Error> {
+    sqlx::query_as!(BillLine,
+        r#"SELECT description AS "description!", tax_code AS "tax_code!", account_id AS "account_id!: i64",
+        net_amount AS "net_amount!: i64", tax_amount AS "tax_amount!: i64"
+        FROM bill_line WHERE bill_id=? ORDER BY id;"#, bill_id)
+        .fetch_all(e)
+        .await
+}
+
+async fn settlements_insert(e: &mut SqliteConnection, metadata: &BatchMetadata, settlements: Vec<Settlement>)
+                           -> Result<(i64, Vec<i64>), Error> {
+    let mut allocations: Vec<(Option<i64>, Option<i64>, i64)> = Vec::new();
+    let mut journals: Vec<Journal> = Vec::new();
+    for settlement in settlements {
+        allocations.push((settlement.document.invoice_id(), settlement.document.bill_id(), settlement.amount));
+        journals.push(settlement.journal);
+    }
+    let (batch_id, journal_ids) = batch_insert(&mut *e, metadata, journals).await?;
+    for ((invoice_id, bill_id, amount), journal_id) in allocations.iter().zip(&journal_ids) {
+        sqlx::query!(
+            "INSERT INTO allocation (journal_id, invoice_id, bill_id, amount, date)
+            VALUES (?, ?, ?, ?, IFNULL(?, DATE('NOW')));",
+            journal_id, invoice_id, bill_id, amount, metadata.date)
+            .execute(&mut *e)
+            .await?;
+    }
+    Ok((batch_id, journal_ids))
+}
+
+/// Post a batch of receipts or payments and allocate them to the documents they settle, in a
+/// single transaction.
+pub async fn settlements_tx(db: &Pool<Sqlite>, metadata: &BatchMetadata, settlements: Vec<Settlement>)
+                            -> Result<(i64, Vec<i64>), Error> {
+    let mut transaction = db.begin().await?;
+    let result = settlements_insert(&mut transaction, metadata, settlements).await?;
+    transaction.commit().await?;
+    Ok(result)
+}
+
+pub async fn payment_run_new_tx(db: &Pool<Sqlite>, due_by: &str, payment_date: &str, cash_account_id: i64,
+                                lines: &[(i64, i64)], actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let payment_run_id = sqlx::query!(
+        "INSERT INTO payment_run (state, due_by, payment_date, cash_account_id, created_by) VALUES (?, ?, ?, ?, ?);",
+        PaymentRunState::Proposed, due_by, payment_date, cash_account_id, actor)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    for (bill_id, amount) in lines {
+        sqlx::query!("INSERT INTO payment_run_line (payment_run_id, bill_id, amount) VALUES (?, ?, ?);",
+            payment_run_id, bill_id, amount)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    let after = json!({"due_by": due_by, "payment_date": payment_date, "cash_account_id": cash_account_id,
+        "lines": lines});
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::PaymentRunNew, payment_run_id.to_string().as_str(),
+                    None, Some(after)).await?;
+    transaction.commit().await?;
+    Ok(payment_run_id)
+}
+
+/// The result of a [`payment_run_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct PaymentRunResult {
+    pub payment_run_id: i64,
+    pub state: PaymentRunState,
+    pub due_by: String,
+    pub payment_date: String,
+    pub cash_account_id: i64,
+    pub batch_id: Option<i64>,
+    pub created_by: Option<i64>,
+}
+
+pub async fn payment_run_query(e: impl SqliteExecutor<'_>, payment_run_id: i64) -> Result<Option<PaymentRunResult>, Error> {
+    sqlx::query_as!(PaymentRunResult,
+        r#"SELECT id AS "payment_run_id!", state AS "state!: PaymentRunState", due_by AS "due_by!: String",
+        payment_date AS "payment_date!: String", cash_account_id AS "cash_account_id!", batch_id,
+        created_by FROM payment_run WHERE id=?;"#, payment_run_id)
+        .fetch_optional(e)
+        .await
+}
+
+/// The inner result of [`payment_run_line_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct PaymentRunLineResult {
+    pub bill_id: i64,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub supplier_reference: Option<String>,
+    pub due_date: String,
+    pub amount: i64,
+}
+
+pub async fn payment_run_line_query(e: impl SqliteExecutor<'_>, payment_run_id: i64)
+                                    -> Result<Vec<PaymentRunLineResult>, Error> {
+    sqlx::query_as!(PaymentRunLineResult,
+        r#"SELECT bill_id AS "bill_id!", contact_id AS "contact_id!", contact.name AS "contact_name!",
+        supplier_reference, due_date AS "due_date!: String", amount AS "amount!"
+        FROM payment_run_line
+            JOIN bill ON payment_run_line.bill_id = bill.id
+            JOIN contact ON bill.contact_id = contact.id
+        WHERE payment_run_id=?
+        ORDER BY contact.name, due_date;"#, payment_run_id)
+        .fetch_all(e)
+        .await
+}
+
+/// Post the payments for a proposed payment run and mark it as confirmed, in a single transaction.
+///
+/// Returns [`Error::RowNotFound`] if the payment run does not exist or is not proposed.
+pub async fn payment_run_confirm_tx(db: &Pool<Sqlite>, payment_run_id: i64, metadata: &BatchMetadata,
+                                    payments: Vec<Settlement>) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let (batch_id, _) = settlements_insert(&mut transaction, metadata, payments).await?;
+    let updated = sqlx::query!("UPDATE payment_run SET state=?, batch_id=? WHERE id=? AND state=?;",
+        PaymentRunState::Confirmed, batch_id, payment_run_id, PaymentRunState::Proposed)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if updated != 1 {
+        return Err(Error::RowNotFound);
+    }
+    audit_event_new(&mut transaction, metadata.created_by, AuditOperation::PaymentRunConfirm,
+                    payment_run_id.to_string().as_str(), Some(json!({"state": PaymentRunState::Proposed})),
+                    Some(json!({"state": PaymentRunState::Confirmed, "batch_id": batch_id}))).await?;
+    transaction.commit().await?;
+    Ok(batch_id)
+}
+
+/// The result of an [`open_item_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct OpenItemResult {
+    pub journal_id: i64,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub contact_kind: ContactKind,
+    pub date: String,
+    /// The due date of the invoice or bill posted by the journal, otherwise the journal date.
+    pub due_date: String,
+    pub invoice_id: Option<i64>,
+    pub bill_id: Option<i64>,
+    pub narrative: String,
+    /// The total of the journal's entries for the contact, positive for a debit.
+    pub amount: i64,
+    /// The total allocated to or from the journal, or the document it posts.
+    pub allocated: i64,
+}
+
+/// Find each journal with entries for a contact, or for any contact, that is not fully allocated.
+///
+/// If `as_at` is given, only journals and allocations dated on or before it are counted.
+pub async fn open_item_query(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, as_at: Option<&str>)
+                             -> Result<Vec<OpenItemResult>, Error> {
+    sqlx::query_as!(OpenItemResult,
+        r#"SELECT journal_id AS "journal_id!", contact_id AS "contact_id!", contact_name AS "contact_name!",
+        contact_kind AS "contact_kind!: ContactKind", date AS "date!: String", due_date AS "due_date!: String",
+        invoice_id, bill_id, narrative AS "narrative!: String", amount AS "amount!: i64", allocated AS "allocated!: i64"
+        FROM (
+            SELECT journal.id AS journal_id, contact.id AS contact_id, contact.name AS contact_name,
+            contact.kind AS contact_kind, batch.date, IFNULL(invoice.due_date, IFNULL(bill.due_date, batch.date)) AS due_date,
+            invoice.id AS invoice_id, bill.id AS bill_id, IFNULL(journal.unstructured_narrative, '') AS narrative,
+            SUM(entry.amount) AS amount,
+            (SELECT IFNULL(SUM(allocation.amount), 0) FROM allocation
+                WHERE (allocation.journal_id = journal.id OR allocation.invoice_id = invoice.id
+                    OR allocation.bill_id = bill.id) AND (?2 IS NULL OR allocation.date <= ?2)) AS allocated
+            FROM entry
+                JOIN journal ON entry.journal_id = journal.id
+                JOIN batch ON journal.batch_id = batch.id
+                JOIN contact ON entry.contact_id = contact.id
+                LEFT JOIN invoice ON invoice.journal_id = journal.id
+                LEFT JOIN bill ON bill.journal_id = journal.id
+            WHERE (?1 IS NULL OR entry.contact_id = ?1) AND (?2 IS NULL OR batch.date <= ?2)
+            GROUP BY journal.id, contact.id
+        )
+        WHERE ABS(amount) != allocated
+        ORDER BY contact_name, contact_id, date, journal_id;"#, contact_id, as_at)
+        .fetch_all(e)
+        .await
+}
+
+/// Allocate part of a journal to an invoice or bill.
+pub async fn allocation_new_tx(db: &Pool<Sqlite>, journal_id: i64, document: &Document, amount: i64, date: &str,
+                               actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let (invoice_id, bill_id) = (document.invoice_id(), document.bill_id());
+    let allocation_id = sqlx::query!(
+        "INSERT INTO allocation (journal_id, invoice_id, bill_id, amount, date) VALUES (?, ?, ?, ?, ?);",
+        journal_id, invoice_id, bill_id, amount, date)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    let after = json!({"journal_id": journal_id, "document": document, "amount": amount, "date": date});
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::AllocationNew, allocation_id.to_string().as_str(),
+                    None, Some(after)).await?;
+    transaction.commit().await?;
+    Ok(allocation_id)
+}
+
+/// Remove an allocation, returning [`Error::RowNotFound`] if it does not exist.
+/// Find the date of an allocation, or [`None`] if the allocation does not exist.
+pub async fn allocation_date_query(e: impl SqliteExecutor<'_>, allocation_id: i64) -> Result<Option<String>, Error> {
+    Ok(sqlx::query!(r#"SELECT date AS "date!: String" FROM allocation WHERE id=?;"#, allocation_id)
+        .fetch_optional(e)
+        .await?
+        .map(|r| r.date))
+}
+
+pub async fn allocation_delete_tx(db: &Pool<Sqlite>, allocation_id: i64, actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let Some(before) = sqlx::query!(
+        r#"SELECT journal_id, invoice_id, bill_id, amount, date AS "date!: String" FROM allocation WHERE id=?;"#,
+        allocation_id)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map(|r| json!({"journal_id": r.journal_id, "invoice_id": r.invoice_id, "bill_id": r.bill_id,
+            "amount": r.amount, "date": r.date})) else {
+        return Err(Error::RowNotFound);
+    };
+    sqlx::query!("DELETE FROM allocation WHERE id=?;", allocation_id)
+        .execute(&mut *transaction)
+        .await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::AllocationDelete, allocation_id.to_string().as_str(),
+                    Some(before), None).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The result of an [`allocation_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct AllocationResult {
+    pub allocation_id: i64,
+    pub journal_id: i64,
+    pub invoice_id: Option<i64>,
+    pub bill_id: Option<i64>,
+    pub amount: i64,
+    pub date: String,
+}
+
+/// List the allocations to or from the journals for a contact.
+pub async fn allocation_list_query(e: impl SqliteExecutor<'_>, contact_id: i64) -> Result<Vec<AllocationResult>, Error> {
+    sqlx::query_as!(AllocationResult,
+        r#"SELECT id AS "allocation_id!", journal_id AS "journal_id!", invoice_id, bill_id, amount AS "amount!",
+        date AS "date!: String"
+        FROM allocation
+        WHERE journal_id IN (SELECT journal_id FROM entry WHERE contact_id = ?1)
+        ORDER BY date, id;"#, contact_id)
+        .fetch_all(e)
+        .await
+}
+
+/// Find the balance of the entries for a contact and/or account dated before `from`.
+pub async fn statement_opening_query(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, account_id: Option<i64>,
+                                     from: &str) -> Result<i64, Error> {
+    Ok(sqlx::query!(
+        r#"SELECT IFNULL(SUM(entry.amount), 0) AS "balance!: i64"
+        FROM entry
+            JOIN journal ON entry.journal_id = journal.id
+            JOIN batch ON journal.batch_id = batch.id
+        WHERE (?1 IS NULL OR entry.contact_id = ?1) AND (?2 IS NULL OR entry.account_id = ?2) AND batch.date < ?3;"#,
+        contact_id, account_id, from)
+        .fetch_one(e)
+        .await?
+        .balance)
+}
+
+/// The inner result of [`statement_line_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct StatementLineResult {
+    pub journal_id: i64,
+    pub date: String,
+    pub narrative: String,
+    /// The total of the journal's entries for the contact and/or account, positive for a debit.
+    pub amount: i64,
+}
+
+/// List the journals with entries for a contact and/or account dated between `from` and `to`
+/// inclusive.
+pub async fn statement_line_query(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, account_id: Option<i64>,
+                                  from: &str, to: &str) -> Result<Vec<StatementLineResult>, Error> {
+    sqlx::query_as!(StatementLineResult,
+        r#"SELECT journal.id AS "journal_id!", batch.date AS "date!: String",
+        IFNULL(journal.unstructured_narrative, '') AS "narrative!", SUM(entry.amount) AS "amount!: i64"
+        FROM entry
+            JOIN journal ON entry.journal_id = journal.id
+            JOIN batch ON journal.batch_id = batch.id
+        WHERE (?1 IS NULL OR entry.contact_id = ?1) AND (?2 IS NULL OR entry.account_id = ?2)
+            AND batch.date >= ?3 AND batch.date <= ?4
+        GROUP BY journal.id
+        ORDER BY batch.date, journal.id;"#, contact_id, account_id, from, to)
+        .fetch_all(e)
+        .await
+}
+
+/// The inner result of [`vat_entry_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct VatEntryResult {
+    pub journal_id: i64,
+    pub date: String,
+    pub narrative: String,
+    pub account_id: i64,
+    pub account_type: AccountType,
+    pub amount: i64,
+    pub tax_code: String,
+    pub treatment: TaxTreatment,
+    /// Whether the journal posts an invoice, credit note or bill.
+    pub is_document: bool,
+}
+
+/// List the entries with a tax code dated on or before `to`.
+pub async fn vat_entry_query(e: impl SqliteExecutor<'_>, to: &str) -> Result<Vec<VatEntryResult>, Error> {
+    sqlx::query_as!(VatEntryResult,
+        r#"SELECT journal.id AS "journal_id!", batch.date AS "date!: String",
+        IFNULL(journal.unstructured_narrative, '') AS "narrative!: String", entry.account_id AS "account_id!",
+        account.type AS "account_type!: AccountType", entry.amount AS "amount!", entry.tax_code AS "tax_code!",
+        tax_code.treatment AS "treatment!: TaxTreatment",
+        (EXISTS (SELECT 1 FROM invoice WHERE journal_id = journal.id)
+            OR EXISTS (SELECT 1 FROM bill WHERE journal_id = journal.id)) AS "is_document!: bool"
+        FROM entry
+            JOIN journal ON entry.journal_id = journal.id
+            JOIN batch ON journal.batch_id = batch.id
+            JOIN account ON entry.account_id = account.id
+            JOIN tax_code ON entry.tax_code = tax_code.code
+        WHERE batch.date <= ?
+        ORDER BY batch.date, journal.id, entry.id;"#, to)
+        .fetch_all(e)
+        .await
+}
+
+/// The inner result of [`vat_settlement_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct VatSettlementResult {
+    pub journal_id: i64,
+    /// The total of the invoice, credit note or bill.
+    pub total: i64,
+    /// The amount allocated to or from the document between the dates.
+    pub settled: i64,
+}
+
+/// Find the amount of each invoice, credit note and bill settled by allocations dated between
+/// `from` and `to` inclusive, by the journal that posts the document.
+pub async fn vat_settlement_query(e: impl SqliteExecutor<'_>, from: &str, to: &str)
+                                  -> Result<Vec<VatSettlementResult>, Error> {
+    sqlx::query_as!(VatSettlementResult,
+        r#"SELECT journal_id AS "journal_id!", total AS "total!: i64", settled AS "settled!: i64"
+        FROM (
+            SELECT invoice.journal_id,
+            (SELECT IFNULL(SUM(net_amount + tax_amount), 0) FROM invoice_line WHERE invoice_id = invoice.id) AS total,
+            (SELECT IFNULL(SUM(amount), 0) FROM allocation
+                WHERE (invoice_id = invoice.id OR journal_id = invoice.journal_id)
+                    AND date >= ?1 AND date <= ?2) AS settled
+            FROM invoice WHERE invoice.journal_id IS NOT NULL
+            UNION ALL
+            SELECT bill.journal_id,
+            (SELECT IFNULL(SUM(net_amount + tax_amount), 0) FROM bill_line WHERE bill_id = bill.id) AS total,
+            (SELECT IFNULL(SUM(amount), 0) FROM allocation
+                WHERE (bill_id = bill.id OR journal_id = bill.journal_id)
+                    AND date >= ?1 AND date <= ?2) AS settled
+            FROM bill
+        )
+        WHERE settled != 0;"#, from, to)
+        .fetch_all(e)
+        .await
+}
+
+/// Record a submitted VAT return and lock the ledger up to the end of its period, in a single
+/// transaction.
+pub async fn vat_return_new_tx(db: &Pool<Sqlite>, vat_return: &VatReturnResult, actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let vat_return_id = sqlx::query!(
+        "INSERT INTO vat_return (period_key, start_date, end_date, scheme, box1, box2, box3, box4, box5, box6, box7,
+        box8, box9, processing_date, form_bundle_number) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+        vat_return.period_key, vat_return.start_date, vat_return.end_date, vat_return.scheme, vat_return.box1,
+        vat_return.box2, vat_return.box3, vat_return.box4, vat_return.box5, vat_return.box6, vat_return.box7,
+        vat_return.box8, vat_return.box9, vat_return.processing_date, vat_return.form_bundle_number)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::VatReturnSubmit, vat_return_id.to_string().as_str(),
+                    None, Some(json!(vat_return))).await?;
+    settings::set_settings_str(&mut transaction, "vatLockDate", vat_return.end_date.as_str(), Some(actor)).await;
+    transaction.commit().await?;
+    Ok(vat_return_id)
+}
+
+/// A submitted VAT return, as stored. Amounts are in minor currency units.
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct VatReturnResult {
+    pub period_key: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub scheme: VatScheme,
+    pub box1: i64,
+    pub box2: i64,
+    pub box3: i64,
+    pub box4: i64,
+    pub box5: i64,
+    pub box6: i64,
+    pub box7: i64,
+    pub box8: i64,
+    pub box9: i64,
+    pub processing_date: String,
+    pub form_bundle_number: String,
+}
+
+pub async fn vat_return_list_query(e: impl SqliteExecutor<'_>) -> Result<Vec<VatReturnResult>, Error> {
+    sqlx::query_as!(VatReturnResult,
+        r#"SELECT period_key AS "period_key!", start_date AS "start_date!: String", end_date AS "end_date!: String",
+        scheme AS "scheme!: VatScheme", box1 AS "box1!", box2 AS "box2!", box3 AS "box3!", box4 AS "box4!",
+        box5 AS "box5!", box6 AS "box6!", box7 AS "box7!", box8 AS "box8!", box9 AS "box9!",
+        processing_date AS "processing_date!", form_bundle_number AS "form_bundle_number!"
+        FROM vat_return ORDER BY start_date;"#)
+        .fetch_all(e)
+        .await
+}
+
+/// Insert a fixed asset. The accounts are expected to have been filled in from the settings.
+pub async fn fixed_asset_new_tx(db: &Pool<Sqlite>, asset: &FixedAssetData, actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let acquisition_date = asset.acquisition_date.to_string();
+    let asset_id = sqlx::query!(
+        "INSERT INTO fixed_asset (name, cost_account_id, accumulated_account_id, depreciation_account_id, cost,
+        residual_value, acquisition_date, useful_life_months, method, rate) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+        asset.name, asset.cost_account_id, asset.accumulated_account_id, asset.depreciation_account_id, asset.cost,
+        asset.residual_value, acquisition_date, asset.useful_life_months, asset.method, asset.rate)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::FixedAssetNew, asset_id.to_string().as_str(), None,
+                    Some(json!(asset))).await?;
+    transaction.commit().await?;
+    Ok(asset_id)
+}
+
+/// The result of a [`fixed_asset_query`] or [`fixed_asset_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct FixedAssetResult {
+    pub asset_id: i64,
+    pub name: String,
+    pub cost_account_id: i64,
+    pub accumulated_account_id: i64,
+    pub depreciation_account_id: i64,
+    pub cost: i64,
+    pub residual_value: i64,
+    pub acquisition_date: String,
+    pub useful_life_months: i64,
+    pub method: DepreciationMethod,
+    pub rate: Option<i64>,
+    pub disposal_date: Option<String>,
+    pub disposal_proceeds: Option<i64>,
+    pub disposal_journal_id: Option<i64>,
+    /// The total depreciation posted.
+    pub accumulated: i64,
+}
+
+pub async fn fixed_asset_query(e: impl SqliteExecutor<'_>, asset_id: i64) -> Result<Option<FixedAssetResult>, Error> {
+    sqlx::query_as!(FixedAssetResult,
+        r#"SELECT id AS "asset_id!", name AS "name!", cost_account_id AS "cost_account_id!",
+        accumulated_account_id AS "accumulated_account_id!", depreciation_account_id AS "depreciation_account_id!",
+        cost AS "cost!", residual_value AS "residual_value!", acquisition_date AS "acquisition_date!: String",
+        useful_life_months AS "useful_life_months!", method AS "method!: DepreciationMethod", rate,
+        disposal_date AS "disposal_date: String", disposal_proceeds, disposal_journal_id,
+        (SELECT IFNULL(SUM(amount), 0) FROM depreciation WHERE asset_id = fixed_asset.id) AS "accumulated!: i64"
+        FROM fixed_asset WHERE id=?;"#, asset_id)
+        .fetch_optional(e)
+        .await
+}
+
+/// List fixed assets, optionally only those not disposed of.
+pub async fn fixed_asset_list_query(e: impl SqliteExecutor<'_>, active: bool) -> Result<Vec<FixedAssetResult>, Error> {
+    sqlx::query_as!(FixedAssetResult,
+        r#"SELECT id AS "asset_id!", name AS "name!", cost_account_id AS "cost_account_id!",
+        accumulated_account_id AS "accumulated_account_id!", depreciation_account_id AS "depreciation_account_id!",
+        cost AS "cost!", residual_value AS "residual_value!", acquisition_date AS "acquisition_date!: String",
+        useful_life_months AS "useful_life_months!", method AS "method!: DepreciationMethod", rate,
+        disposal_date AS "disposal_date: String", disposal_proceeds, disposal_journal_id,
+        (SELECT IFNULL(SUM(amount), 0) FROM depreciation WHERE asset_id = fixed_asset.id) AS "accumulated!: i64"
+        FROM fixed_asset
+        WHERE ?1 = FALSE OR disposal_date IS NULL
+        ORDER BY acquisition_date, id;"#, active)
+        .fetch_all(e)
+        .await
+}
+
+/// The inner result of [`depreciation_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct DepreciationResult {
+    pub month: String,
+    pub amount: i64,
+    pub journal_id: i64,
+}
+
+/// List the depreciation posted for an asset, by month.
+pub async fn depreciation_query(e: impl SqliteExecutor<'_>, asset_id: i64) -> Result<Vec<DepreciationResult>, Error> {
+    sqlx::query_as!(DepreciationResult,
+        r#"SELECT month AS "month!", amount AS "amount!", journal_id AS "journal_id!"
+        FROM depreciation WHERE asset_id=? ORDER BY month;"#, asset_id)
+        .fetch_all(e)
+        .await
+}
+
+/// Post a batch of depreciation journals and record the monthly charges each one posts, in a
+/// single transaction.
+pub async fn depreciation_run_tx(db: &Pool<Sqlite>, metadata: &BatchMetadata,
+                                 journals: Vec<(Journal, Vec<DepreciationCharge>)>) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let (charges, journals): (Vec<_>, Vec<_>) = journals.into_iter().map(|(j, c)| (c, j)).unzip();
+    let (batch_id, journal_ids) = batch_insert(&mut transaction, metadata, journals).await?;
+    for (charges, journal_id) in charges.iter().zip(&journal_ids) {
+        for charge in charges {
+            sqlx::query!("INSERT INTO depreciation (asset_id, month, amount, journal_id) VALUES (?, ?, ?, ?);",
+                charge.asset_id, charge.month, charge.amount, journal_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(batch_id)
+}
+
+/// Post the disposal of a fixed asset and mark it as disposed, in a single transaction.
+///
+/// Returns [`Error::RowNotFound`] if the asset does not exist or has already been disposed of.
+pub async fn fixed_asset_dispose_tx(db: &Pool<Sqlite>, asset_id: i64, date: &str, proceeds: i64,
+                                    metadata: &BatchMetadata, journal: Journal) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let (_, journal_ids) = batch_insert(&mut transaction, metadata, vec![journal]).await?;
+    let updated = sqlx::query!(
+        "UPDATE fixed_asset SET disposal_date=?, disposal_proceeds=?, disposal_journal_id=?
+        WHERE id=? AND disposal_date IS NULL;",
+        date, proceeds, journal_ids[0], asset_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if updated != 1 {
+        return Err(Error::RowNotFound);
+    }
+    let after = json!({"disposal_date": date, "disposal_proceeds": proceeds, "disposal_journal_id": journal_ids[0]});
+    audit_event_new(&mut transaction, metadata.created_by, AuditOperation::FixedAssetDispose,
+                    asset_id.to_string().as_str(), None, Some(after)).await?;
+    transaction.commit().await?;
+    Ok(journal_ids[0])
+}
+
+/// Insert an amortisation schedule. The balance account is expected to have been filled in from
+/// the settings.
+pub async fn amortisation_schedule_new_tx(db: &Pool<Sqlite>, schedule: &ScheduleData, actor: i64)
+                                        -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let start_date = schedule.start_date.to_string();
+    let schedule_id = sqlx::query!(
+        "INSERT INTO amortisation_schedule (kind, description, total, start_date, periods, expense_account_id,
+        balance_account_id) VALUES (?, ?, ?, ?, ?, ?, ?);",
+        schedule.kind, schedule.description, schedule.total, start_date, schedule.periods,
+        schedule.expense_account_id, schedule.balance_account_id)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::AmortisationScheduleNew,
+                    schedule_id.to_string().as_str(), None, Some(json!(schedule))).await?;
+    transaction.commit().await?;
+    Ok(schedule_id)
+}
+
+/// The result of an [`amortisation_schedule_query`] or [`amortisation_schedule_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct ScheduleResult {
+    pub schedule_id: i64,
+    pub kind: ScheduleKind,
+    pub description: String,
+    pub total: i64,
+    pub start_date: String,
+    pub periods: i64,
+    pub expense_account_id: i64,
+    pub balance_account_id: i64,
+    /// The total amortised so far.
+    pub amortised: i64,
+    /// The number of periods posted so far.
+    pub periods_posted: i64,
+}
+
+pub async fn amortisation_schedule_query(e: impl SqliteExecutor<'_>, schedule_id: i64)
+                                         -> Result<Option<ScheduleResult>, Error> {
+    sqlx::query_as!(ScheduleResult,
+        r#"SELECT id AS "schedule_id!", kind AS "kind!: ScheduleKind", description AS "description!",
+        total AS "total!", start_date AS "start_date!: String", periods AS "periods!",
+        expense_account_id AS "expense_account_id!", balance_account_id AS "balance_account_id!",
+        (SELECT IFNULL(SUM(amount), 0) FROM amortisation WHERE schedule_id = amortisation_schedule.id) AS "amortised!: i64",
+        (SELECT COUNT(*) FROM amortisation WHERE schedule_id = amortisation_schedule.id) AS "periods_posted!: i64"
+        FROM amortisation_schedule WHERE id=?;"#, schedule_id)
+        .fetch_optional(e)
+        .await
+}
+
+/// List amortisation schedules, optionally only those with periods still to post.
+pub async fn amortisation_schedule_list_query(e: impl SqliteExecutor<'_>, active: bool)
+                                              -> Result<Vec<ScheduleResult>, Error> {
+    sqlx::query_as!(ScheduleResult,
+        r#"SELECT id AS "schedule_id!", kind AS "kind!: ScheduleKind", description AS "description!",
+        total AS "total!", start_date AS "start_date!: String", periods AS "periods!",
+        expense_account_id AS "expense_account_id!", balance_account_id AS "balance_account_id!",
+        (SELECT IFNULL(SUM(amount), 0) FROM amortisation WHERE schedule_id = amortisation_schedule.id) AS "amortised!: i64",
+        (SELECT COUNT(*) FROM amortisation WHERE schedule_id = amortisation_schedule.id) AS "periods_posted!: i64"
+        FROM amortisation_schedule
+        WHERE ?1 = FALSE OR periods > (SELECT COUNT(*) FROM amortisation WHERE schedule_id = amortisation_schedule.id)
+        ORDER BY start_date, id;"#, active)
+        .fetch_all(e)
+        .await
+}
+
+/// The inner result of [`amortisation_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct AmortisationResult {
+    pub period: i64,
+    pub amount: i64,
+    pub journal_id: i64,
+}
+
+/// List the periods posted for an amortisation schedule.
+pub async fn amortisation_query(e: impl SqliteExecutor<'_>, schedule_id: i64) -> Result<Vec<AmortisationResult>, Error> {
+    sqlx::query_as!(AmortisationResult,
+        r#"SELECT period AS "period!", amount AS "amount!", journal_id AS "journal_id!"
+        FROM amortisation WHERE schedule_id=? ORDER BY period;"#, schedule_id)
+        .fetch_all(e)
+        .await
+}
+
+/// Post the batches of an amortisation run and record the periods each journal posts, in a single
+/// transaction. Returns the batch IDs in the order given.
+pub async fn amortisation_run_tx(db: &Pool<Sqlite>, batches: Vec<AmortisationBatch>) -> Result<Vec<i64>, Error> {
+    let mut transaction = db.begin().await?;
+    let mut batch_ids: Vec<i64> = Vec::new();
+    for batch in batches {
+        let (batch_id, journal_ids) = batch_insert(&mut transaction, &batch.metadata, batch.journals).await?;
+        for (charge, journal_id) in batch.charges.iter().zip(&journal_ids) {
+            sqlx::query!("INSERT INTO amortisation (schedule_id, period, amount, journal_id) VALUES (?, ?, ?, ?);",
+                charge.schedule_id, charge.period, charge.amount, journal_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        batch_ids.push(batch_id);
+    }
+    transaction.commit().await?;
+    Ok(batch_ids)
+}
+
+/// Insert a recurring journal and its entries in a single transaction.
+pub async fn recurring_journal_new_tx(db: &Pool<Sqlite>, recurring: &RecurringJournalData, actor: i64)
+                                      -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let start_date = recurring.start_date.to_string();
+    let end_date = recurring.end_date.map(|d| d.to_string());
+    let recurring_journal_id = sqlx::query!(
+        "INSERT INTO recurring_journal (unstructured_narrative, recurrence, day, start_date, end_date)
+        VALUES (?, ?, ?, ?, ?);",
+        recurring.unstructured_narrative, recurring.recurrence, recurring.day, start_date, end_date)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    for entry in &recurring.entries {
+        let tags = tags_json(&entry.tags);
+        sqlx::query!(
+            "INSERT INTO recurring_journal_entry (recurring_journal_id, account_id, amount, currency_amount, contact_id,
+            tax_code, tags) VALUES (?, ?, ?, ?, ?, ?, ?);",
+            recurring_journal_id, entry.account, entry.amount, entry.currency_amount, entry.contact, entry.tax_code,
+            tags)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::RecurringJournalNew,
+                    recurring_journal_id.to_string().as_str(), None, Some(json!(recurring))).await?;
+    transaction.commit().await?;
+    Ok(recurring_journal_id)
+}
+
+/// The result of a [`recurring_journal_query`] or [`recurring_journal_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct RecurringJournalResult {
+    pub recurring_journal_id: i64,
+    pub unstructured_narrative: String,
+    pub recurrence: Recurrence,
+    pub day: Option<i64>,
+    pub start_date: String,
+    pub end_date: Option<String>,
+    pub last_posted: Option<String>,
+}
+
+pub async fn recurring_journal_query(e: impl SqliteExecutor<'_>, recurring_journal_id: i64)
+                                     -> Result<Option<RecurringJournalResult>, Error> {
+    sqlx::query_as!(RecurringJournalResult,
+        r#"SELECT id AS "recurring_journal_id!", unstructured_narrative AS "unstructured_narrative!",
+        recurrence AS "recurrence!: Recurrence", day, start_date AS "start_date!: String", end_date AS "end_date: String",
+        (SELECT MAX(date) FROM recurring_journal_posting WHERE recurring_journal_id = recurring_journal.id)
+            AS "last_posted: String"
+        FROM recurring_journal WHERE id=?;"#, recurring_journal_id)
+        .fetch_optional(e)
+        .await
+}
+
+pub async fn recurring_journal_list_query(e: impl SqliteExecutor<'_>) -> Result<Vec<RecurringJournalResult>, Error> {
+    sqlx::query_as!(RecurringJournalResult,
+        r#"SELECT id AS "recurring_journal_id!", unstructured_narrative AS "unstructured_narrative!",
+        recurrence AS "recurrence!: Recurrence", day, start_date AS "start_date!: String", end_date AS "end_date: String",
+        (SELECT MAX(date) FROM recurring_journal_posting WHERE recurring_journal_id = recurring_journal.id)
+            AS "last_posted: String"
+        FROM recurring_journal ORDER BY id;"#)
+        .fetch_all(e)
+        .await
+}
+
+pub async fn recurring_journal_entry_query(e: impl SqliteExecutor<'_>, recurring_journal_id: i64)
+                                           -> Result<Vec<EntryData>, Error> {
+    Ok(sqlx::query_as!(EntryDataResult,
+        r#"SELECT account_id AS "account!", amount AS "amount!", currency_amount, contact_id AS contact, tax_code, tags
+        FROM recurring_journal_entry WHERE recurring_journal_id=? ORDER BY id;"#, recurring_journal_id)
+        .fetch_all(e)
+        .await?
+        .into_iter()
+        .map(EntryData::from)
+        .collect())
+}
+
+/// The inner result of [`recurring_journal_posting_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct RecurringPostingResult {
+    pub date: String,
+    pub journal_id: i64,
+}
+
+/// List the occurrences of a recurring journal that have been posted, by date.
+pub async fn recurring_journal_posting_query(e: impl SqliteExecutor<'_>, recurring_journal_id: i64)
+                                             -> Result<Vec<RecurringPostingResult>, Error> {
+    sqlx::query_as!(RecurringPostingResult,
+        r#"SELECT date AS "date!: String", journal_id AS "journal_id!"
+        FROM recurring_journal_posting WHERE recurring_journal_id=? ORDER BY date;"#, recurring_journal_id)
+        .fetch_all(e)
+        .await
+}
+
+/// Post a batch of recurring journals and record the occurrence each one posts, given in the same
+/// order, in a single transaction.
+pub async fn recurring_journal_post_tx(db: &Pool<Sqlite>, metadata: &BatchMetadata, journals: Vec<Journal>,
+                                       occurrences: &[RecurringOccurrence]) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let (batch_id, journal_ids) = batch_insert(&mut transaction, metadata, journals).await?;
+    for (occurrence, journal_id) in occurrences.iter().zip(&journal_ids) {
+        sqlx::query!(
+            "INSERT INTO recurring_journal_posting (recurring_journal_id, date, journal_id) VALUES (?, ?, ?);",
+            occurrence.recurring_journal_id, occurrence.date, journal_id)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(batch_id)
+}
+
+/// Insert a draft batch and its journals in a single transaction.
+pub async fn draft_new_tx(db: &Pool<Sqlite>, user: &str, draft: &DraftData, actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let date = draft.date.map(|d| d.to_string());
+    let draft_id = sqlx::query!(
+        "INSERT INTO draft_batch (date, state, created_by, created_by_user_id) VALUES (?, ?, ?, ?);",
+        date, DraftState::Draft, user, actor)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    for journal in &draft.journals {
+        let draft_journal_id = sqlx::query!(
+            "INSERT INTO draft_journal (draft_batch_id, unstructured_narrative) VALUES (?, ?);",
+            draft_id, journal.unstructured_narrative)
+            .execute(&mut *transaction)
+            .await?
+            .last_insert_rowid();
+        for entry in &journal.entries {
+            let tags = tags_json(&entry.tags);
+            sqlx::query!(
+                "INSERT INTO draft_entry (draft_journal_id, account_id, amount, currency_amount, contact_id, tax_code,
+                tags) VALUES (?, ?, ?, ?, ?, ?, ?);",
+                draft_journal_id, entry.account, entry.amount, entry.currency_amount, entry.contact, entry.tax_code,
+                tags)
+                .execute(&mut *transaction)
+                .await?;
+        }
+    }
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::DraftNew, draft_id.to_string().as_str(), None,
+                    Some(json!(draft))).await?;
+    transaction.commit().await?;
+    Ok(draft_id)
+}
+
+/// The result of a [`draft_query`] or [`draft_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct DraftResult {
+    pub draft_id: i64,
+    pub date: Option<String>,
+    pub state: DraftState,
+    pub created_by: String,
+    pub created_by_user_id: Option<i64>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_by_user_id: Option<i64>,
+    pub review_note: Option<String>,
+    pub batch_id: Option<i64>,
+    pub total: i64,
+}
+
+pub async fn draft_query(e: impl SqliteExecutor<'_>, draft_id: i64) -> Result<Option<DraftResult>, Error> {
+    sqlx::query_as!(DraftResult,
+        r#"SELECT id AS "draft_id!", date AS "date: String", state AS "state!: DraftState", created_by AS "created_by!",
+        created_by_user_id, reviewed_by, reviewed_by_user_id, review_note, batch_id,
+        (SELECT IFNULL(SUM(MAX(amount, 0)), 0) FROM draft_entry
+            JOIN draft_journal ON draft_journal.id = draft_entry.draft_journal_id
+            WHERE draft_journal.draft_batch_id = draft_batch.id) AS "total!: i64"
+        FROM draft_batch WHERE id=?;"#, draft_id)
+        .fetch_optional(e)
+        .await
+}
+
+/// List draft batches, optionally only those in a state.
+pub async fn draft_list_query(e: impl SqliteExecutor<'_>, state: Option<DraftState>) -> Result<Vec<DraftResult>, Error> {
+    sqlx::query_as!(DraftResult,
+        r#"SELECT id AS "draft_id!", date AS "date: String", state AS "state!: DraftState", created_by AS "created_by!",
+        created_by_user_id, reviewed_by, reviewed_by_user_id, review_note, batch_id,
+        (SELECT IFNULL(SUM(MAX(amount, 0)), 0) FROM draft_entry
+            JOIN draft_journal ON draft_journal.id = draft_entry.draft_journal_id
+            WHERE draft_journal.draft_batch_id = draft_batch.id) AS "total!: i64"
+        FROM draft_batch
+        WHERE ?1 IS NULL OR state = ?1
+        ORDER BY id;"#, state)
+        .fetch_all(e)
+        .await
+}
+
+/// List the journals of a draft batch with their entries.
+pub async fn draft_journal_query(e: &Pool<Sqlite>, draft_id: i64) -> Result<Vec<DraftJournal>, Error> {
+    let journals = sqlx::query!(
+        r#"SELECT id AS "draft_journal_id!", unstructured_narrative AS "unstructured_narrative!"
+        FROM draft_journal WHERE draft_batch_id=? ORDER BY id;"#, draft_id)
+        .fetch_all(e)
+        .await?;
+    let mut results: Vec<DraftJournal> = Vec::new();
+    for journal in journals {
+        let entries = sqlx::query_as!(EntryDataResult,
+            r#"SELECT account_id AS "account!", amount AS "amount!", currency_amount, contact_id AS contact, tax_code,
+            tags
+            FROM draft_entry WHERE draft_journal_id=? ORDER BY id;"#, journal.draft_journal_id)
+            .fetch_all(e)
+            .await?
+            .into_iter()
+            .map(EntryData::from)
+            .collect();
+        results.push(DraftJournal {
+            unstructured_narrative: journal.unstructured_narrative,
+            entries,
+        });
+    }
+    Ok(results)
+}
+
+/// Move a draft batch from one state to another using an existing connection or transaction.
+///
+/// Returns [`Error::RowNotFound`] if the draft is not in the `from` state.
+async fn draft_state_set(e: &mut SqliteConnection, draft_id: i64, from: DraftState, to: DraftState,
+                         reviewed_by: Option<&str>, review_note: Option<&str>, actor: i64) -> Result<(), Error> {
+    let updated = sqlx::query!(
+        "UPDATE draft_batch SET state=?1, reviewed_by=IFNULL(?2, reviewed_by),
+        reviewed_by_user_id=IIF(?2 IS NULL, reviewed_by_user_id, ?3), review_note=IFNULL(?4, review_note)
+        WHERE id=?5 AND state=?6;",
+        to, reviewed_by, actor, review_note, draft_id, from)
+        .execute(&mut *e)
+        .await?
+        .rows_affected();
+    if updated != 1 {
+        return Err(Error::RowNotFound);
+    }
+    let operation = match to {
+        DraftState::Approved => AuditOperation::DraftApprove,
+        DraftState::Rejected => AuditOperation::DraftReject,
+        _ => AuditOperation::DraftSubmit,
+    };
+    let after = json!({"state": to, "reviewed_by": reviewed_by, "review_note": review_note});
+    audit_event_new(e, Some(actor), operation, draft_id.to_string().as_str(), Some(json!({"state": from})),
+                    Some(after)).await
+}
+
+/// Move a draft batch from one state to another.
+///
+/// Returns [`Error::RowNotFound`] if the draft is not in the `from` state.
+pub async fn draft_state_set_tx(db: &Pool<Sqlite>, draft_id: i64, from: DraftState, to: DraftState,
+                                reviewed_by: Option<&str>, review_note: Option<&str>, actor: i64)
+                                -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    draft_state_set(&mut transaction, draft_id, from, to, reviewed_by, review_note, actor).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Mark a submitted draft batch as approved and post its journals, in a single transaction.
+///
+/// Returns [`Error::RowNotFound`] if the draft is not submitted.
+pub async fn draft_approve_tx(db: &Pool<Sqlite>, draft_id: i64, reviewed_by: &str, review_note: Option<&str>,
+                              metadata: &BatchMetadata, journals: Vec<Journal>) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    draft_state_set(&mut transaction, draft_id, DraftState::Submitted, DraftState::Approved, Some(reviewed_by),
+                    review_note, metadata.created_by.unwrap()).await?;
+    let (batch_id, _) = batch_insert(&mut transaction, metadata, journals).await?;
+    sqlx::query!("UPDATE draft_batch SET batch_id=? WHERE id=?;", batch_id, draft_id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(batch_id)
+}
+
+/// Insert a user. The actor is [`None`] when the server creates the first user.
+pub async fn user_new_tx(db: &Pool<Sqlite>, name: &str, role: Role, actor: Option<i64>) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let user_id = sqlx::query!("INSERT INTO user (name, role) VALUES (?, ?);", name, role)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, actor, AuditOperation::UserNew, user_id.to_string().as_str(), None,
+                    Some(json!({"name": name, "role": role}))).await?;
+    transaction.commit().await?;
+    Ok(user_id)
+}
+
+/// The result of a user query.
+#[derive(sqlx::FromRow, Debug)]
+pub struct UserResult {
+    pub user_id: i64,
+    pub name: String,
+    pub role: Role,
+}
+
+pub async fn user_query(e: impl SqliteExecutor<'_>, user_id: i64) -> Result<Option<UserResult>, Error> {
+    sqlx::query_as!(UserResult,
+        r#"SELECT id AS "user_id!", name AS "name!", role AS "role!: Role" FROM user WHERE id=?;"#, user_id)
+        .fetch_optional(e)
+        .await
+}
+
+pub async fn user_by_name_query(e: impl SqliteExecutor<'_>, name: &str) -> Result<Option<UserResult>, Error> {
+    sqlx::query_as!(UserResult,
+        r#"SELECT id AS "user_id!", name AS "name!", role AS "role!: Role" FROM user WHERE name=?;"#, name)
+        .fetch_optional(e)
+        .await
+}
+
+pub async fn user_list_query(e: impl SqliteExecutor<'_>) -> Result<Vec<UserResult>, Error> {
+    sqlx::query_as!(UserResult,
+        r#"SELECT id AS "user_id!", name AS "name!", role AS "role!: Role" FROM user ORDER BY id;"#)
+        .fetch_all(e)
+        .await
+}
+
+/// Find the user holding an API token that has not been revoked, given the token's hash.
+pub async fn user_by_token_query(e: impl SqliteExecutor<'_>, token_hash: &str) -> Result<Option<UserResult>, Error> {
+    sqlx::query_as!(UserResult,
+        r#"SELECT user.id AS "user_id!", user.name AS "name!", user.role AS "role!: Role"
+        FROM api_token
+        JOIN user ON user.id = api_token.user_id
+        WHERE api_token.token_hash=? AND NOT api_token.revoked;"#, token_hash)
+        .fetch_optional(e)
+        .await
+}
+
+/// Change a user's role.
+///
+/// Returns [`Error::RowNotFound`] if the user does not exist.
+pub async fn user_role_set_tx(db: &Pool<Sqlite>, user_id: i64, role: Role, actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let Some(before) = user_query(&mut *transaction, user_id).await? else {
+        return Err(Error::RowNotFound);
+    };
+    sqlx::query!("UPDATE user SET role=? WHERE id=?;", role, user_id)
+        .execute(&mut *transaction)
+        .await?;
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::UserRoleSet, user_id.to_string().as_str(),
+                    Some(json!({"role": before.role})), Some(json!({"role": role}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The number of users with a role.
+pub async fn user_role_count_query(e: impl SqliteExecutor<'_>, role: Role) -> Result<i64, Error> {
+    Ok(sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM user WHERE role=?;"#, role)
+        .fetch_one(e)
+        .await?
+        .count)
+}
+
+/// Insert an API token. The hash is not recorded in the audit log. The actor is [`None`] when the
+/// server creates the first user.
+pub async fn token_new_tx(db: &Pool<Sqlite>, user_id: i64, token_hash: &str, description: Option<&str>,
+                          actor: Option<i64>) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let token_id = sqlx::query!("INSERT INTO api_token (user_id, token_hash, description) VALUES (?, ?, ?);",
+        user_id, token_hash, description)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, actor, AuditOperation::TokenNew, token_id.to_string().as_str(), None,
+                    Some(json!({"user_id": user_id, "description": description}))).await?;
+    transaction.commit().await?;
+    Ok(token_id)
+}
+
+/// The user an API token belongs to, if the token exists.
+pub async fn token_user_query(e: impl SqliteExecutor<'_>, token_id: i64) -> Result<Option<i64>, Error> {
+    Ok(sqlx::query!("SELECT user_id FROM api_token WHERE id=?;", token_id)
+        .fetch_optional(e)
+        .await?
+        .map(|r| r.user_id))
+}
+
+/// Revoke an API token.
+///
+/// Returns [`Error::RowNotFound`] if the token does not exist.
+pub async fn token_revoke_tx(db: &Pool<Sqlite>, token_id: i64, actor: i64) -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let updated = sqlx::query!("UPDATE api_token SET revoked=TRUE WHERE id=?;", token_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if updated != 1 {
+        return Err(Error::RowNotFound);
+    }
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::TokenRevoke, token_id.to_string().as_str(), None,
+                    Some(json!({"revoked": true}))).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn entity_new_tx(db: &Pool<Sqlite>, slug: &str, database_url: &str, actor: i64) -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let entity_id = sqlx::query!("INSERT INTO entity (slug, database_url) VALUES (?, ?);", slug, database_url)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::EntityNew, slug, None,
+                    Some(json!({"database_url": database_url}))).await?;
+    transaction.commit().await?;
+    Ok(entity_id)
+}
+
+/// Register the server database as an entity when the server first starts.
+pub async fn entity_bootstrap(e: impl SqliteExecutor<'_>, slug: &str, database_url: &str) -> Result<(), Error> {
+    sqlx::query!("INSERT INTO entity (slug, database_url) VALUES (?, ?);", slug, database_url)
+        .execute(e)
+        .await?;
+    Ok(())
+}
+
+/// The result of an entity query.
+#[derive(sqlx::FromRow, Debug)]
+pub struct EntityResult {
+    pub entity_id: i64,
+    pub slug: String,
+    pub database_url: String,
+}
+
+pub async fn entity_by_slug_query(e: impl SqliteExecutor<'_>, slug: &str) -> Result<Option<EntityResult>, Error> {
+    sqlx::query_as!(EntityResult,
+        r#"SELECT id AS "entity_id!", slug AS "slug!", database_url AS "database_url!" FROM entity WHERE slug=?;"#,
+        slug)
+        .fetch_optional(e)
+        .await
+}
+
+/// List entities, optionally only those granted to a user.
+pub async fn entity_list_query(e: impl SqliteExecutor<'_>, user_id: Option<i64>) -> Result<Vec<EntityResult>, Error> {
+    sqlx::query_as!(EntityResult,
+        r#"SELECT id AS "entity_id!", slug AS "slug!", database_url AS "database_url!" FROM entity
+        WHERE ?1 IS NULL OR id IN (SELECT entity_id FROM entity_grant WHERE user_id = ?1)
+        ORDER BY slug;"#, user_id)
+        .fetch_all(e)
+        .await
+}
+
+/// The users granted access to an entity.
+pub async fn entity_grant_query(e: impl SqliteExecutor<'_>, entity_id: i64) -> Result<Vec<i64>, Error> {
+    Ok(sqlx::query!("SELECT user_id FROM entity_grant WHERE entity_id=? ORDER BY user_id;", entity_id)
+        .fetch_all(e)
+        .await?
+        .into_iter()
+        .map(|r| r.user_id)
+        .collect())
+}
+
+/// Grant a user access to an entity. Granting access twice has no effect.
+pub async fn entity_grant_tx(db: &Pool<Sqlite>, entity_id: i64, slug: &str, user_id: i64, actor: i64)
+                             -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let inserted = sqlx::query!("INSERT OR IGNORE INTO entity_grant (entity_id, user_id) VALUES (?, ?);",
+        entity_id, user_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if inserted == 1 {
+        audit_event_new(&mut transaction, Some(actor), AuditOperation::EntityGrant, slug, None,
+                        Some(json!({"user_id": user_id}))).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Revoke a user's access to an entity.
+///
+/// Returns [`Error::RowNotFound`] if the user had not been granted access.
+pub async fn entity_revoke_tx(db: &Pool<Sqlite>, entity_id: i64, slug: &str, user_id: i64, actor: i64)
+                              -> Result<(), Error> {
+    let mut transaction = db.begin().await?;
+    let deleted = sqlx::query!("DELETE FROM entity_grant WHERE entity_id=? AND user_id=?;", entity_id, user_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    if deleted != 1 {
+        return Err(Error::RowNotFound);
+    }
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::EntityRevoke, slug,
+                    Some(json!({"user_id": user_id})), None).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The next reference to use for an intercompany journal that was not given one.
+pub async fn intercompany_journal_next_id_query(e: impl SqliteExecutor<'_>) -> Result<i64, Error> {
+    Ok(sqlx::query!(r#"SELECT IFNULL(MAX(id), 0) + 1 AS "next_id!: i64" FROM intercompany_journal;"#)
+        .fetch_one(e)
+        .await?
+        .next_id)
+}
+
+/// Whether an intercompany journal has already been recorded with a reference.
+pub async fn intercompany_reference_query(e: impl SqliteExecutor<'_>, reference: &str) -> Result<bool, Error> {
+    Ok(sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM intercompany_journal WHERE reference=?) AS "exists!: bool";"#, reference)
+        .fetch_one(e)
+        .await?
+        .exists)
+}
+
+/// Record an intercompany journal once it has been posted in the books of both entities.
+pub async fn intercompany_journal_new_tx(db: &Pool<Sqlite>, journal: &IntercompanyJournalRecord, actor: i64)
+                                         -> Result<i64, Error> {
+    let mut transaction = db.begin().await?;
+    let intercompany_journal_id = sqlx::query!(
+        "INSERT INTO intercompany_journal (reference, date, amount, from_entity_id, from_journal_id, to_entity_id,
+        to_journal_id, created_by)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?);",
+        journal.reference, journal.date, journal.amount, journal.from_entity_id, journal.from_journal_id,
+        journal.to_entity_id, journal.to_journal_id, actor)
+        .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+    audit_event_new(&mut transaction, Some(actor), AuditOperation::IntercompanyJournalNew, journal.reference.as_str(),
+                    None, Some(json!(journal))).await?;
+    transaction.commit().await?;
+    Ok(intercompany_journal_id)
+}
+
+/// The result of an [`intercompany_journal_list_query`].
+#[derive(sqlx::FromRow, Debug)]
+pub struct IntercompanyJournalResult {
+    pub intercompany_journal_id: i64,
+    pub reference: String,
+    pub date: String,
+    pub amount: i64,
+    pub from_entity: String,
+    pub from_journal_id: i64,
+    pub to_entity: String,
+    pub to_journal_id: i64,
+    pub created_by: i64,
+}
+
+/// List the intercompany journals dated between `from` and `to` inclusive, either of which may be
+/// [`None`] for no limit.
+pub async fn intercompany_journal_list_query(e: impl SqliteExecutor<'_>, from: Option<&str>, to: Option<&str>)
+                                             -> Result<Vec<IntercompanyJournalResult>, Error> {
+    sqlx::query_as!(IntercompanyJournalResult,
+        r#"SELECT intercompany_journal.id AS "intercompany_journal_id!", reference, date AS "date!: String", amount,
+        f.slug AS from_entity, from_journal_id, t.slug AS to_entity, to_journal_id, created_by
+        FROM intercompany_journal
+            JOIN entity f ON intercompany_journal.from_entity_id = f.id
+            JOIN entity t ON intercompany_journal.to_entity_id = t.id
+        WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+        ORDER BY date, intercompany_journal.id;"#, from, to)
+        .fetch_all(e)
+        .await
+}
+
+/// Record an audit event using the connection or transaction of the change it records, so that the
+/// event is written if and only if the change is.
+pub async fn audit_event_new(e: &mut SqliteConnection, user_id: Option<i64>, operation: AuditOperation, subject: &str,
+                             before: Option<Value>, after: Option<Value>) -> Result<(), Error> {
+    let before = before.map(|v| v.to_string());
+    let after = after.map(|v| v.to_string());
+    sqlx::query!("INSERT INTO audit_event (user_id, operation, subject, before, after) VALUES (?, ?, ?, ?, ?);",
+        user_id, operation, subject, before, after)
+        .execute(&mut *e)
+        .await?;
+    Ok(())
+}
+
+/// The result of an [`audit_event_query`]. The before and after values are JSON.
+#[derive(sqlx::FromRow, Debug)]
+pub struct AuditEventResult {
+    pub audit_event_id: i64,
+    pub user_id: Option<i64>,
+    pub created_at: String,
+    pub operation: AuditOperation,
+    pub subject: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Find the most recent audit events matching a filter, up to `limit` of them.
+pub async fn audit_event_query(e: impl SqliteExecutor<'_>, filter: &AuditFilter, limit: i64)
+                               -> Result<Vec<AuditEventResult>, Error> {
+    let from = filter.from.map(|d| d.to_string());
+    let to = filter.to.map(|d| d.to_string());
+    sqlx::query_as!(AuditEventResult,
+        r#"SELECT id AS "audit_event_id!", user_id, created_at AS "created_at!: String",
+        operation AS "operation!: AuditOperation", subject, before, after
+        FROM audit_event
+        WHERE (?1 IS NULL OR user_id = ?1) AND (?2 IS NULL OR operation = ?2) AND (?3 IS NULL OR subject = ?3)
+            AND (?4 IS NULL OR DATE(created_at) >= ?4) AND (?5 IS NULL OR DATE(created_at) <= ?5)
+        ORDER BY id DESC
+        LIMIT ?6;"#, filter.user_id, filter.operation, filter.subject, from, to, limit)
+        .fetch_all(e)
+        .await
+}
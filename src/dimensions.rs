@@ -0,0 +1,235 @@
+//! Analytical dimensions, such as project, department or cost centre.
+//!
+//! Each journal entry may be tagged with one value of any number of dimensions, so that the ledger
+//! can be reported by project or department without an account for each. The dimensions and the
+//! values allowed for each are configured in advance, and every batch is checked against them
+//! before it is posted.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::db;
+use crate::error::Error::InstructionError;
+use crate::users::{Role, User};
+
+/// The tags of a journal entry: the value of each dimension the entry is tagged with.
+pub type Tags = BTreeMap<String, String>;
+
+/// A value of a dimension, used to filter reports.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Tag {
+    pub dimension: String,
+    pub value: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DimensionValue {
+    pub value: String,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Dimension {
+    pub name: String,
+    pub description: Option<String>,
+    pub values: Vec<DimensionValue>,
+}
+
+fn name_check(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!(InstructionError(format!("{kind} must be 1-32 letters, digits, hyphens or underscores")));
+    }
+    Ok(())
+}
+
+fn description_check(description: Option<&str>) -> Result<()> {
+    if description.is_some_and(|d| d.len() > 140) {
+        bail!(InstructionError("description over 140 chars".to_string()));
+    }
+    Ok(())
+}
+
+/// List the dimensions with their values.
+pub async fn dimension_list(e: &Pool<Sqlite>) -> Result<Vec<Dimension>> {
+    let mut values: BTreeMap<String, Vec<DimensionValue>> = BTreeMap::new();
+    for r in db::dimension_value_query(e, None).await? {
+        values.entry(r.dimension).or_default().push(DimensionValue {
+            value: r.value,
+            description: r.description,
+        });
+    }
+    Ok(db::dimension_list_query(e).await?
+        .into_iter()
+        .map(|r| Dimension {
+            values: values.remove(&r.name).unwrap_or_default(),
+            name: r.name,
+            description: r.description,
+        })
+        .collect())
+}
+
+/// The values of a dimension, failing if the dimension does not exist.
+pub async fn dimension_values(e: &Pool<Sqlite>, dimension: &str) -> Result<Vec<String>> {
+    if !db::dimension_list_query(e).await?.iter().any(|d| d.name == dimension) {
+        bail!(InstructionError(format!("dimension {dimension} does not exist")));
+    }
+    Ok(db::dimension_value_query(e, Some(dimension)).await?
+        .into_iter()
+        .map(|r| r.value)
+        .collect())
+}
+
+pub async fn dimension_new(e: &Pool<Sqlite>, name: &str, description: Option<&str>, user: &User) -> Result<()> {
+    user.require(Role::Admin)?;
+    name_check("dimension name", name)?;
+    description_check(description)?;
+    if db::dimension_list_query(e).await?.iter().any(|d| d.name == name) {
+        bail!(InstructionError(format!("dimension {name} already exists")));
+    }
+    Ok(db::dimension_new_tx(e, name, description, user.user_id).await?)
+}
+
+/// Add a value to the list of values allowed for a dimension.
+pub async fn dimension_value_new(e: &Pool<Sqlite>, dimension: &str, value: &str, description: Option<&str>,
+                                 user: &User) -> Result<()> {
+    user.require(Role::Bookkeeper)?;
+    name_check("dimension value", value)?;
+    description_check(description)?;
+    if dimension_values(e, dimension).await?.iter().any(|v| v == value) {
+        bail!(InstructionError(format!("{dimension} already has the value {value}")));
+    }
+    Ok(db::dimension_value_new_tx(e, dimension, value, description, user.user_id).await?)
+}
+
+/// Check that entries are tagged only with values configured for their dimensions.
+pub async fn tags_validate<'a>(e: impl SqliteExecutor<'_>, tags: impl Iterator<Item=&'a Tags>) -> Result<()> {
+    let tags: Vec<&Tags> = tags.filter(|t| !t.is_empty()).collect();
+    if tags.is_empty() {
+        return Ok(());
+    }
+    let values: Vec<(String, String)> = db::dimension_value_query(e, None).await?
+        .into_iter()
+        .map(|r| (r.dimension, r.value))
+        .collect();
+    for (dimension, value) in tags.into_iter().flatten() {
+        if !values.iter().any(|(d, v)| d == dimension && v == value) {
+            bail!(InstructionError(format!("{value} is not a value of the dimension {dimension}")));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::{entities, ledger};
+    use crate::error::Error;
+    use crate::ledger::{AccountSummary, BatchMetadata, Journal, JournalEntry};
+
+    fn user(role: Role) -> User {
+        User {
+            user_id: 1,
+            name: "user".to_string(),
+            role,
+        }
+    }
+
+    fn is_instruction_error(result: Result<()>) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs.iter().map(|(d, v)| (d.to_string(), v.to_string())).collect()
+    }
+
+    /// Configure the `project` dimension with the values `alpha` and `beta`.
+    async fn projects(e: &Pool<Sqlite>) {
+        dimension_new(e, "project", Some("Client projects"), &user(Role::Admin)).await.unwrap();
+        for value in ["alpha", "beta"] {
+            dimension_value_new(e, "project", value, None, &user(Role::Bookkeeper)).await.unwrap();
+        }
+    }
+
+    fn entry(account: i64, amount: i64, tags: Tags) -> JournalEntry {
+        JournalEntry {
+            account,
+            amount,
+            tags,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn dimensions_are_configured_before_use() {
+        let db = entities::memory_connect().await;
+        let permission_error = dimension_new(&db, "project", None, &user(Role::Bookkeeper)).await.unwrap_err();
+        assert!(matches!(permission_error.downcast_ref::<Error>(), Some(Error::PermissionError(_))));
+        projects(&db).await;
+        assert!(is_instruction_error(dimension_new(&db, "project", None, &user(Role::Admin)).await));
+        assert!(is_instruction_error(dimension_new(&db, "cost centre", None, &user(Role::Admin)).await));
+        assert!(is_instruction_error(dimension_value_new(&db, "project", "alpha", None, &user(Role::Bookkeeper))
+            .await));
+        assert!(is_instruction_error(dimension_value_new(&db, "department", "sales", None, &user(Role::Bookkeeper))
+            .await));
+
+        let dimensions = dimension_list(&db).await.unwrap();
+        assert_eq!(dimensions.len(), 1);
+        let values: Vec<&str> = dimensions[0].values.iter().map(|v| v.value.as_str()).collect();
+        assert_eq!(values, vec!["alpha", "beta"]);
+    }
+
+    #[actix_web::test]
+    async fn tags_validate_accepts_only_configured_values() {
+        let db = entities::memory_connect().await;
+        projects(&db).await;
+        let valid = [tags(&[]), tags(&[("project", "alpha")]), tags(&[("project", "beta")])];
+        tags_validate(&db, valid.iter()).await.unwrap();
+        assert!(is_instruction_error(tags_validate(&db, [tags(&[("project", "gamma")])].iter()).await));
+        assert!(is_instruction_error(tags_validate(&db, [tags(&[("department", "alpha")])].iter()).await));
+    }
+
+    #[actix_web::test]
+    async fn account_list_filters_balances_by_tag() {
+        let db = entities::memory_connect().await;
+        projects(&db).await;
+        let journal = Journal {
+            unstructured_narrative: "Travel".to_string(),
+            entries: vec![
+                entry(550, 1000, tags(&[("project", "alpha")])),
+                entry(550, 300, tags(&[("project", "beta")])),
+                entry(550, 200, tags(&[])),
+                entry(100, -1500, tags(&[])),
+            ],
+        };
+        let metadata = BatchMetadata {
+            date: Some("2024-04-01".to_string()),
+            ..Default::default()
+        };
+        ledger::batch_new(&db, &metadata, vec![journal]).await.unwrap();
+
+        let balance = |accounts: Vec<AccountSummary>, account_id: i64| accounts
+            .into_iter()
+            .find(|a| a.account_id == account_id)
+            .unwrap()
+            .account_balance;
+        let as_at = NaiveDate::from_ymd_opt(2024, 4, 30).unwrap();
+        let alpha = Tag {
+            dimension: "project".to_string(),
+            value: "alpha".to_string(),
+        };
+        assert_eq!(balance(ledger::account_list(&db, as_at, Some(&alpha)).await.unwrap(), 550), 1000);
+        assert_eq!(balance(ledger::account_list(&db, as_at, Some(&alpha)).await.unwrap(), 100), 0);
+        assert_eq!(balance(ledger::account_list(&db, as_at, None).await.unwrap(), 550), 1500);
+
+        let journal = Journal {
+            unstructured_narrative: "Travel".to_string(),
+            entries: vec![entry(550, 100, tags(&[("project", "gamma")])), entry(100, -100, tags(&[]))],
+        };
+        assert!(ledger::batch_new(&db, &metadata, vec![journal]).await.is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Rendering of documents sent to contacts, such as invoices and statements.
+//!
+//! Each document has an HTML template and a PDF layout template, both rendered with Tera. The PDF
+//! layout is plain text in the format described in [`crate::pdf`]. The entity details shown on
+//! documents are taken from the `entityName`, `entityAddress`, `entityRegisteredNumber`,
+//! `entityVatNumber` and `entityEmail` settings.
+
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+use tera::{Context, Tera, Value};
+
+use crate::{contacts, invoicing, pdf, settings, statements};
+use crate::error::Error::InstructionError;
+use crate::statements::StatementSubject;
+use crate::users::{Role, User};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    #[default]
+    Html,
+    Pdf,
+}
+
+pub struct RenderedDocument {
+    pub content_type: &'static str,
+    pub filename: String,
+    pub body: Vec<u8>,
+}
+
+/// The entity details shown on documents.
+#[derive(Clone, Serialize)]
+pub struct Entity {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    pub registered_number: Option<String>,
+    pub vat_number: Option<String>,
+    pub email: Option<String>,
+}
+
+pub async fn entity(e: &Pool<Sqlite>) -> Entity {
+    Entity {
+        name: settings::get_settings_str(e, "entityName").await,
+        address: settings::get_settings_str(e, "entityAddress").await,
+        registered_number: settings::get_settings_str(e, "entityRegisteredNumber").await,
+        vat_number: settings::get_settings_str(e, "entityVatNumber").await,
+        email: settings::get_settings_str(e, "entityEmail").await,
+    }
+}
+
+/// Register the filters used by document templates.
+///
+/// * `money` formats an amount in minor currency units with two decimal places and thousands
+///   separators.
+pub fn register_filters(tera: &mut Tera) {
+    tera.register_filter("money", money);
+}
+
+fn money(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let amount = value.as_i64().ok_or_else(|| tera::Error::msg("money filter expects an integer"))?;
+    Ok(Value::String(money_format(amount)))
+}
+
+pub fn money_format(amount: i64) -> String {
+    let units = (amount.unsigned_abs() / 100).to_string();
+    let mut grouped = String::new();
+    for (i, c) in units.chars().enumerate() {
+        if i > 0 && (units.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{sign}{grouped}.{:02}", amount.unsigned_abs() % 100)
+}
+
+/// Render a template in the given format.
+///
+/// `name` is the template name without an extension: the HTML template is `{name}.html` and the
+/// PDF layout template is `{name}.pdf.txt`.
+pub fn render(tmpl: &Tera, name: &str, ctx: &Context, format: DocumentFormat, title: &str, filename: &str)
+              -> Result<RenderedDocument> {
+    Ok(match format {
+        DocumentFormat::Html => RenderedDocument {
+            content_type: "text/html; charset=utf-8",
+            filename: format!("{filename}.html"),
+            body: tmpl.render(format!("{name}.html").as_str(), ctx)?.into_bytes(),
+        },
+        DocumentFormat::Pdf => RenderedDocument {
+            content_type: "application/pdf",
+            filename: format!("{filename}.pdf"),
+            body: pdf::render(tmpl.render(format!("{name}.pdf.txt").as_str(), ctx)?.as_str(), title),
+        },
+    })
+}
+
+async fn base_currency(e: impl SqliteExecutor<'_>) -> Option<String> {
+    settings::get_settings_str(e, "baseCurrency").await
+}
+
+/// Render an invoice or credit note.
+pub async fn invoice_render(e: &Pool<Sqlite>, tmpl: &Tera, invoice_id: i64, format: DocumentFormat)
+                            -> Result<RenderedDocument> {
+    let invoice = invoicing::invoice_detail(e, invoice_id).await?;
+    let contact = contacts::contact_detail(e, invoice.contact_id).await?;
+    let mut ctx = Context::new();
+    ctx.insert("entity", &entity(e).await);
+    ctx.insert("currency", &base_currency(e).await);
+    ctx.insert("footer", &settings::get_settings_str(e, "invoiceFooter").await);
+    ctx.insert("contact", &contact);
+    ctx.insert("invoice", &invoice);
+    let number = invoice.number.clone().unwrap_or_else(|| format!("draft-{invoice_id}"));
+    let title = format!("{} {number}", invoice.kind.title());
+    render(tmpl, invoice.kind.template_name(), &ctx, format, title.as_str(), number.as_str())
+}
+
+async fn statement_context(e: &Pool<Sqlite>, statement: &statements::Statement) -> Context {
+    let mut ctx = Context::new();
+    ctx.insert("entity", &entity(e).await);
+    ctx.insert("currency", &base_currency(e).await);
+    ctx.insert("statement", statement);
+    ctx
+}
+
+/// Render the statement for a contact or account.
+pub async fn statement_render(e: &Pool<Sqlite>, tmpl: &Tera, subject: StatementSubject, from: NaiveDate,
+                              to: NaiveDate, format: DocumentFormat) -> Result<RenderedDocument> {
+    let statement = statements::statement(e, subject, from, to).await?;
+    let ctx = statement_context(e, &statement).await;
+    let title = format!("Statement {}", statement.name);
+    render(tmpl, "statement", &ctx, format, title.as_str(), subject.filename(statement.to.as_str()).as_str())
+}
+
+/// Check that a generated file name is a single path component, so that the file is written in
+/// the directory it is joined to.
+fn filename_check(filename: &str) -> Result<()> {
+    let mut components = Path::new(filename).components();
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+        bail!(InstructionError(format!("{filename} is not a file name")));
+    }
+    Ok(())
+}
+
+/// Render the statement for each contact or account that has a balance or transactions, and
+/// write them to files in `directory`, returning the paths written.
+#[allow(clippy::too_many_arguments)]
+pub async fn statements_write(e: &Pool<Sqlite>, tmpl: &Tera, subjects: Vec<StatementSubject>, from: NaiveDate,
+                              to: NaiveDate, format: DocumentFormat, directory: &Path, user: &User)
+                              -> Result<Vec<String>> {
+    user.require(Role::Bookkeeper)?;
+    std::fs::create_dir_all(directory)?;
+    let mut paths = Vec::new();
+    for subject in subjects {
+        let statement = statements::statement(e, subject, from, to).await?;
+        if statement.is_empty() {
+            continue;
+        }
+        let ctx = statement_context(e, &statement).await;
+        let title = format!("Statement {}", statement.name);
+        let document = render(tmpl, "statement", &ctx, format, title.as_str(),
+                              subject.filename(statement.to.as_str()).as_str())?;
+        filename_check(document.filename.as_str())?;
+        let path = directory.join(document.filename);
+        std::fs::write(&path, document.body)?;
+        paths.push(path.display().to_string());
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filename_check_rejects_path_components() {
+        filename_check("statement-contact-1-2024-12-31.pdf").unwrap();
+        for filename in ["../statement.pdf", "statements/statement.pdf", "/tmp/statement.pdf", "..", ""] {
+            assert!(filename_check(filename).is_err(), "{filename}");
+        }
+    }
+}
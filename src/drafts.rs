@@ -0,0 +1,327 @@
+//! Draft batches and maker-checker approval.
+//!
+//! A draft batch holds journals that have not been posted: they are stored apart from the ledger,
+//! so they have no effect on balances. The user who created a draft submits it for review, and a
+//! different user approves or rejects it. Only on approval is the batch posted to the ledger.
+//!
+//! Journals entered directly whose debits total more than the `journalApprovalThreshold` setting,
+//! if it is set, must be posted this way, and [`ledger::journal_new`] refuses them. Journals
+//! generated from documents and runs, such as invoices, bills and depreciation, are not checked,
+//! as they have no draft to be posted through.
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, ledger, settings, tax};
+use crate::error::Error::InstructionError;
+use crate::ledger::{BatchMetadata, EntryData, Journal, JournalEntry};
+use crate::users::{Role, User};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum DraftState {
+    /// Being prepared, and not yet ready for review.
+    Draft,
+    /// Awaiting approval by a different user.
+    Submitted,
+    /// Approved and posted to the ledger.
+    Approved,
+    /// Rejected, and will not be posted.
+    Rejected,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DraftJournal {
+    pub unstructured_narrative: String,
+    /// The entries, which balance once any tax has been added as for `POST /journal/new`.
+    pub entries: Vec<EntryData>,
+}
+
+/// The details of a draft batch, as given when the draft is created.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DraftData {
+    /// The date to post the batch on. Defaults to the date it is approved.
+    pub date: Option<NaiveDate>,
+    pub journals: Vec<DraftJournal>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Draft {
+    pub draft_id: i64,
+    pub date: Option<String>,
+    pub state: DraftState,
+    pub created_by: String,
+    /// The ID of the user who created the draft. Only missing for drafts created before user IDs were
+    /// recorded by users who no longer exist.
+    pub created_by_user_id: Option<i64>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_by_user_id: Option<i64>,
+    pub review_note: Option<String>,
+    /// The batch posted when the draft was approved.
+    pub batch_id: Option<i64>,
+    /// The total of the debits in the draft, before tax.
+    pub total: i64,
+    /// The journals. Only filled in by [`draft_detail`].
+    pub journals: Vec<DraftJournal>,
+}
+
+impl Draft {
+    /// Whether the draft was created by the user, by user ID if it was recorded.
+    fn created_by(&self, user: &User) -> bool {
+        match self.created_by_user_id {
+            Some(user_id) => user_id == user.user_id,
+            None => self.created_by == user.name,
+        }
+    }
+}
+
+impl From<db::DraftResult> for Draft {
+    fn from(r: db::DraftResult) -> Self {
+        Draft {
+            draft_id: r.draft_id,
+            date: r.date,
+            state: r.state,
+            created_by: r.created_by,
+            created_by_user_id: r.created_by_user_id,
+            reviewed_by: r.reviewed_by,
+            reviewed_by_user_id: r.reviewed_by_user_id,
+            review_note: r.review_note,
+            batch_id: r.batch_id,
+            total: r.total,
+            journals: Vec::new(),
+        }
+    }
+}
+
+/// The journals of a draft with tax added at the rates effective on `date`.
+async fn draft_journals(e: &Pool<Sqlite>, journals: &[DraftJournal], date: &str) -> Result<Vec<Journal>> {
+    let mut journals: Vec<Journal> = journals
+        .iter()
+        .map(|j| Journal {
+            unstructured_narrative: j.unstructured_narrative.clone(),
+            entries: j.entries.iter().map(JournalEntry::from).collect(),
+        })
+        .collect();
+    tax::journals_tax(e, &mut journals, date).await?;
+    ledger::journals_validate(e, &journals).await?;
+    Ok(journals)
+}
+
+/// Check that no journal needs approval because of the `journalApprovalThreshold` setting.
+pub async fn threshold_check<'a>(e: impl SqliteExecutor<'_>,
+                                 journals: impl IntoIterator<Item = &'a Journal>) -> Result<()> {
+    if let Some(threshold) = settings::get_settings_int(e, "journalApprovalThreshold").await {
+        for journal in journals {
+            let total: i64 = journal.entries.iter().map(|e| e.amount.max(0)).sum();
+            if total > threshold {
+                bail!(InstructionError(format!("journal {} of {total} is over the approval threshold of {threshold} \
+                    and must be posted as a draft", journal.unstructured_narrative)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create a draft batch, returning the draft ID.
+pub async fn draft_new(e: &Pool<Sqlite>, user: &User, draft: &DraftData) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    if draft.journals.is_empty() {
+        bail!(InstructionError("draft has no journals".to_string()));
+    }
+    let date = draft.date.unwrap_or_else(|| chrono::Utc::now().date_naive()).to_string();
+    draft_journals(e, &draft.journals, date.as_str()).await?;
+    Ok(db::draft_new_tx(e, user.name.as_str(), draft, user.user_id).await?)
+}
+
+pub async fn draft_detail(e: &Pool<Sqlite>, draft_id: i64) -> Result<Draft> {
+    let Some(result) = db::draft_query(e, draft_id).await? else {
+        bail!(InstructionError(format!("draft {draft_id} does not exist")));
+    };
+    let mut draft = Draft::from(result);
+    draft.journals = db::draft_journal_query(e, draft_id).await?;
+    Ok(draft)
+}
+
+pub async fn draft_list(e: impl SqliteExecutor<'_>, state: Option<DraftState>) -> Result<Vec<Draft>> {
+    Ok(db::draft_list_query(e, state).await?
+        .into_iter()
+        .map(Draft::from)
+        .collect())
+}
+
+/// Move a draft from one state to another, recording the reviewer if there is one.
+async fn draft_transition(e: &Pool<Sqlite>, draft_id: i64, from: DraftState, to: DraftState,
+                          reviewed_by: Option<&str>, note: Option<&str>, user: &User) -> Result<()> {
+    match db::draft_state_set_tx(e, draft_id, from, to, reviewed_by, note, user.user_id).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("draft {draft_id} is not {from:?}"))),
+        result => Ok(result?),
+    }
+}
+
+/// Submit a draft for approval. Only the user who created it may submit it.
+pub async fn draft_submit(e: &Pool<Sqlite>, draft_id: i64, user: &User) -> Result<Draft> {
+    user.require(Role::Bookkeeper)?;
+    let draft = draft_detail(e, draft_id).await?;
+    if !draft.created_by(user) {
+        bail!(InstructionError(format!("draft {draft_id} can only be submitted by {}", draft.created_by)));
+    }
+    draft_transition(e, draft_id, DraftState::Draft, DraftState::Submitted, None, None, user).await?;
+    draft_detail(e, draft_id).await
+}
+
+/// Approve a submitted draft and post it to the ledger. The approver must not be the user who
+/// created the draft.
+pub async fn draft_approve(e: &Pool<Sqlite>, draft_id: i64, user: &User, note: Option<&str>) -> Result<Draft> {
+    user.require(Role::Approver)?;
+    let draft = draft_detail(e, draft_id).await?;
+    review_check(&draft, user, note)?;
+    let date = draft.date.clone().unwrap_or_else(|| chrono::Utc::now().date_naive().to_string());
+    let journals = draft_journals(e, &draft.journals, date.as_str()).await?;
+    let metadata = BatchMetadata {
+        date: Some(date),
+        generated_by: None,
+        created_by: Some(user.user_id),
+    };
+    ledger::batch_validate(e, &metadata, &journals).await?;
+    match db::draft_approve_tx(e, draft_id, user.name.as_str(), note, &metadata, journals).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("draft {draft_id} is not submitted"))),
+        result => result?,
+    };
+    draft_detail(e, draft_id).await
+}
+
+/// Reject a submitted draft. The reviewer must not be the user who created the draft.
+pub async fn draft_reject(e: &Pool<Sqlite>, draft_id: i64, user: &User, note: Option<&str>) -> Result<Draft> {
+    user.require(Role::Approver)?;
+    let draft = draft_detail(e, draft_id).await?;
+    review_check(&draft, user, note)?;
+    draft_transition(e, draft_id, DraftState::Submitted, DraftState::Rejected, Some(user.name.as_str()), note, user)
+        .await?;
+    draft_detail(e, draft_id).await
+}
+
+fn review_check(draft: &Draft, user: &User, note: Option<&str>) -> Result<()> {
+    if draft.state != DraftState::Submitted {
+        bail!(InstructionError(format!("draft {} is not submitted", draft.draft_id)));
+    }
+    if draft.created_by(user) {
+        bail!(InstructionError("a draft must be reviewed by a different user to the one who created it".to_string()));
+    }
+    if note.is_some_and(|n| n.len() > 140) {
+        bail!(InstructionError("review note over 140 chars".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+    use crate::ledger::AccountType;
+    use crate::recurring::{self, Recurrence, RecurringJournalData};
+
+    fn user(user_id: i64, name: &str, role: Role) -> User {
+        User {
+            user_id,
+            name: name.to_string(),
+            role,
+        }
+    }
+
+    fn entry(account: i64, amount: i64) -> EntryData {
+        EntryData {
+            account,
+            amount,
+            currency_amount: None,
+            contact: None,
+            tax_code: None,
+            tags: Default::default(),
+        }
+    }
+
+    async fn expense_account(db: &Pool<Sqlite>) -> i64 {
+        ledger::account_new(db, Some(500), &"Rent".to_string(), &AccountType::IndirectExpense, None, None,
+                            &user(1, "admin", Role::Admin))
+            .await
+            .unwrap()
+    }
+
+    async fn threshold_set(db: &Pool<Sqlite>, threshold: i64) {
+        let mut connection = db.acquire().await.unwrap();
+        settings::set_settings_int(&mut connection, "journalApprovalThreshold", threshold, None).await;
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    #[actix_web::test]
+    async fn draft_review_compares_user_ids_not_names() {
+        let db = entities::memory_connect().await;
+        let rent = expense_account(&db).await;
+        let maker = user(2, "sam", Role::Approver);
+        let draft = DraftData {
+            date: None,
+            journals: vec![DraftJournal {
+                unstructured_narrative: "Rent".to_string(),
+                entries: vec![entry(rent, 1000), entry(100, -1000)],
+            }],
+        };
+        let draft_id = draft_new(&db, &maker, &draft).await.unwrap();
+        draft_submit(&db, draft_id, &maker).await.unwrap();
+
+        let renamed_maker = user(2, "samantha", Role::Approver);
+        assert!(is_instruction_error(draft_approve(&db, draft_id, &renamed_maker, None).await));
+
+        let checker = user(3, "sam", Role::Approver);
+        let approved = draft_approve(&db, draft_id, &checker, None).await.unwrap();
+        assert_eq!(approved.state, DraftState::Approved);
+        assert_eq!(approved.reviewed_by_user_id, Some(3));
+    }
+
+    #[actix_web::test]
+    async fn journal_new_refuses_journals_over_threshold_that_drafts_can_post() {
+        let db = entities::memory_connect().await;
+        let rent = expense_account(&db).await;
+        let maker = user(2, "sam", Role::Approver);
+        threshold_set(&db, 999).await;
+        let entries = vec![entry(rent, 1000), entry(100, -1000)];
+        let journal_entries: Vec<JournalEntry> = entries.iter().map(JournalEntry::from).collect();
+        assert!(is_instruction_error(ledger::journal_new(&db, "Rent".to_string(), journal_entries, &maker).await));
+
+        let draft = DraftData {
+            date: None,
+            journals: vec![DraftJournal {
+                unstructured_narrative: "Rent".to_string(),
+                entries: entries.clone(),
+            }],
+        };
+        let draft_id = draft_new(&db, &maker, &draft).await.unwrap();
+        draft_submit(&db, draft_id, &maker).await.unwrap();
+        draft_approve(&db, draft_id, &user(3, "alex", Role::Approver), None).await.unwrap();
+        assert_eq!(ledger::account_detail(&db, rent).await.unwrap().balance, 1000);
+    }
+
+    #[actix_web::test]
+    async fn recurring_journal_post_is_not_held_by_the_threshold() {
+        let db = entities::memory_connect().await;
+        let rent = expense_account(&db).await;
+        let bookkeeper = user(2, "sam", Role::Bookkeeper);
+        let recurring = RecurringJournalData {
+            unstructured_narrative: "Rent".to_string(),
+            recurrence: Recurrence::EndOfMonth,
+            day: None,
+            start_date: NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+            end_date: None,
+            entries: vec![entry(rent, 1000), entry(100, -1000)],
+        };
+        recurring::recurring_journal_new(&db, &recurring, &bookkeeper).await.unwrap();
+        threshold_set(&db, 999).await;
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        assert!(recurring::recurring_journal_post(&db, date, &bookkeeper).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,241 @@
+//! Entities: the companies whose books the server keeps.
+//!
+//! Each entity keeps its books in its own SQLite database, created from the same migrations as the
+//! server database, and is addressed by its slug in URLs under `/api/v1/entity/{slug}`. The server
+//! database holds the users and their API tokens, the entities and the users granted access to
+//! each one. Admins may access every entity; other users only those they have been granted.
+//!
+//! When the server starts with no entities, [`bootstrap`] registers the server database itself as
+//! the `default` entity, so that books kept before there were several entities remain available.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+use crate::{db, settings};
+use crate::error::Error::{InstructionError, PermissionError};
+use crate::users::{Role, User};
+
+/// The slug of the entity registered by [`bootstrap`].
+const DEFAULT_SLUG: &str = "default";
+
+#[derive(Clone, Serialize)]
+pub struct Entity {
+    pub entity_id: i64,
+    pub slug: String,
+    /// The users granted access to the entity, in addition to admins.
+    pub user_ids: Vec<i64>,
+}
+
+/// Open the database of an existing entity.
+pub async fn entity_connect(database_url: &str) -> Result<Pool<Sqlite>> {
+    let options = SqliteConnectOptions::from_str(database_url)?;
+    Ok(SqlitePoolOptions::new().connect_with(options).await?)
+}
+
+/// Open an in-memory database with the migrations run, for tests.
+#[cfg(test)]
+pub async fn memory_connect() -> Pool<Sqlite> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    pool
+}
+
+/// List the entities a user may access.
+pub async fn entity_list(e: &Pool<Sqlite>, user: &User) -> Result<Vec<Entity>> {
+    let user_id = if user.role == Role::Admin { None } else { Some(user.user_id) };
+    let mut entities: Vec<Entity> = Vec::new();
+    for r in db::entity_list_query(e, user_id).await? {
+        entities.push(Entity {
+            entity_id: r.entity_id,
+            user_ids: db::entity_grant_query(e, r.entity_id).await?,
+            slug: r.slug,
+        });
+    }
+    Ok(entities)
+}
+
+/// The database URLs of every entity, by slug, for opening when the server starts.
+pub async fn entity_database_urls(e: &Pool<Sqlite>) -> Result<Vec<(String, String)>> {
+    Ok(db::entity_list_query(e, None).await?
+        .into_iter()
+        .map(|r| (r.slug, r.database_url))
+        .collect())
+}
+
+/// Create an entity with a new database in `entity_dir`, returning the entity and its database.
+/// The database is removed again if the entity cannot be registered.
+pub async fn entity_new(e: &Pool<Sqlite>, slug: &str, name: &str, entity_dir: &str, user: &User)
+                        -> Result<(Entity, Pool<Sqlite>)> {
+    user.require(Role::Admin)?;
+    let slug_valid = slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if slug.is_empty() || slug.len() > 32 || !slug_valid {
+        bail!(InstructionError("entity slug must be 1-32 lowercase letters, digits or hyphens".to_string()));
+    }
+    if name.is_empty() || name.len() > 140 {
+        bail!(InstructionError("entity name must be 1-140 chars".to_string()));
+    }
+    if db::entity_by_slug_query(e, slug).await?.is_some() {
+        bail!(InstructionError(format!("entity {slug} already exists")));
+    }
+    let path = std::path::Path::new(entity_dir).join(format!("{slug}.db"));
+    if path.exists() {
+        bail!(InstructionError(format!("database {} already exists", path.display())));
+    }
+    std::fs::create_dir_all(entity_dir)?;
+    let database_url = format!("sqlite://{}", path.display());
+    let (entity_id, pool) = match entity_database_new(e, slug, name, database_url.as_str(), user).await {
+        Ok(result) => result,
+        Err(err) => {
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            return Err(err);
+        }
+    };
+    Ok((Entity {
+        entity_id,
+        slug: slug.to_string(),
+        user_ids: Vec::new(),
+    }, pool))
+}
+
+/// Create the database of a new entity and run the migrations, then register the entity, returning
+/// its ID. If any step fails the database is closed, so that [`entity_new`] can remove it.
+async fn entity_database_new(e: &Pool<Sqlite>, slug: &str, name: &str, database_url: &str, user: &User)
+                             -> Result<(i64, Pool<Sqlite>)> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+    let pool = SqlitePoolOptions::new().connect_with(options).await?;
+    let result: Result<i64> = async {
+        sqlx::migrate!().run(&pool).await?;
+        let mut transaction = pool.begin().await?;
+        settings::set_settings_str(&mut transaction, "entityName", name, Some(user.user_id)).await;
+        transaction.commit().await?;
+        Ok(db::entity_new_tx(e, slug, database_url, user.user_id).await?)
+    }.await;
+    match result {
+        Ok(entity_id) => Ok((entity_id, pool)),
+        Err(err) => {
+            pool.close().await;
+            Err(err)
+        }
+    }
+}
+
+/// Grant a user access to an entity.
+pub async fn entity_grant(e: &Pool<Sqlite>, slug: &str, user_id: i64, user: &User) -> Result<()> {
+    user.require(Role::Admin)?;
+    let Some(entity) = db::entity_by_slug_query(e, slug).await? else {
+        bail!(InstructionError(format!("entity {slug} does not exist")));
+    };
+    if db::user_query(e, user_id).await?.is_none() {
+        bail!(InstructionError(format!("user {user_id} does not exist")));
+    }
+    Ok(db::entity_grant_tx(e, entity.entity_id, slug, user_id, user.user_id).await?)
+}
+
+/// Revoke a user's access to an entity.
+pub async fn entity_revoke(e: &Pool<Sqlite>, slug: &str, user_id: i64, user: &User) -> Result<()> {
+    user.require(Role::Admin)?;
+    let Some(entity) = db::entity_by_slug_query(e, slug).await? else {
+        bail!(InstructionError(format!("entity {slug} does not exist")));
+    };
+    match db::entity_revoke_tx(e, entity.entity_id, slug, user_id, user.user_id).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("user {user_id} has no access to {slug}"))),
+        result => Ok(result?),
+    }
+}
+
+/// The books of an entity, once a user's access to them has been checked.
+#[derive(Clone)]
+pub struct EntityDb {
+    pub entity_id: i64,
+    pub slug: String,
+    pub db: Pool<Sqlite>,
+}
+
+/// Open the books of an entity for a user, checking the user may access them.
+pub async fn entity_access(e: &Pool<Sqlite>, pools: &BTreeMap<String, Pool<Sqlite>>, slug: &str, user: &User)
+                           -> Result<EntityDb> {
+    let (Some(entity), Some(db)) = (db::entity_by_slug_query(e, slug).await?, pools.get(slug)) else {
+        bail!(InstructionError(format!("entity {slug} does not exist")));
+    };
+    if user.role != Role::Admin && !db::entity_grant_query(e, entity.entity_id).await?.contains(&user.user_id) {
+        bail!(PermissionError(format!("{} has not been granted access to {slug}", user.name)));
+    }
+    Ok(EntityDb {
+        entity_id: entity.entity_id,
+        slug: entity.slug,
+        db: db.clone(),
+    })
+}
+
+/// Open the books of a group of entities for a user, given as a comma-separated list of slugs, or
+/// every entity the user may access if [`None`].
+pub async fn entity_group(e: &Pool<Sqlite>, pools: &BTreeMap<String, Pool<Sqlite>>, slugs: Option<&str>,
+                          user: &User) -> Result<Vec<EntityDb>> {
+    let slugs: Vec<String> = match slugs {
+        Some(slugs) => slugs.split(',').map(|s| s.trim().to_string()).collect(),
+        None => entity_list(e, user).await?.into_iter().map(|entity| entity.slug).collect(),
+    };
+    let mut group: Vec<EntityDb> = Vec::new();
+    for slug in slugs {
+        if group.iter().any(|entity| entity.slug == slug) {
+            bail!(InstructionError(format!("entity {slug} is given twice")));
+        }
+        group.push(entity_access(e, pools, slug.as_str(), user).await?);
+    }
+    if group.is_empty() {
+        bail!(InstructionError("no entities to report on".to_string()));
+    }
+    Ok(group)
+}
+
+/// Register the server database as the `default` entity if there are no entities, returning
+/// whether it was registered.
+pub async fn bootstrap(e: &Pool<Sqlite>, database_url: &str) -> Result<bool> {
+    if !db::entity_list_query(e, None).await?.is_empty() {
+        return Ok(false);
+    }
+    db::entity_bootstrap(e, DEFAULT_SLUG, database_url).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin() -> User {
+        User {
+            user_id: 1,
+            name: "admin".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    #[actix_web::test]
+    async fn entity_new_removes_database_if_registration_fails() {
+        let server = memory_connect().await;
+        sqlx::query("CREATE TRIGGER entity_refuse BEFORE INSERT ON entity BEGIN SELECT RAISE(ABORT, 'refused'); END;")
+            .execute(&server)
+            .await
+            .unwrap();
+        let entity_dir = std::env::temp_dir().join(format!("minibooks-entities-{}", std::process::id()));
+        let entity_dir = entity_dir.to_str().unwrap();
+
+        assert!(entity_new(&server, "acme", "Acme Ltd", entity_dir, &admin()).await.is_err());
+        assert!(!std::path::Path::new(entity_dir).join("acme.db").exists());
+        assert!(db::entity_by_slug_query(&server, "acme").await.unwrap().is_none());
+        std::fs::remove_dir_all(entity_dir).unwrap();
+    }
+}
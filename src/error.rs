@@ -1,9 +1,53 @@
+use std::fmt;
+
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug, Error)]
 pub enum Error {
     #[error("instruction contained invalid value: {0}")]
     InstructionError(String),
+    #[error("permission denied: {0}")]
+    PermissionError(String),
     #[error("journal entry does not balance")]
     JournalBalanceError,
+    #[error("iXBRL document is not valid: {0}")]
+    IxbrlError(String),
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::PermissionError(_) => StatusCode::FORBIDDEN,
+            Error::InstructionError(_) | Error::JournalBalanceError | Error::IxbrlError(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// The error returned by a handler. An [`Error`] gives the response its status, and any other
+/// error, such as a database error, is an internal server error.
+#[derive(Debug)]
+pub struct HandlerError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for HandlerError {
+    fn from(e: E) -> Self {
+        HandlerError(e.into())
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ResponseError for HandlerError {
+    fn status_code(&self) -> StatusCode {
+        match self.0.downcast_ref::<Error>() {
+            Some(e) => e.status_code(),
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
@@ -0,0 +1,126 @@
+//! Foreign currency accounts and revaluation.
+//!
+//! Accounts may be held in a currency other than the base currency set in the `baseCurrency`
+//! setting. Entries to these accounts record both the base currency amount, which is used for all
+//! balances and reports, and the amount in the account currency.
+//!
+//! # Revaluation
+//!
+//! Monetary accounts (see [`crate::ledger::AccountType::is_monetary`]) held in a foreign currency
+//! are revalued using the most recent exchange rate on or before the revaluation date. The
+//! difference between the revalued amount and the base currency balance is posted against the
+//! account named by the `unrealisedExchangeAccount` setting, and reversed on the first day of the
+//! following month.
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use sqlx::{Pool, Sqlite};
+
+use crate::db;
+use crate::error::Error::InstructionError;
+use crate::ledger::{BatchMetadata, Journal, JournalEntry, journals_validate};
+use crate::settings;
+use crate::users::{Role, User};
+
+pub async fn exchange_rate_set(e: &Pool<Sqlite>, currency: &str, date: NaiveDate, rate: f64, user: &User)
+                               -> Result<()> {
+    user.require(Role::Bookkeeper)?;
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+        bail!(InstructionError("currency is not an ISO 4217 code".to_string()));
+    }
+    if !rate.is_finite() || rate <= 0.0 {
+        bail!(InstructionError("exchange rate must be positive".to_string()));
+    }
+    Ok(db::exchange_rate_set_tx(e, currency, date.to_string().as_str(), rate, user.user_id).await?)
+}
+
+/// The batches posted by [`revalue`].
+pub struct Revaluation {
+    pub batch_id: i64,
+    pub reversal_batch_id: i64,
+    pub journal_count: usize,
+}
+
+/// Revalue all foreign currency monetary accounts as at `date`.
+///
+/// Returns [`None`] if no account needed revaluing, in which case no batches are posted.
+pub async fn revalue(db: &Pool<Sqlite>, date: NaiveDate, user: &User) -> Result<Option<Revaluation>> {
+    user.require(Role::Bookkeeper)?;
+    let base_currency = settings::get_settings_str(db, "baseCurrency").await.unwrap();
+    let gains_account = settings::get_settings_int(db, "unrealisedExchangeAccount").await.unwrap();
+    let date_str = date.to_string();
+    let balances = db::foreign_account_balance_query(db, base_currency.as_str(), date_str.as_str()).await?;
+
+    let mut journals: Vec<Journal> = Vec::new();
+    let mut reversals: Vec<Journal> = Vec::new();
+    for balance in balances.into_iter().filter(|b| b.account_type.is_monetary()) {
+        let Some(rate) = db::exchange_rate_query(db, balance.currency.as_str(), date_str.as_str()).await? else {
+            bail!(InstructionError(format!("no {} exchange rate on or before {date}", balance.currency)));
+        };
+        let revalued = (balance.currency_balance as f64 * rate).round() as i64;
+        let difference = revalued - balance.balance;
+        if difference == 0 {
+            continue;
+        }
+        let account = format!("{:<08}", balance.account_id);
+        journals.push(revaluation_journal(
+            format!("Unrealised exchange revaluation of {account} ({}) at {rate}", balance.currency),
+            balance.account_id, gains_account, difference));
+        reversals.push(revaluation_journal(
+            format!("Reversal of unrealised exchange revaluation of {account} ({})", balance.currency),
+            balance.account_id, gains_account, -difference));
+    }
+    if journals.is_empty() {
+        return Ok(None);
+    }
+    journals_validate(db, &journals).await?;
+    journals_validate(db, &reversals).await?;
+
+    let journal_count = journals.len();
+    let batch_ids = db::batches_new_tx(db, vec![
+        (BatchMetadata {
+            date: Some(date_str),
+            generated_by: Some("fxRevaluation".to_string()),
+            created_by: Some(user.user_id),
+        }, journals),
+        (BatchMetadata {
+            date: Some(next_period_start(date).to_string()),
+            generated_by: Some("fxRevaluationReversal".to_string()),
+            created_by: Some(user.user_id),
+        }, reversals),
+    ]).await?;
+    Ok(Some(Revaluation {
+        batch_id: batch_ids[0],
+        reversal_batch_id: batch_ids[1],
+        journal_count,
+    }))
+}
+
+fn revaluation_journal(unstructured_narrative: String, account: i64, gains_account: i64, difference: i64) -> Journal {
+    Journal {
+        unstructured_narrative,
+        entries: vec![
+            JournalEntry {
+                account,
+                amount: difference,
+                currency_amount: Some(0),
+                ..Default::default()
+            },
+            JournalEntry {
+                account: gains_account,
+                amount: -difference,
+                ..Default::default()
+            },
+        ],
+    }
+}
+
+/// The first day of the month following `date`.
+pub fn next_period_start(date: NaiveDate) -> NaiveDate {
+    NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap() + Months::new(1)
+}
+
+/// The last day of the month containing `date`.
+pub fn period_end(date: NaiveDate) -> NaiveDate {
+    next_period_start(date) - Days::new(1)
+}
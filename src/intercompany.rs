@@ -0,0 +1,334 @@
+//! Intercompany journals between the entities hosted by the server.
+//!
+//! An intercompany account is an account in the books of one entity that holds the balance owed to
+//! or by another, named by its `counterparty`. An intercompany journal is posted in the books of
+//! both entities at once, with mirrored entries and a shared reference:
+//!
+//! * in the books of the first entity, `amount` is debited to its intercompany account for the
+//!   second and credited to `from_account`;
+//! * in the books of the second entity, `amount` is debited to `to_account` and credited to its
+//!   intercompany account for the first.
+//!
+//! So a positive amount increases what the second entity owes the first. The two intercompany
+//! balances then cancel out, which [`intercompany_mismatches`] checks and the consolidated reports
+//! in [`crate::consolidation`] rely on.
+//!
+//! The books are separate databases, so the journal cannot be posted in a single transaction.
+//! Everything that can be checked beforehand is checked before anything is posted, including that
+//! the reference is unused. If a later step still fails, the journals already posted are reversed
+//! by compensating journals in the same books.
+
+use std::collections::BTreeMap;
+use std::ops::Index;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+use crate::{db, entities, ledger};
+use crate::error::Error::InstructionError;
+use crate::ledger::{AccountType, BatchMetadata, Journal, JournalEntry};
+use crate::users::{Role, User};
+
+/// The details of an intercompany journal, as given when it is posted.
+#[derive(Clone, Deserialize)]
+pub struct IntercompanyJournalData {
+    pub from_entity: String,
+    pub to_entity: String,
+    /// The shared reference. Defaults to `IC` and a sequence number.
+    pub reference: Option<String>,
+    /// Defaults to the current date.
+    pub date: Option<NaiveDate>,
+    pub unstructured_narrative: String,
+    /// The amount owed to the first entity by the second as a result of the journal.
+    pub amount: i64,
+    /// The account credited in the books of the first entity.
+    pub from_account: i64,
+    /// The account debited in the books of the second entity.
+    pub to_account: i64,
+}
+
+/// An intercompany journal as recorded in the server database.
+#[derive(Clone, Serialize)]
+pub struct IntercompanyJournalRecord {
+    pub reference: String,
+    pub date: String,
+    pub amount: i64,
+    pub from_entity_id: i64,
+    pub from_journal_id: i64,
+    pub to_entity_id: i64,
+    pub to_journal_id: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct IntercompanyJournal {
+    pub intercompany_journal_id: i64,
+    pub reference: String,
+    pub date: String,
+    pub amount: i64,
+    pub from_entity: String,
+    pub from_journal_id: i64,
+    pub to_entity: String,
+    pub to_journal_id: i64,
+    pub created_by: i64,
+}
+
+impl From<db::IntercompanyJournalResult> for IntercompanyJournal {
+    fn from(r: db::IntercompanyJournalResult) -> Self {
+        IntercompanyJournal {
+            intercompany_journal_id: r.intercompany_journal_id,
+            reference: r.reference,
+            date: r.date,
+            amount: r.amount,
+            from_entity: r.from_entity,
+            from_journal_id: r.from_journal_id,
+            to_entity: r.to_entity,
+            to_journal_id: r.to_journal_id,
+            created_by: r.created_by,
+        }
+    }
+}
+
+/// The intercompany balances between two entities, which should cancel out.
+#[derive(Clone, Serialize)]
+pub struct IntercompanyBalance {
+    pub entity: String,
+    pub counterparty: String,
+    /// The balance of the intercompany accounts for `counterparty` in the books of `entity`.
+    pub balance: i64,
+    /// The balance of the intercompany accounts for `entity` in the books of `counterparty`.
+    pub counterparty_balance: i64,
+    /// The sum of the two balances, zero when they match.
+    pub difference: i64,
+}
+
+/// Find the intercompany account for `counterparty` in the books of `entity`.
+async fn intercompany_account(e: &Pool<Sqlite>, entity: &str, counterparty: &str) -> Result<i64> {
+    match db::intercompany_account_query(e, counterparty).await? {
+        Some(account_id) => Ok(account_id),
+        None => bail!(InstructionError(format!("{entity} has no intercompany account for {counterparty}"))),
+    }
+}
+
+/// Check that an account can take the other side of an intercompany journal.
+async fn account_check(e: &Pool<Sqlite>, entity: &str, account_id: i64, intercompany_account_id: i64) -> Result<()> {
+    match db::account_type_query(e, account_id).await? {
+        None => bail!(InstructionError(format!("account {account_id} does not exist in {entity}"))),
+        Some(AccountType::System) => bail!(InstructionError(format!("account {account_id} is a system account"))),
+        Some(_) if account_id == intercompany_account_id => {
+            bail!(InstructionError(format!("account {account_id} is the intercompany account")))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Post an intercompany journal in the books of both entities, returning it as recorded.
+///
+/// The user needs the Bookkeeper role and access to both entities.
+pub async fn intercompany_journal_new(server: &Pool<Sqlite>, pools: &BTreeMap<String, Pool<Sqlite>>,
+                                      data: &IntercompanyJournalData, user: &User) -> Result<IntercompanyJournal> {
+    user.require(Role::Bookkeeper)?;
+    let from = data.from_entity.as_str();
+    let to = data.to_entity.as_str();
+    if from == to {
+        bail!(InstructionError("an intercompany journal needs two different entities".to_string()));
+    }
+    if data.amount == 0 {
+        bail!(InstructionError("amount must not be zero".to_string()));
+    }
+    let from_entity = entities::entity_access(server, pools, from, user).await?;
+    let to_entity = entities::entity_access(server, pools, to, user).await?;
+    let reference = match &data.reference {
+        Some(reference) if reference.is_empty() || reference.len() > 32 => {
+            bail!(InstructionError("reference must be 1-32 chars".to_string()))
+        }
+        Some(reference) => reference.clone(),
+        None => format!("IC{:06}", db::intercompany_journal_next_id_query(server).await?),
+    };
+    if db::intercompany_reference_query(server, reference.as_str()).await? {
+        bail!(InstructionError(format!("intercompany journal {reference} already exists")));
+    }
+    let date = data.date.unwrap_or_else(|| chrono::Utc::now().date_naive()).to_string();
+
+    let from_ic_account = intercompany_account(&from_entity.db, from, to).await?;
+    let to_ic_account = intercompany_account(&to_entity.db, to, from).await?;
+    account_check(&from_entity.db, from, data.from_account, from_ic_account).await?;
+    account_check(&to_entity.db, to, data.to_account, to_ic_account).await?;
+
+    let unstructured_narrative = ledger::narrative_truncate(format!("{reference}: {}", data.unstructured_narrative));
+    let journal = |debit: i64, credit: i64| vec![Journal {
+        unstructured_narrative: unstructured_narrative.clone(),
+        entries: vec![
+            JournalEntry { account: debit, amount: data.amount, ..Default::default() },
+            JournalEntry { account: credit, amount: -data.amount, ..Default::default() },
+        ],
+    }];
+    let from_journals = journal(from_ic_account, data.from_account);
+    let to_journals = journal(data.to_account, to_ic_account);
+    let metadata = BatchMetadata {
+        date: Some(date.clone()),
+        generated_by: Some("intercompany".to_string()),
+        created_by: Some(user.user_id),
+    };
+    ledger::batch_validate(&from_entity.db, &metadata, &from_journals).await?;
+    ledger::batch_validate(&to_entity.db, &metadata, &to_journals).await?;
+
+    let reversal = BatchMetadata {
+        generated_by: Some("intercompanyReversal".to_string()),
+        ..metadata.clone()
+    };
+    let from_journal_id = *ledger::batch_new(&from_entity.db, &metadata, from_journals).await?.1.index(0);
+    let to_journal_id = match ledger::batch_new(&to_entity.db, &metadata, to_journals).await {
+        Ok((_, journal_ids)) => journal_ids[0],
+        Err(e) => {
+            ledger::batch_new(&from_entity.db, &reversal, journal(data.from_account, from_ic_account)).await?;
+            return Err(e);
+        }
+    };
+    let record = IntercompanyJournalRecord {
+        reference,
+        date,
+        amount: data.amount,
+        from_entity_id: from_entity.entity_id,
+        from_journal_id,
+        to_entity_id: to_entity.entity_id,
+        to_journal_id,
+    };
+    let intercompany_journal_id = match db::intercompany_journal_new_tx(server, &record, user.user_id).await {
+        Ok(intercompany_journal_id) => intercompany_journal_id,
+        Err(e) => {
+            ledger::batch_new(&from_entity.db, &reversal, journal(data.from_account, from_ic_account)).await?;
+            ledger::batch_new(&to_entity.db, &reversal, journal(to_ic_account, data.to_account)).await?;
+            return Err(e.into());
+        }
+    };
+    Ok(IntercompanyJournal {
+        intercompany_journal_id,
+        reference: record.reference,
+        date: record.date,
+        amount: record.amount,
+        from_entity: from.to_string(),
+        from_journal_id,
+        to_entity: to.to_string(),
+        to_journal_id,
+        created_by: user.user_id,
+    })
+}
+
+/// List the intercompany journals dated between `from` and `to` inclusive, either of which may be
+/// [`None`] for no limit, between entities the user may access.
+pub async fn intercompany_journal_list(server: &Pool<Sqlite>, from: Option<NaiveDate>, to: Option<NaiveDate>,
+                                       user: &User) -> Result<Vec<IntercompanyJournal>> {
+    let accessible: Vec<String> = entities::entity_list(server, user).await?
+        .into_iter()
+        .map(|e| e.slug)
+        .collect();
+    let from = from.map(|d| d.to_string());
+    let to = to.map(|d| d.to_string());
+    Ok(db::intercompany_journal_list_query(server, from.as_deref(), to.as_deref()).await?
+        .into_iter()
+        .map(IntercompanyJournal::from)
+        .filter(|j| accessible.contains(&j.from_entity) && accessible.contains(&j.to_entity))
+        .collect())
+}
+
+/// The balance of each entity's intercompany accounts, by entity and counterparty, as at a date.
+pub async fn intercompany_balances(group: &[entities::EntityDb], as_at: &str)
+                                   -> Result<BTreeMap<(String, String), i64>> {
+    let mut balances: BTreeMap<(String, String), i64> = BTreeMap::new();
+    for entity in group {
+        for account in db::account_balance_query(&entity.db, None, Some(as_at), None).await? {
+            if let Some(counterparty) = account.counterparty {
+                *balances.entry((entity.slug.clone(), counterparty)).or_default() += account.balance;
+            }
+        }
+    }
+    Ok(balances)
+}
+
+/// Compare the intercompany balances between each pair of entities in a group as at a date,
+/// returning every pair with a balance on either side. Only pairs with a non-zero difference are
+/// returned if `mismatched_only` is set.
+pub async fn intercompany_mismatches(group: &[entities::EntityDb], as_at: NaiveDate, mismatched_only: bool)
+                                     -> Result<Vec<IntercompanyBalance>> {
+    let balances = intercompany_balances(group, as_at.to_string().as_str()).await?;
+    let mut results: Vec<IntercompanyBalance> = Vec::new();
+    for (i, entity) in group.iter().enumerate() {
+        for counterparty in &group[i + 1..] {
+            let balance = balances.get(&(entity.slug.clone(), counterparty.slug.clone())).copied();
+            let counterparty_balance = balances.get(&(counterparty.slug.clone(), entity.slug.clone())).copied();
+            if balance.is_none() && counterparty_balance.is_none() {
+                continue;
+            }
+            let balance = balance.unwrap_or(0);
+            let counterparty_balance = counterparty_balance.unwrap_or(0);
+            let difference = balance + counterparty_balance;
+            if mismatched_only && difference == 0 {
+                continue;
+            }
+            results.push(IntercompanyBalance {
+                entity: entity.slug.clone(),
+                counterparty: counterparty.slug.clone(),
+                balance,
+                counterparty_balance,
+                difference,
+            });
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn admin() -> User {
+        User {
+            user_id: 1,
+            name: "admin".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    /// Set up a server with two entities, `parent` and `child`, each with an intercompany account
+    /// for the other.
+    async fn group() -> (Pool<Sqlite>, BTreeMap<String, Pool<Sqlite>>) {
+        let server = entities::memory_connect().await;
+        let mut pools = BTreeMap::new();
+        for (slug, counterparty) in [("parent", "child"), ("child", "parent")] {
+            let db = entities::memory_connect().await;
+            db::entity_bootstrap(&server, slug, format!("sqlite://{slug}.db").as_str()).await.unwrap();
+            ledger::account_new(&db, Some(200), &format!("Due from {counterparty}"), &AccountType::CurrentAsset, None,
+                                Some(counterparty), &admin()).await.unwrap();
+            pools.insert(slug.to_string(), db);
+        }
+        (server, pools)
+    }
+
+    fn data(reference: &str) -> IntercompanyJournalData {
+        IntercompanyJournalData {
+            from_entity: "parent".to_string(),
+            to_entity: "child".to_string(),
+            reference: Some(reference.to_string()),
+            date: None,
+            unstructured_narrative: "Loan".to_string(),
+            amount: 1000,
+            from_account: 100,
+            to_account: 100,
+        }
+    }
+
+    #[actix_web::test]
+    async fn intercompany_journal_new_refuses_duplicate_reference_before_posting() {
+        let (server, pools) = group().await;
+        intercompany_journal_new(&server, &pools, &data("LOAN1"), &admin()).await.unwrap();
+
+        let result = intercompany_journal_new(&server, &pools, &data("LOAN1"), &admin()).await;
+        assert!(matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_))));
+        for (slug, balance) in [("parent", -1000), ("child", 1000)] {
+            assert_eq!(ledger::account_detail(&pools[slug], 100).await.unwrap().balance, balance, "{slug}");
+        }
+    }
+}
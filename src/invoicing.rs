@@ -0,0 +1,506 @@
+//! Sales invoices and credit notes.
+//!
+//! Invoices are created as drafts and may be edited freely until they are approved. Approving an
+//! invoice assigns the next number from the `invoicePrefix` and `nextInvoiceNumber` settings and
+//! posts a journal debiting the customer's control account (or the `debtorsControlAccount`
+//! setting) and crediting revenue and the `outputVatAccount` setting. Tax is calculated at the
+//! rate of each line's tax code effective on the invoice date.
+//!
+//! Credit notes are numbered from the `creditNotePrefix` and `nextCreditNoteNumber` settings and
+//! post the reverse journal.
+//!
+//! Receipts, whole or part, are allocated to invoices so that the amount outstanding on each
+//! invoice is known.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{contacts, db, ledger};
+use crate::allocations::{Document, Settlement};
+use crate::contacts::ContactKind;
+use crate::error::Error::InstructionError;
+use crate::ledger::{AccountType, BatchMetadata, Journal, JournalEntry};
+use crate::{purchases, settings, tax, vat};
+use crate::tax::TaxTreatment;
+use crate::users::{Role, User};
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum InvoiceKind {
+    #[default]
+    Invoice,
+    CreditNote,
+}
+
+impl InvoiceKind {
+    /// The settings holding the number prefix and the next number for documents of this kind.
+    fn number_settings(&self) -> (&'static str, &'static str) {
+        match self {
+            InvoiceKind::Invoice => ("invoicePrefix", "nextInvoiceNumber"),
+            InvoiceKind::CreditNote => ("creditNotePrefix", "nextCreditNoteNumber"),
+        }
+    }
+
+    /// The sign applied to amounts posted to the customer's account.
+    fn sign(&self) -> i64 {
+        match self {
+            InvoiceKind::Invoice => 1,
+            InvoiceKind::CreditNote => -1,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            InvoiceKind::Invoice => "Invoice",
+            InvoiceKind::CreditNote => "Credit note",
+        }
+    }
+
+    /// The name of the templates used to render documents of this kind, without an extension.
+    pub fn template_name(&self) -> &'static str {
+        match self {
+            InvoiceKind::Invoice => "invoice",
+            InvoiceKind::CreditNote => "credit_note",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum InvoiceState {
+    /// The invoice may be edited and has not been posted to the ledger.
+    Draft,
+    /// The invoice has been numbered and posted to the ledger.
+    Approved,
+}
+
+/// An invoice, as given when it is created or edited.
+#[derive(Clone, Deserialize)]
+pub struct InvoiceData {
+    #[serde(default)]
+    pub kind: InvoiceKind,
+    pub contact_id: i64,
+    pub date: NaiveDate,
+    pub due_date: NaiveDate,
+    pub reference: Option<String>,
+    pub lines: Vec<InvoiceLineData>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct InvoiceLineData {
+    pub description: String,
+    pub quantity: f64,
+    /// The unit price, excluding tax.
+    pub unit_price: i64,
+    pub tax_code: String,
+    /// The revenue account to credit. Defaults to the `salesAccount` setting.
+    pub account_id: Option<i64>,
+}
+
+/// The invoice fields stored in the database.
+#[derive(Serialize)]
+pub struct InvoiceHeader {
+    pub kind: InvoiceKind,
+    pub contact_id: i64,
+    pub date: String,
+    pub due_date: String,
+    pub reference: Option<String>,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow, Serialize)]
+pub struct InvoiceLine {
+    pub description: String,
+    pub quantity: f64,
+    pub unit_price: i64,
+    pub tax_code: String,
+    pub account_id: i64,
+    pub net_amount: i64,
+    pub tax_amount: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Invoice {
+    pub invoice_id: i64,
+    pub kind: InvoiceKind,
+    pub number: Option<String>,
+    pub state: InvoiceState,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub date: String,
+    pub due_date: String,
+    pub reference: Option<String>,
+    pub journal_id: Option<i64>,
+    pub net_total: i64,
+    pub tax_total: i64,
+    pub total: i64,
+    pub allocated: i64,
+    pub outstanding: i64,
+    pub lines: Vec<InvoiceLine>,
+}
+
+impl Invoice {
+    fn from_result(r: db::InvoiceResult, lines: Vec<InvoiceLine>) -> Self {
+        let total = r.net_total + r.tax_total;
+        Invoice {
+            invoice_id: r.invoice_id,
+            kind: r.kind,
+            number: r.number,
+            state: r.state,
+            contact_id: r.contact_id,
+            contact_name: r.contact_name,
+            date: r.date,
+            due_date: r.due_date,
+            reference: r.reference,
+            journal_id: r.journal_id,
+            net_total: r.net_total,
+            tax_total: r.tax_total,
+            total,
+            allocated: r.allocated,
+            outstanding: total - r.allocated,
+            lines,
+        }
+    }
+}
+
+/// The customer's control account, or the `debtorsControlAccount` setting.
+pub async fn debtors_account(e: &Pool<Sqlite>, contact_id: i64) -> Result<i64> {
+    Ok(match contacts::contact_detail(e, contact_id).await?.control_account_id {
+        Some(account_id) => account_id,
+        None => settings::get_settings_int(e, "debtorsControlAccount").await.unwrap(),
+    })
+}
+
+/// Validate an invoice and calculate the net and tax amounts of each line.
+async fn invoice_prepare(e: &Pool<Sqlite>, invoice: &InvoiceData) -> Result<(InvoiceHeader, Vec<InvoiceLine>)> {
+    let contact = contacts::contact_detail(e, invoice.contact_id).await?;
+    if contact.kind != ContactKind::Customer {
+        bail!(InstructionError("invoices can only be raised to customers".to_string()));
+    }
+    if invoice.due_date < invoice.date {
+        bail!(InstructionError("due date is before invoice date".to_string()));
+    }
+    if invoice.reference.as_ref().is_some_and(|r| r.len() > 140) {
+        bail!(InstructionError("reference over 140 chars".to_string()));
+    }
+    let sales_account = settings::get_settings_int(e, "salesAccount").await.unwrap();
+    let mut lines: Vec<InvoiceLine> = Vec::new();
+    for line in &invoice.lines {
+        if line.description.len() > 140 {
+            bail!(InstructionError("line description over 140 chars".to_string()));
+        }
+        if !line.quantity.is_finite() {
+            bail!(InstructionError("line quantity is not a number".to_string()));
+        }
+        if line.quantity < 0.0 || line.unit_price < 0 {
+            bail!(InstructionError("line quantity and unit price must not be negative: raise a credit note instead"
+                .to_string()));
+        }
+        let account_id = line.account_id.unwrap_or(sales_account);
+        match db::account_type_query(e, account_id).await? {
+            Some(AccountType::Revenue) | Some(AccountType::OtherIncome) => {}
+            _ => bail!(InstructionError(format!("account {account_id} is not a revenue account"))),
+        }
+        let (treatment, rate) = tax::tax_code(e, line.tax_code.as_str(), invoice.date.to_string().as_str()).await?;
+        let net_amount = (line.quantity * line.unit_price as f64).round() as i64;
+        let tax_amount = match treatment {
+            // The customer accounts for the tax on reverse charge sales.
+            TaxTreatment::ReverseCharge => 0,
+            _ => tax::tax_amount(net_amount, rate),
+        };
+        lines.push(InvoiceLine {
+            description: line.description.clone(),
+            quantity: line.quantity,
+            unit_price: line.unit_price,
+            tax_code: line.tax_code.clone(),
+            account_id,
+            net_amount,
+            tax_amount,
+        });
+    }
+    Ok((InvoiceHeader {
+        kind: invoice.kind.clone(),
+        contact_id: invoice.contact_id,
+        date: invoice.date.to_string(),
+        due_date: invoice.due_date.to_string(),
+        reference: invoice.reference.clone(),
+    }, lines))
+}
+
+/// Create a draft invoice, returning the invoice ID.
+pub async fn invoice_new(e: &Pool<Sqlite>, invoice: &InvoiceData, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    let (header, lines) = invoice_prepare(e, invoice).await?;
+    Ok(db::invoice_new_tx(e, &header, &lines, user.user_id).await?)
+}
+
+/// Replace the contents of a draft invoice.
+pub async fn invoice_update(e: &Pool<Sqlite>, invoice_id: i64, invoice: &InvoiceData, user: &User) -> Result<()> {
+    user.require(Role::Bookkeeper)?;
+    let (header, lines) = invoice_prepare(e, invoice).await?;
+    match db::invoice_update_tx(e, invoice_id, &header, &lines, user.user_id).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("invoice {invoice_id} is not a draft"))),
+        result => Ok(result?),
+    }
+}
+
+pub async fn invoice_detail(e: &Pool<Sqlite>, invoice_id: i64) -> Result<Invoice> {
+    let Some(result) = db::invoice_query(e, invoice_id).await? else {
+        bail!(InstructionError(format!("invoice {invoice_id} does not exist")));
+    };
+    let lines = db::invoice_line_query(e, invoice_id).await?;
+    Ok(Invoice::from_result(result, lines))
+}
+
+/// List invoices, without their lines.
+pub async fn invoice_list(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, state: Option<&InvoiceState>)
+                          -> Result<Vec<Invoice>> {
+    Ok(db::invoice_list_query(e, contact_id, state).await?
+        .into_iter()
+        .map(|r| Invoice::from_result(r, Vec::new()))
+        .collect())
+}
+
+/// Approve a draft invoice, posting it to the ledger.
+pub async fn invoice_approve(e: &Pool<Sqlite>, invoice_id: i64, user: &User) -> Result<Invoice> {
+    user.require(Role::Bookkeeper)?;
+    let invoice = invoice_detail(e, invoice_id).await?;
+    if invoice.state != InvoiceState::Draft {
+        bail!(InstructionError(format!("invoice {invoice_id} is not a draft")));
+    }
+    if invoice.lines.is_empty() {
+        bail!(InstructionError("invoice has no lines".to_string()));
+    }
+    let contact = contacts::contact_detail(e, invoice.contact_id).await?;
+    let debtors_account = debtors_account(e, invoice.contact_id).await?;
+    let output_vat_account = settings::get_settings_int(e, "outputVatAccount").await.unwrap();
+    let (prefix_setting, number_setting) = invoice.kind.number_settings();
+    let prefix = settings::get_settings_str(e, prefix_setting).await.unwrap_or_default();
+    let sign = invoice.kind.sign();
+
+    let mut entries = vec![JournalEntry {
+        account: debtors_account,
+        amount: sign * invoice.total,
+        contact: Some(invoice.contact_id),
+        ..Default::default()
+    }];
+    let mut revenue: BTreeMap<(i64, &str), i64> = BTreeMap::new();
+    let mut taxes: BTreeMap<&str, i64> = BTreeMap::new();
+    for line in &invoice.lines {
+        *revenue.entry((line.account_id, line.tax_code.as_str())).or_default() += line.net_amount;
+        *taxes.entry(line.tax_code.as_str()).or_default() += line.tax_amount;
+    }
+    for ((account, tax_code), amount) in revenue {
+        entries.push(JournalEntry {
+            account,
+            amount: -sign * amount,
+            tax_code: Some(tax_code.to_string()),
+            ..Default::default()
+        });
+    }
+    for (tax_code, amount) in taxes.into_iter().filter(|(_, amount)| *amount != 0) {
+        entries.push(JournalEntry {
+            account: output_vat_account,
+            amount: -sign * amount,
+            tax_code: Some(tax_code.to_string()),
+            ..Default::default()
+        });
+    }
+    let journal = |number: &str| Journal {
+        unstructured_narrative: ledger::narrative_truncate(format!("{} {number} {}", invoice.kind.title(), contact.name)),
+        entries: entries.clone(),
+    };
+    let metadata = BatchMetadata {
+        date: Some(invoice.date.clone()),
+        generated_by: Some("invoice".to_string()),
+        created_by: Some(user.user_id),
+    };
+    // The number is only assigned when the journal is posted. It does not affect validation, as
+    // the narrative is truncated to fit.
+    let journals = [journal("")];
+    ledger::batch_validate(e, &metadata, &journals).await?;
+    match db::invoice_approve_tx(e, invoice_id, prefix.as_str(), number_setting, &metadata, journal, user.user_id)
+        .await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("invoice {invoice_id} is not a draft"))),
+        result => result?,
+    };
+    invoice_detail(e, invoice_id).await
+}
+
+/// Receive some or all of the amount outstanding on an approved invoice into a cash account,
+/// returning the journal ID.
+pub async fn invoice_receive(e: &Pool<Sqlite>, invoice_id: i64, amount: i64, date: NaiveDate,
+                             cash_account_id: i64, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    purchases::cash_account_validate(e, cash_account_id).await?;
+    vat::lock_check(e, date.to_string().as_str()).await?;
+    let invoice = invoice_detail(e, invoice_id).await?;
+    if invoice.state != InvoiceState::Approved || invoice.kind != InvoiceKind::Invoice {
+        bail!(InstructionError(format!("invoice {invoice_id} is not an approved invoice")));
+    }
+    if amount <= 0 || amount > invoice.outstanding {
+        bail!(InstructionError(format!("receipt must be between 1 and {} for invoice {invoice_id}",
+            invoice.outstanding)));
+    }
+    let receipt = Settlement {
+        document: Document::Invoice(invoice_id),
+        amount,
+        journal: Journal {
+            unstructured_narrative: ledger::narrative_truncate(format!("Receipt for invoice {} {}",
+                invoice.number.as_deref().unwrap_or(""), invoice.contact_name)),
+            entries: vec![
+                JournalEntry {
+                    account: cash_account_id,
+                    amount,
+                    ..Default::default()
+                },
+                JournalEntry {
+                    account: debtors_account(e, invoice.contact_id).await?,
+                    amount: -amount,
+                    contact: Some(invoice.contact_id),
+                    ..Default::default()
+                },
+            ],
+        },
+    };
+    ledger::journals_validate(e, std::slice::from_ref(&receipt.journal)).await?;
+    let metadata = BatchMetadata {
+        date: Some(date.to_string()),
+        generated_by: Some("invoiceReceipt".to_string()),
+        created_by: Some(user.user_id),
+    };
+    Ok(db::settlements_tx(e, &metadata, vec![receipt]).await?.1[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contacts::ContactData;
+    use crate::entities;
+
+    fn bookkeeper() -> User {
+        User {
+            user_id: 1,
+            name: "bookkeeper".to_string(),
+            role: Role::Bookkeeper,
+        }
+    }
+
+    fn line(quantity: f64, unit_price: i64, tax_code: &str) -> InvoiceLineData {
+        InvoiceLineData {
+            description: "Consulting".to_string(),
+            quantity,
+            unit_price,
+            tax_code: tax_code.to_string(),
+            account_id: None,
+        }
+    }
+
+    async fn customer(e: &Pool<Sqlite>) -> i64 {
+        contacts::contact_new(e, &ContactData {
+            kind: ContactKind::Customer,
+            name: "Customer".to_string(),
+            address: None,
+            postcode: None,
+            country: None,
+            email: None,
+            tax_id: None,
+            control_account_id: None,
+        }, &bookkeeper()).await.unwrap()
+    }
+
+    fn invoice_data(kind: InvoiceKind, contact_id: i64, lines: Vec<InvoiceLineData>) -> InvoiceData {
+        InvoiceData {
+            kind,
+            contact_id,
+            date: NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+            due_date: NaiveDate::from_ymd_opt(2024, 4, 30).unwrap(),
+            reference: None,
+            lines,
+        }
+    }
+
+    async fn draft_invoice(e: &Pool<Sqlite>) -> i64 {
+        let contact_id = customer(e).await;
+        let invoice = invoice_data(InvoiceKind::Invoice, contact_id, vec![line(1.0, 10000, "STANDARD")]);
+        invoice_new(e, &invoice, &bookkeeper()).await.unwrap()
+    }
+
+    async fn balance(e: &Pool<Sqlite>, account_id: i64) -> i64 {
+        ledger::account_detail(e, account_id).await.unwrap().balance
+    }
+
+    #[actix_web::test]
+    async fn invoice_approve_numbers_and_posts_once() {
+        let db = entities::memory_connect().await;
+        let invoice_id = draft_invoice(&db).await;
+        let invoice = invoice_approve(&db, invoice_id, &bookkeeper()).await.unwrap();
+        assert_eq!(invoice.number.as_deref(), Some("INV-00001"));
+        assert!(invoice.journal_id.is_some());
+        assert_eq!(settings::get_settings_int(&db, "nextInvoiceNumber").await, Some(2));
+
+        assert!(invoice_approve(&db, invoice_id, &bookkeeper()).await.is_err());
+        assert_eq!(settings::get_settings_int(&db, "nextInvoiceNumber").await, Some(2));
+        let batches = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM batch;"#)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(batches, 1);
+    }
+
+    #[actix_web::test]
+    async fn invoice_approve_posts_debtors_revenue_and_vat() {
+        let db = entities::memory_connect().await;
+        let invoice_id = draft_invoice(&db).await;
+        let invoice = invoice_approve(&db, invoice_id, &bookkeeper()).await.unwrap();
+        assert_eq!((invoice.net_total, invoice.tax_total, invoice.outstanding), (10000, 2000, 12000));
+        assert_eq!(balance(&db, 120).await, 12000);
+        assert_eq!(balance(&db, 400).await, -10000);
+        assert_eq!(balance(&db, 270).await, -2000);
+    }
+
+    #[actix_web::test]
+    async fn invoice_approve_posts_credit_notes_in_reverse() {
+        let db = entities::memory_connect().await;
+        let contact_id = customer(&db).await;
+        let credit_note = invoice_data(InvoiceKind::CreditNote, contact_id, vec![line(2.0, 2500, "STANDARD")]);
+        let invoice_id = invoice_new(&db, &credit_note, &bookkeeper()).await.unwrap();
+        let credit_note = invoice_approve(&db, invoice_id, &bookkeeper()).await.unwrap();
+        assert_eq!(credit_note.number.as_deref(), Some("CN-00001"));
+        assert_eq!(settings::get_settings_int(&db, "nextInvoiceNumber").await, Some(1));
+        assert_eq!(balance(&db, 120).await, -6000);
+        assert_eq!(balance(&db, 400).await, 5000);
+        assert_eq!(balance(&db, 270).await, 1000);
+    }
+
+    #[actix_web::test]
+    async fn invoice_new_calculates_vat_by_tax_code() {
+        let db = entities::memory_connect().await;
+        let contact_id = customer(&db).await;
+        let lines = vec![line(1.0, 10000, "STANDARD"), line(3.0, 1750, "REDUCED"), line(1.0, 4000, "ZERO"),
+                         line(1.0, 8000, "REVERSE_CHARGE")];
+        let invoice_id = invoice_new(&db, &invoice_data(InvoiceKind::Invoice, contact_id, lines), &bookkeeper())
+            .await
+            .unwrap();
+        let invoice = invoice_detail(&db, invoice_id).await.unwrap();
+        let taxes: Vec<i64> = invoice.lines.iter().map(|l| l.tax_amount).collect();
+        assert_eq!(taxes, vec![2000, 263, 0, 0]);
+
+        invoice_approve(&db, invoice_id, &bookkeeper()).await.unwrap();
+        assert_eq!(balance(&db, 270).await, -2263);
+        assert_eq!(balance(&db, 120).await, 27250 + 2263);
+    }
+
+    #[actix_web::test]
+    async fn invoice_new_rejects_negative_lines() {
+        let db = entities::memory_connect().await;
+        let contact_id = customer(&db).await;
+        for line in [line(-1.0, 10000, "STANDARD"), line(1.0, -10000, "STANDARD")] {
+            let invoice = invoice_data(InvoiceKind::Invoice, contact_id, vec![line]);
+            assert!(invoice_new(&db, &invoice, &bookkeeper()).await.is_err());
+        }
+    }
+}
@@ -0,0 +1,658 @@
+//! Inline XBRL (iXBRL) tagging of accounts for filing with Companies House.
+//!
+//! Accounts are tagged with the taxonomies published by the FRC: FRS 102, or FRS 105 for the
+//! statutory accounts of micro-entities in [`crate::statutory`]. Each figure is a fact
+//! naming a concept and a context, which identifies the company by its registered number, from the
+//! `entityRegisteredNumber` setting, and gives the date or period the figure is for, with a
+//! dimension member where the concept alone is ambiguous, such as creditors falling due within one
+//! year.
+//!
+//! Every document is checked by [`document_check`] before it is returned: it must be well-formed
+//! XHTML, reference a taxonomy schema, and every fact must use a concept known to [`CONCEPTS`]
+//! with a context of the right period type, a declared unit and a value in its format. A fact
+//! given more than once for a context must have the same value each time, and net assets must equal
+//! equity. This catches malformed output locally, but is not a full validation against the taxonomy, which
+//! Companies House does when the accounts are filed.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use roxmltree::Node;
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+use tera::{Context, Tera};
+
+use crate::{documents, reports, settings};
+use crate::error::Error::{InstructionError, IxbrlError};
+use crate::reports::BalanceSheet;
+
+/// The entry point of the FRS 102 taxonomy.
+pub const FRS_102_SCHEMA: &str = "https://xbrl.frc.org.uk/FRS-102/2023-01-01/FRS-102-2023-01-01.xsd";
+/// The entry point of the FRS 105 taxonomy, for micro-entities.
+pub const FRS_105_SCHEMA: &str = "https://xbrl.frc.org.uk/FRS-105/2023-01-01/FRS-105-2023-01-01.xsd";
+const SCHEMAS: [&str; 2] = [FRS_102_SCHEMA, FRS_105_SCHEMA];
+
+const CORE: &str = "http://xbrl.frc.org.uk/fr/2023-01-01/core";
+const BUSINESS: &str = "http://xbrl.frc.org.uk/cd/2023-01-01/business";
+const DIRECTORS_REPORT: &str = "http://xbrl.frc.org.uk/reports/2023-01-01/direp";
+const XHTML: &str = "http://www.w3.org/1999/xhtml";
+const INLINE_XBRL: &str = "http://www.xbrl.org/2013/inlineXBRL";
+const TRANSFORMATIONS: &str = "http://www.xbrl.org/inlineXBRL/transformation/2020-02-12";
+const XBRL_INSTANCE: &str = "http://www.xbrl.org/2003/instance";
+const XBRL_DIMENSIONS: &str = "http://xbrl.org/2006/xbrldi";
+const LINKBASE: &str = "http://www.xbrl.org/2003/linkbase";
+const XLINK: &str = "http://www.w3.org/1999/xlink";
+const ISO4217: &str = "http://www.xbrl.org/2003/iso4217";
+/// The scheme of entity identifiers that are Companies House registered numbers.
+const COMPANIES_HOUSE: &str = "http://www.companieshouse.gov.uk/";
+
+/// The namespace of each prefix used for concepts, dimensions and members.
+const PREFIXES: [(&str, &str); 3] = [("core", CORE), ("bus", BUSINESS), ("direp", DIRECTORS_REPORT)];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ConceptType {
+    Monetary,
+    /// A number without a unit, such as a number of employees.
+    Pure,
+    Text,
+    Date,
+    Boolean,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PeriodType {
+    Instant,
+    Duration,
+}
+
+/// The concepts that may be tagged, with their type and period type.
+const CONCEPTS: [(&str, ConceptType, PeriodType); 25] = [
+    ("core:FixedAssets", ConceptType::Monetary, PeriodType::Instant),
+    ("core:TotalInventories", ConceptType::Monetary, PeriodType::Instant),
+    ("core:Debtors", ConceptType::Monetary, PeriodType::Instant),
+    ("core:CashBankOnHand", ConceptType::Monetary, PeriodType::Instant),
+    ("core:CurrentAssets", ConceptType::Monetary, PeriodType::Instant),
+    ("core:Creditors", ConceptType::Monetary, PeriodType::Instant),
+    ("core:NetCurrentAssetsLiabilities", ConceptType::Monetary, PeriodType::Instant),
+    ("core:TotalAssetsLessCurrentLiabilities", ConceptType::Monetary, PeriodType::Instant),
+    ("core:NetAssetsLiabilities", ConceptType::Monetary, PeriodType::Instant),
+    ("core:Equity", ConceptType::Monetary, PeriodType::Instant),
+    ("core:PrepaymentsAccruedIncomeNotExpressedWithinCurrentAssetSubtotal", ConceptType::Monetary,
+        PeriodType::Instant),
+    ("core:AverageNumberEmployeesDuringPeriod", ConceptType::Pure, PeriodType::Duration),
+    ("core:DateAuthorisationFinancialStatementsForIssue", ConceptType::Date, PeriodType::Duration),
+    ("bus:EntityCurrentLegalOrRegisteredName", ConceptType::Text, PeriodType::Duration),
+    ("bus:UKCompaniesHouseRegisteredNumber", ConceptType::Text, PeriodType::Duration),
+    ("bus:StartDateForPeriodCoveredByReport", ConceptType::Date, PeriodType::Duration),
+    ("bus:EndDateForPeriodCoveredByReport", ConceptType::Date, PeriodType::Duration),
+    ("bus:BalanceSheetDate", ConceptType::Date, PeriodType::Duration),
+    ("bus:EntityDormantTruefalse", ConceptType::Boolean, PeriodType::Duration),
+    ("bus:NameEntityOfficer", ConceptType::Text, PeriodType::Duration),
+    ("direp:StatementThatCompanyEntitledToExemptionFromAuditUnderSection477CompaniesAct2006RelatingToSmallCompanies",
+        ConceptType::Text, PeriodType::Duration),
+    ("direp:StatementThatCompanyEntitledToExemptionFromAuditUnderSection480CompaniesAct2006RelatingToDormantCompanies",
+        ConceptType::Text, PeriodType::Duration),
+    ("direp:StatementThatMembersHaveNotRequiredCompanyToObtainAnAudit", ConceptType::Text, PeriodType::Duration),
+    ("direp:StatementThatDirectorsAcknowledgeTheirResponsibilitiesUnderCompaniesAct", ConceptType::Text,
+        PeriodType::Duration),
+    ("direp:StatementThatAccountsHaveBeenPreparedInAccordanceWithProvisionsSmallCompaniesRegime",
+        ConceptType::Text, PeriodType::Duration),
+];
+
+/// The concepts every document must have a fact for.
+const REQUIRED_CONCEPTS: [&str; 3] = ["bus:EntityCurrentLegalOrRegisteredName",
+    "bus:UKCompaniesHouseRegisteredNumber", "bus:BalanceSheetDate"];
+
+const MATURITIES: &str = "core:MaturitiesOrExpirationPeriodsDimension";
+const EQUITY_CLASSES: &str = "core:EquityClassesOfEquityDimension";
+pub const OFFICERS: &str = "bus:EntityOfficersDimension";
+
+/// The dimensions that may qualify a context, with their members.
+const DIMENSIONS: [(&str, &[&str]); 3] = [
+    (MATURITIES, &["core:WithinOneYear", "core:AfterOneYear"]),
+    (EQUITY_CLASSES, &["core:ShareCapital", "core:RetainedEarningsAccumulatedLosses"]),
+    (OFFICERS, &["bus:Director1"]),
+];
+
+/// The layout of a balance sheet.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum BalanceSheetFormat {
+    /// The full format, with the components of current assets and of capital and reserves.
+    Full,
+    /// The micro-entity format of FRS 105, with prepayments shown apart from current assets and
+    /// only the total of capital and reserves.
+    Micro,
+}
+
+/// A context: the date or period of a fact, optionally qualified by a dimension member.
+#[derive(Clone, Serialize)]
+pub struct XbrlContext {
+    pub id: String,
+    /// The first date of a duration, or [`None`] for an instant.
+    pub start: Option<String>,
+    /// The date of an instant, or the last date of a duration.
+    pub end: String,
+    pub dimension: Option<String>,
+    pub member: Option<String>,
+}
+
+/// A fact, as rendered by a template.
+#[derive(Clone, Serialize)]
+pub struct Fact {
+    pub name: String,
+    pub context: String,
+    /// The value as displayed, without the sign of a monetary amount.
+    pub value: String,
+    pub negative: bool,
+    /// Whether the amount is shown in brackets, as a deduction or a negative amount.
+    pub bracketed: bool,
+}
+
+/// A row of a tagged statement, with a fact for each date of the statement.
+#[derive(Clone, Serialize)]
+pub struct Row {
+    pub label: String,
+    /// `heading`, `line`, `subtotal` or `total`.
+    pub style: &'static str,
+    pub facts: Vec<Fact>,
+}
+
+/// The contexts of a document, added as its facts are tagged.
+#[derive(Default)]
+pub struct Tagging {
+    pub contexts: Vec<XbrlContext>,
+}
+
+impl Tagging {
+    /// The id of the context for a date, or a period if `start` is given, adding it if it is new.
+    fn context(&mut self, start: Option<&str>, end: &str, member: Option<(&str, &str)>) -> String {
+        let mut id = match start {
+            Some(start) => format!("d-{start}-{end}"),
+            None => format!("i-{end}"),
+        };
+        if let Some((_, member)) = member {
+            id.push('-');
+            id.push_str(member.split_once(':').map_or(member, |(_, local)| local));
+        }
+        if !self.contexts.iter().any(|c| c.id == id) {
+            self.contexts.push(XbrlContext {
+                id: id.clone(),
+                start: start.map(String::from),
+                end: end.to_string(),
+                dimension: member.map(|(dimension, _)| dimension.to_string()),
+                member: member.map(|(_, member)| member.to_string()),
+            });
+        }
+        id
+    }
+
+    /// Tag a monetary amount as at a date. A deducted amount is shown in brackets when positive.
+    pub fn monetary(&mut self, concept: &str, date: &str, member: Option<(&str, &str)>, amount: i64,
+                    deducted: bool) -> Fact {
+        Fact {
+            name: concept.to_string(),
+            context: self.context(None, date, member),
+            value: documents::money_format(amount.abs()),
+            negative: amount < 0,
+            bracketed: amount != 0 && deducted != (amount < 0),
+        }
+    }
+
+    /// Tag a text, date or boolean value for a period.
+    pub fn text(&mut self, concept: &str, start: &str, end: &str, member: Option<(&str, &str)>, value: &str)
+                -> Fact {
+        Fact {
+            name: concept.to_string(),
+            context: self.context(Some(start), end, member),
+            value: value.to_string(),
+            negative: false,
+            bracketed: false,
+        }
+    }
+
+    /// Tag a number without a unit for a period.
+    pub fn pure(&mut self, concept: &str, start: &str, end: &str, value: i64) -> Fact {
+        Fact {
+            name: concept.to_string(),
+            context: self.context(Some(start), end, None),
+            value: value.abs().to_string(),
+            negative: value < 0,
+            bracketed: value < 0,
+        }
+    }
+}
+
+/// The facts identifying the company and the period covered by its accounts, which are hidden.
+pub fn company_facts(tagging: &mut Tagging, name: &str, registered_number: &str, start: &str, end: &str)
+                     -> Vec<Fact> {
+    vec![
+        tagging.text("bus:EntityCurrentLegalOrRegisteredName", start, end, None, name),
+        tagging.text("bus:UKCompaniesHouseRegisteredNumber", start, end, None, registered_number),
+        tagging.text("bus:StartDateForPeriodCoveredByReport", start, end, None, start),
+        tagging.text("bus:EndDateForPeriodCoveredByReport", start, end, None, end),
+        tagging.text("bus:BalanceSheetDate", start, end, None, end),
+    ]
+}
+
+/// The rows of the balance sheet, each tagged for every date of the balance sheet. Lines that are
+/// nil at every date are left out, but totals are always shown.
+pub fn balance_sheet_rows(tagging: &mut Tagging, report: &BalanceSheet, format: BalanceSheetFormat) -> Vec<Row> {
+    let current_assets_less_prepayments: Vec<i64> = report.current_assets
+        .iter()
+        .zip(&report.prepayments)
+        .map(|(assets, prepayments)| assets - prepayments)
+        .collect();
+    let mut rows: Vec<Row> = Vec::new();
+    let mut row = |label: &str, style: &'static str, concept: &str, member: Option<(&str, &str)>,
+                   amounts: &[i64], deducted: bool| {
+        if style == "line" && amounts.iter().all(|a| *a == 0) {
+            return;
+        }
+        rows.push(Row {
+            label: label.to_string(),
+            style,
+            facts: report.dates
+                .iter()
+                .zip(amounts)
+                .map(|(date, amount)| tagging.monetary(concept, date, member, *amount, deducted))
+                .collect(),
+        });
+    };
+    let within_one_year = Some((MATURITIES, "core:WithinOneYear"));
+    let after_one_year = Some((MATURITIES, "core:AfterOneYear"));
+    if format == BalanceSheetFormat::Micro {
+        row("Fixed assets", "line", "core:FixedAssets", None, &report.fixed_assets, false);
+        row("Current assets", "line", "core:CurrentAssets", None, &current_assets_less_prepayments, false);
+        row("Prepayments and accrued income", "line",
+            "core:PrepaymentsAccruedIncomeNotExpressedWithinCurrentAssetSubtotal", None, &report.prepayments, false);
+        row("Creditors: amounts falling due within one year", "line", "core:Creditors", within_one_year,
+            &report.creditors_within_one_year, true);
+        row("Net current assets (liabilities)", "subtotal", "core:NetCurrentAssetsLiabilities", None,
+            &report.net_current_assets, false);
+        row("Total assets less current liabilities", "subtotal", "core:TotalAssetsLessCurrentLiabilities", None,
+            &report.total_assets_less_current_liabilities, false);
+        row("Creditors: amounts falling due after more than one year", "line", "core:Creditors", after_one_year,
+            &report.creditors_after_one_year, true);
+        row("Net assets (liabilities)", "total", "core:NetAssetsLiabilities", None, &report.net_assets, false);
+        row("Capital and reserves", "total", "core:Equity", None, &report.equity, false);
+        return rows;
+    }
+    row("Fixed assets", "line", "core:FixedAssets", None, &report.fixed_assets, false);
+    row("Stocks", "line", "core:TotalInventories", None, &report.stocks, false);
+    row("Debtors", "line", "core:Debtors", None, &report.debtors, false);
+    row("Cash at bank and in hand", "line", "core:CashBankOnHand", None, &report.cash, false);
+    row("Current assets", "subtotal", "core:CurrentAssets", None, &report.current_assets, false);
+    row("Creditors: amounts falling due within one year", "line", "core:Creditors", within_one_year,
+        &report.creditors_within_one_year, true);
+    row("Net current assets (liabilities)", "subtotal", "core:NetCurrentAssetsLiabilities", None,
+        &report.net_current_assets, false);
+    row("Total assets less current liabilities", "subtotal", "core:TotalAssetsLessCurrentLiabilities", None,
+        &report.total_assets_less_current_liabilities, false);
+    row("Creditors: amounts falling due after more than one year", "line", "core:Creditors", after_one_year,
+        &report.creditors_after_one_year, true);
+    row("Net assets (liabilities)", "total", "core:NetAssetsLiabilities", None, &report.net_assets, false);
+    row("Capital and reserves", "heading", "", None, &[], false);
+    row("Called up share capital", "line", "core:Equity", Some((EQUITY_CLASSES, "core:ShareCapital")),
+        &report.share_capital, false);
+    row("Profit and loss account", "line", "core:Equity",
+        Some((EQUITY_CLASSES, "core:RetainedEarningsAccumulatedLosses")), &report.profit_and_loss_reserve, false);
+    row("Shareholders' funds", "total", "core:Equity", None, &report.equity, false);
+    rows
+}
+
+/// The entity name and registered number, failing if either is not set or the number is not a
+/// Companies House registered number.
+pub async fn company(e: &Pool<Sqlite>) -> Result<(String, String)> {
+    let entity = documents::entity(e).await;
+    let Some(name) = entity.name.filter(|n| !n.is_empty()) else {
+        bail!(InstructionError("the entityName setting is needed to tag accounts".to_string()));
+    };
+    let Some(registered_number) = entity.registered_number else {
+        bail!(InstructionError("the entityRegisteredNumber setting is needed to tag accounts".to_string()));
+    };
+    if registered_number.len() != 8 || !registered_number.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+        bail!(InstructionError(format!("{registered_number} is not a Companies House registered number")));
+    }
+    Ok((name, registered_number))
+}
+
+/// The currency monetary facts are tagged in.
+pub async fn currency(e: &Pool<Sqlite>) -> String {
+    settings::get_settings_str(e, "baseCurrency").await.unwrap_or("GBP".to_string())
+}
+
+/// Render the balance sheet as an iXBRL document, as at a date and each of the dates it is
+/// compared with, for the fiscal year ending on that date.
+pub async fn balance_sheet_render(e: &Pool<Sqlite>, tmpl: &Tera, as_at: NaiveDate, comparisons: &[NaiveDate])
+                                  -> Result<String> {
+    let (name, registered_number) = company(e).await?;
+    let report = reports::balance_sheet(e, as_at, comparisons).await?;
+    let mut tagging = Tagging::default();
+    let hidden = company_facts(&mut tagging, &name, &registered_number, &report.period_start, &report.dates[0]);
+    let rows = balance_sheet_rows(&mut tagging, &report, BalanceSheetFormat::Full);
+    let mut ctx = Context::new();
+    ctx.insert("entity_name", &name);
+    ctx.insert("registered_number", &registered_number);
+    ctx.insert("currency", &currency(e).await);
+    ctx.insert("schema", FRS_102_SCHEMA);
+    ctx.insert("report", &report);
+    ctx.insert("hidden", &hidden);
+    ctx.insert("rows", &rows);
+    ctx.insert("contexts", &tagging.contexts);
+    let html = tmpl.render("balance_sheet.html", &ctx)?;
+    document_check(&html)?;
+    Ok(html)
+}
+
+fn is_element(node: &Node, namespace: &str, name: &str) -> bool {
+    node.is_element() && node.tag_name().namespace() == Some(namespace) && node.tag_name().name() == name
+}
+
+/// Resolve a prefixed name in the scope of a node to its namespace and local name.
+fn qname<'a>(node: &Node<'a, '_>, name: &'a str) -> Option<(&'a str, &'a str)> {
+    let (prefix, local) = name.trim().split_once(':')?;
+    Some((node.lookup_namespace_uri(Some(prefix))?, local))
+}
+
+/// Whether a resolved name is the same as a name with one of the [`PREFIXES`].
+fn qname_is(resolved: Option<(&str, &str)>, name: &str) -> bool {
+    let (Some((namespace, local)), Some((prefix, name_local))) = (resolved, name.split_once(':')) else {
+        return false;
+    };
+    local == name_local && PREFIXES.iter().any(|(p, n)| *p == prefix && *n == namespace)
+}
+
+fn text(node: &Node) -> String {
+    node.descendants().filter(|n| n.is_text()).filter_map(|n| n.text()).collect()
+}
+
+fn child_date(node: &Node, name: &str) -> Option<NaiveDate> {
+    let child = node.descendants().find(|n| is_element(n, XBRL_INSTANCE, name))?;
+    NaiveDate::parse_from_str(text(&child).trim(), "%Y-%m-%d").ok()
+}
+
+/// The value of a valid numeric fact, taking account of its `sign` and `scale`.
+fn fact_number(fact: &Node, value: &str) -> Option<f64> {
+    let digits: String = value.chars().filter(|c| !matches!(c, ',' | ' ')).collect();
+    let scale: i32 = fact.attribute("scale").map_or(Ok(0), str::parse).ok()?;
+    let number = digits.trim().parse::<f64>().ok()? * 10f64.powi(scale);
+    Some(if fact.attribute("sign") == Some("-") { -number } else { number })
+}
+
+/// Whether a value is a decimal number, as a fact without a format must be.
+fn decimal_valid(value: &str) -> bool {
+    let value = value.trim().strip_prefix('-').unwrap_or(value.trim());
+    let (units, fraction) = value.split_once('.').unwrap_or((value, ""));
+    !units.is_empty() && units.chars().all(|c| c.is_ascii_digit()) && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Whether a value is a number with a decimal point and comma or space separators, as the
+/// `ixt:num-dot-decimal` format requires.
+fn num_dot_decimal_valid(value: &str) -> bool {
+    let (units, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+    units.starts_with(|c: char| c.is_ascii_digit())
+        && units.ends_with(|c: char| c.is_ascii_digit())
+        && units.chars().all(|c| c.is_ascii_digit() || c == ',' || c == ' ')
+        && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Check an iXBRL document, returning each problem found.
+pub fn document_problems(html: &str) -> Vec<String> {
+    let doc = match roxmltree::Document::parse(html) {
+        Ok(doc) => doc,
+        Err(e) => return vec![format!("document is not well-formed XML: {e}")],
+    };
+    let mut problems: Vec<String> = Vec::new();
+    if !is_element(&doc.root_element(), XHTML, "html") {
+        problems.push("root element is not an XHTML html element".to_string());
+    }
+    let headers = doc.descendants().filter(|n| is_element(n, INLINE_XBRL, "header")).count();
+    if headers != 1 {
+        problems.push(format!("document has {headers} ix:header elements rather than one"));
+    }
+    if !doc.descendants().any(|n| is_element(&n, LINKBASE, "schemaRef")
+        && n.attribute((XLINK, "href")).is_some_and(|href| SCHEMAS.contains(&href))) {
+        problems.push("document does not reference an FRS 102 or FRS 105 taxonomy schema".to_string());
+    }
+
+    let mut contexts: BTreeMap<&str, Option<PeriodType>> = BTreeMap::new();
+    let mut identifiers: BTreeSet<String> = BTreeSet::new();
+    for context in doc.descendants().filter(|n| is_element(n, XBRL_INSTANCE, "context")) {
+        let Some(id) = context.attribute("id") else {
+            problems.push("context has no id".to_string());
+            continue;
+        };
+        match context.descendants().find(|n| is_element(n, XBRL_INSTANCE, "identifier")) {
+            Some(identifier) if identifier.attribute("scheme") == Some(COMPANIES_HOUSE)
+                && !text(&identifier).trim().is_empty() => {
+                identifiers.insert(text(&identifier).trim().to_string());
+            }
+            _ => problems.push(format!("context {id} does not identify the company by its registered number")),
+        }
+        let period = match (child_date(&context, "instant"), child_date(&context, "startDate"),
+                            child_date(&context, "endDate")) {
+            (Some(_), None, None) => Some(PeriodType::Instant),
+            (None, Some(start), Some(end)) if start <= end => Some(PeriodType::Duration),
+            _ => {
+                problems.push(format!("context {id} does not have a valid period"));
+                None
+            }
+        };
+        for member in context.descendants().filter(|n| is_element(n, XBRL_DIMENSIONS, "explicitMember")) {
+            let value = text(&member);
+            let dimension = member.attribute("dimension").and_then(|d| qname(&member, d));
+            let member_name = qname(&member, value.as_str());
+            if !DIMENSIONS.iter().any(|(d, members)| qname_is(dimension, d)
+                && members.iter().any(|m| qname_is(member_name, m))) {
+                problems.push(format!("context {id} has an unknown dimension member {}", value.trim()));
+            }
+        }
+        if contexts.insert(id, period).is_some() {
+            problems.push(format!("context {id} is defined more than once"));
+        }
+    }
+    if identifiers.len() > 1 {
+        problems.push("contexts identify more than one company".to_string());
+    }
+
+    // The type of fact each unit may be used for.
+    let mut units: BTreeMap<&str, ConceptType> = BTreeMap::new();
+    for unit in doc.descendants().filter(|n| is_element(n, XBRL_INSTANCE, "unit")) {
+        let id = unit.attribute("id").unwrap_or_default();
+        let measure = unit.descendants().find(|n| is_element(n, XBRL_INSTANCE, "measure"));
+        match measure.and_then(|m| m.text().and_then(|t| qname(&m, t))) {
+            Some((ISO4217, code)) if code.len() == 3 && code.chars().all(|c| c.is_ascii_uppercase()) => {
+                units.insert(id, ConceptType::Monetary);
+            }
+            Some((XBRL_INSTANCE, "pure")) => {
+                units.insert(id, ConceptType::Pure);
+            }
+            _ => problems.push(format!("unit {id} is not an ISO 4217 currency or pure")),
+        }
+    }
+
+    let mut tagged: BTreeSet<&str> = BTreeSet::new();
+    // The value of each fact by concept and context, and of each numeric fact as a number.
+    let mut values: BTreeMap<(&str, &str), String> = BTreeMap::new();
+    let mut numbers: BTreeMap<(&str, &str), f64> = BTreeMap::new();
+    for fact in doc.descendants().filter(|n| is_element(n, INLINE_XBRL, "nonFraction")
+        || is_element(n, INLINE_XBRL, "nonNumeric")) {
+        let name = fact.attribute("name").unwrap_or_default();
+        let resolved = qname(&fact, name);
+        let Some((concept, concept_type, period_type)) = CONCEPTS.iter().find(|(c, ..)| qname_is(resolved, c)) else {
+            problems.push(format!("fact {name} is not a known concept"));
+            continue;
+        };
+        tagged.insert(concept);
+        let numeric = fact.tag_name().name() == "nonFraction";
+        if numeric != matches!(concept_type, ConceptType::Monetary | ConceptType::Pure) {
+            problems.push(format!("fact {name} is tagged as the wrong type"));
+        }
+        match fact.attribute("contextRef").and_then(|c| contexts.get(c)) {
+            None => problems.push(format!("fact {name} refers to a context that is not defined")),
+            Some(Some(period)) if period != period_type => {
+                problems.push(format!("fact {name} needs a context with a period of type {period_type:?}"))
+            }
+            Some(_) => {}
+        }
+        let value = text(&fact);
+        if numeric {
+            match fact.attribute("unitRef").and_then(|u| units.get(u)) {
+                None => problems.push(format!("fact {name} refers to a unit that is not defined")),
+                Some(unit_type) if unit_type != concept_type => {
+                    problems.push(format!("fact {name} has a unit of the wrong type"))
+                }
+                Some(_) => {}
+            }
+            if fact.attribute("decimals").is_none() {
+                problems.push(format!("fact {name} has no decimals attribute"));
+            }
+            if fact.attribute("sign").is_some_and(|s| s != "-") {
+                problems.push(format!("fact {name} has an invalid sign"));
+            }
+            let valid = match fact.attribute("format") {
+                None => decimal_valid(&value),
+                Some(format) if qname(&fact, format) == Some((TRANSFORMATIONS, "num-dot-decimal")) => {
+                    num_dot_decimal_valid(&value)
+                }
+                Some(format) => {
+                    problems.push(format!("fact {name} has an unsupported format {format}"));
+                    true
+                }
+            };
+            if !valid {
+                problems.push(format!("fact {name} has the value {} which is not a number", value.trim()));
+            } else if let Some(number) = fact_number(&fact, &value) {
+                numbers.insert((concept, fact.attribute("contextRef").unwrap_or_default()), number);
+            }
+        } else if *concept_type == ConceptType::Date
+            && NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").is_err() {
+            problems.push(format!("fact {name} has the value {} which is not a date", value.trim()));
+        } else if *concept_type == ConceptType::Boolean && !matches!(value.trim(), "true" | "false") {
+            problems.push(format!("fact {name} has the value {} which is not true or false", value.trim()));
+        } else if value.trim().is_empty() {
+            problems.push(format!("fact {name} is empty"));
+        }
+        let context = fact.attribute("contextRef").unwrap_or_default();
+        let value = match numbers.get(&(concept, context)) {
+            Some(number) if numeric => number.to_string(),
+            _ => value.trim().to_string(),
+        };
+        if values.insert((concept, context), value.clone()).is_some_and(|previous| previous != value) {
+            problems.push(format!("fact {name} is given more than once for context {context} with different values"));
+        }
+    }
+    for ((concept, context), net_assets) in &numbers {
+        if *concept != "core:NetAssetsLiabilities" {
+            continue;
+        }
+        if let Some(equity) = numbers.get(&("core:Equity", context)) {
+            if (net_assets - equity).abs() > 0.005 {
+                problems.push(format!("balance sheet for context {context} does not balance: net assets are \
+                    {net_assets} but equity is {equity}"));
+            }
+        }
+    }
+    for concept in REQUIRED_CONCEPTS {
+        if !tagged.contains(concept) {
+            problems.push(format!("document has no {concept} fact"));
+        }
+    }
+    problems
+}
+
+/// Check an iXBRL document, failing with every problem found.
+pub fn document_check(html: &str) -> Result<()> {
+    let problems = document_problems(html);
+    if !problems.is_empty() {
+        bail!(IxbrlError(problems.join("; ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document for a company with a period context, an instant context at the balance sheet date
+    /// and a GBP unit, with the required facts and the given balance sheet facts.
+    fn document(facts: &str) -> String {
+        format!(r#"<html xmlns="{XHTML}" xmlns:ix="{INLINE_XBRL}" xmlns:ixt="{TRANSFORMATIONS}"
+    xmlns:xbrli="{XBRL_INSTANCE}" xmlns:link="{LINKBASE}" xmlns:xlink="{XLINK}" xmlns:iso4217="{ISO4217}"
+    xmlns:core="{CORE}" xmlns:bus="{BUSINESS}">
+<head><title>Accounts</title></head>
+<body>
+<div style="display: none"><ix:header>
+    <ix:references><link:schemaRef xlink:type="simple" xlink:href="{FRS_102_SCHEMA}"/></ix:references>
+    <ix:resources>
+        <xbrli:context id="period">
+            <xbrli:entity><xbrli:identifier scheme="{COMPANIES_HOUSE}">01234567</xbrli:identifier></xbrli:entity>
+            <xbrli:period>
+                <xbrli:startDate>2024-01-01</xbrli:startDate><xbrli:endDate>2024-12-31</xbrli:endDate>
+            </xbrli:period>
+        </xbrli:context>
+        <xbrli:context id="end">
+            <xbrli:entity><xbrli:identifier scheme="{COMPANIES_HOUSE}">01234567</xbrli:identifier></xbrli:entity>
+            <xbrli:period><xbrli:instant>2024-12-31</xbrli:instant></xbrli:period>
+        </xbrli:context>
+        <xbrli:unit id="GBP"><xbrli:measure>iso4217:GBP</xbrli:measure></xbrli:unit>
+    </ix:resources>
+</ix:header></div>
+<p><ix:nonNumeric name="bus:EntityCurrentLegalOrRegisteredName" contextRef="period">Acme Ltd</ix:nonNumeric></p>
+<p><ix:nonNumeric name="bus:UKCompaniesHouseRegisteredNumber" contextRef="period">01234567</ix:nonNumeric></p>
+<p><ix:nonNumeric name="bus:BalanceSheetDate" contextRef="period">2024-12-31</ix:nonNumeric></p>
+{facts}
+</body>
+</html>"#)
+    }
+
+    fn fact(name: &str, context: &str, unit: &str, value: &str) -> String {
+        format!(r#"<p><ix:nonFraction name="{name}" contextRef="{context}" unitRef="{unit}" decimals="2"
+            format="ixt:num-dot-decimal">{value}</ix:nonFraction></p>"#)
+    }
+
+    #[test]
+    fn document_problems_accepts_valid_document() {
+        let facts = [
+            fact("core:NetAssetsLiabilities", "end", "GBP", "1,000.00"),
+            fact("core:Equity", "end", "GBP", "1,000.00"),
+            fact("core:Equity", "end", "GBP", "1,000.00"),
+        ];
+        assert_eq!(document_problems(&document(&facts.concat())), Vec::<String>::new());
+    }
+
+    #[test]
+    fn document_problems_finds_missing_context() {
+        let facts = fact("core:NetAssetsLiabilities", "start", "GBP", "1,000.00");
+        assert_eq!(document_problems(&document(&facts)),
+                   vec!["fact core:NetAssetsLiabilities refers to a context that is not defined"]);
+    }
+
+    #[test]
+    fn document_problems_finds_unknown_unit() {
+        let facts = fact("core:NetAssetsLiabilities", "end", "USD", "1,000.00");
+        assert_eq!(document_problems(&document(&facts)),
+                   vec!["fact core:NetAssetsLiabilities refers to a unit that is not defined"]);
+    }
+
+    #[test]
+    fn document_problems_finds_unbalanced_sheet() {
+        let facts = [
+            fact("core:NetAssetsLiabilities", "end", "GBP", "1,000.00"),
+            fact("core:Equity", "end", "GBP", "900.00"),
+        ];
+        assert_eq!(document_problems(&document(&facts.concat())),
+                   vec!["balance sheet for context end does not balance: net assets are 1000 but equity is 900"]);
+    }
+
+    #[test]
+    fn document_problems_finds_inconsistent_duplicate_facts() {
+        let facts = [
+            fact("core:CashBankOnHand", "end", "GBP", "1,000.00"),
+            fact("core:CashBankOnHand", "end", "GBP", "1,100.00"),
+        ];
+        assert_eq!(document_problems(&document(&facts.concat())),
+                   vec!["fact core:CashBankOnHand is given more than once for context end with different values"]);
+    }
+}
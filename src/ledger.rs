@@ -1,11 +1,14 @@
 use std::ops::Index;
 use std::str::FromStr;
 use anyhow::{bail, Result};
-use serde::Serialize;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite, SqliteExecutor};
 
-use crate::db;
+use crate::{contacts, db, dimensions, drafts, tax, vat};
+use crate::dimensions::{Tag, Tags};
 use crate::error::Error::{InstructionError, JournalBalanceError};
+use crate::users::{Role, User};
 
 /// Ledger account type.
 ///
@@ -56,6 +59,28 @@ pub enum AccountType {
     System,
 }
 
+impl AccountType {
+    /// Whether balances on accounts of this type are monetary items, settled in a fixed number of
+    /// currency units, and so are revalued when held in a foreign currency.
+    pub fn is_monetary(&self) -> bool {
+        matches!(self, AccountType::Cash | AccountType::CurrentAsset | AccountType::CurrentLiability
+            | AccountType::NonCurrentLiability)
+    }
+
+    /// Whether accounts of this type are reported in the profit and loss account rather than the
+    /// balance sheet.
+    pub fn is_profit_and_loss(&self) -> bool {
+        matches!(self, AccountType::Revenue | AccountType::OtherIncome | AccountType::DirectExpense
+            | AccountType::IndirectExpense)
+    }
+
+    /// Whether accounts of this type normally have a credit balance.
+    pub fn is_credit(&self) -> bool {
+        matches!(self, AccountType::CurrentLiability | AccountType::Equity | AccountType::NonCurrentLiability
+            | AccountType::OtherIncome | AccountType::Revenue | AccountType::System)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseAccountTypeError;
 
@@ -90,8 +115,13 @@ pub struct AccountSummary {
     pub timestamp: String,
 }
 
-pub async fn account_list(e: impl SqliteExecutor<'_>) -> Result<Vec<AccountSummary>> {
-    let results = db::account_list_query(e).await?;
+/// List the accounts with their balances as at a date, optionally only from the entries with a tag.
+/// Batches dated after `as_at`, such as the reversal of a revaluation, are left out, so that the
+/// balances agree with the reports for the same date.
+pub async fn account_list(e: impl SqliteExecutor<'_>, as_at: NaiveDate, tag: Option<&Tag>)
+                          -> Result<Vec<AccountSummary>> {
+    let as_at = as_at.to_string();
+    let results = db::account_list_query(e, Some(as_at.as_str()), tag).await?;
     Ok(results
         .into_iter()
         .map(|r| AccountSummary {
@@ -127,35 +157,142 @@ pub async fn account_detail(e: impl SqliteExecutor<'_>, account_id: i64) -> Resu
     })
 }
 
-pub async fn account_new(e: &Pool<Sqlite>, account_id: Option<i64>, account_name: &String, account_type: &AccountType) -> Result<i64> {
-    match account_id {
-        Some(id) => if id < 1 || id > 990 {
+/// Create a new account, returning the account ID.
+///
+/// If `currency` is given the account is held in that foreign currency and entries posted to it
+/// should carry the amount in that currency as well as the base currency amount. If `counterparty`
+/// is given the account holds intercompany balances with that entity, as described in
+/// [`crate::intercompany`].
+pub async fn account_new(e: &Pool<Sqlite>, account_id: Option<i64>, account_name: &String, account_type: &AccountType,
+                         currency: Option<&str>, counterparty: Option<&str>, user: &User) -> Result<i64> {
+    user.require(Role::Admin)?;
+    if let Some(id) = account_id {
+        if !(1..=990).contains(&id) {
             bail!(InstructionError("account id out of range (1-999)".to_string()));
-        },
-        None => {}
+        }
     }
     if account_name.len() > 140 {
         bail!(InstructionError("account name over 140 chars".to_string()))
     }
-    Ok(db::account_new_tx(e, account_id, &account_name, &account_type).await?)
+    if let Some(currency) = currency {
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            bail!(InstructionError("currency is not an ISO 4217 code".to_string()));
+        }
+    }
+    if let Some(counterparty) = counterparty {
+        if !matches!(account_type, AccountType::CurrentAsset | AccountType::CurrentLiability
+            | AccountType::NonCurrentAsset | AccountType::NonCurrentLiability) {
+            bail!(InstructionError("intercompany accounts must be receivables or payables".to_string()));
+        }
+        if counterparty.is_empty() || counterparty.len() > 32 {
+            bail!(InstructionError("counterparty is not an entity slug".to_string()));
+        }
+    }
+    Ok(db::account_new_tx(e, account_id, account_name, account_type, currency, counterparty, user.user_id).await?)
+}
+
+/// Mark a liability account as holding money for a client, so that it is sent a statement by
+/// [`crate::statements::statement_subjects`], or unmark it.
+pub async fn account_client_set(e: &Pool<Sqlite>, account_id: i64, client: bool, user: &User) -> Result<()> {
+    user.require(Role::Admin)?;
+    match db::account_type_query(e, account_id).await? {
+        None => bail!(InstructionError(format!("account {account_id} does not exist"))),
+        Some(AccountType::CurrentLiability) | Some(AccountType::NonCurrentLiability) => {}
+        Some(_) => bail!(InstructionError("client accounts must be liabilities".to_string())),
+    }
+    Ok(db::account_client_set_tx(e, account_id, client, user.user_id).await?)
 }
 
+/// Archive an account, so that nothing more can be posted to it. The account keeps its history
+/// and still appears in reports, but must have a zero balance to be archived.
+pub async fn account_archive(e: &Pool<Sqlite>, account_id: i64, user: &User) -> Result<()> {
+    user.require(Role::Admin)?;
+    if db::account_type_query(e, account_id).await?.is_none() {
+        bail!(InstructionError(format!("account {account_id} does not exist")));
+    }
+    if account_detail(e, account_id).await?.balance != 0 {
+        bail!(InstructionError(format!("account {account_id} has a balance and cannot be archived")));
+    }
+    match db::account_archive_tx(e, account_id, user.user_id).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("account {account_id} is already archived"))),
+        result => Ok(result?),
+    }
+}
+
+/// Metadata recorded against a batch when it is posted.
+#[derive(Clone, Default, Serialize)]
+pub struct BatchMetadata {
+    /// The date of the batch, as an SQLite DATE. Defaults to the current date.
+    pub date: Option<String>,
+    /// The process that generated the batch, or [`None`] if the batch was posted by a user.
+    pub generated_by: Option<String>,
+    /// The user who posted the batch, or whose request generated it.
+    pub created_by: Option<i64>,
+}
+
+#[derive(Serialize)]
 pub struct Journal {
     pub unstructured_narrative: String,
     pub entries: Vec<JournalEntry>,
 }
 
+#[derive(Clone, Default, Serialize)]
 pub struct JournalEntry {
     pub account: i64,
     pub amount: i64,
+    /// The amount in the account currency, for entries to foreign currency accounts.
+    pub currency_amount: Option<i64>,
+    /// The contact the entry relates to, for entries to control accounts.
+    pub contact: Option<i64>,
+    /// The tax code of the supply the entry records, or of the tax posted by the entry.
+    pub tax_code: Option<String>,
+    /// The dimension values the entry is tagged with, by dimension, as described in [`dimensions`].
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
 }
 
-/// Post a batch of journals and return the batch ID and a [`Vec`] of journal IDs created.
-///
-/// The journals will be validated to ensure that they balance and that the narrative length is 140
-/// characters or less.
-pub async fn batch_new(e: &Pool<Sqlite>, journals: Vec<Journal>) -> Result<(i64, Vec<i64>)> {
-    for journal in &journals {
+/// A journal entry as stored for posting later, by a recurring journal or a draft.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EntryData {
+    pub account: i64,
+    pub amount: i64,
+    pub currency_amount: Option<i64>,
+    pub contact: Option<i64>,
+    pub tax_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+}
+
+impl From<&EntryData> for JournalEntry {
+    fn from(e: &EntryData) -> Self {
+        JournalEntry {
+            account: e.account,
+            amount: e.amount,
+            currency_amount: e.currency_amount,
+            contact: e.contact,
+            tax_code: e.tax_code.clone(),
+            tags: e.tags.clone(),
+        }
+    }
+}
+
+/// Truncate a generated narrative to the 140 character limit.
+pub fn narrative_truncate(mut unstructured_narrative: String) -> String {
+    if unstructured_narrative.len() > 140 {
+        let mut end = 140;
+        while !unstructured_narrative.is_char_boundary(end) {
+            end -= 1;
+        }
+        unstructured_narrative.truncate(end);
+    }
+    unstructured_narrative
+}
+
+/// Validate that each journal balances, that the narrative length is 140 characters or less and
+/// that entries carry an amount in the account currency if, and only if, their account is held in
+/// a foreign currency.
+pub async fn journals_validate(e: &Pool<Sqlite>, journals: &[Journal]) -> Result<()> {
+    for journal in journals {
         if journal.unstructured_narrative.len() > 140 {
                 bail!(InstructionError("unstructured narrative over 140 chars".to_string()));
         }
@@ -167,15 +304,204 @@ pub async fn batch_new(e: &Pool<Sqlite>, journals: Vec<Journal>) -> Result<(i64,
             bail!(JournalBalanceError);
         }
     }
-    Ok(db::batch_new_tx(e, journals).await?)
+    let archived_accounts = db::archived_account_query(e).await?;
+    if let Some(entry) = journals.iter().flat_map(|j| &j.entries).find(|e| archived_accounts.contains(&e.account)) {
+        bail!(InstructionError(format!("account {} is archived", entry.account)));
+    }
+    let foreign_accounts = db::foreign_account_query(e).await?;
+    for entry in journals.iter().flat_map(|j| &j.entries) {
+        match (foreign_accounts.contains(&entry.account), entry.currency_amount) {
+            (true, None) => bail!(InstructionError(format!(
+                "account {} is held in a foreign currency and the entry has no currency amount", entry.account))),
+            (false, Some(_)) => bail!(InstructionError(format!(
+                "account {} is held in the base currency and the entry has a currency amount", entry.account))),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Post a batch of journals and return the batch ID and a [`Vec`] of journal IDs created.
+///
+/// The journals will be validated to ensure that they balance, that the narrative length is 140
+/// characters or less, that entries reference only existing contacts and that entries are tagged
+/// only with configured dimension values. Journals with tax-coded entries cannot be dated in a
+/// submitted VAT period.
+pub async fn batch_new(e: &Pool<Sqlite>, metadata: &BatchMetadata, journals: Vec<Journal>) -> Result<(i64, Vec<i64>)> {
+    batch_validate(e, metadata, &journals).await?;
+    Ok(db::batch_new_tx(e, metadata, journals).await?)
+}
+
+/// Validate a batch as [`batch_new`] does, for callers that post the batch themselves.
+pub async fn batch_validate(e: &Pool<Sqlite>, metadata: &BatchMetadata, journals: &[Journal]) -> Result<()> {
+    journals_validate(e, journals).await?;
+    dimensions::tags_validate(e, journals.iter().flat_map(|j| &j.entries).map(|e| &e.tags)).await?;
+    contacts::contacts_validate(e, journals.iter().flat_map(|j| &j.entries).filter_map(|e| e.contact)).await?;
+    if journals.iter().flat_map(|j| &j.entries).any(|e| e.tax_code.is_some()) {
+        let date = metadata.date.clone().unwrap_or_else(|| chrono::Utc::now().date_naive().to_string());
+        vat::lock_check(e, date.as_str()).await?;
+    }
+    Ok(())
 }
 
 /// Wrapper function for [`batch_new`] to allow posting a batch with a single journal.
 ///
+/// Tax is added for entries that carry a tax code, at the rates effective today, as described in
+/// [`tax::journals_tax`]. Journals over the approval threshold must be posted as drafts instead,
+/// as described in [`drafts`].
+///
 /// Returns the journal ID created.
-pub async fn journal_new(e: &Pool<Sqlite>, unstructured_narrative: String, entries: Vec<JournalEntry>) -> Result<i64> {
-    Ok(*batch_new(e, vec![Journal {
+pub async fn journal_new(e: &Pool<Sqlite>, unstructured_narrative: String, entries: Vec<JournalEntry>, user: &User)
+                         -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    let mut journals = vec![Journal {
         unstructured_narrative,
         entries,
-    }]).await?.1.index(0))
+    }];
+    let today = chrono::Utc::now().date_naive().to_string();
+    tax::journals_tax(e, &mut journals, today.as_str()).await?;
+    drafts::threshold_check(e, &journals).await?;
+    let metadata = BatchMetadata {
+        created_by: Some(user.user_id),
+        ..Default::default()
+    };
+    Ok(*batch_new(e, &metadata, journals).await?.1.index(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn admin() -> User {
+        User {
+            user_id: 1,
+            name: "admin".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    fn journal(entries: Vec<JournalEntry>) -> Journal {
+        Journal {
+            unstructured_narrative: "Test".to_string(),
+            entries,
+        }
+    }
+
+    fn entry(account: i64, amount: i64, currency_amount: Option<i64>) -> JournalEntry {
+        JournalEntry {
+            account,
+            amount,
+            currency_amount,
+            ..Default::default()
+        }
+    }
+
+    async fn euro_account(db: &Pool<Sqlite>) -> i64 {
+        account_new(db, Some(101), &"Euro Account".to_string(), &AccountType::Cash, Some("EUR"), None, &admin())
+            .await
+            .unwrap()
+    }
+
+    fn is_instruction_error(result: Result<()>) -> bool {
+        matches!(result.unwrap_err().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    #[actix_web::test]
+    async fn journals_validate_accepts_currency_amounts_on_foreign_accounts_only() {
+        let db = entities::memory_connect().await;
+        let euro = euro_account(&db).await;
+        let journals = [journal(vec![entry(euro, 8600, Some(10000)), entry(100, -8600, None)])];
+        journals_validate(&db, &journals).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn journals_validate_rejects_foreign_entry_without_currency_amount() {
+        let db = entities::memory_connect().await;
+        let euro = euro_account(&db).await;
+        let journals = [journal(vec![entry(euro, 8600, None), entry(100, -8600, None)])];
+        assert!(is_instruction_error(journals_validate(&db, &journals).await));
+    }
+
+    #[actix_web::test]
+    async fn journals_validate_rejects_base_entry_with_currency_amount() {
+        let db = entities::memory_connect().await;
+        let euro = euro_account(&db).await;
+        let journals = [journal(vec![entry(euro, 8600, Some(10000)), entry(100, -8600, Some(-10000))])];
+        assert!(is_instruction_error(journals_validate(&db, &journals).await));
+    }
+
+    #[actix_web::test]
+    async fn journals_validate_treats_accounts_in_the_base_currency_as_base() {
+        let db = entities::memory_connect().await;
+        let sterling = account_new(&db, Some(102), &"Sterling Account".to_string(), &AccountType::Cash, Some("GBP"),
+                                   None, &admin()).await.unwrap();
+        let journals = [journal(vec![entry(sterling, 8600, Some(8600)), entry(100, -8600, None)])];
+        assert!(is_instruction_error(journals_validate(&db, &journals).await));
+    }
+
+    #[actix_web::test]
+    async fn batch_validate_rejects_unknown_contact() {
+        let db = entities::memory_connect().await;
+        let journals = [journal(vec![
+            JournalEntry {
+                contact: Some(1),
+                ..entry(100, 100, None)
+            },
+            entry(100, -100, None),
+        ])];
+        assert!(is_instruction_error(batch_validate(&db, &BatchMetadata::default(), &journals).await));
+    }
+
+    #[actix_web::test]
+    async fn account_list_leaves_out_batches_after_the_date() {
+        let db = entities::memory_connect().await;
+        let euro = euro_account(&db).await;
+        for (date, amount) in [("2024-03-31", 500), ("2024-04-01", -500)] {
+            let metadata = BatchMetadata {
+                date: Some(date.to_string()),
+                generated_by: Some("revaluation".to_string()),
+                created_by: Some(1),
+            };
+            let journals = vec![journal(vec![entry(euro, amount, Some(0)), entry(100, -amount, None)])];
+            batch_new(&db, &metadata, journals).await.unwrap();
+        }
+        let balance = |accounts: Vec<AccountSummary>| accounts
+            .into_iter()
+            .find(|a| a.account_id == euro)
+            .unwrap()
+            .account_balance;
+        let as_at = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        assert_eq!(balance(account_list(&db, as_at, None).await.unwrap()), 500);
+        let as_at = NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+        assert_eq!(balance(account_list(&db, as_at, None).await.unwrap()), 0);
+    }
+
+    #[actix_web::test]
+    async fn account_archive_refuses_further_postings() {
+        let db = entities::memory_connect().await;
+        let euro = euro_account(&db).await;
+        let bookkeeper = User {
+            role: Role::Bookkeeper,
+            ..admin()
+        };
+        let permission_error = account_archive(&db, euro, &bookkeeper).await.unwrap_err();
+        assert!(matches!(permission_error.downcast_ref::<Error>(), Some(Error::PermissionError(_))));
+
+        account_archive(&db, euro, &admin()).await.unwrap();
+        let journals = vec![journal(vec![entry(euro, 100, Some(90)), entry(100, -100, None)])];
+        assert!(is_instruction_error(journals_validate(&db, &journals).await));
+        assert!(is_instruction_error(account_archive(&db, euro, &admin()).await));
+        assert!(is_instruction_error(account_archive(&db, 999, &admin()).await));
+    }
+
+    #[actix_web::test]
+    async fn account_archive_refuses_accounts_with_a_balance() {
+        let db = entities::memory_connect().await;
+        let euro = euro_account(&db).await;
+        let journals = vec![journal(vec![entry(euro, 100, Some(90)), entry(100, -100, None)])];
+        batch_new(&db, &BatchMetadata::default(), journals).await.unwrap();
+        assert!(is_instruction_error(account_archive(&db, euro, &admin()).await));
+    }
 }
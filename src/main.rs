@@ -1,17 +1,55 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
 use actix_web::{App, HttpServer, web};
+use actix_web::middleware::from_fn;
 use dotenvy::dotenv;
 use sqlx::{Pool, Sqlite};
 use sqlx::sqlite::SqlitePoolOptions;
 use tera::Tera;
 
+mod aging;
+mod allocations;
+mod amortisation;
+mod assets;
+mod audit;
+mod budgets;
+mod consolidation;
+mod contacts;
+mod dimensions;
+mod documents;
+mod drafts;
+mod entities;
 mod ledger;
 mod settings;
+mod statements;
+mod statutory;
+mod tax;
+mod vat;
 mod db;
 mod services;
 mod error;
+mod fx;
+mod intercompany;
+mod invoicing;
+mod ixbrl;
+mod pdf;
+mod purchases;
+mod recurring;
+mod reports;
+mod users;
 
 struct AppState {
+    /// The server database, holding the users and the entities.
     db: Pool<Sqlite>,
+    /// The database of each entity, by slug.
+    entities: RwLock<BTreeMap<String, Pool<Sqlite>>>,
+    /// The directory to create the databases of new entities in.
+    entity_dir: String,
+    /// The directory to write bulk statements to, in a subdirectory for each entity.
+    statement_dir: String,
+    /// The service VAT returns are submitted to, if configured.
+    vat_submitter: Option<Arc<dyn vat::VatSubmitter>>,
     tmpl: Tera,
 }
 
@@ -24,21 +62,145 @@ async fn main() -> std::io::Result<()> {
         .connect(database_url.as_str())
         .await
         .expect("database connection is successful");
+    if let Some(token) = users::bootstrap(&pool).await.unwrap() {
+        println!("Created user admin with API token {token}");
+    }
+    if entities::bootstrap(&pool, database_url.as_str()).await.unwrap() {
+        println!("Created entity default for {database_url}");
+    }
+    let mut entity_pools = BTreeMap::new();
+    for (slug, entity_url) in entities::entity_database_urls(&pool).await.unwrap() {
+        let entity_pool = if entity_url == database_url {
+            pool.clone()
+        } else {
+            entities::entity_connect(entity_url.as_str())
+                .await
+                .expect("entity database connection is successful")
+        };
+        entity_pools.insert(slug, entity_pool);
+    }
+    let entity_dir = std::env::var("ENTITY_DIR").unwrap_or("entities".to_string());
+    let statement_dir = std::env::var("STATEMENT_DIR").unwrap_or("statements".to_string());
+    let vat_submitter = std::env::var("HMRC_API_URL").ok()
+        .zip(std::env::var("HMRC_ACCESS_TOKEN").ok())
+        .map(|(api_url, access_token)| {
+            Arc::new(vat::HmrcSubmitter { api_url, access_token }) as Arc<dyn vat::VatSubmitter>
+        });
+
+    let template_dir = std::env::var("TEMPLATE_DIR")
+        .unwrap_or(concat!(env!("CARGO_MANIFEST_DIR"), "/src/templates").to_string());
+    let mut tera = Tera::new(format!("{template_dir}/**/*").as_str()).unwrap();
+    documents::register_filters(&mut tera);
 
-    let tera = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "\\src\\templates\\**\\*")).unwrap();
+    // Shared by every worker, so that entities created through one are seen by all
+    let state = web::Data::new(AppState {
+        db: pool,
+        entities: RwLock::new(entity_pools),
+        entity_dir,
+        statement_dir,
+        vat_submitter,
+        tmpl: tera,
+    });
 
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(AppState{
-                db: pool.clone(),
-                tmpl: tera.clone(),
-            }))
+            .app_data(state.clone())
             .service(web::scope("/api/v1")
-                .service(services::account_list)
-                .service(services::account_detail)
-                .service(services::account_new)
-                .service(services::journal_new)
-                .service(services::report_balance_sheet)
+                .wrap(from_fn(services::authentication))
+                .service(services::user_list)
+                .service(services::user_me)
+                .service(services::user_new)
+                .service(services::user_role_set)
+                .service(services::token_new)
+                .service(services::token_revoke)
+                .service(services::audit_events)
+                .service(services::entity_list)
+                .service(services::entity_new)
+                .service(services::entity_grant)
+                .service(services::entity_revoke)
+                .service(services::intercompany_journal_new)
+                .service(services::intercompany_journal_list)
+                .service(services::report_consolidated_balance_sheet)
+                .service(services::report_consolidated_profit_and_loss)
+                .service(services::report_intercompany_mismatches)
+                .service(services::ixbrl_check)
+                .service(web::scope("/entity/{slug}")
+                    .wrap(from_fn(services::entity_selection))
+                    .service(services::entity_audit_events)
+                    .service(services::account_list)
+                    .service(services::account_detail)
+                    .service(services::account_new)
+                    .service(services::account_client_set)
+                    .service(services::account_archive)
+                    .service(services::journal_new)
+                    .service(services::dimension_list)
+                    .service(services::dimension_new)
+                    .service(services::dimension_value_new)
+                    .service(services::budget_detail)
+                    .service(services::budget_set)
+                    .service(services::budget_import)
+                    .service(services::draft_list)
+                    .service(services::draft_new)
+                    .service(services::draft_detail)
+                    .service(services::draft_submit)
+                    .service(services::draft_approve)
+                    .service(services::draft_reject)
+                    .service(services::recurring_journal_list)
+                    .service(services::recurring_journal_new)
+                    .service(services::recurring_journal_post)
+                    .service(services::recurring_journal_detail)
+                    .service(services::exchange_rate_set)
+                    .service(services::fx_revalue)
+                    .service(services::tax_code_list)
+                    .service(services::tax_code_new)
+                    .service(services::tax_rate_set)
+                    .service(services::vat_return_list)
+                    .service(services::vat_return_mtd)
+                    .service(services::vat_return_submit)
+                    .service(services::vat_return)
+                    .service(services::contact_list)
+                    .service(services::contact_detail)
+                    .service(services::contact_new)
+                    .service(services::account_contact_balances)
+                    .service(services::contact_open_items)
+                    .service(services::contact_cash_on_account)
+                    .service(services::contact_allocations)
+                    .service(services::allocation_new)
+                    .service(services::allocation_delete)
+                    .service(services::contact_statement)
+                    .service(services::account_statement)
+                    .service(services::statement_bulk)
+                    .service(services::invoice_list)
+                    .service(services::invoice_detail)
+                    .service(services::invoice_new)
+                    .service(services::invoice_update)
+                    .service(services::invoice_approve)
+                    .service(services::invoice_render)
+                    .service(services::invoice_receive)
+                    .service(services::bill_list)
+                    .service(services::bill_detail)
+                    .service(services::bill_new)
+                    .service(services::bill_pay)
+                    .service(services::payment_run_propose)
+                    .service(services::payment_run_detail)
+                    .service(services::payment_run_confirm)
+                    .service(services::fixed_asset_list)
+                    .service(services::fixed_asset_new)
+                    .service(services::depreciation_run)
+                    .service(services::fixed_asset_detail)
+                    .service(services::fixed_asset_dispose)
+                    .service(services::amortisation_list)
+                    .service(services::amortisation_new)
+                    .service(services::amortisation_run)
+                    .service(services::amortisation_detail)
+                    .service(services::report_balance_sheet)
+                    .service(services::report_accounts)
+                    .service(services::report_profit_and_loss)
+                    .service(services::report_budget_variance)
+                    .service(services::report_cash_flow)
+                    .service(services::report_aged_debtors)
+                    .service(services::report_aged_creditors)
+                )
             )
     })
         .bind(("127.0.0.1", 8080))?
@@ -0,0 +1,197 @@
+//! Print-ready PDF output, generated locally from plain text layouts.
+//!
+//! Documents are laid out on A4 pages using the standard Helvetica fonts, which every PDF reader
+//! provides, so no fonts are embedded. Each line of the layout is one of:
+//!
+//! * `# text` -- a title, in large bold type.
+//! * `## text` -- a heading, in bold type.
+//! * `---` -- a horizontal rule.
+//! * An empty line -- vertical space.
+//! * `text | text | ...` -- a row of columns. The first column is left aligned and wrapped to
+//!   the space left by the other columns. Each further column is right aligned to a fixed stop,
+//!   with the last column aligned to the right margin.
+//!
+//! Characters outside the Windows-1252 character set are replaced with `?`.
+
+use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str};
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const COLUMN_WIDTH: f32 = 80.0;
+const BODY_SIZE: f32 = 10.0;
+const HEADING_SIZE: f32 = 12.0;
+const TITLE_SIZE: f32 = 18.0;
+
+const FONT_REGULAR: Name = Name(b"F1");
+const FONT_BOLD: Name = Name(b"F2");
+
+/// Widths of the printable ASCII characters in Helvetica, in thousandths of the font size.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+fn text_width(text: &str, size: f32) -> f32 {
+    text.chars()
+        .map(|c| match c as u32 {
+            0x20..=0x7e => HELVETICA_WIDTHS[(c as u32 - 0x20) as usize],
+            _ => 556,
+        } as f32)
+        .sum::<f32>() * size / 1000.0
+}
+
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => c as u8,
+            '€' => 0x80,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Split text into lines no wider than `width`, breaking at spaces where possible.
+fn wrap(text: &str, size: f32, width: f32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        let candidate = if line.is_empty() { word.to_string() } else { format!("{line} {word}") };
+        if text_width(&candidate, size) > width && !line.is_empty() {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        } else {
+            line = candidate;
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+struct Writer {
+    pages: Vec<Content>,
+    y: f32,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer {
+            pages: vec![Content::new()],
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    /// Move down by `height`, starting a new page if there is no room left.
+    fn advance(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            self.pages.push(Content::new());
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+        self.y -= height;
+    }
+
+    fn text(&mut self, font: Name, size: f32, x: f32, text: &str) {
+        let y = self.y;
+        self.pages.last_mut().unwrap()
+            .begin_text()
+            .set_font(font, size)
+            .next_line(x, y)
+            .show(Str(&win_ansi(text)))
+            .end_text();
+    }
+
+    fn rule(&mut self) {
+        self.advance(6.0);
+        let y = self.y + 2.0;
+        self.pages.last_mut().unwrap()
+            .set_line_width(0.5)
+            .move_to(MARGIN, y)
+            .line_to(PAGE_WIDTH - MARGIN, y)
+            .stroke();
+    }
+
+    fn row(&mut self, columns: &[&str]) {
+        let right = PAGE_WIDTH - MARGIN;
+        if columns.len() == 1 {
+            for line in wrap(columns[0], BODY_SIZE, right - MARGIN) {
+                self.advance(BODY_SIZE * 1.4);
+                self.text(FONT_REGULAR, BODY_SIZE, MARGIN, &line);
+            }
+            return;
+        }
+        let stops = columns.len() - 1;
+        let first_width = right - stops as f32 * COLUMN_WIDTH - MARGIN;
+        let first = wrap(columns[0], BODY_SIZE, first_width);
+        self.advance(BODY_SIZE * 1.4);
+        self.text(FONT_REGULAR, BODY_SIZE, MARGIN, &first[0]);
+        for (i, column) in columns[1..].iter().enumerate() {
+            let stop = right - (stops - 1 - i) as f32 * COLUMN_WIDTH;
+            self.text(FONT_REGULAR, BODY_SIZE, stop - text_width(column, BODY_SIZE), column);
+        }
+        for line in &first[1..] {
+            self.advance(BODY_SIZE * 1.4);
+            self.text(FONT_REGULAR, BODY_SIZE, MARGIN, line);
+        }
+    }
+}
+
+/// Lay out a document and return the PDF file contents.
+pub fn render(layout: &str, title: &str) -> Vec<u8> {
+    let mut writer = Writer::new();
+    for line in layout.lines() {
+        let line = line.trim_end();
+        if let Some(text) = line.strip_prefix("## ") {
+            writer.advance(HEADING_SIZE * 1.6);
+            writer.text(FONT_BOLD, HEADING_SIZE, MARGIN, text);
+        } else if let Some(text) = line.strip_prefix("# ") {
+            writer.advance(TITLE_SIZE * 1.4);
+            writer.text(FONT_BOLD, TITLE_SIZE, MARGIN, text);
+        } else if line == "---" {
+            writer.rule();
+        } else if line.trim().is_empty() {
+            writer.advance(BODY_SIZE * 0.7);
+        } else {
+            let columns: Vec<&str> = line.split('|').map(|c| c.trim()).collect();
+            writer.row(&columns);
+        }
+    }
+
+    let mut pdf = Pdf::new();
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let regular_id = Ref::new(3);
+    let bold_id = Ref::new(4);
+    let info_id = Ref::new(5);
+    let mut next_id = 6;
+    let mut page_ids: Vec<Ref> = Vec::new();
+    for content in writer.pages {
+        let page_id = Ref::new(next_id);
+        let content_id = Ref::new(next_id + 1);
+        next_id += 2;
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        page.resources().fonts().pair(FONT_REGULAR, regular_id).pair(FONT_BOLD, bold_id);
+        drop(page);
+        pdf.stream(content_id, &content.finish());
+        page_ids.push(page_id);
+    }
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    let count = page_ids.len() as i32;
+    pdf.pages(page_tree_id).kids(page_ids).count(count);
+    pdf.type1_font(regular_id).base_font(Name(b"Helvetica")).encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.type1_font(bold_id).base_font(Name(b"Helvetica-Bold")).encoding_predefined(Name(b"WinAnsiEncoding"));
+    pdf.document_info(info_id).title(pdf_writer::TextStr(title)).producer(pdf_writer::TextStr("minibooks"));
+    pdf.finish()
+}
@@ -0,0 +1,532 @@
+//! Supplier bills and payment runs.
+//!
+//! Bills are posted to the ledger when they are recorded, debiting expense accounts and the
+//! `inputVatAccount` setting and crediting the supplier's control account (or the
+//! `creditorsControlAccount` setting). Reverse charge lines carry no tax on the bill, but the tax
+//! is posted to both the input and output VAT accounts. Payments, whole or part, are allocated to
+//! bills so that the amount outstanding on each bill is known.
+//!
+//! A payment run proposes payment of the amount outstanding on every bill due on or before a
+//! date. The proposal is stored so that it can be reviewed by another user, and confirming it posts
+//! one payment journal per bill from a cash account in a single batch.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{contacts, db, ledger};
+use crate::allocations::{Document, Settlement};
+use crate::contacts::ContactKind;
+use crate::error::Error::InstructionError;
+use crate::ledger::{AccountType, BatchMetadata, Journal, JournalEntry};
+use crate::{settings, tax, vat};
+use crate::tax::TaxTreatment;
+use crate::users::{Role, User};
+
+/// A bill, as given when it is recorded.
+#[derive(Clone, Deserialize)]
+pub struct BillData {
+    pub contact_id: i64,
+    /// The supplier's own invoice number.
+    pub supplier_reference: Option<String>,
+    pub date: NaiveDate,
+    pub due_date: NaiveDate,
+    pub lines: Vec<BillLineData>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BillLineData {
+    pub description: String,
+    /// The expense account to debit.
+    pub account_id: i64,
+    pub net_amount: i64,
+    pub tax_code: String,
+    /// The tax shown on the supplier's bill, if it differs from the amount calculated from the
+    /// tax code because of rounding.
+    pub tax_amount: Option<i64>,
+}
+
+/// The bill fields stored in the database.
+#[derive(Serialize)]
+pub struct BillHeader {
+    pub contact_id: i64,
+    pub supplier_reference: Option<String>,
+    pub date: String,
+    pub due_date: String,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow, Serialize)]
+pub struct BillLine {
+    pub description: String,
+    pub tax_code: String,
+    pub account_id: i64,
+    pub net_amount: i64,
+    pub tax_amount: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Bill {
+    pub bill_id: i64,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub supplier_reference: Option<String>,
+    pub date: String,
+    pub due_date: String,
+    pub journal_id: i64,
+    pub net_total: i64,
+    pub tax_total: i64,
+    pub total: i64,
+    pub paid: i64,
+    pub outstanding: i64,
+    pub lines: Vec<BillLine>,
+}
+
+impl Bill {
+    fn from_result(r: db::BillResult, lines: Vec<BillLine>) -> Self {
+        let total = r.net_total + r.tax_total;
+        Bill {
+            bill_id: r.bill_id,
+            contact_id: r.contact_id,
+            contact_name: r.contact_name,
+            supplier_reference: r.supplier_reference,
+            date: r.date,
+            due_date: r.due_date,
+            journal_id: r.journal_id,
+            net_total: r.net_total,
+            tax_total: r.tax_total,
+            total,
+            paid: r.paid,
+            outstanding: total - r.paid,
+            lines,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum PaymentRunState {
+    /// The payment run has been proposed and may be reviewed before it is confirmed.
+    Proposed,
+    /// The payments in the run have been posted to the ledger.
+    Confirmed,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PaymentRun {
+    pub payment_run_id: i64,
+    pub state: PaymentRunState,
+    pub due_by: String,
+    pub payment_date: String,
+    pub cash_account_id: i64,
+    pub batch_id: Option<i64>,
+    /// The ID of the user who proposed the run.
+    pub created_by: Option<i64>,
+    pub total: i64,
+    pub lines: Vec<PaymentRunLine>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PaymentRunLine {
+    pub bill_id: i64,
+    pub contact_id: i64,
+    pub contact_name: String,
+    pub supplier_reference: Option<String>,
+    pub due_date: String,
+    pub amount: i64,
+}
+
+/// The supplier's control account, or the `creditorsControlAccount` setting.
+pub async fn creditors_account(e: &Pool<Sqlite>, contact_id: i64) -> Result<i64> {
+    Ok(match contacts::contact_detail(e, contact_id).await?.control_account_id {
+        Some(account_id) => account_id,
+        None => settings::get_settings_int(e, "creditorsControlAccount").await.unwrap(),
+    })
+}
+
+pub async fn cash_account_validate(e: impl SqliteExecutor<'_>, account_id: i64) -> Result<()> {
+    if db::account_type_query(e, account_id).await? != Some(AccountType::Cash) {
+        bail!(InstructionError(format!("account {account_id} is not a cash account")));
+    }
+    Ok(())
+}
+
+/// Record a bill and post it to the ledger, returning the bill ID.
+pub async fn bill_new(e: &Pool<Sqlite>, bill: &BillData, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    let contact = contacts::contact_detail(e, bill.contact_id).await?;
+    if contact.kind != ContactKind::Supplier {
+        bail!(InstructionError("bills can only be recorded from suppliers".to_string()));
+    }
+    if bill.due_date < bill.date {
+        bail!(InstructionError("due date is before bill date".to_string()));
+    }
+    if bill.supplier_reference.as_ref().is_some_and(|r| r.len() > 140) {
+        bail!(InstructionError("supplier reference over 140 chars".to_string()));
+    }
+    if bill.lines.is_empty() {
+        bail!(InstructionError("bill has no lines".to_string()));
+    }
+    vat::lock_check(e, bill.date.to_string().as_str()).await?;
+    let mut lines: Vec<BillLine> = Vec::new();
+    let mut reverse_charges: BTreeMap<&str, i64> = BTreeMap::new();
+    for line in &bill.lines {
+        if line.description.len() > 140 {
+            bail!(InstructionError("line description over 140 chars".to_string()));
+        }
+        match db::account_type_query(e, line.account_id).await? {
+            Some(AccountType::DirectExpense) | Some(AccountType::IndirectExpense) => {}
+            // Capital purchases, to be added to the fixed asset register.
+            Some(AccountType::NonCurrentAsset) => {}
+            _ => bail!(InstructionError(format!("account {} is not an expense or fixed asset account", line.account_id))),
+        }
+        let (treatment, rate) = tax::tax_code(e, line.tax_code.as_str(), bill.date.to_string().as_str()).await?;
+        let tax_amount = match treatment {
+            TaxTreatment::ReverseCharge => {
+                *reverse_charges.entry(line.tax_code.as_str()).or_default() += tax::tax_amount(line.net_amount, rate);
+                0
+            }
+            _ => line.tax_amount.unwrap_or(tax::tax_amount(line.net_amount, rate)),
+        };
+        lines.push(BillLine {
+            description: line.description.clone(),
+            tax_code: line.tax_code.clone(),
+            account_id: line.account_id,
+            net_amount: line.net_amount,
+            tax_amount,
+        });
+    }
+
+    let creditors_account = creditors_account(e, bill.contact_id).await?;
+    let input_vat_account = settings::get_settings_int(e, "inputVatAccount").await.unwrap();
+    let output_vat_account = settings::get_settings_int(e, "outputVatAccount").await.unwrap();
+    let mut expenses: BTreeMap<(i64, &str), i64> = BTreeMap::new();
+    let mut taxes: BTreeMap<&str, i64> = BTreeMap::new();
+    for line in &lines {
+        *expenses.entry((line.account_id, line.tax_code.as_str())).or_default() += line.net_amount;
+        *taxes.entry(line.tax_code.as_str()).or_default() += line.tax_amount;
+    }
+    let total: i64 = lines.iter().map(|l| l.net_amount + l.tax_amount).sum();
+    let mut entries: Vec<JournalEntry> = expenses
+        .into_iter()
+        .map(|((account, tax_code), amount)| JournalEntry {
+            account,
+            amount,
+            tax_code: Some(tax_code.to_string()),
+            ..Default::default()
+        })
+        .collect();
+    for (tax_code, amount) in taxes.into_iter().filter(|(_, amount)| *amount != 0) {
+        entries.push(JournalEntry {
+            account: input_vat_account,
+            amount,
+            tax_code: Some(tax_code.to_string()),
+            ..Default::default()
+        });
+    }
+    for (tax_code, amount) in reverse_charges.into_iter().filter(|(_, amount)| *amount != 0) {
+        entries.push(JournalEntry {
+            account: input_vat_account,
+            amount,
+            tax_code: Some(tax_code.to_string()),
+            ..Default::default()
+        });
+        entries.push(JournalEntry {
+            account: output_vat_account,
+            amount: -amount,
+            tax_code: Some(tax_code.to_string()),
+            ..Default::default()
+        });
+    }
+    entries.push(JournalEntry {
+        account: creditors_account,
+        amount: -total,
+        contact: Some(bill.contact_id),
+        ..Default::default()
+    });
+    let journal = Journal {
+        unstructured_narrative: ledger::narrative_truncate(format!("Bill {} {}",
+            bill.supplier_reference.as_deref().unwrap_or(""), contact.name)),
+        entries,
+    };
+    ledger::journals_validate(e, std::slice::from_ref(&journal)).await?;
+
+    let header = BillHeader {
+        contact_id: bill.contact_id,
+        supplier_reference: bill.supplier_reference.clone(),
+        date: bill.date.to_string(),
+        due_date: bill.due_date.to_string(),
+    };
+    let metadata = BatchMetadata {
+        date: Some(header.date.clone()),
+        generated_by: Some("bill".to_string()),
+        created_by: Some(user.user_id),
+    };
+    Ok(db::bill_new_tx(e, &header, &lines, &metadata, journal).await?)
+}
+
+pub async fn bill_detail(e: &Pool<Sqlite>, bill_id: i64) -> Result<Bill> {
+    let Some(result) = db::bill_query(e, bill_id).await? else {
+        bail!(InstructionError(format!("bill {bill_id} does not exist")));
+    };
+    let lines = db::bill_line_query(e, bill_id).await?;
+    Ok(Bill::from_result(result, lines))
+}
+
+/// List bills, without their lines.
+pub async fn bill_list(e: impl SqliteExecutor<'_>, contact_id: Option<i64>, outstanding: bool,
+                       due_by: Option<NaiveDate>) -> Result<Vec<Bill>> {
+    let due_by = due_by.map(|d| d.to_string());
+    Ok(db::bill_list_query(e, contact_id, outstanding, due_by.as_deref()).await?
+        .into_iter()
+        .map(|r| Bill::from_result(r, Vec::new()))
+        .collect())
+}
+
+/// Build the payment of `amount` against a bill from a cash account.
+async fn bill_payment(e: &Pool<Sqlite>, bill: &Bill, amount: i64, cash_account_id: i64) -> Result<Settlement> {
+    if amount <= 0 || amount > bill.outstanding {
+        bail!(InstructionError(format!("payment must be between 1 and {} for bill {}", bill.outstanding,
+            bill.bill_id)));
+    }
+    Ok(Settlement {
+        document: Document::Bill(bill.bill_id),
+        amount,
+        journal: Journal {
+            unstructured_narrative: ledger::narrative_truncate(format!("Payment of bill {} {}",
+                bill.supplier_reference.as_deref().unwrap_or(""), bill.contact_name)),
+            entries: vec![
+                JournalEntry {
+                    account: creditors_account(e, bill.contact_id).await?,
+                    amount,
+                    contact: Some(bill.contact_id),
+                    ..Default::default()
+                },
+                JournalEntry {
+                    account: cash_account_id,
+                    amount: -amount,
+                    ..Default::default()
+                },
+            ],
+        },
+    })
+}
+
+/// Pay some or all of the amount outstanding on a bill, returning the journal ID.
+pub async fn bill_pay(e: &Pool<Sqlite>, bill_id: i64, amount: i64, date: NaiveDate, cash_account_id: i64, user: &User)
+                      -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    cash_account_validate(e, cash_account_id).await?;
+    vat::lock_check(e, date.to_string().as_str()).await?;
+    let bill = bill_detail(e, bill_id).await?;
+    let payment = bill_payment(e, &bill, amount, cash_account_id).await?;
+    ledger::journals_validate(e, std::slice::from_ref(&payment.journal)).await?;
+    let metadata = BatchMetadata {
+        date: Some(date.to_string()),
+        generated_by: Some("billPayment".to_string()),
+        created_by: Some(user.user_id),
+    };
+    Ok(db::settlements_tx(e, &metadata, vec![payment]).await?.1[0])
+}
+
+/// Propose paying the amount outstanding on every bill due on or before `due_by`.
+///
+/// Returns [`None`] if there are no bills to pay.
+pub async fn payment_run_propose(e: &Pool<Sqlite>, due_by: NaiveDate, payment_date: NaiveDate,
+                                 cash_account_id: i64, user: &User) -> Result<Option<i64>> {
+    user.require(Role::Bookkeeper)?;
+    cash_account_validate(e, cash_account_id).await?;
+    let lines: Vec<(i64, i64)> = bill_list(e, None, true, Some(due_by)).await?
+        .into_iter()
+        .filter(|b| b.outstanding > 0)
+        .map(|b| (b.bill_id, b.outstanding))
+        .collect();
+    if lines.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(db::payment_run_new_tx(e, due_by.to_string().as_str(), payment_date.to_string().as_str(),
+                                   cash_account_id, &lines, user.user_id).await?))
+}
+
+pub async fn payment_run_detail(e: &Pool<Sqlite>, payment_run_id: i64) -> Result<PaymentRun> {
+    let Some(r) = db::payment_run_query(e, payment_run_id).await? else {
+        bail!(InstructionError(format!("payment run {payment_run_id} does not exist")));
+    };
+    let lines: Vec<PaymentRunLine> = db::payment_run_line_query(e, payment_run_id).await?
+        .into_iter()
+        .map(|l| PaymentRunLine {
+            bill_id: l.bill_id,
+            contact_id: l.contact_id,
+            contact_name: l.contact_name,
+            supplier_reference: l.supplier_reference,
+            due_date: l.due_date,
+            amount: l.amount,
+        })
+        .collect();
+    Ok(PaymentRun {
+        payment_run_id: r.payment_run_id,
+        state: r.state,
+        due_by: r.due_by,
+        payment_date: r.payment_date,
+        cash_account_id: r.cash_account_id,
+        batch_id: r.batch_id,
+        created_by: r.created_by,
+        total: lines.iter().map(|l| l.amount).sum(),
+        lines,
+    })
+}
+
+/// Confirm a proposed payment run, posting its payments in a single batch and returning the
+/// batch ID. The run must be confirmed by a different user to the one who proposed it.
+pub async fn payment_run_confirm(e: &Pool<Sqlite>, payment_run_id: i64, user: &User) -> Result<i64> {
+    user.require(Role::Approver)?;
+    let run = payment_run_detail(e, payment_run_id).await?;
+    if run.state != PaymentRunState::Proposed {
+        bail!(InstructionError(format!("payment run {payment_run_id} is not proposed")));
+    }
+    if run.created_by == Some(user.user_id) {
+        bail!(InstructionError("a payment run must be confirmed by a different user to the one who proposed it"
+            .to_string()));
+    }
+    vat::lock_check(e, run.payment_date.as_str()).await?;
+    let mut payments: Vec<Settlement> = Vec::new();
+    for line in &run.lines {
+        let bill = bill_detail(e, line.bill_id).await?;
+        payments.push(bill_payment(e, &bill, line.amount, run.cash_account_id).await?);
+    }
+    for payment in &payments {
+        ledger::journals_validate(e, std::slice::from_ref(&payment.journal)).await?;
+    }
+    let metadata = BatchMetadata {
+        date: Some(run.payment_date.clone()),
+        generated_by: Some("paymentRun".to_string()),
+        created_by: Some(user.user_id),
+    };
+    match db::payment_run_confirm_tx(e, payment_run_id, &metadata, payments).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("payment run {payment_run_id} is not proposed"))),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contacts::ContactData;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn user(user_id: i64, role: Role) -> User {
+        User {
+            user_id,
+            name: format!("user{user_id}"),
+            role,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 4, day).unwrap()
+    }
+
+    /// Record a bill for 100.00 plus standard rate VAT from a new supplier, returning its ID.
+    async fn bill(e: &Pool<Sqlite>, due_day: u32) -> i64 {
+        let admin = user(1, Role::Admin);
+        let expense_account = ledger::account_new(e, None, &"Rent".to_string(), &AccountType::IndirectExpense, None,
+                                                  None, &admin).await.unwrap();
+        let contact_id = contacts::contact_new(e, &ContactData {
+            kind: ContactKind::Supplier,
+            name: "Landlord".to_string(),
+            address: None,
+            postcode: None,
+            country: None,
+            email: None,
+            tax_id: None,
+            control_account_id: None,
+        }, &admin).await.unwrap();
+        let data = BillData {
+            contact_id,
+            supplier_reference: Some("R-1".to_string()),
+            date: date(1),
+            due_date: date(due_day),
+            lines: vec![BillLineData {
+                description: "Rent".to_string(),
+                account_id: expense_account,
+                net_amount: 10000,
+                tax_code: "STANDARD".to_string(),
+                tax_amount: None,
+            }],
+        };
+        bill_new(e, &data, &admin).await.unwrap()
+    }
+
+    async fn balance(e: &Pool<Sqlite>, account_id: i64) -> i64 {
+        ledger::account_detail(e, account_id).await.unwrap().balance
+    }
+
+    #[actix_web::test]
+    async fn bill_new_posts_expense_vat_and_creditors() {
+        let db = entities::memory_connect().await;
+        let bill = bill_detail(&db, bill(&db, 30).await).await.unwrap();
+        assert_eq!((bill.net_total, bill.tax_total, bill.outstanding), (10000, 2000, 12000));
+        assert_eq!(balance(&db, bill.lines[0].account_id).await, 10000);
+        assert_eq!(balance(&db, 271).await, 2000);
+        assert_eq!(balance(&db, 260).await, -12000);
+        assert_eq!(db::account_type_query(&db, 271).await.unwrap(), Some(AccountType::CurrentAsset));
+    }
+
+    #[actix_web::test]
+    async fn bill_pay_allows_part_payment_up_to_the_amount_outstanding() {
+        let db = entities::memory_connect().await;
+        let bill_id = bill(&db, 30).await;
+        let bookkeeper = user(1, Role::Bookkeeper);
+        bill_pay(&db, bill_id, 5000, date(10), 100, &bookkeeper).await.unwrap();
+        assert_eq!(bill_detail(&db, bill_id).await.unwrap().outstanding, 7000);
+        assert!(is_instruction_error(bill_pay(&db, bill_id, 7001, date(11), 100, &bookkeeper).await));
+        assert!(is_instruction_error(bill_pay(&db, bill_id, 7000, date(11), 271, &bookkeeper).await));
+        bill_pay(&db, bill_id, 7000, date(11), 100, &bookkeeper).await.unwrap();
+        assert_eq!(bill_detail(&db, bill_id).await.unwrap().outstanding, 0);
+        assert_eq!(balance(&db, 260).await, 0);
+        assert_eq!(balance(&db, 100).await, -12000);
+    }
+
+    #[actix_web::test]
+    async fn payment_run_pays_bills_due_once_confirmed_by_another_user() {
+        let db = entities::memory_connect().await;
+        let due = bill(&db, 15).await;
+        let later = bill(&db, 30).await;
+        let payment_run_id = payment_run_propose(&db, date(20), date(20), 100, &user(1, Role::Approver)).await
+            .unwrap()
+            .unwrap();
+        let run = payment_run_detail(&db, payment_run_id).await.unwrap();
+        assert_eq!(run.lines.iter().map(|l| l.bill_id).collect::<Vec<_>>(), vec![due]);
+        assert_eq!(run.total, 12000);
+
+        assert!(is_instruction_error(payment_run_confirm(&db, payment_run_id, &user(1, Role::Approver)).await));
+        payment_run_confirm(&db, payment_run_id, &user(2, Role::Approver)).await.unwrap();
+        assert!(is_instruction_error(payment_run_confirm(&db, payment_run_id, &user(2, Role::Approver)).await));
+        assert_eq!(bill_detail(&db, due).await.unwrap().outstanding, 0);
+        assert_eq!(bill_detail(&db, later).await.unwrap().outstanding, 12000);
+        assert_eq!(balance(&db, 100).await, -12000);
+    }
+
+    #[actix_web::test]
+    async fn payment_run_propose_returns_none_with_no_bills_due() {
+        let db = entities::memory_connect().await;
+        bill(&db, 30).await;
+        let proposed = payment_run_propose(&db, date(20), date(20), 100, &user(1, Role::Bookkeeper)).await.unwrap();
+        assert!(proposed.is_none());
+    }
+
+    #[actix_web::test]
+    async fn account_new_numbers_current_liabilities_after_seeded_accounts() {
+        let db = entities::memory_connect().await;
+        let account_id = ledger::account_new(&db, None, &"Loan".to_string(), &AccountType::CurrentLiability, None,
+                                             None, &user(1, Role::Admin)).await.unwrap();
+        assert_eq!(account_id, 272);
+    }
+}
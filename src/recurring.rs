@@ -0,0 +1,355 @@
+//! Recurring journal templates.
+//!
+//! A template holds the entries of a journal that is posted regularly, such as rent or salaries,
+//! with a rule for when it recurs between its start date and an optional end date.
+//! [`recurring_journal_post`] posts every occurrence that has fallen due and has not yet been
+//! posted, so it can be run as often as needed without posting any occurrence twice.
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, fx, ledger, tax};
+use crate::error::Error::InstructionError;
+use crate::ledger::{BatchMetadata, EntryData, Journal, JournalEntry};
+use crate::users::{Role, User};
+
+/// When a recurring journal falls due.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum Recurrence {
+    /// Monthly on the day of the month given by the template, or the last day of shorter months.
+    MonthlyOnDay,
+    /// Every three months from the start date.
+    Quarterly,
+    /// Every year from the start date.
+    Annually,
+    /// On the last day of every month.
+    EndOfMonth,
+}
+
+/// The details of a recurring journal, as given when the template is created.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RecurringJournalData {
+    pub unstructured_narrative: String,
+    pub recurrence: Recurrence,
+    /// The day of the month, for [`Recurrence::MonthlyOnDay`].
+    pub day: Option<i64>,
+    pub start_date: NaiveDate,
+    /// The last date an occurrence may fall on, if the journal stops recurring.
+    pub end_date: Option<NaiveDate>,
+    /// The entries, which balance once any tax has been added as for `POST /journal/new`.
+    pub entries: Vec<EntryData>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RecurringPosting {
+    /// The date of the occurrence.
+    pub date: String,
+    pub journal_id: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RecurringJournal {
+    pub recurring_journal_id: i64,
+    pub unstructured_narrative: String,
+    pub recurrence: Recurrence,
+    pub day: Option<i64>,
+    pub start_date: String,
+    pub end_date: Option<String>,
+    /// The date of the last occurrence posted.
+    pub last_posted: Option<String>,
+    /// The entries and postings. Only filled in by [`recurring_journal_detail`].
+    pub entries: Vec<EntryData>,
+    pub postings: Vec<RecurringPosting>,
+}
+
+impl From<db::RecurringJournalResult> for RecurringJournal {
+    fn from(r: db::RecurringJournalResult) -> Self {
+        RecurringJournal {
+            recurring_journal_id: r.recurring_journal_id,
+            unstructured_narrative: r.unstructured_narrative,
+            recurrence: r.recurrence,
+            day: r.day,
+            start_date: r.start_date,
+            end_date: r.end_date,
+            last_posted: r.last_posted,
+            entries: Vec::new(),
+            postings: Vec::new(),
+        }
+    }
+}
+
+impl RecurringJournal {
+    /// The dates of every occurrence up to and including `to`, in order.
+    pub fn occurrences(&self, to: NaiveDate) -> Vec<NaiveDate> {
+        let start = NaiveDate::parse_from_str(self.start_date.as_str(), "%Y-%m-%d").unwrap();
+        let to = match &self.end_date {
+            Some(end_date) => to.min(NaiveDate::parse_from_str(end_date.as_str(), "%Y-%m-%d").unwrap()),
+            None => to,
+        };
+        let month_start = NaiveDate::from_ymd_opt(start.year(), start.month(), 1).unwrap();
+        let mut dates: Vec<NaiveDate> = Vec::new();
+        for n in 0.. {
+            let date = match self.recurrence {
+                Recurrence::MonthlyOnDay => {
+                    let month_end = fx::period_end(month_start + Months::new(n));
+                    let day = (self.day.unwrap_or(1) as u32).min(month_end.day());
+                    month_end.with_day(day).unwrap()
+                }
+                Recurrence::Quarterly => start + Months::new(3 * n),
+                Recurrence::Annually => start + Months::new(12 * n),
+                Recurrence::EndOfMonth => fx::period_end(month_start + Months::new(n)),
+            };
+            if date > to {
+                break;
+            }
+            if date >= start {
+                dates.push(date);
+            }
+        }
+        dates
+    }
+
+    /// The journal for the occurrence on `date`, with tax added at the rates effective then. The
+    /// narrative is shortened if need be to keep the date of the occurrence.
+    async fn journal(&self, e: &Pool<Sqlite>, date: NaiveDate) -> Result<Journal> {
+        let suffix = format!(" ({date})");
+        let mut narrative = self.unstructured_narrative.clone();
+        while narrative.len() + suffix.len() > 140 {
+            narrative.pop();
+        }
+        let mut journals = vec![Journal {
+            unstructured_narrative: narrative + suffix.as_str(),
+            entries: self.entries.iter().map(JournalEntry::from).collect(),
+        }];
+        tax::journals_tax(e, &mut journals, date.to_string().as_str()).await?;
+        Ok(journals.remove(0))
+    }
+}
+
+/// Create a recurring journal, returning its ID.
+pub async fn recurring_journal_new(e: &Pool<Sqlite>, recurring: &RecurringJournalData, user: &User) -> Result<i64> {
+    user.require(Role::Bookkeeper)?;
+    if recurring.unstructured_narrative.is_empty() || recurring.unstructured_narrative.len() > 140 {
+        bail!(InstructionError("unstructured narrative must be 1-140 chars".to_string()));
+    }
+    match (recurring.recurrence, recurring.day) {
+        (Recurrence::MonthlyOnDay, Some(day)) if (1..=31).contains(&day) => {}
+        (Recurrence::MonthlyOnDay, _) => bail!(InstructionError("day must be 1-31".to_string())),
+        (_, Some(_)) => bail!(InstructionError("day only applies to the monthly on day recurrence".to_string())),
+        (_, None) => {}
+    }
+    if recurring.end_date.is_some_and(|end_date| end_date < recurring.start_date) {
+        bail!(InstructionError("recurring journal ends before it starts".to_string()));
+    }
+    if recurring.entries.is_empty() {
+        bail!(InstructionError("recurring journal has no entries".to_string()));
+    }
+    let template = RecurringJournal {
+        recurring_journal_id: 0,
+        unstructured_narrative: recurring.unstructured_narrative.clone(),
+        recurrence: recurring.recurrence,
+        day: recurring.day,
+        start_date: recurring.start_date.to_string(),
+        end_date: None,
+        last_posted: None,
+        entries: recurring.entries.clone(),
+        postings: Vec::new(),
+    };
+    ledger::journals_validate(e, &[template.journal(e, recurring.start_date).await?]).await?;
+    Ok(db::recurring_journal_new_tx(e, recurring, user.user_id).await?)
+}
+
+/// Get a recurring journal with its entries and the occurrences posted.
+pub async fn recurring_journal_detail(e: &Pool<Sqlite>, recurring_journal_id: i64) -> Result<RecurringJournal> {
+    let Some(result) = db::recurring_journal_query(e, recurring_journal_id).await? else {
+        bail!(InstructionError(format!("recurring journal {recurring_journal_id} does not exist")));
+    };
+    let mut recurring = RecurringJournal::from(result);
+    recurring.entries = db::recurring_journal_entry_query(e, recurring_journal_id).await?;
+    recurring.postings = db::recurring_journal_posting_query(e, recurring_journal_id).await?
+        .into_iter()
+        .map(|r| RecurringPosting {
+            date: r.date,
+            journal_id: r.journal_id,
+        })
+        .collect();
+    Ok(recurring)
+}
+
+pub async fn recurring_journal_list(e: impl SqliteExecutor<'_>) -> Result<Vec<RecurringJournal>> {
+    Ok(db::recurring_journal_list_query(e).await?
+        .into_iter()
+        .map(RecurringJournal::from)
+        .collect())
+}
+
+/// An occurrence of a recurring journal posted by [`recurring_journal_post`].
+pub struct RecurringOccurrence {
+    pub recurring_journal_id: i64,
+    pub date: String,
+}
+
+/// The batch posted by [`recurring_journal_post`].
+pub struct RecurringRun {
+    pub batch_id: i64,
+    pub journal_count: usize,
+}
+
+/// Post every occurrence of every recurring journal due on or before `date` that has not already
+/// been posted, in a single batch dated `date`.
+///
+/// Each journal's narrative records the date of its occurrence. Returns [`None`] if nothing was
+/// due, in which case no batch is posted.
+pub async fn recurring_journal_post(e: &Pool<Sqlite>, date: NaiveDate, user: &User) -> Result<Option<RecurringRun>> {
+    user.require(Role::Bookkeeper)?;
+    let mut journals: Vec<Journal> = Vec::new();
+    let mut occurrences: Vec<RecurringOccurrence> = Vec::new();
+    for recurring in recurring_journal_list(e).await? {
+        let recurring = recurring_journal_detail(e, recurring.recurring_journal_id).await?;
+        let posted: BTreeSet<&str> = recurring.postings.iter().map(|p| p.date.as_str()).collect();
+        for occurrence in recurring.occurrences(date) {
+            let occurrence_str = occurrence.to_string();
+            if posted.contains(occurrence_str.as_str()) {
+                continue;
+            }
+            journals.push(recurring.journal(e, occurrence).await?);
+            occurrences.push(RecurringOccurrence {
+                recurring_journal_id: recurring.recurring_journal_id,
+                date: occurrence_str,
+            });
+        }
+    }
+    if journals.is_empty() {
+        return Ok(None);
+    }
+    let metadata = BatchMetadata {
+        date: Some(date.to_string()),
+        generated_by: Some("recurringJournal".to_string()),
+        created_by: Some(user.user_id),
+    };
+    ledger::batch_validate(e, &metadata, &journals).await?;
+    let journal_count = journals.len();
+    let batch_id = db::recurring_journal_post_tx(e, &metadata, journals, &occurrences).await?;
+    Ok(Some(RecurringRun {
+        batch_id,
+        journal_count,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn bookkeeper() -> User {
+        User {
+            user_id: 1,
+            name: "bookkeeper".to_string(),
+            role: Role::Bookkeeper,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn entry(account: i64, amount: i64) -> EntryData {
+        EntryData {
+            account,
+            amount,
+            currency_amount: None,
+            contact: None,
+            tax_code: None,
+            tags: Default::default(),
+        }
+    }
+
+    fn data(unstructured_narrative: &str, recurrence: Recurrence, day: Option<i64>, start_date: &str)
+            -> RecurringJournalData {
+        RecurringJournalData {
+            unstructured_narrative: unstructured_narrative.to_string(),
+            recurrence,
+            day,
+            start_date: date(start_date),
+            end_date: None,
+            entries: vec![entry(230, 1000), entry(100, -1000)],
+        }
+    }
+
+    fn template(data: &RecurringJournalData) -> RecurringJournal {
+        RecurringJournal {
+            recurring_journal_id: 0,
+            unstructured_narrative: data.unstructured_narrative.clone(),
+            recurrence: data.recurrence,
+            day: data.day,
+            start_date: data.start_date.to_string(),
+            end_date: data.end_date.map(|d| d.to_string()),
+            last_posted: None,
+            entries: data.entries.clone(),
+            postings: Vec::new(),
+        }
+    }
+
+    fn occurrences(data: &RecurringJournalData, to: &str) -> Vec<String> {
+        template(data).occurrences(date(to)).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn occurrences_fall_on_the_day_or_the_end_of_shorter_months() {
+        let monthly = data("Rent", Recurrence::MonthlyOnDay, Some(31), "2024-01-15");
+        assert_eq!(occurrences(&monthly, "2024-04-30"), vec!["2024-01-31", "2024-02-29", "2024-03-31", "2024-04-30"]);
+        let monthly = data("Rent", Recurrence::MonthlyOnDay, Some(10), "2024-01-15");
+        assert_eq!(occurrences(&monthly, "2024-03-09"), vec!["2024-02-10"]);
+        let quarterly = data("Rent", Recurrence::Quarterly, None, "2024-01-31");
+        assert_eq!(occurrences(&quarterly, "2024-12-31"), vec!["2024-01-31", "2024-04-30", "2024-07-31", "2024-10-31"]);
+        let mut end_of_month = data("Rent", Recurrence::EndOfMonth, None, "2024-01-15");
+        end_of_month.end_date = Some(date("2024-03-30"));
+        assert_eq!(occurrences(&end_of_month, "2024-12-31"), vec!["2024-01-31", "2024-02-29"]);
+    }
+
+    #[actix_web::test]
+    async fn recurring_journal_post_posts_each_occurrence_once() {
+        let db = entities::memory_connect().await;
+        let recurring = data("Rent", Recurrence::MonthlyOnDay, Some(1), "2024-01-01");
+        let recurring_journal_id = recurring_journal_new(&db, &recurring, &bookkeeper()).await.unwrap();
+
+        let run = recurring_journal_post(&db, date("2024-03-15"), &bookkeeper()).await.unwrap().unwrap();
+        assert_eq!(run.journal_count, 3);
+        assert!(recurring_journal_post(&db, date("2024-03-31"), &bookkeeper()).await.unwrap().is_none());
+        let run = recurring_journal_post(&db, date("2024-04-01"), &bookkeeper()).await.unwrap().unwrap();
+        assert_eq!(run.journal_count, 1);
+
+        let recurring = recurring_journal_detail(&db, recurring_journal_id).await.unwrap();
+        let dates: Vec<&str> = recurring.postings.iter().map(|p| p.date.as_str()).collect();
+        assert_eq!(dates, vec!["2024-01-01", "2024-02-01", "2024-03-01", "2024-04-01"]);
+        assert_eq!(ledger::account_detail(&db, 230).await.unwrap().balance, 4000);
+    }
+
+    #[actix_web::test]
+    async fn recurring_journal_new_allows_140_char_narratives() {
+        let db = entities::memory_connect().await;
+        let narrative = "x".repeat(140);
+        let recurring = data(narrative.as_str(), Recurrence::EndOfMonth, None, "2024-01-01");
+        recurring_journal_new(&db, &recurring, &bookkeeper()).await.unwrap();
+        let journal = template(&recurring).journal(&db, date("2024-01-31")).await.unwrap();
+        assert_eq!(journal.unstructured_narrative, format!("{} (2024-01-31)", "x".repeat(127)));
+
+        let recurring = data("x".repeat(141).as_str(), Recurrence::EndOfMonth, None, "2024-01-01");
+        assert!(is_instruction_error(recurring_journal_new(&db, &recurring, &bookkeeper()).await));
+        let mut recurring = data("Rent", Recurrence::EndOfMonth, Some(1), "2024-01-01");
+        assert!(is_instruction_error(recurring_journal_new(&db, &recurring, &bookkeeper()).await));
+        recurring.entries.pop();
+        recurring.day = None;
+        assert!(recurring_journal_new(&db, &recurring, &bookkeeper()).await.is_err());
+    }
+}
@@ -0,0 +1,615 @@
+//! The financial statements and the budget variance report.
+//!
+//! The profit and loss account totals the profit and loss accounts over a period, either for all entries, for the
+//! entries tagged with one value of a dimension, or with a column for each value of a dimension.
+//! Amounts are balances, debits positive and credits negative, as in the other reports, while the
+//! profit is positive for a profit.
+//!
+//! The balance sheet shows the assets, liabilities and capital as at a date in the statutory
+//! format, and is tagged for filing by [`crate::ixbrl`].
+//!
+//! Each financial statement may be compared with other periods, or other dates for a balance
+//! sheet, which are shown as additional columns, as statutory accounts show the prior year.
+//!
+//! The budget variance report compares the same totals with the budgets for a period of whole
+//! months and for the fiscal year to date. Variances are positive where the result is better than
+//! budgeted: income over budget, or expenses under it.
+//!
+//! The cash flow statement uses the indirect method, starting from the profit for a period, and
+//! shows cash inflows as positive and outflows as negative.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+
+use crate::{budgets, db, dimensions, settings};
+use crate::dimensions::{Tag, Tags};
+use crate::error::Error::InstructionError;
+use crate::ledger::AccountType;
+
+/// The column for entries not tagged with the dimension of a pivoted report.
+const UNTAGGED: &str = "untagged";
+/// The column totalling a pivoted report.
+const TOTAL: &str = "total";
+
+#[derive(Clone, Serialize)]
+pub struct ReportLine {
+    pub account_id: i64,
+    pub account_name: String,
+    pub account_type: AccountType,
+    /// The amount in each column of the report.
+    pub amounts: Vec<i64>,
+}
+
+/// A period, or a date if `from` is [`None`], that a report is compared with.
+#[derive(Clone, Serialize)]
+pub struct Period {
+    pub from: Option<String>,
+    pub to: String,
+}
+
+impl Period {
+    fn new(from: Option<NaiveDate>, to: NaiveDate) -> Self {
+        Period { from: from.map(|d| d.to_string()), to: to.to_string() }
+    }
+}
+
+/// Parse a comma-separated list of periods to compare a report with, each given as `from..to`.
+pub fn periods_parse(compare: Option<&str>) -> Result<Vec<(NaiveDate, NaiveDate)>> {
+    let mut periods: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+    for period in compare.into_iter().flat_map(|c| c.split(',')).map(str::trim).filter(|p| !p.is_empty()) {
+        let parsed = period
+            .split_once("..")
+            .and_then(|(from, to)| Some((from.parse::<NaiveDate>().ok()?, to.parse::<NaiveDate>().ok()?)));
+        match parsed {
+            Some((from, to)) if from <= to => periods.push((from, to)),
+            _ => bail!(InstructionError(format!("{period} is not a period given as from..to"))),
+        }
+    }
+    Ok(periods)
+}
+
+/// Parse a comma-separated list of dates to compare a balance sheet with.
+pub fn dates_parse(compare: Option<&str>) -> Result<Vec<NaiveDate>> {
+    let mut dates: Vec<NaiveDate> = Vec::new();
+    for date in compare.into_iter().flat_map(|c| c.split(',')).map(str::trim).filter(|d| !d.is_empty()) {
+        match date.parse::<NaiveDate>() {
+            Ok(date) => dates.push(date),
+            Err(_) => bail!(InstructionError(format!("{date} is not a date"))),
+        }
+    }
+    Ok(dates)
+}
+
+/// The total of each column of some report lines.
+fn column_totals(lines: &[ReportLine], columns: usize) -> Vec<i64> {
+    (0..columns)
+        .map(|i| lines.iter().map(|l| l.amounts[i]).sum())
+        .collect()
+}
+
+#[derive(Clone, Serialize)]
+pub struct ProfitAndLoss {
+    pub from: String,
+    pub to: String,
+    /// The periods the report is compared with, each with the same columns as the report period.
+    pub comparisons: Vec<Period>,
+    /// The tag the entries were filtered by, if any.
+    pub tag: Option<Tag>,
+    /// The heading of each column of a period: `total` alone, or each value of the pivoted
+    /// dimension followed by `untagged` and `total`.
+    pub columns: Vec<String>,
+    /// The accounts with an amount in any column. The amounts are those of each column of the
+    /// report period, followed by those of each comparison in turn.
+    pub lines: Vec<ReportLine>,
+    /// The profit in each column, in the same order as the amounts of the lines.
+    pub profit: Vec<i64>,
+}
+
+/// The profit and loss account for a period and the periods it is compared with, optionally for
+/// the entries with a tag only, or with a column for each value of the dimension `pivot`.
+pub async fn profit_and_loss(e: &Pool<Sqlite>, from: NaiveDate, to: NaiveDate,
+                             comparisons: &[(NaiveDate, NaiveDate)], tag: Option<&Tag>, pivot: Option<&str>)
+                             -> Result<ProfitAndLoss> {
+    if from > to {
+        bail!(InstructionError("period ends before it starts".to_string()));
+    }
+    if let Some(tag) = tag {
+        let tags = Tags::from([(tag.dimension.clone(), tag.value.clone())]);
+        dimensions::tags_validate(e, [&tags].into_iter()).await?;
+    }
+    let mut columns: Vec<String> = Vec::new();
+    if let Some(dimension) = pivot {
+        if tag.is_some_and(|t| t.dimension == dimension) {
+            bail!(InstructionError(format!("cannot pivot by {dimension} when filtering by it")));
+        }
+        columns = dimensions::dimension_values(e, dimension).await?;
+        columns.push(UNTAGGED.to_string());
+    }
+    columns.push(TOTAL.to_string());
+
+    let mut lines: Vec<ReportLine> = Vec::new();
+    for (i, (from, to)) in [(from, to)].iter().chain(comparisons).enumerate() {
+        let (from_date, to_date) = (from.to_string(), to.to_string());
+        let accounts = db::account_balance_query(e, Some(from_date.as_str()), Some(to_date.as_str()), tag).await?;
+        let mut balances: BTreeMap<(i64, String), i64> = BTreeMap::new();
+        if let Some(dimension) = pivot {
+            for r in db::account_dimension_balance_query(e, Some(from_date.as_str()), Some(to_date.as_str()),
+                                                         dimension, tag).await? {
+                balances.insert((r.account_id, r.value.unwrap_or(UNTAGGED.to_string())), r.balance);
+            }
+        }
+        for (j, a) in accounts.into_iter().filter(|a| a.account_type.is_profit_and_loss()).enumerate() {
+            let amounts = columns.iter().map(|c| match c.as_str() {
+                TOTAL => a.balance,
+                value => balances.get(&(a.account_id, value.to_string())).copied().unwrap_or(0),
+            });
+            if i == 0 {
+                lines.push(ReportLine {
+                    account_id: a.account_id,
+                    account_name: a.account_name,
+                    account_type: a.account_type,
+                    amounts: amounts.collect(),
+                });
+            } else {
+                lines[j].amounts.extend(amounts);
+            }
+        }
+    }
+    let lines: Vec<ReportLine> = lines.into_iter().filter(|l| l.amounts.iter().any(|a| *a != 0)).collect();
+    let profit = column_totals(&lines, columns.len() * (comparisons.len() + 1))
+        .into_iter()
+        .map(|a| -a)
+        .collect();
+    Ok(ProfitAndLoss {
+        from: from.to_string(),
+        to: to.to_string(),
+        comparisons: comparisons.iter().map(|(from, to)| Period::new(Some(*from), *to)).collect(),
+        tag: tag.cloned(),
+        columns,
+        lines,
+        profit,
+    })
+}
+
+/// The balance sheet in the statutory format.
+///
+/// Unlike the other reports, amounts are shown as they are presented in statutory accounts:
+/// assets, creditors and capital are positive, and a negative amount is a deficit, such as net
+/// current liabilities or accumulated losses.
+#[derive(Clone, Serialize)]
+pub struct BalanceSheet {
+    /// The date of the balance sheet, followed by the dates it is compared with. Each line has an
+    /// amount for each date in turn.
+    pub dates: Vec<String>,
+    /// The first day of the fiscal year ending with the balance sheet date.
+    pub period_start: String,
+    pub fixed_assets: Vec<i64>,
+    pub stocks: Vec<i64>,
+    /// Current assets other than cash and stocks, including prepayments.
+    pub debtors: Vec<i64>,
+    /// Prepayments, which are included in debtors.
+    pub prepayments: Vec<i64>,
+    pub cash: Vec<i64>,
+    pub current_assets: Vec<i64>,
+    pub creditors_within_one_year: Vec<i64>,
+    pub net_current_assets: Vec<i64>,
+    pub total_assets_less_current_liabilities: Vec<i64>,
+    pub creditors_after_one_year: Vec<i64>,
+    pub net_assets: Vec<i64>,
+    /// The balances of the equity accounts, such as called up share capital.
+    pub share_capital: Vec<i64>,
+    /// The profit accumulated on the profit and loss accounts, with unrealised exchange
+    /// differences on the system accounts.
+    pub profit_and_loss_reserve: Vec<i64>,
+    pub equity: Vec<i64>,
+}
+
+/// The balance sheet as at a date, and as at each of the dates it is compared with.
+pub async fn balance_sheet(e: &Pool<Sqlite>, as_at: NaiveDate, comparisons: &[NaiveDate]) -> Result<BalanceSheet> {
+    let dates: Vec<String> = [as_at].iter().chain(comparisons).map(|d| d.to_string()).collect();
+    if dates.iter().enumerate().any(|(i, d)| dates[..i].contains(d)) {
+        bail!(InstructionError("a balance sheet cannot be compared with the same date twice".to_string()));
+    }
+    let mut balances: Vec<BTreeMap<String, i64>> = Vec::new();
+    for date in &dates {
+        let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+        for a in db::account_balance_query(e, None, Some(date.as_str()), None).await? {
+            let line = match a.account_type {
+                AccountType::System => "ProfitAndLoss".to_string(),
+                t if t.is_profit_and_loss() => "ProfitAndLoss".to_string(),
+                t => format!("{t:?}"),
+            };
+            *totals.entry(line).or_default() += a.balance;
+        }
+        balances.push(totals);
+    }
+    let total = |types: &[&str], sign: i64| -> Vec<i64> {
+        balances.iter()
+            .map(|totals| sign * types.iter().map(|t| totals.get(*t).copied().unwrap_or(0)).sum::<i64>())
+            .collect()
+    };
+    let add = |a: &[i64], b: &[i64]| -> Vec<i64> { a.iter().zip(b).map(|(a, b)| a + b).collect() };
+    let subtract = |a: &[i64], b: &[i64]| -> Vec<i64> { a.iter().zip(b).map(|(a, b)| a - b).collect() };
+
+    let fixed_assets = total(&["NonCurrentAsset"], 1);
+    let stocks = total(&["Inventory"], 1);
+    let debtors = total(&["CurrentAsset", "Prepayments"], 1);
+    let prepayments = total(&["Prepayments"], 1);
+    let cash = total(&["Cash"], 1);
+    let current_assets = add(&add(&stocks, &debtors), &cash);
+    let creditors_within_one_year = total(&["CurrentLiability"], -1);
+    let net_current_assets = subtract(&current_assets, &creditors_within_one_year);
+    let total_assets_less_current_liabilities = add(&fixed_assets, &net_current_assets);
+    let creditors_after_one_year = total(&["NonCurrentLiability"], -1);
+    let net_assets = subtract(&total_assets_less_current_liabilities, &creditors_after_one_year);
+    let share_capital = total(&["Equity"], -1);
+    let profit_and_loss_reserve = total(&["ProfitAndLoss"], -1);
+    let equity = add(&share_capital, &profit_and_loss_reserve);
+    Ok(BalanceSheet {
+        dates,
+        period_start: budgets::year_start(e, as_at).await?.to_string(),
+        fixed_assets,
+        stocks,
+        debtors,
+        prepayments,
+        cash,
+        current_assets,
+        creditors_within_one_year,
+        net_current_assets,
+        total_assets_less_current_liabilities,
+        creditors_after_one_year,
+        net_assets,
+        share_capital,
+        profit_and_loss_reserve,
+        equity,
+    })
+}
+
+/// An actual amount compared with its budget.
+#[derive(Clone, Serialize)]
+pub struct BudgetComparison {
+    pub actual: i64,
+    pub budget: i64,
+    /// Positive where the actual amount is better than the budget.
+    pub variance: i64,
+    /// The variance as a percentage of the budget, in tenths of a percent, or [`None`] if nothing
+    /// was budgeted.
+    pub variance_percent: Option<i64>,
+}
+
+impl BudgetComparison {
+    fn new(actual: i64, budget: i64, variance: i64) -> Self {
+        BudgetComparison {
+            actual,
+            budget,
+            variance,
+            variance_percent: (budget != 0).then(|| {
+                let scaled = variance as i128 * 1000;
+                let budget = budget.unsigned_abs() as i128;
+                ((scaled + scaled.signum() * budget / 2) / budget) as i64
+            }),
+        }
+    }
+
+    /// Compare the balance of an account with its budget.
+    fn balance(actual: i64, budget: i64) -> Self {
+        BudgetComparison::new(actual, budget, budget - actual)
+    }
+
+    /// Compare a profit with the budgeted profit.
+    fn profit(actual: i64, budget: i64) -> Self {
+        BudgetComparison::new(actual, budget, actual - budget)
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct BudgetVarianceLine {
+    pub account_id: i64,
+    pub account_name: String,
+    pub account_type: AccountType,
+    pub period: BudgetComparison,
+    pub year_to_date: BudgetComparison,
+}
+
+#[derive(Clone, Serialize)]
+pub struct BudgetVariance {
+    pub from: String,
+    pub to: String,
+    /// The first day of the fiscal year the period falls in.
+    pub year_start: String,
+    /// The tag the entries and budgets were filtered by, if any.
+    pub tag: Option<Tag>,
+    /// The accounts with an actual or budgeted amount in the period or year to date.
+    pub lines: Vec<BudgetVarianceLine>,
+    /// The profit for the period, positive for a profit.
+    pub period_profit: BudgetComparison,
+    pub year_to_date_profit: BudgetComparison,
+}
+
+/// Compare the profit and loss accounts with their budgets for a period of whole months, from the
+/// first day of a month to the last day of a month in the same fiscal year, and for the fiscal
+/// year to the end of the period, optionally for the entries and budgets with a tag only.
+pub async fn budget_variance(e: &Pool<Sqlite>, from: NaiveDate, to: NaiveDate, tag: Option<&Tag>)
+                             -> Result<BudgetVariance> {
+    if from > to {
+        bail!(InstructionError("period ends before it starts".to_string()));
+    }
+    let to_month = to.with_day(1).unwrap();
+    if from.day() != 1 || to.succ_opt().is_some_and(|d| d.day() != 1) {
+        bail!(InstructionError("period must be whole months".to_string()));
+    }
+    let year_start = budgets::year_start(e, to).await?;
+    if from < year_start {
+        bail!(InstructionError(format!("period must be within the fiscal year starting {year_start}")));
+    }
+    if let Some(tag) = tag {
+        let tags = Tags::from([(tag.dimension.clone(), tag.value.clone())]);
+        dimensions::tags_validate(e, [&tags].into_iter()).await?;
+    }
+    let (from_date, to_date, year_start_date) = (from.to_string(), to.to_string(), year_start.to_string());
+    let period = db::account_balance_query(e, Some(from_date.as_str()), Some(to_date.as_str()), tag).await?;
+    let year_to_date = db::account_balance_query(e, Some(year_start_date.as_str()), Some(to_date.as_str()), tag)
+        .await?;
+    let period_budget = budgets::budget_totals(e, from, to_month, tag).await?;
+    let year_to_date_budget = budgets::budget_totals(e, year_start, to_month, tag).await?;
+
+    let lines: Vec<BudgetVarianceLine> = period
+        .into_iter()
+        .zip(year_to_date)
+        .filter(|(a, _)| a.account_type.is_profit_and_loss())
+        .map(|(a, y)| BudgetVarianceLine {
+            period: BudgetComparison::balance(a.balance, period_budget.get(&a.account_id).copied().unwrap_or(0)),
+            year_to_date: BudgetComparison::balance(y.balance,
+                                                    year_to_date_budget.get(&a.account_id).copied().unwrap_or(0)),
+            account_id: a.account_id,
+            account_name: a.account_name,
+            account_type: a.account_type,
+        })
+        .filter(|l| [&l.period, &l.year_to_date].iter().any(|c| c.actual != 0 || c.budget != 0))
+        .collect();
+    let profit = |comparison: fn(&BudgetVarianceLine) -> &BudgetComparison| BudgetComparison::profit(
+        -lines.iter().map(|l| comparison(l).actual).sum::<i64>(),
+        -lines.iter().map(|l| comparison(l).budget).sum::<i64>());
+    Ok(BudgetVariance {
+        period_profit: profit(|l| &l.period),
+        year_to_date_profit: profit(|l| &l.year_to_date),
+        from: from_date,
+        to: to_date,
+        year_start: year_start_date,
+        tag: tag.cloned(),
+        lines,
+    })
+}
+
+/// The lines of the cash flow statement adjusting the profit for working capital, one for each
+/// account type whose movement is reversed.
+const WORKING_CAPITAL_LINES: [AccountType; 4] = [AccountType::CurrentAsset, AccountType::Inventory,
+    AccountType::Prepayments, AccountType::CurrentLiability];
+const FINANCING_LINES: [AccountType; 2] = [AccountType::NonCurrentLiability, AccountType::Equity];
+
+#[derive(Clone, Serialize)]
+pub struct CashFlowLine {
+    /// What the line shows: `Depreciation`, `DisposalGainOrLoss`, or the type of the accounts
+    /// whose movement it reverses.
+    pub line: String,
+    /// The amount for the period, followed by the amount for each comparison in turn, as for each
+    /// total of a [`CashFlowStatement`].
+    pub amounts: Vec<i64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct CashFlowStatement {
+    pub from: String,
+    pub to: String,
+    pub comparisons: Vec<Period>,
+    /// The profit for the period, positive for a profit.
+    pub profit: Vec<i64>,
+    /// The adjustments to the profit for non-cash items and movements in working capital.
+    pub operating: Vec<CashFlowLine>,
+    pub net_operating: Vec<i64>,
+    /// The net cash paid for non-current assets and received from their disposal.
+    pub investing: Vec<CashFlowLine>,
+    pub net_investing: Vec<i64>,
+    pub financing: Vec<CashFlowLine>,
+    pub net_financing: Vec<i64>,
+    /// The movement on system accounts, which hold unrealised exchange gains and losses on
+    /// revaluing balances held in foreign currencies, including cash.
+    pub exchange_differences: Vec<i64>,
+    pub net_cash_flow: Vec<i64>,
+    /// The balance of the cash accounts at the start and end of the period. The difference is the
+    /// net cash flow.
+    pub opening_cash: Vec<i64>,
+    pub closing_cash: Vec<i64>,
+}
+
+impl CashFlowStatement {
+    /// Add the amounts of the statement for a comparison after those of this statement.
+    fn append(&mut self, other: CashFlowStatement) {
+        self.comparisons.push(Period { from: Some(other.from), to: other.to });
+        for (lines, other) in [(&mut self.operating, other.operating), (&mut self.investing, other.investing),
+            (&mut self.financing, other.financing)] {
+            for (line, other) in lines.iter_mut().zip(other) {
+                line.amounts.extend(other.amounts);
+            }
+        }
+        for (amounts, other) in [(&mut self.profit, other.profit), (&mut self.net_operating, other.net_operating),
+            (&mut self.net_investing, other.net_investing), (&mut self.net_financing, other.net_financing),
+            (&mut self.exchange_differences, other.exchange_differences),
+            (&mut self.net_cash_flow, other.net_cash_flow), (&mut self.opening_cash, other.opening_cash),
+            (&mut self.closing_cash, other.closing_cash)] {
+            amounts.extend(other);
+        }
+    }
+}
+
+/// The statement of cash flows for a period, and each of the periods it is compared with, by the
+/// indirect method.
+///
+/// Depreciation and the gain or loss on disposal of fixed assets are found from the accounts
+/// given by the `depreciationAccount` and `assetDisposalAccount` settings. They are added back to
+/// the profit and taken out of the movement in non-current assets, leaving the cash paid for
+/// assets and the proceeds of disposals as the investing cash flow.
+pub async fn cash_flow(e: &Pool<Sqlite>, from: NaiveDate, to: NaiveDate, comparisons: &[(NaiveDate, NaiveDate)])
+                       -> Result<CashFlowStatement> {
+    if from > to {
+        bail!(InstructionError("period ends before it starts".to_string()));
+    }
+    let mut statement = cash_flow_period(e, from, to).await?;
+    for (from, to) in comparisons {
+        statement.append(cash_flow_period(e, *from, *to).await?);
+    }
+    Ok(statement)
+}
+
+async fn cash_flow_period(e: &Pool<Sqlite>, from: NaiveDate, to: NaiveDate) -> Result<CashFlowStatement> {
+    let (from_date, to_date) = (from.to_string(), to.to_string());
+    let opening_date = from.pred_opt().unwrap().to_string();
+    let movements = db::account_balance_query(e, Some(from_date.as_str()), Some(to_date.as_str()), None).await?;
+    let opening_cash: i64 = db::account_balance_query(e, None, Some(opening_date.as_str()), None).await?
+        .iter()
+        .filter(|a| a.account_type == AccountType::Cash)
+        .map(|a| a.balance)
+        .sum();
+    let movement = |f: &dyn Fn(&db::AccountBalanceResult) -> bool| -> i64 {
+        movements.iter().filter(|a| f(a)).map(|a| a.balance).sum()
+    };
+    let type_movement = |account_type: &AccountType| movement(&|a| a.account_type == *account_type);
+    let account_movement = |account_id: Option<i64>| movement(&|a| Some(a.account_id) == account_id);
+    let line = |line: String, amount: i64| CashFlowLine { line, amounts: vec![amount] };
+
+    let profit = -movement(&|a| a.account_type.is_profit_and_loss());
+    let depreciation = account_movement(settings::get_settings_int(e, "depreciationAccount").await);
+    let disposal = account_movement(settings::get_settings_int(e, "assetDisposalAccount").await);
+    let mut operating = vec![
+        line("Depreciation".to_string(), depreciation),
+        line("DisposalGainOrLoss".to_string(), disposal),
+    ];
+    operating.extend(WORKING_CAPITAL_LINES.iter().map(|t| line(format!("{t:?}"), -type_movement(t))));
+    let investing = vec![line(format!("{:?}", AccountType::NonCurrentAsset),
+                              -type_movement(&AccountType::NonCurrentAsset) - depreciation - disposal)];
+    let financing: Vec<CashFlowLine> = FINANCING_LINES
+        .iter()
+        .map(|t| line(format!("{t:?}"), -type_movement(t)))
+        .collect();
+
+    let total = |lines: &[CashFlowLine]| lines.iter().map(|l| l.amounts[0]).sum::<i64>();
+    let net_operating = profit + total(&operating);
+    let net_investing = total(&investing);
+    let net_financing = total(&financing);
+    let exchange_differences = -type_movement(&AccountType::System);
+    Ok(CashFlowStatement {
+        from: from_date,
+        to: to_date,
+        comparisons: Vec::new(),
+        profit: vec![profit],
+        operating,
+        net_operating: vec![net_operating],
+        investing,
+        net_investing: vec![net_investing],
+        financing,
+        net_financing: vec![net_financing],
+        exchange_differences: vec![exchange_differences],
+        net_cash_flow: vec![net_operating + net_investing + net_financing + exchange_differences],
+        opening_cash: vec![opening_cash],
+        closing_cash: vec![opening_cash + type_movement(&AccountType::Cash)],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities, ledger};
+    use crate::ledger::{BatchMetadata, Journal, JournalEntry};
+    use crate::users::{Role, User};
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    /// Post a journal of `(account, amount)` entries on a date.
+    async fn post(e: &Pool<Sqlite>, date: &str, entries: &[(i64, i64)]) {
+        let journal = Journal {
+            unstructured_narrative: "Test".to_string(),
+            entries: entries
+                .iter()
+                .map(|(account, amount)| JournalEntry { account: *account, amount: *amount, ..Default::default() })
+                .collect(),
+        };
+        let metadata = BatchMetadata {
+            date: Some(date.to_string()),
+            ..Default::default()
+        };
+        ledger::batch_new(e, &metadata, vec![journal]).await.unwrap();
+    }
+
+    /// An entity which raised share capital, bought a van and made a sale in the first quarter of
+    /// 2024, and collected part of the sale and paid rent in the second.
+    async fn entity() -> Pool<Sqlite> {
+        let db = entities::memory_connect().await;
+        let admin = User { user_id: 1, name: "admin".to_string(), role: Role::Admin };
+        let share_capital = ledger::account_new(&db, None, &"Share Capital".to_string(), &AccountType::Equity,
+                                                None, None, &admin).await.unwrap();
+        let rent = ledger::account_new(&db, None, &"Rent".to_string(), &AccountType::IndirectExpense, None, None,
+                                       &admin).await.unwrap();
+        post(&db, "2024-01-10", &[(100, 10000), (share_capital, -10000)]).await;
+        post(&db, "2024-02-01", &[(120, 1200), (400, -1000), (270, -200)]).await;
+        post(&db, "2024-02-15", &[(180, 3000), (100, -3000)]).await;
+        post(&db, "2024-03-20", &[(100, 600), (120, -600)]).await;
+        post(&db, "2024-03-31", &[(550, 250), (181, -250)]).await;
+        post(&db, "2024-04-10", &[(100, 600), (120, -600)]).await;
+        post(&db, "2024-05-01", &[(rent, 100), (100, -100)]).await;
+        db
+    }
+
+    fn amounts(lines: &[CashFlowLine], line: &str) -> Vec<i64> {
+        lines.iter().find(|l| l.line == line).unwrap().amounts.clone()
+    }
+
+    #[actix_web::test]
+    async fn cash_flow_reconciles_to_the_movement_in_cash() {
+        let db = entity().await;
+        let q2 = (date("2024-04-01"), date("2024-06-30"));
+        let statement = cash_flow(&db, date("2024-01-01"), date("2024-03-31"), &[q2]).await.unwrap();
+
+        assert_eq!(statement.profit, vec![750, -100]);
+        assert_eq!(amounts(&statement.operating, "Depreciation"), vec![250, 0]);
+        assert_eq!(amounts(&statement.operating, "CurrentAsset"), vec![-600, 600]);
+        assert_eq!(amounts(&statement.operating, "CurrentLiability"), vec![200, 0]);
+        assert_eq!(statement.net_operating, vec![600, 500]);
+        assert_eq!(statement.net_investing, vec![-3000, 0]);
+        assert_eq!(statement.net_financing, vec![10000, 0]);
+        assert_eq!(statement.net_cash_flow, vec![7600, 500]);
+        assert_eq!((statement.opening_cash, statement.closing_cash), (vec![0, 7600], vec![7600, 8100]));
+
+        assert!(cash_flow(&db, date("2024-03-31"), date("2024-01-01"), &[]).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn reports_show_a_column_for_each_comparison() {
+        let db = entity().await;
+        let q2 = periods_parse(Some("2024-04-01..2024-06-30")).unwrap();
+        let report = profit_and_loss(&db, date("2024-01-01"), date("2024-03-31"), &q2, None, None).await.unwrap();
+        let line = |account_id: i64| report.lines.iter().find(|l| l.account_id == account_id).unwrap().amounts.clone();
+        assert_eq!(report.comparisons[0].from.as_deref(), Some("2024-04-01"));
+        assert_eq!((line(400), line(550)), (vec![-1000, 0], vec![250, 0]));
+        assert_eq!(report.profit, vec![750, -100]);
+
+        let dates = dates_parse(Some("2023-12-31, 2024-06-30")).unwrap();
+        let sheet = balance_sheet(&db, date("2024-03-31"), &dates).await.unwrap();
+        assert_eq!(sheet.dates, vec!["2024-03-31", "2023-12-31", "2024-06-30"]);
+        assert_eq!(sheet.fixed_assets, vec![2750, 0, 2750]);
+        assert_eq!(sheet.cash, vec![7600, 0, 8100]);
+        assert_eq!(sheet.debtors, vec![600, 0, 0]);
+        assert_eq!(sheet.creditors_within_one_year, vec![200, 0, 200]);
+        assert_eq!(sheet.profit_and_loss_reserve, vec![750, 0, 650]);
+        assert_eq!(sheet.net_assets, sheet.equity);
+
+        assert!(periods_parse(Some("2024-06-30..2024-04-01")).is_err());
+        assert!(dates_parse(Some("2024-13-01")).is_err());
+        assert!(balance_sheet(&db, date("2024-03-31"), &[date("2024-03-31")]).await.is_err());
+    }
+}
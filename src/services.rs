@@ -1,13 +1,441 @@
+use std::path::Path;
 use std::str::FromStr;
 
-use actix_web::{get, HttpResponse, post, Responder, web};
+use actix_web::{get, HttpMessage, HttpResponse, post, Responder, web};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized};
+use actix_web::http::header;
+use actix_web::middleware::Next;
 use actix_web::web::Data;
 use serde::{Deserialize, Serialize};
 use tera::Context;
 
-use crate::{AppState, ledger};
-use crate::ledger::{AccountSummary, AccountType, JournalEntry};
+use chrono::NaiveDate;
+
+use crate::{AppState, aging, allocations, amortisation, assets, audit, budgets, contacts, consolidation, dimensions, documents, drafts, entities, fx, intercompany, invoicing, ixbrl, ledger, purchases, recurring, reports, statements, statutory, tax, users, vat};
+use crate::aging::{AgedLedger, AgedReport};
+use crate::allocations::{Allocation, Document, OpenItem};
+use crate::amortisation::{Schedule, ScheduleData, ScheduleKind};
+use crate::assets::{DepreciationMethod, FixedAsset, FixedAssetData};
+use crate::audit::{AuditEvent, AuditFilter};
+use crate::budgets::{Budget, BudgetLine};
+use crate::consolidation::ConsolidatedReport;
+use crate::contacts::{Contact, ContactData, ContactKind};
+use crate::dimensions::{Dimension, Tag, Tags};
+use crate::documents::{DocumentFormat, RenderedDocument};
+use crate::drafts::{Draft, DraftData, DraftJournal, DraftState};
+use crate::entities::{Entity, EntityDb};
+use crate::error::{Error, HandlerError};
+use crate::intercompany::{IntercompanyJournal, IntercompanyJournalData};
+use crate::invoicing::{Invoice, InvoiceData, InvoiceKind, InvoiceLine, InvoiceState};
+use crate::purchases::{Bill, BillData, BillLine, PaymentRun, PaymentRunState};
+use crate::ledger::{AccountType, JournalEntry};
+use crate::recurring::{Recurrence, RecurringJournal, RecurringJournalData};
+use crate::reports::{BudgetComparison, CashFlowLine, Period, ReportLine};
 use crate::settings::get_settings_str;
+use crate::statements::StatementSubject;
+use crate::statutory::{AccountsVariant, Approval};
+use crate::tax::TaxTreatment;
+use crate::users::{ApiToken, Role, User};
+use crate::vat::{MtdVatReturn, VatBoxes, VatReceipt, VatScheme};
+
+/// Reject calls without a valid API token in the `Authorization` header, and make the user the
+/// token belongs to available to handlers as [`web::ReqData<User>`].
+pub async fn authentication(req: ServiceRequest, next: Next<impl MessageBody>)
+                            -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req.app_data::<Data<AppState>>().unwrap().clone();
+    let token = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let user = match token {
+        Some(token) => users::authenticate(&state.db, token.trim())
+            .await
+            .map_err(|e| ErrorInternalServerError(e.to_string()))?,
+        None => None,
+    };
+    let Some(user) = user else {
+        return Err(ErrorUnauthorized("a valid API token is required"));
+    };
+    req.extensions_mut().insert(user);
+    next.call(req).await
+}
+
+/// Select the entity named by the `slug` in the URL, rejecting calls for unknown entities or by
+/// users without access to it, and make its books available to handlers as
+/// [`web::ReqData<EntityDb>`]. Runs after [`authentication`].
+pub async fn entity_selection(req: ServiceRequest, next: Next<impl MessageBody>)
+                              -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req.app_data::<Data<AppState>>().unwrap().clone();
+    let slug = req.match_info().get("slug").unwrap_or_default().to_string();
+    let user = req.extensions().get::<User>().cloned().unwrap();
+    let pools = state.entities.read().unwrap().clone();
+    let entity = match entities::entity_access(&state.db, &pools, slug.as_str(), &user).await {
+        Ok(entity) => entity,
+        Err(e) => return match e.downcast_ref::<Error>() {
+            Some(Error::PermissionError(_)) => Err(ErrorForbidden(e.to_string())),
+            _ => Err(ErrorNotFound(e.to_string())),
+        },
+    };
+    req.extensions_mut().insert(entity);
+    next.call(req).await
+}
+
+#[derive(Serialize)]
+struct UserListResponse {
+    users: Vec<User>,
+}
+
+#[get("/user/list")]
+pub async fn user_list(state: Data<AppState>, user: web::ReqData<User>)
+                       -> Result<web::Json<UserListResponse>, HandlerError> {
+    Ok(web::Json(UserListResponse {
+        users: users::user_list(&state.db, &user).await?,
+    }))
+}
+
+#[get("/user/me")]
+pub async fn user_me(user: web::ReqData<User>) -> web::Json<User> {
+    web::Json(user.into_inner())
+}
+
+#[derive(Clone, Deserialize)]
+pub struct UserCreateData {
+    name: String,
+    /// Defaults to a viewer.
+    role: Option<Role>,
+}
+
+#[derive(Serialize)]
+struct TokenResponse {
+    token_id: i64,
+    user_id: i64,
+    /// The token, which is only ever shown here.
+    token: String,
+}
+
+impl From<ApiToken> for TokenResponse {
+    fn from(t: ApiToken) -> Self {
+        TokenResponse {
+            token_id: t.token_id,
+            user_id: t.user_id,
+            token: t.token,
+        }
+    }
+}
+
+/// Create a user with an API token.
+#[post("/user/new")]
+pub async fn user_new(state: Data<AppState>, user: web::ReqData<User>, item: web::Json<UserCreateData>)
+                      -> Result<web::Json<TokenResponse>, HandlerError> {
+    let role = item.role.unwrap_or(Role::Viewer);
+    let user_id = users::user_new(&state.db, item.name.as_str(), role, &user).await?;
+    Ok(web::Json(users::token_new(&state.db, user_id, None, &user).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct UserRoleData {
+    role: Role,
+}
+
+#[post("/user/{user_id}/role")]
+pub async fn user_role_set(state: Data<AppState>, user: web::ReqData<User>, path: web::Path<(i64, )>,
+                           item: web::Json<UserRoleData>) -> Result<web::Json<User>, HandlerError> {
+    let user_id = path.into_inner().0;
+    Ok(web::Json(users::user_role_set(&state.db, user_id, item.role, &user).await?))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TokenCreateData {
+    description: Option<String>,
+}
+
+#[post("/user/{user_id}/token")]
+pub async fn token_new(state: Data<AppState>, user: web::ReqData<User>, path: web::Path<(i64, )>,
+                       item: web::Json<TokenCreateData>) -> Result<web::Json<TokenResponse>, HandlerError> {
+    let user_id = path.into_inner().0;
+    Ok(web::Json(users::token_new(&state.db, user_id, item.description.as_deref(), &user).await?.into()))
+}
+
+#[derive(Serialize)]
+struct TokenRevokeResponse;
+
+#[post("/token/{token_id}/revoke")]
+pub async fn token_revoke(state: Data<AppState>, user: web::ReqData<User>, path: web::Path<(i64, )>)
+                          -> Result<web::Json<TokenRevokeResponse>, HandlerError> {
+    let token_id = path.into_inner().0;
+    users::token_revoke(&state.db, token_id, &user).await?;
+    Ok(web::Json(TokenRevokeResponse {}))
+}
+
+#[derive(Serialize)]
+struct AuditEventListResponse {
+    events: Vec<AuditEvent>,
+}
+
+#[get("/audit/events")]
+pub async fn audit_events(state: Data<AppState>, user: web::ReqData<User>, query: web::Query<AuditFilter>)
+                          -> Result<web::Json<AuditEventListResponse>, HandlerError> {
+    Ok(web::Json(AuditEventListResponse {
+        events: audit::audit_events(&state.db, &state.db, &query, &user).await?,
+    }))
+}
+
+#[get("/audit/events")]
+pub async fn entity_audit_events(state: Data<AppState>, entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                 query: web::Query<AuditFilter>)
+                                 -> Result<web::Json<AuditEventListResponse>, HandlerError> {
+    Ok(web::Json(AuditEventListResponse {
+        events: audit::audit_events(&entity.db, &state.db, &query, &user).await?,
+    }))
+}
+
+#[derive(Serialize)]
+struct EntityListResponse {
+    entities: Vec<Entity>,
+}
+
+#[get("/entity/list")]
+pub async fn entity_list(state: Data<AppState>, user: web::ReqData<User>)
+                         -> Result<web::Json<EntityListResponse>, HandlerError> {
+    Ok(web::Json(EntityListResponse {
+        entities: entities::entity_list(&state.db, &user).await?,
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct EntityCreateData {
+    slug: String,
+    /// The name of the entity, as the `entityName` setting of its books.
+    name: String,
+}
+
+#[post("/entity/new")]
+pub async fn entity_new(state: Data<AppState>, user: web::ReqData<User>, item: web::Json<EntityCreateData>)
+                        -> Result<web::Json<Entity>, HandlerError> {
+    let (entity, db) = entities::entity_new(&state.db, item.slug.as_str(), item.name.as_str(),
+                                            state.entity_dir.as_str(), &user).await?;
+    state.entities.write().unwrap().insert(entity.slug.clone(), db);
+    Ok(web::Json(entity))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct EntityGrantData {
+    user_id: i64,
+}
+
+#[derive(Serialize)]
+struct EntityGrantResponse {}
+
+#[post("/entity/{slug}/grant")]
+pub async fn entity_grant(state: Data<AppState>, user: web::ReqData<User>, path: web::Path<(String, )>,
+                          item: web::Json<EntityGrantData>) -> Result<web::Json<EntityGrantResponse>, HandlerError> {
+    let slug = path.into_inner().0;
+    entities::entity_grant(&state.db, slug.as_str(), item.user_id, &user).await?;
+    Ok(web::Json(EntityGrantResponse {}))
+}
+
+#[post("/entity/{slug}/revoke")]
+pub async fn entity_revoke(state: Data<AppState>, user: web::ReqData<User>, path: web::Path<(String, )>,
+                           item: web::Json<EntityGrantData>) -> Result<web::Json<EntityGrantResponse>, HandlerError> {
+    let slug = path.into_inner().0;
+    entities::entity_revoke(&state.db, slug.as_str(), item.user_id, &user).await?;
+    Ok(web::Json(EntityGrantResponse {}))
+}
+
+#[derive(Serialize)]
+struct IntercompanyJournalResponse {
+    intercompany_journal_id: i64,
+    reference: String,
+    date: String,
+    amount: String,
+    from_entity: String,
+    from_journal_id: i64,
+    to_entity: String,
+    to_journal_id: i64,
+    created_by: i64,
+}
+
+impl From<IntercompanyJournal> for IntercompanyJournalResponse {
+    fn from(j: IntercompanyJournal) -> Self {
+        IntercompanyJournalResponse {
+            intercompany_journal_id: j.intercompany_journal_id,
+            reference: j.reference,
+            date: j.date,
+            amount: j.amount.to_string(),
+            from_entity: j.from_entity,
+            from_journal_id: j.from_journal_id,
+            to_entity: j.to_entity,
+            to_journal_id: j.to_journal_id,
+            created_by: j.created_by,
+        }
+    }
+}
+
+#[post("/intercompany/new")]
+pub async fn intercompany_journal_new(state: Data<AppState>, user: web::ReqData<User>,
+                                      item: web::Json<IntercompanyJournalData>)
+                                      -> Result<web::Json<IntercompanyJournalResponse>, HandlerError> {
+    let pools = state.entities.read().unwrap().clone();
+    Ok(web::Json(intercompany::intercompany_journal_new(&state.db, &pools, &item, &user).await?.into()))
+}
+
+#[derive(Deserialize)]
+pub struct IntercompanyJournalListQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct IntercompanyJournalListResponse {
+    journals: Vec<IntercompanyJournalResponse>,
+}
+
+#[get("/intercompany/list")]
+pub async fn intercompany_journal_list(state: Data<AppState>, user: web::ReqData<User>,
+                                       query: web::Query<IntercompanyJournalListQuery>)
+                                       -> Result<web::Json<IntercompanyJournalListResponse>, HandlerError> {
+    let results = intercompany::intercompany_journal_list(&state.db, query.from, query.to, &user).await?;
+    Ok(web::Json(IntercompanyJournalListResponse {
+        journals: results.into_iter().map(IntercompanyJournalResponse::from).collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ConsolidatedReportQuery {
+    /// A comma-separated list of entity slugs. Defaults to every entity the user may access.
+    entities: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    /// A comma-separated list of dates, for a balance sheet, or of periods given as `from..to` to
+    /// compare the report with.
+    compare: Option<String>,
+    /// For the intercompany mismatch report, whether to leave out balances that match.
+    #[serde(default)]
+    mismatched_only: bool,
+}
+
+#[derive(Serialize)]
+struct ConsolidatedLineResponse {
+    line: String,
+    entities: Vec<String>,
+    eliminations: String,
+    consolidated: String,
+    comparatives: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ConsolidatedReportResponse {
+    entities: Vec<String>,
+    from: Option<String>,
+    to: String,
+    lines: Vec<ConsolidatedLineResponse>,
+    total: String,
+    comparisons: Vec<Period>,
+    comparative_totals: Vec<String>,
+}
+
+impl From<ConsolidatedReport> for ConsolidatedReportResponse {
+    fn from(r: ConsolidatedReport) -> Self {
+        ConsolidatedReportResponse {
+            entities: r.entities,
+            from: r.from,
+            to: r.to,
+            lines: r.lines
+                .into_iter()
+                .map(|l| ConsolidatedLineResponse {
+                    line: l.line,
+                    entities: l.entities.iter().map(|a| a.to_string()).collect(),
+                    eliminations: l.eliminations.to_string(),
+                    consolidated: l.consolidated.to_string(),
+                    comparatives: l.comparatives.iter().map(|a| a.to_string()).collect(),
+                })
+                .collect(),
+            total: r.total.to_string(),
+            comparisons: r.comparisons,
+            comparative_totals: r.comparative_totals.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+async fn entity_group(state: &AppState, user: &User, query: &ConsolidatedReportQuery)
+                      -> Result<Vec<EntityDb>, HandlerError> {
+    let pools = state.entities.read().unwrap().clone();
+    Ok(entities::entity_group(&state.db, &pools, query.entities.as_deref(), user).await?)
+}
+
+#[get("/report/consolidated/balance")]
+pub async fn report_consolidated_balance_sheet(state: Data<AppState>, user: web::ReqData<User>,
+                                               query: web::Query<ConsolidatedReportQuery>)
+                                               -> Result<web::Json<ConsolidatedReportResponse>, HandlerError> {
+    let group = entity_group(&state, &user, &query).await?;
+    let as_at = query.to.unwrap_or_else(|| chrono::Local::now().date_naive());
+    let comparisons = reports::dates_parse(query.compare.as_deref())?;
+    Ok(web::Json(consolidation::consolidated_balance_sheet(&state.db, &group, as_at, &comparisons).await?.into()))
+}
+
+#[get("/report/consolidated/profit-and-loss")]
+pub async fn report_consolidated_profit_and_loss(state: Data<AppState>, user: web::ReqData<User>,
+                                                 query: web::Query<ConsolidatedReportQuery>)
+                                                 -> Result<web::Json<ConsolidatedReportResponse>, HandlerError> {
+    let group = entity_group(&state, &user, &query).await?;
+    let to = query.to.unwrap_or_else(|| chrono::Local::now().date_naive());
+    let from = query.from.ok_or_else(|| Error::InstructionError("from is required".to_string()))?;
+    let comparisons = reports::periods_parse(query.compare.as_deref())?;
+    Ok(web::Json(consolidation::consolidated_profit_and_loss(&state.db, &group, from, to, &comparisons)
+        .await?.into()))
+}
+
+#[derive(Serialize)]
+struct IntercompanyBalanceResponse {
+    entity: String,
+    counterparty: String,
+    balance: String,
+    counterparty_balance: String,
+    difference: String,
+}
+
+#[derive(Serialize)]
+struct IntercompanyMismatchResponse {
+    as_at: String,
+    balances: Vec<IntercompanyBalanceResponse>,
+}
+
+#[get("/report/intercompany/mismatches")]
+pub async fn report_intercompany_mismatches(state: Data<AppState>, user: web::ReqData<User>,
+                                            query: web::Query<ConsolidatedReportQuery>)
+                                            -> Result<web::Json<IntercompanyMismatchResponse>, HandlerError> {
+    let group = entity_group(&state, &user, &query).await?;
+    let as_at = query.to.unwrap_or_else(|| chrono::Local::now().date_naive());
+    let results = intercompany::intercompany_mismatches(&group, as_at, query.mismatched_only).await?;
+    Ok(web::Json(IntercompanyMismatchResponse {
+        as_at: as_at.to_string(),
+        balances: results
+            .into_iter()
+            .map(|b| IntercompanyBalanceResponse {
+                entity: b.entity,
+                counterparty: b.counterparty,
+                balance: b.balance.to_string(),
+                counterparty_balance: b.counterparty_balance.to_string(),
+                difference: b.difference.to_string(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct IxbrlCheckResponse {
+    valid: bool,
+    problems: Vec<String>,
+}
+
+/// Check an iXBRL document, such as accounts prepared elsewhere, before it is filed.
+#[post("/ixbrl/check")]
+pub async fn ixbrl_check(body: String) -> web::Json<IxbrlCheckResponse> {
+    let problems = ixbrl::document_problems(&body);
+    web::Json(IxbrlCheckResponse { valid: problems.is_empty(), problems })
+}
 
 #[derive(Serialize)]
 struct AccountDetailResponse {
@@ -21,18 +449,19 @@ struct AccountDetailResponse {
 }
 
 #[get("/account/{account_id}")]
-pub async fn account_detail(state: Data<AppState>, path: web::Path<(i64, )>) -> web::Json<AccountDetailResponse> {
-   let account_id = path.into_inner().0;
-    let result = ledger::account_detail(&state.db, account_id).await.unwrap();
-    web::Json(AccountDetailResponse {
+pub async fn account_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                            -> Result<web::Json<AccountDetailResponse>, HandlerError> {
+    let account_id = path.into_inner().1;
+    let result = ledger::account_detail(&entity.db, account_id).await?;
+    Ok(web::Json(AccountDetailResponse {
         account_name: result.account_name,
-        account_id: format!("{account_id:<08}"),
+        account_id: format!("{:<08}", result.account_id),
         account_type: format!("{:?}", result.account_type),
         total_debits: result.total_debits.to_string(),
         total_credits: result.total_credits.to_string(),
         balance: result.balance.to_string(),
         timestamp: result.timestamp.to_string(),
-    })
+    }))
 }
 
 #[derive(Serialize)]
@@ -48,9 +477,34 @@ struct AccountListAccountResponse {
     balance: String,
 }
 
+/// Restrict balances to the entries tagged with a dimension value. Both fields or neither must be
+/// given.
+#[derive(Deserialize)]
+pub struct TagQuery {
+    dimension: Option<String>,
+    value: Option<String>,
+}
+
+impl TagQuery {
+    fn tag(&self) -> Option<Tag> {
+        match (&self.dimension, &self.value) {
+            (Some(dimension), Some(value)) => Some(Tag { dimension: dimension.clone(), value: value.clone() }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AccountListQuery {
+    /// Defaults to the current date.
+    as_at: Option<NaiveDate>,
+}
+
 #[get("/account/list")]
-pub async fn account_list(state: Data<AppState>) -> web::Json<AccountListResponse> {
-    let results = ledger::account_list(&state.db).await.unwrap();
+pub async fn account_list(entity: web::ReqData<EntityDb>, query: web::Query<AccountListQuery>,
+                          tag: web::Query<TagQuery>) -> Result<web::Json<AccountListResponse>, HandlerError> {
+    let as_at = query.as_at.unwrap_or_else(|| chrono::Local::now().date_naive());
+    let results = ledger::account_list(&entity.db, as_at, tag.tag().as_ref()).await?;
     let mut accounts: Vec<AccountListAccountResponse> = Vec::new();
     let mut timestamp: String = "".to_string();
     for result in results {
@@ -61,10 +515,10 @@ pub async fn account_list(state: Data<AppState>) -> web::Json<AccountListRespons
         });
         timestamp = result.timestamp;
     }
-    web::Json(AccountListResponse {
+    Ok(web::Json(AccountListResponse {
         accounts,
         timestamp,
-    })
+    }))
 }
 
 #[derive(Clone, Deserialize)]
@@ -72,6 +526,9 @@ struct AccountCreateData {
     account_id: Option<i64>,
     account_name: String,
     account_type: String,
+    currency: Option<String>,
+    /// The slug of the entity, for an intercompany account.
+    counterparty: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -82,16 +539,46 @@ struct AccountCreateResponse {
 }
 
 #[post("/account/new")]
-pub async fn account_new(state: Data<AppState>, item: web::Json<AccountCreateData>) -> web::Json<AccountCreateResponse> {
+pub async fn account_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                         item: web::Json<AccountCreateData>) -> Result<web::Json<AccountCreateResponse>, HandlerError> {
     let account_id = item.account_id;
     let account_name = item.account_name.clone();
-    let account_type = AccountType::from_str(item.account_type.as_str()).unwrap();
-    let created_account_id = ledger::account_new(&state.db, account_id, &account_name, &account_type).await.unwrap();
-    web::Json(AccountCreateResponse {
+    let account_type = AccountType::from_str(item.account_type.as_str())
+        .map_err(|_| Error::InstructionError(format!("unknown account type {}", item.account_type)))?;
+    let created_account_id = ledger::account_new(&entity.db, account_id, &account_name, &account_type,
+                                                 item.currency.as_deref(), item.counterparty.as_deref(), &user)
+        .await?;
+    Ok(web::Json(AccountCreateResponse {
         account_id: format!("{created_account_id:<08}"),
         account_name: account_name.to_string(),
         account_type: format!("{account_type:?}"),
-    })
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AccountClientData {
+    client: bool,
+}
+
+#[derive(Serialize)]
+struct AccountClientResponse;
+
+#[post("/account/{account_id}/client")]
+pub async fn account_client_set(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                path: web::Path<(String, i64)>, item: web::Json<AccountClientData>)
+                                -> Result<web::Json<AccountClientResponse>, HandlerError> {
+    ledger::account_client_set(&entity.db, path.into_inner().1, item.client, &user).await?;
+    Ok(web::Json(AccountClientResponse {}))
+}
+
+#[derive(Serialize)]
+struct AccountArchiveResponse;
+
+#[post("/account/{account_id}/archive")]
+pub async fn account_archive(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i64)>)
+                             -> Result<web::Json<AccountArchiveResponse>, HandlerError> {
+    ledger::account_archive(&entity.db, path.into_inner().1, &user).await?;
+    Ok(web::Json(AccountArchiveResponse {}))
 }
 
 #[derive(Clone, Deserialize)]
@@ -104,89 +591,1895 @@ pub struct JournalCreateData {
 pub struct JournalCreateEntryData {
     account: i64,
     amount: i64,
+    currency_amount: Option<i64>,
+    contact: Option<i64>,
+    tax_code: Option<String>,
+    #[serde(default)]
+    tags: Tags,
 }
 
 #[derive(Serialize)]
 struct JournalCreateResponse;
 
 #[post("/journal/new")]
-pub async fn journal_new(state: Data<AppState>, item: web::Json<JournalCreateData>) -> web::Json<JournalCreateResponse> {
+pub async fn journal_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                         item: web::Json<JournalCreateData>) -> Result<web::Json<JournalCreateResponse>, HandlerError> {
     let item = item.clone();
     let journal_entries: Vec<JournalEntry> = item.entries
         .into_iter()
         .map(|e| JournalEntry {
             account: e.account,
             amount: e.amount,
+            currency_amount: e.currency_amount,
+            contact: e.contact,
+            tax_code: e.tax_code,
+            tags: e.tags,
         })
         .collect();
-    ledger::journal_new(&state.db, item.unstructured_narrative.unwrap_or("".to_string()), journal_entries).await.unwrap();
-    web::Json(JournalCreateResponse {})
+    ledger::journal_new(&entity.db, item.unstructured_narrative.unwrap_or("".to_string()), journal_entries, &user)
+        .await?;
+    Ok(web::Json(JournalCreateResponse {}))
 }
 
-fn filter_accounts_list<F>(accounts: &Vec<AccountSummary>, f: F) -> Vec<AccountSummary>
-    where F: Fn(&AccountSummary) -> bool {
-    accounts
-        .clone()
-        .into_iter()
-        .filter(f)
-        .collect()
+#[derive(Serialize)]
+struct DraftListResponse {
+    drafts: Vec<DraftResponse>,
 }
 
-fn sum_filter_accounts_list<F>(accounts: &Vec<AccountSummary>, f: F) -> i64
-    where F: Fn(&AccountSummary) -> bool
-{
-    accounts
-        .clone()
-        .into_iter()
-        .filter(f)
-        .map(|a| a.account_balance)
-        .sum()
+#[derive(Serialize)]
+struct DraftResponse {
+    draft_id: i64,
+    date: Option<String>,
+    state: DraftState,
+    created_by: String,
+    reviewed_by: Option<String>,
+    review_note: Option<String>,
+    batch_id: Option<i64>,
+    total: String,
+    journals: Vec<DraftJournal>,
 }
 
-#[get("/report/balance")]
-pub async fn report_balance_sheet(state: Data<AppState>) -> impl Responder {
-    let mut ctx = Context::new();
-    let entity_name = get_settings_str(&state.db, "entityName").await.unwrap();
-    ctx.insert("entity_name", &entity_name);
-    let accounts = ledger::account_list(&state.db).await.unwrap();
-
-    let cash: Vec<AccountSummary> = filter_accounts_list(&accounts, |a| a.account_type == AccountType::Cash);
-    ctx.insert("cash", &cash);
-
-    let total_cash = sum_filter_accounts_list(
-        &accounts, |a| a.account_type == AccountType::Cash);
-    ctx.insert("total_cash", &total_cash);
-
-    let current_assets: Vec<AccountSummary> = filter_accounts_list(&accounts, |a| {
-        match a.account_type {
-            AccountType::CurrentAsset => true,
-            AccountType::Inventory => true,
-            AccountType::Prepayments => true,
-            _ => false,
-        }
-    });
-    ctx.insert("current_assets", &current_assets);
-
-    let total_current_assets = sum_filter_accounts_list(&accounts, |a| match a.account_type {
-            AccountType::Cash => true,
-            AccountType::CurrentAsset => true,
-            AccountType::Inventory => true,
-            AccountType::Prepayments => true,
-            _ => false,
-        });
-    ctx.insert("total_current_assets", &total_current_assets);
+impl From<Draft> for DraftResponse {
+    fn from(d: Draft) -> Self {
+        DraftResponse {
+            draft_id: d.draft_id,
+            date: d.date,
+            state: d.state,
+            created_by: d.created_by,
+            reviewed_by: d.reviewed_by,
+            review_note: d.review_note,
+            batch_id: d.batch_id,
+            total: d.total.to_string(),
+            journals: d.journals,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DraftListQuery {
+    state: Option<DraftState>,
+}
+
+#[get("/draft/list")]
+pub async fn draft_list(entity: web::ReqData<EntityDb>, query: web::Query<DraftListQuery>)
+                        -> Result<web::Json<DraftListResponse>, HandlerError> {
+    let results = drafts::draft_list(&entity.db, query.state).await?;
+    Ok(web::Json(DraftListResponse {
+        drafts: results.into_iter().map(DraftResponse::from).collect(),
+    }))
+}
+
+#[get("/draft/{draft_id}")]
+pub async fn draft_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                          -> Result<web::Json<DraftResponse>, HandlerError> {
+    let draft_id = path.into_inner().1;
+    Ok(web::Json(drafts::draft_detail(&entity.db, draft_id).await?.into()))
+}
+
+
+#[post("/draft/new")]
+pub async fn draft_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<DraftData>)
+                       -> Result<web::Json<DraftResponse>, HandlerError> {
+    let draft_id = drafts::draft_new(&entity.db, &user, &item).await?;
+    Ok(web::Json(drafts::draft_detail(&entity.db, draft_id).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DraftReviewData {
+    note: Option<String>,
+}
+
+#[post("/draft/{draft_id}/submit")]
+pub async fn draft_submit(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i64)>)
+                          -> Result<web::Json<DraftResponse>, HandlerError> {
+    let draft_id = path.into_inner().1;
+    Ok(web::Json(drafts::draft_submit(&entity.db, draft_id, &user).await?.into()))
+}
+
+#[post("/draft/{draft_id}/approve")]
+pub async fn draft_approve(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i64)>,
+                           item: web::Json<DraftReviewData>) -> Result<web::Json<DraftResponse>, HandlerError> {
+    let draft_id = path.into_inner().1;
+    Ok(web::Json(drafts::draft_approve(&entity.db, draft_id, &user, item.note.as_deref()).await?.into()))
+}
+
+#[post("/draft/{draft_id}/reject")]
+pub async fn draft_reject(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i64)>,
+                          item: web::Json<DraftReviewData>) -> Result<web::Json<DraftResponse>, HandlerError> {
+    let draft_id = path.into_inner().1;
+    Ok(web::Json(drafts::draft_reject(&entity.db, draft_id, &user, item.note.as_deref()).await?.into()))
+}
+
+#[derive(Serialize)]
+struct RecurringJournalListResponse {
+    recurring_journals: Vec<RecurringJournalResponse>,
+}
+
+#[derive(Serialize)]
+struct RecurringJournalResponse {
+    recurring_journal_id: i64,
+    unstructured_narrative: String,
+    recurrence: Recurrence,
+    day: Option<i64>,
+    start_date: String,
+    end_date: Option<String>,
+    last_posted: Option<String>,
+    entries: Vec<RecurringEntryResponse>,
+    postings: Vec<RecurringPostingResponse>,
+}
+
+#[derive(Serialize)]
+struct RecurringEntryResponse {
+    account_id: String,
+    amount: String,
+    currency_amount: Option<String>,
+    contact: Option<i64>,
+    tax_code: Option<String>,
+    #[serde(skip_serializing_if = "Tags::is_empty")]
+    tags: Tags,
+}
+
+#[derive(Serialize)]
+struct RecurringPostingResponse {
+    date: String,
+    journal_id: i64,
+}
+
+impl From<RecurringJournal> for RecurringJournalResponse {
+    fn from(r: RecurringJournal) -> Self {
+        RecurringJournalResponse {
+            recurring_journal_id: r.recurring_journal_id,
+            unstructured_narrative: r.unstructured_narrative,
+            recurrence: r.recurrence,
+            day: r.day,
+            start_date: r.start_date,
+            end_date: r.end_date,
+            last_posted: r.last_posted,
+            entries: r.entries
+                .into_iter()
+                .map(|e| RecurringEntryResponse {
+                    account_id: format!("{:<08}", e.account),
+                    amount: e.amount.to_string(),
+                    currency_amount: e.currency_amount.map(|a| a.to_string()),
+                    contact: e.contact,
+                    tax_code: e.tax_code,
+                    tags: e.tags,
+                })
+                .collect(),
+            postings: r.postings
+                .into_iter()
+                .map(|p| RecurringPostingResponse {
+                    date: p.date,
+                    journal_id: p.journal_id,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[get("/recurring-journal/list")]
+pub async fn recurring_journal_list(entity: web::ReqData<EntityDb>)
+                                    -> Result<web::Json<RecurringJournalListResponse>, HandlerError> {
+    let results = recurring::recurring_journal_list(&entity.db).await?;
+    Ok(web::Json(RecurringJournalListResponse {
+        recurring_journals: results.into_iter().map(RecurringJournalResponse::from).collect(),
+    }))
+}
+
+#[get("/recurring-journal/{recurring_journal_id}")]
+pub async fn recurring_journal_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                                      -> Result<web::Json<RecurringJournalResponse>, HandlerError> {
+    let recurring_journal_id = path.into_inner().1;
+    Ok(web::Json(recurring::recurring_journal_detail(&entity.db, recurring_journal_id).await?.into()))
+}
+
+#[post("/recurring-journal/new")]
+pub async fn recurring_journal_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                   item: web::Json<RecurringJournalData>)
+                                   -> Result<web::Json<RecurringJournalResponse>, HandlerError> {
+    let recurring_journal_id = recurring::recurring_journal_new(&entity.db, &item, &user).await?;
+    Ok(web::Json(recurring::recurring_journal_detail(&entity.db, recurring_journal_id).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RecurringJournalPostData {
+    /// Post occurrences due on or before this date. Defaults to today.
+    date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct RecurringJournalPostResponse {
+    batch_id: Option<i64>,
+    journal_count: usize,
+}
+
+#[post("/recurring-journal/post")]
+pub async fn recurring_journal_post(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                    item: web::Json<RecurringJournalPostData>)
+                                    -> Result<web::Json<RecurringJournalPostResponse>, HandlerError> {
+    let date = item.date.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let result = recurring::recurring_journal_post(&entity.db, date, &user).await?;
+    Ok(web::Json(RecurringJournalPostResponse {
+        batch_id: result.as_ref().map(|r| r.batch_id),
+        journal_count: result.map(|r| r.journal_count).unwrap_or(0),
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ExchangeRateData {
+    currency: String,
+    date: NaiveDate,
+    rate: f64,
+}
+
+#[derive(Serialize)]
+struct ExchangeRateResponse;
+
+#[post("/fx/rate")]
+pub async fn exchange_rate_set(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                               item: web::Json<ExchangeRateData>)
+                               -> Result<web::Json<ExchangeRateResponse>, HandlerError> {
+    fx::exchange_rate_set(&entity.db, item.currency.as_str(), item.date, item.rate, &user).await?;
+    Ok(web::Json(ExchangeRateResponse {}))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RevaluationData {
+    date: NaiveDate,
+}
+
+#[derive(Serialize)]
+struct RevaluationResponse {
+    batch_id: Option<i64>,
+    reversal_batch_id: Option<i64>,
+    journal_count: usize,
+}
+
+#[post("/fx/revalue")]
+pub async fn fx_revalue(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                        item: web::Json<RevaluationData>) -> Result<web::Json<RevaluationResponse>, HandlerError> {
+    let result = fx::revalue(&entity.db, item.date, &user).await?;
+    Ok(web::Json(RevaluationResponse {
+        batch_id: result.as_ref().map(|r| r.batch_id),
+        reversal_batch_id: result.as_ref().map(|r| r.reversal_batch_id),
+        journal_count: result.map(|r| r.journal_count).unwrap_or(0),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct TaxCodeListQuery {
+    date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct TaxCodeListResponse {
+    date: String,
+    tax_codes: Vec<TaxCodeResponse>,
+}
+
+#[derive(Serialize)]
+struct TaxCodeResponse {
+    code: String,
+    name: String,
+    treatment: TaxTreatment,
+    rate: Option<i64>,
+}
+
+#[get("/tax-code/list")]
+pub async fn tax_code_list(entity: web::ReqData<EntityDb>, query: web::Query<TaxCodeListQuery>)
+                           -> Result<web::Json<TaxCodeListResponse>, HandlerError> {
+    let date = query.date.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let results = tax::tax_code_list(&entity.db, date).await?;
+    Ok(web::Json(TaxCodeListResponse {
+        date: date.to_string(),
+        tax_codes: results
+            .into_iter()
+            .map(|t| TaxCodeResponse {
+                code: t.code,
+                name: t.name,
+                treatment: t.treatment,
+                rate: t.rate,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TaxCodeData {
+    code: String,
+    name: String,
+    treatment: TaxTreatment,
+}
+
+#[derive(Serialize)]
+struct TaxCodeCreateResponse;
+
+#[post("/tax-code/new")]
+pub async fn tax_code_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<TaxCodeData>)
+                          -> Result<web::Json<TaxCodeCreateResponse>, HandlerError> {
+    tax::tax_code_new(&entity.db, item.code.as_str(), item.name.as_str(), &item.treatment, &user).await?;
+    Ok(web::Json(TaxCodeCreateResponse {}))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TaxRateData {
+    effective_from: NaiveDate,
+    /// The rate in basis points.
+    rate: i64,
+}
+
+#[derive(Serialize)]
+struct TaxRateResponse;
+
+#[post("/tax-code/{code}/rate")]
+pub async fn tax_rate_set(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, String)>,
+                          item: web::Json<TaxRateData>) -> Result<web::Json<TaxRateResponse>, HandlerError> {
+    let code = path.into_inner().1;
+    tax::tax_rate_set(&entity.db, code.as_str(), item.effective_from, item.rate, &user).await?;
+    Ok(web::Json(TaxRateResponse {}))
+}
+
+#[derive(Deserialize)]
+pub struct VatReturnQuery {
+    start: NaiveDate,
+    end: NaiveDate,
+    scheme: Option<VatScheme>,
+    /// Only list the transactions behind this box.
+    #[serde(rename = "box")]
+    box_number: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct VatReturnResponse {
+    start: String,
+    end: String,
+    scheme: VatScheme,
+    boxes: VatBoxesResponse,
+    transactions: Vec<VatTransactionResponse>,
+}
+
+#[derive(Serialize)]
+struct VatBoxesResponse {
+    box1: String,
+    box2: String,
+    box3: String,
+    box4: String,
+    box5: String,
+    box6: String,
+    box7: String,
+    box8: String,
+    box9: String,
+}
+
+impl From<&VatBoxes> for VatBoxesResponse {
+    fn from(b: &VatBoxes) -> Self {
+        VatBoxesResponse {
+            box1: b.box1.to_string(),
+            box2: b.box2.to_string(),
+            box3: b.box3.to_string(),
+            box4: b.box4.to_string(),
+            box5: b.box5.to_string(),
+            box6: b.box6.to_string(),
+            box7: b.box7.to_string(),
+            box8: b.box8.to_string(),
+            box9: b.box9.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct VatTransactionResponse {
+    #[serde(rename = "box")]
+    box_number: u8,
+    journal_id: i64,
+    date: String,
+    narrative: String,
+    account_id: String,
+    tax_code: String,
+    amount: String,
+}
 
-    let current_liabilities: Vec<AccountSummary> = filter_accounts_list(
-        &accounts, |a| a.account_type == AccountType::CurrentLiability);
-    ctx.insert("current_liabilities", &current_liabilities);
+#[get("/vat-return")]
+pub async fn vat_return(entity: web::ReqData<EntityDb>, query: web::Query<VatReturnQuery>)
+                        -> Result<web::Json<VatReturnResponse>, HandlerError> {
+    let result = vat::vat_return(&entity.db, query.start, query.end, query.scheme).await?;
+    Ok(web::Json(VatReturnResponse {
+        boxes: (&result.boxes).into(),
+        transactions: result.transactions
+            .into_iter()
+            .filter(|t| query.box_number.is_none_or(|b| b == t.box_number))
+            .map(|t| VatTransactionResponse {
+                box_number: t.box_number,
+                journal_id: t.journal_id,
+                date: t.date,
+                narrative: t.narrative,
+                account_id: format!("{:<08}", t.account_id),
+                tax_code: t.tax_code,
+                amount: t.amount.to_string(),
+            })
+            .collect(),
+        start: result.start,
+        end: result.end,
+        scheme: result.scheme,
+    }))
+}
 
-    let total_current_liabilities: i64 = sum_filter_accounts_list(
-        &accounts, |a| a.account_type == AccountType::CurrentLiability);
-    ctx.insert("total_current_liabilities", &total_current_liabilities);
+#[derive(Deserialize)]
+pub struct VatReturnMtdQuery {
+    period_key: String,
+    start: NaiveDate,
+    end: NaiveDate,
+    scheme: Option<VatScheme>,
+}
 
-    let net_assets = total_current_assets + total_current_liabilities;
-    ctx.insert("net_assets", &net_assets);
+#[get("/vat-return/mtd")]
+pub async fn vat_return_mtd(entity: web::ReqData<EntityDb>, query: web::Query<VatReturnMtdQuery>)
+                            -> Result<web::Json<MtdVatReturn>, HandlerError> {
+    let result = vat::vat_return(&entity.db, query.start, query.end, query.scheme).await?;
+    Ok(web::Json(MtdVatReturn::new(query.period_key.as_str(), &result.boxes)))
+}
 
-    let rendered = state.tmpl.render("balance_sheet.html", &ctx).unwrap();
-    HttpResponse::Ok().body(rendered)
+#[derive(Clone, Deserialize)]
+pub struct VatReturnSubmitData {
+    period_key: String,
+    start: NaiveDate,
+    end: NaiveDate,
+    scheme: Option<VatScheme>,
+}
+
+#[post("/vat-return/submit")]
+pub async fn vat_return_submit(state: Data<AppState>, entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                               item: web::Json<VatReturnSubmitData>)
+                               -> Result<web::Json<VatReceipt>, HandlerError> {
+    let receipt = vat::vat_return_submit(&entity.db, state.vat_submitter.clone(), item.period_key.as_str(),
+                                         item.start, item.end, item.scheme, &user).await?;
+    Ok(web::Json(receipt))
+}
+
+#[derive(Serialize)]
+struct VatReturnListResponse {
+    vat_returns: Vec<SubmittedVatReturnResponse>,
+}
+
+#[derive(Serialize)]
+struct SubmittedVatReturnResponse {
+    period_key: String,
+    start: String,
+    end: String,
+    scheme: VatScheme,
+    boxes: VatBoxesResponse,
+    receipt: VatReceipt,
+}
+
+#[get("/vat-return/list")]
+pub async fn vat_return_list(entity: web::ReqData<EntityDb>) -> Result<web::Json<VatReturnListResponse>, HandlerError> {
+    let results = vat::vat_return_list(&entity.db).await?;
+    Ok(web::Json(VatReturnListResponse {
+        vat_returns: results
+            .into_iter()
+            .map(|r| SubmittedVatReturnResponse {
+                boxes: (&r.boxes).into(),
+                period_key: r.period_key,
+                start: r.start,
+                end: r.end,
+                scheme: r.scheme,
+                receipt: r.receipt,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct ContactResponse {
+    contact_id: i64,
+    kind: ContactKind,
+    name: String,
+    address: Option<String>,
+    postcode: Option<String>,
+    country: Option<String>,
+    email: Option<String>,
+    tax_id: Option<String>,
+    control_account_id: Option<String>,
+    balance: String,
+}
+
+impl From<Contact> for ContactResponse {
+    fn from(c: Contact) -> Self {
+        ContactResponse {
+            contact_id: c.contact_id,
+            kind: c.kind,
+            name: c.name,
+            address: c.address,
+            postcode: c.postcode,
+            country: c.country,
+            email: c.email,
+            tax_id: c.tax_id,
+            control_account_id: c.control_account_id.map(|a| format!("{a:<08}")),
+            balance: c.balance.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ContactListQuery {
+    kind: Option<ContactKind>,
+}
+
+#[derive(Serialize)]
+struct ContactListResponse {
+    contacts: Vec<ContactResponse>,
+}
+
+#[get("/contact/list")]
+pub async fn contact_list(entity: web::ReqData<EntityDb>, query: web::Query<ContactListQuery>)
+                          -> Result<web::Json<ContactListResponse>, HandlerError> {
+    let results = contacts::contact_list(&entity.db, query.kind.as_ref()).await?;
+    Ok(web::Json(ContactListResponse {
+        contacts: results.into_iter().map(ContactResponse::from).collect(),
+    }))
+}
+
+#[get("/contact/{contact_id}")]
+pub async fn contact_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                            -> Result<web::Json<ContactResponse>, HandlerError> {
+    let contact_id = path.into_inner().1;
+    Ok(web::Json(contacts::contact_detail(&entity.db, contact_id).await?.into()))
+}
+
+#[post("/contact/new")]
+pub async fn contact_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<ContactData>)
+                         -> Result<web::Json<ContactResponse>, HandlerError> {
+    let contact_id = contacts::contact_new(&entity.db, &item, &user).await?;
+    Ok(web::Json(contacts::contact_detail(&entity.db, contact_id).await?.into()))
+}
+
+#[derive(Serialize)]
+struct AccountContactBalancesResponse {
+    account_id: String,
+    contacts: Vec<AccountContactBalanceResponse>,
+}
+
+#[derive(Serialize)]
+struct AccountContactBalanceResponse {
+    contact_id: Option<i64>,
+    contact_name: Option<String>,
+    balance: String,
+}
+
+#[get("/account/{account_id}/contacts")]
+pub async fn account_contact_balances(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                                      -> Result<web::Json<AccountContactBalancesResponse>, HandlerError> {
+    let account_id = path.into_inner().1;
+    let results = contacts::account_contact_balances(&entity.db, account_id).await?;
+    Ok(web::Json(AccountContactBalancesResponse {
+        account_id: format!("{account_id:<08}"),
+        contacts: results
+            .into_iter()
+            .map(|r| AccountContactBalanceResponse {
+                contact_id: r.contact_id,
+                contact_name: r.contact_name,
+                balance: r.balance.to_string(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct OpenItemQuery {
+    as_at: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct OpenItemsResponse {
+    contact_id: i64,
+    items: Vec<OpenItemResponse>,
+}
+
+#[derive(Serialize)]
+struct OpenItemResponse {
+    journal_id: i64,
+    date: String,
+    due_date: String,
+    document: Option<Document>,
+    narrative: String,
+    amount: String,
+    allocated: String,
+    outstanding: String,
+}
+
+impl From<OpenItem> for OpenItemResponse {
+    fn from(i: OpenItem) -> Self {
+        OpenItemResponse {
+            journal_id: i.journal_id,
+            date: i.date,
+            due_date: i.due_date,
+            document: i.document,
+            narrative: i.narrative,
+            amount: i.amount.to_string(),
+            allocated: i.allocated.to_string(),
+            outstanding: i.outstanding.to_string(),
+        }
+    }
+}
+
+#[get("/contact/{contact_id}/open-items")]
+pub async fn contact_open_items(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>,
+                                query: web::Query<OpenItemQuery>)
+                                -> Result<web::Json<OpenItemsResponse>, HandlerError> {
+    let contact_id = path.into_inner().1;
+    let results = allocations::open_items(&entity.db, contact_id, query.as_at).await?;
+    Ok(web::Json(OpenItemsResponse {
+        contact_id,
+        items: results.into_iter().map(OpenItemResponse::from).collect(),
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CashOnAccountData {
+    amount: i64,
+    date: NaiveDate,
+    cash_account_id: i64,
+}
+
+#[post("/contact/{contact_id}/on-account")]
+pub async fn contact_cash_on_account(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                     path: web::Path<(String, i64)>, item: web::Json<CashOnAccountData>)
+                                     -> Result<web::Json<OpenItemsResponse>, HandlerError> {
+    let contact_id = path.into_inner().1;
+    allocations::cash_on_account(&entity.db, contact_id, item.amount, item.date, item.cash_account_id, &user)
+        .await?;
+    let results = allocations::open_items(&entity.db, contact_id, None).await?;
+    Ok(web::Json(OpenItemsResponse {
+        contact_id,
+        items: results.into_iter().map(OpenItemResponse::from).collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct AllocationsResponse {
+    contact_id: i64,
+    allocations: Vec<AllocationResponse>,
+}
+
+#[derive(Serialize)]
+struct AllocationResponse {
+    allocation_id: i64,
+    journal_id: i64,
+    document: Document,
+    amount: String,
+    date: String,
+}
+
+impl From<Allocation> for AllocationResponse {
+    fn from(a: Allocation) -> Self {
+        AllocationResponse {
+            allocation_id: a.allocation_id,
+            journal_id: a.journal_id,
+            document: a.document,
+            amount: a.amount.to_string(),
+            date: a.date,
+        }
+    }
+}
+
+#[get("/contact/{contact_id}/allocations")]
+pub async fn contact_allocations(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                                 -> Result<web::Json<AllocationsResponse>, HandlerError> {
+    let contact_id = path.into_inner().1;
+    let results = allocations::allocation_list(&entity.db, contact_id).await?;
+    Ok(web::Json(AllocationsResponse {
+        contact_id,
+        allocations: results.into_iter().map(AllocationResponse::from).collect(),
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AllocationData {
+    journal_id: i64,
+    document: Document,
+    amount: i64,
+    date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct AllocationCreateResponse {
+    allocation_id: i64,
+}
+
+#[post("/allocation/new")]
+pub async fn allocation_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<AllocationData>)
+                            -> Result<web::Json<AllocationCreateResponse>, HandlerError> {
+    let allocation_id = allocations::allocation_new(&entity.db, item.journal_id, item.document, item.amount, item.date,
+                                                    &user).await?;
+    Ok(web::Json(AllocationCreateResponse { allocation_id }))
+}
+
+#[derive(Serialize)]
+struct AllocationDeleteResponse;
+
+#[post("/allocation/{allocation_id}/delete")]
+pub async fn allocation_delete(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i64)>)
+                               -> Result<web::Json<AllocationDeleteResponse>, HandlerError> {
+    allocations::allocation_delete(&entity.db, path.into_inner().1, &user).await?;
+    Ok(web::Json(AllocationDeleteResponse {}))
+}
+
+#[derive(Serialize)]
+struct InvoiceResponse {
+    invoice_id: i64,
+    kind: InvoiceKind,
+    number: Option<String>,
+    state: InvoiceState,
+    contact_id: i64,
+    contact_name: String,
+    date: String,
+    due_date: String,
+    reference: Option<String>,
+    journal_id: Option<i64>,
+    net_total: String,
+    tax_total: String,
+    total: String,
+    allocated: String,
+    outstanding: String,
+    lines: Vec<InvoiceLineResponse>,
+}
+
+#[derive(Serialize)]
+struct InvoiceLineResponse {
+    description: String,
+    quantity: f64,
+    unit_price: String,
+    tax_code: String,
+    account_id: String,
+    net_amount: String,
+    tax_amount: String,
+}
+
+impl From<Invoice> for InvoiceResponse {
+    fn from(i: Invoice) -> Self {
+        InvoiceResponse {
+            invoice_id: i.invoice_id,
+            kind: i.kind,
+            number: i.number,
+            state: i.state,
+            contact_id: i.contact_id,
+            contact_name: i.contact_name,
+            date: i.date,
+            due_date: i.due_date,
+            reference: i.reference,
+            journal_id: i.journal_id,
+            net_total: i.net_total.to_string(),
+            tax_total: i.tax_total.to_string(),
+            total: i.total.to_string(),
+            allocated: i.allocated.to_string(),
+            outstanding: i.outstanding.to_string(),
+            lines: i.lines.into_iter().map(InvoiceLineResponse::from).collect(),
+        }
+    }
+}
+
+impl From<InvoiceLine> for InvoiceLineResponse {
+    fn from(l: InvoiceLine) -> Self {
+        InvoiceLineResponse {
+            description: l.description,
+            quantity: l.quantity,
+            unit_price: l.unit_price.to_string(),
+            tax_code: l.tax_code,
+            account_id: format!("{:<08}", l.account_id),
+            net_amount: l.net_amount.to_string(),
+            tax_amount: l.tax_amount.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InvoiceListQuery {
+    contact_id: Option<i64>,
+    state: Option<InvoiceState>,
+}
+
+#[derive(Serialize)]
+struct InvoiceListResponse {
+    invoices: Vec<InvoiceResponse>,
+}
+
+#[get("/invoice/list")]
+pub async fn invoice_list(entity: web::ReqData<EntityDb>, query: web::Query<InvoiceListQuery>)
+                          -> Result<web::Json<InvoiceListResponse>, HandlerError> {
+    let results = invoicing::invoice_list(&entity.db, query.contact_id, query.state.as_ref()).await?;
+    Ok(web::Json(InvoiceListResponse {
+        invoices: results.into_iter().map(InvoiceResponse::from).collect(),
+    }))
+}
+
+#[get("/invoice/{invoice_id}")]
+pub async fn invoice_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                            -> Result<web::Json<InvoiceResponse>, HandlerError> {
+    let invoice_id = path.into_inner().1;
+    Ok(web::Json(invoicing::invoice_detail(&entity.db, invoice_id).await?.into()))
+}
+
+#[post("/invoice/new")]
+pub async fn invoice_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<InvoiceData>)
+                         -> Result<web::Json<InvoiceResponse>, HandlerError> {
+    let invoice_id = invoicing::invoice_new(&entity.db, &item, &user).await?;
+    Ok(web::Json(invoicing::invoice_detail(&entity.db, invoice_id).await?.into()))
+}
+
+#[post("/invoice/{invoice_id}/edit")]
+pub async fn invoice_update(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i64)>,
+                            item: web::Json<InvoiceData>) -> Result<web::Json<InvoiceResponse>, HandlerError> {
+    let invoice_id = path.into_inner().1;
+    invoicing::invoice_update(&entity.db, invoice_id, &item, &user).await?;
+    Ok(web::Json(invoicing::invoice_detail(&entity.db, invoice_id).await?.into()))
+}
+
+#[post("/invoice/{invoice_id}/approve")]
+pub async fn invoice_approve(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                             path: web::Path<(String, i64)>) -> Result<web::Json<InvoiceResponse>, HandlerError> {
+    let invoice_id = path.into_inner().1;
+    Ok(web::Json(invoicing::invoice_approve(&entity.db, invoice_id, &user).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct InvoiceReceiveData {
+    amount: i64,
+    date: NaiveDate,
+    cash_account_id: i64,
+}
+
+#[post("/invoice/{invoice_id}/receive")]
+pub async fn invoice_receive(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                             path: web::Path<(String, i64)>, item: web::Json<InvoiceReceiveData>)
+                             -> Result<web::Json<InvoiceResponse>, HandlerError> {
+    let invoice_id = path.into_inner().1;
+    invoicing::invoice_receive(&entity.db, invoice_id, item.amount, item.date, item.cash_account_id, &user)
+        .await?;
+    Ok(web::Json(invoicing::invoice_detail(&entity.db, invoice_id).await?.into()))
+}
+
+#[derive(Deserialize)]
+pub struct RenderQuery {
+    #[serde(default)]
+    format: DocumentFormat,
+}
+
+fn document_response(document: RenderedDocument) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(document.content_type)
+        .insert_header(("Content-Disposition", format!("inline; filename=\"{}\"", document.filename)))
+        .body(document.body)
+}
+
+#[get("/invoice/{invoice_id}/render")]
+pub async fn invoice_render(state: Data<AppState>, entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>,
+                            query: web::Query<RenderQuery>)
+                            -> Result<impl Responder, HandlerError> {
+    let invoice_id = path.into_inner().1;
+    Ok(document_response(documents::invoice_render(&entity.db, &state.tmpl, invoice_id, query.format).await?))
+}
+
+#[derive(Deserialize)]
+pub struct StatementQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    #[serde(default)]
+    format: DocumentFormat,
+}
+
+#[get("/contact/{contact_id}/statement")]
+pub async fn contact_statement(state: Data<AppState>, entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>,
+                               query: web::Query<StatementQuery>)
+                               -> Result<impl Responder, HandlerError> {
+    let subject = StatementSubject::Contact(path.into_inner().1);
+    let document = documents::statement_render(&entity.db, &state.tmpl, subject, query.from, query.to, query.format)
+        .await?;
+    Ok(document_response(document))
+}
+
+#[get("/account/{account_id}/statement")]
+pub async fn account_statement(state: Data<AppState>, entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>,
+                               query: web::Query<StatementQuery>)
+                               -> Result<impl Responder, HandlerError> {
+    let subject = StatementSubject::Account(path.into_inner().1);
+    let document = documents::statement_render(&entity.db, &state.tmpl, subject, query.from, query.to, query.format)
+        .await?;
+    Ok(document_response(document))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct StatementBulkData {
+    from: NaiveDate,
+    to: NaiveDate,
+    #[serde(default)]
+    format: DocumentFormat,
+    contact_kind: Option<ContactKind>,
+    account_type: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StatementBulkResponse {
+    files: Vec<String>,
+}
+
+#[post("/statement/bulk")]
+pub async fn statement_bulk(state: Data<AppState>, entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                            item: web::Json<StatementBulkData>)
+                            -> Result<web::Json<StatementBulkResponse>, HandlerError> {
+    let account_type = item.account_type.as_deref()
+        .map(AccountType::from_str)
+        .transpose()
+        .map_err(|_| Error::InstructionError("unknown account type".to_string()))?;
+    let subjects = statements::statement_subjects(&entity.db, item.contact_kind.as_ref(), account_type.as_ref())
+        .await?;
+    let directory = Path::new(state.statement_dir.as_str()).join(entity.slug.as_str());
+    let files = documents::statements_write(&entity.db, &state.tmpl, subjects, item.from, item.to, item.format,
+                                            &directory, &user).await?;
+    Ok(web::Json(StatementBulkResponse { files }))
+}
+
+#[derive(Serialize)]
+struct BillResponse {
+    bill_id: i64,
+    contact_id: i64,
+    contact_name: String,
+    supplier_reference: Option<String>,
+    date: String,
+    due_date: String,
+    journal_id: i64,
+    net_total: String,
+    tax_total: String,
+    total: String,
+    paid: String,
+    outstanding: String,
+    lines: Vec<BillLineResponse>,
+}
+
+#[derive(Serialize)]
+struct BillLineResponse {
+    description: String,
+    tax_code: String,
+    account_id: String,
+    net_amount: String,
+    tax_amount: String,
+}
+
+impl From<Bill> for BillResponse {
+    fn from(b: Bill) -> Self {
+        BillResponse {
+            bill_id: b.bill_id,
+            contact_id: b.contact_id,
+            contact_name: b.contact_name,
+            supplier_reference: b.supplier_reference,
+            date: b.date,
+            due_date: b.due_date,
+            journal_id: b.journal_id,
+            net_total: b.net_total.to_string(),
+            tax_total: b.tax_total.to_string(),
+            total: b.total.to_string(),
+            paid: b.paid.to_string(),
+            outstanding: b.outstanding.to_string(),
+            lines: b.lines.into_iter().map(BillLineResponse::from).collect(),
+        }
+    }
+}
+
+impl From<BillLine> for BillLineResponse {
+    fn from(l: BillLine) -> Self {
+        BillLineResponse {
+            description: l.description,
+            tax_code: l.tax_code,
+            account_id: format!("{:<08}", l.account_id),
+            net_amount: l.net_amount.to_string(),
+            tax_amount: l.tax_amount.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BillListQuery {
+    contact_id: Option<i64>,
+    #[serde(default)]
+    outstanding: bool,
+    due_by: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct BillListResponse {
+    bills: Vec<BillResponse>,
+}
+
+#[get("/bill/list")]
+pub async fn bill_list(entity: web::ReqData<EntityDb>, query: web::Query<BillListQuery>)
+                       -> Result<web::Json<BillListResponse>, HandlerError> {
+    let results = purchases::bill_list(&entity.db, query.contact_id, query.outstanding, query.due_by).await?;
+    Ok(web::Json(BillListResponse {
+        bills: results.into_iter().map(BillResponse::from).collect(),
+    }))
+}
+
+#[get("/bill/{bill_id}")]
+pub async fn bill_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                         -> Result<web::Json<BillResponse>, HandlerError> {
+    let bill_id = path.into_inner().1;
+    Ok(web::Json(purchases::bill_detail(&entity.db, bill_id).await?.into()))
+}
+
+#[post("/bill/new")]
+pub async fn bill_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                      item: web::Json<BillData>) -> Result<web::Json<BillResponse>, HandlerError> {
+    let bill_id = purchases::bill_new(&entity.db, &item, &user).await?;
+    Ok(web::Json(purchases::bill_detail(&entity.db, bill_id).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BillPayData {
+    amount: i64,
+    date: NaiveDate,
+    cash_account_id: i64,
+}
+
+#[post("/bill/{bill_id}/pay")]
+pub async fn bill_pay(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                      path: web::Path<(String, i64)>, item: web::Json<BillPayData>)
+                      -> Result<web::Json<BillResponse>, HandlerError> {
+    let bill_id = path.into_inner().1;
+    purchases::bill_pay(&entity.db, bill_id, item.amount, item.date, item.cash_account_id, &user).await?;
+    Ok(web::Json(purchases::bill_detail(&entity.db, bill_id).await?.into()))
+}
+
+#[derive(Serialize)]
+struct PaymentRunResponse {
+    payment_run_id: i64,
+    state: PaymentRunState,
+    due_by: String,
+    payment_date: String,
+    cash_account_id: String,
+    batch_id: Option<i64>,
+    total: String,
+    lines: Vec<PaymentRunLineResponse>,
+}
+
+#[derive(Serialize)]
+struct PaymentRunLineResponse {
+    bill_id: i64,
+    contact_id: i64,
+    contact_name: String,
+    supplier_reference: Option<String>,
+    due_date: String,
+    amount: String,
+}
+
+impl From<PaymentRun> for PaymentRunResponse {
+    fn from(r: PaymentRun) -> Self {
+        PaymentRunResponse {
+            payment_run_id: r.payment_run_id,
+            state: r.state,
+            due_by: r.due_by,
+            payment_date: r.payment_date,
+            cash_account_id: format!("{:<08}", r.cash_account_id),
+            batch_id: r.batch_id,
+            total: r.total.to_string(),
+            lines: r.lines
+                .into_iter()
+                .map(|l| PaymentRunLineResponse {
+                    bill_id: l.bill_id,
+                    contact_id: l.contact_id,
+                    contact_name: l.contact_name,
+                    supplier_reference: l.supplier_reference,
+                    due_date: l.due_date,
+                    amount: l.amount.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PaymentRunProposeData {
+    due_by: NaiveDate,
+    payment_date: NaiveDate,
+    cash_account_id: i64,
+}
+
+#[post("/payment-run/propose")]
+pub async fn payment_run_propose(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                 item: web::Json<PaymentRunProposeData>)
+                                 -> Result<web::Json<Option<PaymentRunResponse>>, HandlerError> {
+    let payment_run_id = purchases::payment_run_propose(&entity.db, item.due_by, item.payment_date,
+                                                        item.cash_account_id, &user).await?;
+    Ok(web::Json(match payment_run_id {
+        Some(id) => Some(purchases::payment_run_detail(&entity.db, id).await?.into()),
+        None => None,
+    }))
+}
+
+#[get("/payment-run/{payment_run_id}")]
+pub async fn payment_run_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                                -> Result<web::Json<PaymentRunResponse>, HandlerError> {
+    let payment_run_id = path.into_inner().1;
+    Ok(web::Json(purchases::payment_run_detail(&entity.db, payment_run_id).await?.into()))
+}
+
+#[post("/payment-run/{payment_run_id}/confirm")]
+pub async fn payment_run_confirm(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                 path: web::Path<(String, i64)>)
+                                 -> Result<web::Json<PaymentRunResponse>, HandlerError> {
+    let payment_run_id = path.into_inner().1;
+    purchases::payment_run_confirm(&entity.db, payment_run_id, &user).await?;
+    Ok(web::Json(purchases::payment_run_detail(&entity.db, payment_run_id).await?.into()))
+}
+
+#[derive(Serialize)]
+struct FixedAssetListResponse {
+    fixed_assets: Vec<FixedAssetResponse>,
+}
+
+#[derive(Serialize)]
+struct FixedAssetResponse {
+    asset_id: i64,
+    name: String,
+    cost_account_id: String,
+    accumulated_account_id: String,
+    depreciation_account_id: String,
+    cost: String,
+    residual_value: String,
+    acquisition_date: String,
+    useful_life_months: i64,
+    method: DepreciationMethod,
+    rate: Option<i64>,
+    accumulated_depreciation: String,
+    net_book_value: String,
+    disposal_date: Option<String>,
+    disposal_proceeds: Option<String>,
+    disposal_journal_id: Option<i64>,
+    schedule: Vec<DepreciationLineResponse>,
+}
+
+#[derive(Serialize)]
+struct DepreciationLineResponse {
+    month: String,
+    amount: String,
+    net_book_value: String,
+    journal_id: Option<i64>,
+}
+
+impl From<FixedAsset> for FixedAssetResponse {
+    fn from(a: FixedAsset) -> Self {
+        FixedAssetResponse {
+            asset_id: a.asset_id,
+            name: a.name,
+            cost_account_id: format!("{:<08}", a.cost_account_id),
+            accumulated_account_id: format!("{:<08}", a.accumulated_account_id),
+            depreciation_account_id: format!("{:<08}", a.depreciation_account_id),
+            cost: a.cost.to_string(),
+            residual_value: a.residual_value.to_string(),
+            acquisition_date: a.acquisition_date,
+            useful_life_months: a.useful_life_months,
+            method: a.method,
+            rate: a.rate,
+            accumulated_depreciation: a.accumulated_depreciation.to_string(),
+            net_book_value: a.net_book_value.to_string(),
+            disposal_date: a.disposal_date,
+            disposal_proceeds: a.disposal_proceeds.map(|p| p.to_string()),
+            disposal_journal_id: a.disposal_journal_id,
+            schedule: a.schedule
+                .into_iter()
+                .map(|l| DepreciationLineResponse {
+                    month: l.month,
+                    amount: l.amount.to_string(),
+                    net_book_value: l.net_book_value.to_string(),
+                    journal_id: l.journal_id,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FixedAssetListQuery {
+    #[serde(default)]
+    active: bool,
+}
+
+#[get("/fixed-asset/list")]
+pub async fn fixed_asset_list(entity: web::ReqData<EntityDb>, query: web::Query<FixedAssetListQuery>)
+                              -> Result<web::Json<FixedAssetListResponse>, HandlerError> {
+    let results = assets::fixed_asset_list(&entity.db, query.active).await?;
+    Ok(web::Json(FixedAssetListResponse {
+        fixed_assets: results.into_iter().map(FixedAssetResponse::from).collect(),
+    }))
+}
+
+#[get("/fixed-asset/{asset_id}")]
+pub async fn fixed_asset_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                                -> Result<web::Json<FixedAssetResponse>, HandlerError> {
+    let asset_id = path.into_inner().1;
+    Ok(web::Json(assets::fixed_asset_detail(&entity.db, asset_id).await?.into()))
+}
+
+#[post("/fixed-asset/new")]
+pub async fn fixed_asset_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<FixedAssetData>)
+                             -> Result<web::Json<FixedAssetResponse>, HandlerError> {
+    let asset_id = assets::fixed_asset_new(&entity.db, &item, &user).await?;
+    Ok(web::Json(assets::fixed_asset_detail(&entity.db, asset_id).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DepreciationRunData {
+    period_end: NaiveDate,
+}
+
+#[derive(Serialize)]
+struct DepreciationRunResponse {
+    batch_id: Option<i64>,
+    journal_count: usize,
+    total: String,
+}
+
+#[post("/fixed-asset/depreciate")]
+pub async fn depreciation_run(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                              item: web::Json<DepreciationRunData>)
+                              -> Result<web::Json<DepreciationRunResponse>, HandlerError> {
+    let result = assets::depreciation_run(&entity.db, item.period_end, &user).await?;
+    Ok(web::Json(DepreciationRunResponse {
+        batch_id: result.as_ref().map(|r| r.batch_id),
+        journal_count: result.as_ref().map(|r| r.journal_count).unwrap_or(0),
+        total: result.map(|r| r.total).unwrap_or(0).to_string(),
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct FixedAssetDisposeData {
+    date: NaiveDate,
+    #[serde(default)]
+    proceeds: i64,
+    cash_account_id: Option<i64>,
+}
+
+#[post("/fixed-asset/{asset_id}/dispose")]
+pub async fn fixed_asset_dispose(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                 path: web::Path<(String, i64)>, item: web::Json<FixedAssetDisposeData>)
+                                 -> Result<web::Json<FixedAssetResponse>, HandlerError> {
+    let asset_id = path.into_inner().1;
+    assets::fixed_asset_dispose(&entity.db, asset_id, item.date, item.proceeds, item.cash_account_id, &user)
+        .await?;
+    Ok(web::Json(assets::fixed_asset_detail(&entity.db, asset_id).await?.into()))
+}
+
+#[derive(Serialize)]
+struct ScheduleListResponse {
+    schedules: Vec<ScheduleResponse>,
+}
+
+#[derive(Serialize)]
+struct ScheduleResponse {
+    schedule_id: i64,
+    kind: ScheduleKind,
+    description: String,
+    total: String,
+    start_date: String,
+    periods: i64,
+    expense_account_id: String,
+    balance_account_id: String,
+    amortised: String,
+    remaining: String,
+    periods_posted: i64,
+    lines: Vec<SchedulePeriodResponse>,
+}
+
+#[derive(Serialize)]
+struct SchedulePeriodResponse {
+    period: i64,
+    date: String,
+    amount: String,
+    journal_id: Option<i64>,
+}
+
+impl From<Schedule> for ScheduleResponse {
+    fn from(s: Schedule) -> Self {
+        ScheduleResponse {
+            schedule_id: s.schedule_id,
+            kind: s.kind,
+            description: s.description,
+            total: s.total.to_string(),
+            start_date: s.start_date,
+            periods: s.periods,
+            expense_account_id: format!("{:<08}", s.expense_account_id),
+            balance_account_id: format!("{:<08}", s.balance_account_id),
+            amortised: s.amortised.to_string(),
+            remaining: s.remaining.to_string(),
+            periods_posted: s.periods_posted,
+            lines: s.lines
+                .into_iter()
+                .map(|l| SchedulePeriodResponse {
+                    period: l.period,
+                    date: l.date,
+                    amount: l.amount.to_string(),
+                    journal_id: l.journal_id,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleListQuery {
+    #[serde(default)]
+    active: bool,
+}
+
+#[get("/amortisation/list")]
+pub async fn amortisation_list(entity: web::ReqData<EntityDb>, query: web::Query<ScheduleListQuery>)
+                               -> Result<web::Json<ScheduleListResponse>, HandlerError> {
+    let results = amortisation::schedule_list(&entity.db, query.active).await?;
+    Ok(web::Json(ScheduleListResponse {
+        schedules: results.into_iter().map(ScheduleResponse::from).collect(),
+    }))
+}
+
+#[get("/amortisation/{schedule_id}")]
+pub async fn amortisation_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i64)>)
+                                 -> Result<web::Json<ScheduleResponse>, HandlerError> {
+    let schedule_id = path.into_inner().1;
+    Ok(web::Json(amortisation::schedule_detail(&entity.db, schedule_id).await?.into()))
+}
+
+#[post("/amortisation/new")]
+pub async fn amortisation_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<ScheduleData>)
+                              -> Result<web::Json<ScheduleResponse>, HandlerError> {
+    let schedule_id = amortisation::schedule_new(&entity.db, &item, &user).await?;
+    Ok(web::Json(amortisation::schedule_detail(&entity.db, schedule_id).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AmortisationRunData {
+    period_end: NaiveDate,
+}
+
+#[derive(Serialize)]
+struct AmortisationRunResponse {
+    batch_ids: Vec<i64>,
+    journal_count: usize,
+    total: String,
+}
+
+#[post("/amortisation/run")]
+pub async fn amortisation_run(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                              item: web::Json<AmortisationRunData>)
+                              -> Result<web::Json<AmortisationRunResponse>, HandlerError> {
+    let result = amortisation::amortisation_run(&entity.db, item.period_end, &user).await?;
+    Ok(web::Json(AmortisationRunResponse {
+        batch_ids: result.as_ref().map(|r| r.batch_ids.clone()).unwrap_or_default(),
+        journal_count: result.as_ref().map(|r| r.journal_count).unwrap_or(0),
+        total: result.map(|r| r.total).unwrap_or(0).to_string(),
+    }))
+}
+
+#[derive(Serialize)]
+struct DimensionListResponse {
+    dimensions: Vec<Dimension>,
+}
+
+#[get("/dimension/list")]
+pub async fn dimension_list(entity: web::ReqData<EntityDb>) -> Result<web::Json<DimensionListResponse>, HandlerError> {
+    Ok(web::Json(DimensionListResponse {
+        dimensions: dimensions::dimension_list(&entity.db).await?,
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DimensionData {
+    name: String,
+    description: Option<String>,
+}
+
+#[post("/dimension/new")]
+pub async fn dimension_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, item: web::Json<DimensionData>)
+                           -> Result<web::Json<DimensionListResponse>, HandlerError> {
+    dimensions::dimension_new(&entity.db, item.name.as_str(), item.description.as_deref(), &user).await?;
+    Ok(web::Json(DimensionListResponse {
+        dimensions: dimensions::dimension_list(&entity.db).await?,
+    }))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DimensionValueData {
+    value: String,
+    description: Option<String>,
+}
+
+#[post("/dimension/{name}/value")]
+pub async fn dimension_value_new(entity: web::ReqData<EntityDb>, user: web::ReqData<User>,
+                                 path: web::Path<(String, String)>, item: web::Json<DimensionValueData>)
+                                 -> Result<web::Json<DimensionListResponse>, HandlerError> {
+    let dimension = path.into_inner().1;
+    dimensions::dimension_value_new(&entity.db, dimension.as_str(), item.value.as_str(), item.description.as_deref(),
+                                    &user).await?;
+    Ok(web::Json(DimensionListResponse {
+        dimensions: dimensions::dimension_list(&entity.db).await?,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ProfitAndLossQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    #[serde(flatten)]
+    tag: TagQuery,
+    /// A dimension to show a column for each value of.
+    pivot: Option<String>,
+    /// A comma-separated list of periods, given as `from..to`, to compare the report with.
+    compare: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReportLineResponse {
+    account_id: String,
+    account_name: String,
+    account_type: AccountType,
+    amounts: Vec<String>,
+}
+
+impl From<ReportLine> for ReportLineResponse {
+    fn from(l: ReportLine) -> Self {
+        ReportLineResponse {
+            account_id: format!("{:<08}", l.account_id),
+            account_name: l.account_name,
+            account_type: l.account_type,
+            amounts: l.amounts.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ProfitAndLossResponse {
+    from: String,
+    to: String,
+    comparisons: Vec<Period>,
+    tag: Option<Tag>,
+    columns: Vec<String>,
+    lines: Vec<ReportLineResponse>,
+    profit: Vec<String>,
+}
+
+#[get("/report/profit-and-loss")]
+pub async fn report_profit_and_loss(entity: web::ReqData<EntityDb>, query: web::Query<ProfitAndLossQuery>)
+                                    -> Result<web::Json<ProfitAndLossResponse>, HandlerError> {
+    let tag = query.tag.tag();
+    let comparisons = reports::periods_parse(query.compare.as_deref())?;
+    let report = reports::profit_and_loss(&entity.db, query.from, query.to, &comparisons, tag.as_ref(),
+                                          query.pivot.as_deref()).await?;
+    Ok(web::Json(ProfitAndLossResponse {
+        from: report.from,
+        to: report.to,
+        comparisons: report.comparisons,
+        tag: report.tag,
+        columns: report.columns,
+        lines: report.lines.into_iter().map(ReportLineResponse::from).collect(),
+        profit: report.profit.iter().map(|a| a.to_string()).collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct BudgetResponse {
+    fiscal_year: i32,
+    months: Vec<String>,
+    lines: Vec<BudgetLineResponse>,
+}
+
+#[derive(Serialize)]
+struct BudgetLineResponse {
+    account_id: String,
+    tag: Option<Tag>,
+    amounts: Vec<String>,
+}
+
+impl From<Budget> for BudgetResponse {
+    fn from(b: Budget) -> Self {
+        BudgetResponse {
+            fiscal_year: b.fiscal_year,
+            months: b.months,
+            lines: b.lines
+                .into_iter()
+                .map(|l| BudgetLineResponse {
+                    account_id: format!("{:<08}", l.account),
+                    tag: l.tag,
+                    amounts: l.amounts.iter().map(|a| a.to_string()).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[get("/budget/{fiscal_year}")]
+pub async fn budget_detail(entity: web::ReqData<EntityDb>, path: web::Path<(String, i32)>)
+                           -> Result<web::Json<BudgetResponse>, HandlerError> {
+    Ok(web::Json(budgets::budget_get(&entity.db, path.1).await?.into()))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BudgetData {
+    lines: Vec<BudgetLine>,
+}
+
+#[post("/budget/{fiscal_year}")]
+pub async fn budget_set(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i32)>,
+                        item: web::Json<BudgetData>) -> Result<web::Json<BudgetResponse>, HandlerError> {
+    budgets::budget_set(&entity.db, path.1, &item.lines, &user).await?;
+    Ok(web::Json(budgets::budget_get(&entity.db, path.1).await?.into()))
+}
+
+/// Set budgets from a CSV file, as described at [`budgets::budget_import`].
+#[post("/budget/{fiscal_year}/import")]
+pub async fn budget_import(entity: web::ReqData<EntityDb>, user: web::ReqData<User>, path: web::Path<(String, i32)>,
+                           body: String) -> Result<web::Json<BudgetResponse>, HandlerError> {
+    budgets::budget_import(&entity.db, path.1, body.as_str(), &user).await?;
+    Ok(web::Json(budgets::budget_get(&entity.db, path.1).await?.into()))
+}
+
+#[derive(Deserialize)]
+pub struct BudgetVarianceQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    #[serde(flatten)]
+    tag: TagQuery,
+}
+
+#[derive(Serialize)]
+struct BudgetComparisonResponse {
+    actual: String,
+    budget: String,
+    variance: String,
+    variance_percent: Option<String>,
+}
+
+impl From<&BudgetComparison> for BudgetComparisonResponse {
+    fn from(c: &BudgetComparison) -> Self {
+        BudgetComparisonResponse {
+            actual: c.actual.to_string(),
+            budget: c.budget.to_string(),
+            variance: c.variance.to_string(),
+            variance_percent: c.variance_percent.map(|p| {
+                format!("{}{}.{}", if p < 0 { "-" } else { "" }, p.abs() / 10, p.abs() % 10)
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BudgetVarianceLineResponse {
+    account_id: String,
+    account_name: String,
+    account_type: AccountType,
+    period: BudgetComparisonResponse,
+    year_to_date: BudgetComparisonResponse,
+}
+
+#[derive(Serialize)]
+struct BudgetVarianceResponse {
+    from: String,
+    to: String,
+    year_start: String,
+    tag: Option<Tag>,
+    lines: Vec<BudgetVarianceLineResponse>,
+    period_profit: BudgetComparisonResponse,
+    year_to_date_profit: BudgetComparisonResponse,
+}
+
+#[get("/report/budget-variance")]
+pub async fn report_budget_variance(entity: web::ReqData<EntityDb>, query: web::Query<BudgetVarianceQuery>)
+                                    -> Result<web::Json<BudgetVarianceResponse>, HandlerError> {
+    let tag = query.tag.tag();
+    let report = reports::budget_variance(&entity.db, query.from, query.to, tag.as_ref()).await?;
+    Ok(web::Json(BudgetVarianceResponse {
+        from: report.from,
+        to: report.to,
+        year_start: report.year_start,
+        tag: report.tag,
+        lines: report.lines
+            .iter()
+            .map(|l| BudgetVarianceLineResponse {
+                account_id: format!("{:<08}", l.account_id),
+                account_name: l.account_name.clone(),
+                account_type: l.account_type.clone(),
+                period: (&l.period).into(),
+                year_to_date: (&l.year_to_date).into(),
+            })
+            .collect(),
+        period_profit: (&report.period_profit).into(),
+        year_to_date_profit: (&report.year_to_date_profit).into(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CashFlowQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    /// A comma-separated list of periods, given as `from..to`, to compare the statement with.
+    compare: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CashFlowLineResponse {
+    line: String,
+    amounts: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CashFlowResponse {
+    from: String,
+    to: String,
+    comparisons: Vec<Period>,
+    profit: Vec<String>,
+    operating: Vec<CashFlowLineResponse>,
+    net_operating: Vec<String>,
+    investing: Vec<CashFlowLineResponse>,
+    net_investing: Vec<String>,
+    financing: Vec<CashFlowLineResponse>,
+    net_financing: Vec<String>,
+    exchange_differences: Vec<String>,
+    net_cash_flow: Vec<String>,
+    opening_cash: Vec<String>,
+    closing_cash: Vec<String>,
+}
+
+fn amounts_response(amounts: &[i64]) -> Vec<String> {
+    amounts.iter().map(|a| a.to_string()).collect()
+}
+
+fn cash_flow_lines(lines: &[CashFlowLine]) -> Vec<CashFlowLineResponse> {
+    lines
+        .iter()
+        .map(|l| CashFlowLineResponse { line: l.line.clone(), amounts: amounts_response(&l.amounts) })
+        .collect()
+}
+
+#[get("/report/cash-flow")]
+pub async fn report_cash_flow(entity: web::ReqData<EntityDb>, query: web::Query<CashFlowQuery>)
+                              -> Result<web::Json<CashFlowResponse>, HandlerError> {
+    let comparisons = reports::periods_parse(query.compare.as_deref())?;
+    let report = reports::cash_flow(&entity.db, query.from, query.to, &comparisons).await?;
+    Ok(web::Json(CashFlowResponse {
+        from: report.from,
+        to: report.to,
+        comparisons: report.comparisons,
+        profit: amounts_response(&report.profit),
+        operating: cash_flow_lines(&report.operating),
+        net_operating: amounts_response(&report.net_operating),
+        investing: cash_flow_lines(&report.investing),
+        net_investing: amounts_response(&report.net_investing),
+        financing: cash_flow_lines(&report.financing),
+        net_financing: amounts_response(&report.net_financing),
+        exchange_differences: amounts_response(&report.exchange_differences),
+        net_cash_flow: amounts_response(&report.net_cash_flow),
+        opening_cash: amounts_response(&report.opening_cash),
+        closing_cash: amounts_response(&report.closing_cash),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct BalanceSheetQuery {
+    /// Defaults to the current date.
+    as_at: Option<NaiveDate>,
+    /// A comma-separated list of dates to compare the balance sheet with.
+    compare: Option<String>,
+}
+
+#[get("/report/balance")]
+pub async fn report_balance_sheet(state: Data<AppState>, entity: web::ReqData<EntityDb>,
+                                  query: web::Query<BalanceSheetQuery>) -> Result<impl Responder, HandlerError> {
+    let as_at = query.as_at.unwrap_or_else(|| chrono::Local::now().date_naive());
+    let comparisons = reports::dates_parse(query.compare.as_deref())?;
+    let rendered = ixbrl::balance_sheet_render(&entity.db, &state.tmpl, as_at, &comparisons).await?;
+    Ok(HttpResponse::Ok().content_type("application/xhtml+xml; charset=utf-8").body(rendered))
+}
+
+#[derive(Deserialize)]
+pub struct AccountsQuery {
+    /// The last day of the fiscal year the accounts are made up to.
+    as_at: NaiveDate,
+    #[serde(default)]
+    variant: AccountsVariant,
+    director: String,
+    approved: NaiveDate,
+    employees: i64,
+}
+
+#[get("/report/accounts")]
+pub async fn report_accounts(state: Data<AppState>, entity: web::ReqData<EntityDb>,
+                             query: web::Query<AccountsQuery>) -> Result<impl Responder, HandlerError> {
+    let approval = Approval {
+        director: query.director.clone(),
+        date: query.approved,
+        employees: query.employees,
+    };
+    let rendered = statutory::accounts_render(&entity.db, &state.tmpl, query.as_at, query.variant, &approval)
+        .await?;
+    Ok(HttpResponse::Ok().content_type("application/xhtml+xml; charset=utf-8").body(rendered))
+}
+
+/// The output format of a report.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Html,
+    Csv,
+}
+
+fn csv_response(filename: &str, rows: Vec<Vec<String>>) -> HttpResponse {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.write_record(row).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{filename}.csv\"")))
+        .body(writer.into_inner().unwrap())
+}
+
+#[derive(Deserialize)]
+pub struct AgedReportQuery {
+    as_at: Option<NaiveDate>,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+#[derive(Serialize)]
+struct AgedReportResponse {
+    as_at: String,
+    contacts: Vec<AgedContactResponse>,
+    total: AgedBalanceResponse,
+}
+
+#[derive(Serialize)]
+struct AgedContactResponse {
+    contact_id: i64,
+    contact_name: String,
+    #[serde(flatten)]
+    balance: AgedBalanceResponse,
+}
+
+#[derive(Serialize)]
+struct AgedBalanceResponse {
+    current: String,
+    days_30: String,
+    days_60: String,
+    days_90: String,
+    days_over_90: String,
+    total: String,
+}
+
+impl From<&aging::AgedBalance> for AgedBalanceResponse {
+    fn from(b: &aging::AgedBalance) -> Self {
+        AgedBalanceResponse {
+            current: b.current.to_string(),
+            days_30: b.days_30.to_string(),
+            days_60: b.days_60.to_string(),
+            days_90: b.days_90.to_string(),
+            days_over_90: b.days_over_90.to_string(),
+            total: b.total.to_string(),
+        }
+    }
+}
+
+async fn aged_report_response(state: Data<AppState>, entity: web::ReqData<EntityDb>, ledger: AgedLedger,
+                              query: AgedReportQuery) -> Result<HttpResponse, HandlerError> {
+    let as_at = query.as_at.unwrap_or_else(|| chrono::Local::now().date_naive());
+    let report: AgedReport = aging::aged_report(&entity.db, ledger, as_at).await?;
+    Ok(match query.format {
+        ReportFormat::Json => HttpResponse::Ok().json(AgedReportResponse {
+            as_at: report.as_at.clone(),
+            contacts: report.contacts
+                .iter()
+                .map(|c| AgedContactResponse {
+                    contact_id: c.contact_id,
+                    contact_name: c.contact_name.clone(),
+                    balance: (&c.balance).into(),
+                })
+                .collect(),
+            total: (&report.total).into(),
+        }),
+        ReportFormat::Html => {
+            let mut ctx = Context::new();
+            ctx.insert("entity_name", &get_settings_str(&entity.db, "entityName").await.unwrap_or_default());
+            ctx.insert("title", ledger.title());
+            ctx.insert("report", &report);
+            HttpResponse::Ok().body(state.tmpl.render("aged_report.html", &ctx)?)
+        }
+        ReportFormat::Csv => {
+            let mut rows = vec![["contact_id", "contact_name", "current", "days_30", "days_60", "days_90",
+                "days_over_90", "total"].map(String::from).to_vec()];
+            let balance_row = |id: String, name: String, b: &aging::AgedBalance| vec![id, name,
+                b.current.to_string(), b.days_30.to_string(), b.days_60.to_string(), b.days_90.to_string(),
+                b.days_over_90.to_string(), b.total.to_string()];
+            for contact in &report.contacts {
+                rows.push(balance_row(contact.contact_id.to_string(), contact.contact_name.clone(), &contact.balance));
+            }
+            rows.push(balance_row("".to_string(), "Total".to_string(), &report.total));
+            let filename = format!("{}-{}", ledger.title().to_lowercase().replace(' ', "-"), report.as_at);
+            csv_response(filename.as_str(), rows)
+        }
+    })
+}
+
+#[get("/report/aged-debtors")]
+pub async fn report_aged_debtors(state: Data<AppState>, entity: web::ReqData<EntityDb>,
+                                    query: web::Query<AgedReportQuery>) -> impl Responder {
+    aged_report_response(state, entity, AgedLedger::Receivables, query.into_inner()).await
+}
+
+#[get("/report/aged-creditors")]
+pub async fn report_aged_creditors(state: Data<AppState>, entity: web::ReqData<EntityDb>,
+                                      query: web::Query<AgedReportQuery>) -> impl Responder {
+    aged_report_response(state, entity, AgedLedger::Payables, query.into_inner()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, test};
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Call `POST /entity/test/journal/new` on the books of a new entity as a user with the role.
+    async fn journal_new_status(role: Role, body: serde_json::Value) -> StatusCode {
+        let entity = EntityDb {
+            entity_id: 1,
+            slug: "test".to_string(),
+            db: entities::memory_connect().await,
+        };
+        let user = User {
+            user_id: 1,
+            name: "sam".to_string(),
+            role,
+        };
+        let app = test::init_service(App::new()
+            .service(web::scope("/entity/{slug}")
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(entity.clone());
+                    req.extensions_mut().insert(user.clone());
+                    srv.call(req)
+                })
+                .service(journal_new)))
+            .await;
+        let req = test::TestRequest::post().uri("/entity/test/journal/new").set_json(body).to_request();
+        test::call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn denied_role_gets_forbidden() {
+        let body = json!({"entries": [{"account": 100, "amount": 100}, {"account": 100, "amount": -100}]});
+        assert_eq!(journal_new_status(Role::Viewer, body).await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn invalid_instruction_gets_bad_request() {
+        let body = json!({"entries": [{"account": 100, "amount": 100}]});
+        assert_eq!(journal_new_status(Role::Bookkeeper, body).await, StatusCode::BAD_REQUEST);
+    }
 }
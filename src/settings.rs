@@ -1,4 +1,8 @@
-use sqlx::SqliteExecutor;
+use serde_json::json;
+use sqlx::{SqliteConnection, SqliteExecutor};
+
+use crate::audit::AuditOperation;
+use crate::db;
 
 pub async fn get_settings_int(e: impl SqliteExecutor<'_>, name: &str) -> Option<i64> {
     let result = sqlx::query!(
@@ -20,22 +24,46 @@ pub async fn get_settings_str(e: impl SqliteExecutor<'_>, name: &str) -> Option<
     result.strValue
 }
 
-pub async fn set_settings_int(e: impl SqliteExecutor<'_>, name: &str, value: i64) {
+/// Set a setting, recording the change in the audit log. Call within the transaction making the
+/// change the setting is part of.
+pub async fn set_settings_int(e: &mut SqliteConnection, name: &str, value: i64, actor: Option<i64>) {
+    let before = sqlx::query!(
+        "SELECT intValue FROM settings WHERE name=?", name
+    )
+        .fetch_optional(&mut *e)
+        .await
+        .unwrap()
+        .and_then(|r| r.intValue);
     sqlx::query!(
         "INSERT OR REPLACE INTO settings (name, intValue) VALUES (?, ?)",
         name, value
     )
-        .execute(e)
+        .execute(&mut *e)
+        .await
+        .unwrap();
+    db::audit_event_new(e, actor, AuditOperation::SettingSet, name, Some(json!(before)), Some(json!(value)))
         .await
         .unwrap();
 }
 
-pub async fn set_settings_str(e: impl SqliteExecutor<'_>, name: &str, value: i64) {
+/// Set a setting, recording the change in the audit log. Call within the transaction making the
+/// change the setting is part of.
+pub async fn set_settings_str(e: &mut SqliteConnection, name: &str, value: &str, actor: Option<i64>) {
+    let before = sqlx::query!(
+        "SELECT strValue FROM settings WHERE name=?", name
+    )
+        .fetch_optional(&mut *e)
+        .await
+        .unwrap()
+        .and_then(|r| r.strValue);
     sqlx::query!(
         "INSERT OR REPLACE INTO settings (name, strValue) VALUES (?, ?)",
         name, value
     )
-        .execute(e)
+        .execute(&mut *e)
+        .await
+        .unwrap();
+    db::audit_event_new(e, actor, AuditOperation::SettingSet, name, Some(json!(before)), Some(json!(value)))
         .await
         .unwrap();
 }
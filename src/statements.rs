@@ -0,0 +1,215 @@
+//! Statements of account for contacts and for client accounts.
+//!
+//! A statement lists each journal for a contact, or for a single account such as a client money
+//! liability account, over a date range, between the opening and closing balances. Balances are
+//! shown the way the recipient reads them: a customer's balance is what they owe, while a
+//! supplier's or client's balance is what is owed or held for them.
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+use crate::{contacts, db, ledger};
+use crate::contacts::{Contact, ContactKind};
+use crate::error::Error::InstructionError;
+use crate::ledger::AccountType;
+
+/// The contact or account that a statement is for.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementSubject {
+    /// All entries for a contact, whichever account they are posted to.
+    Contact(i64),
+    /// All entries on an account.
+    Account(i64),
+}
+
+impl StatementSubject {
+    /// The file name, without an extension, for the statement ending on `to`.
+    pub fn filename(&self, to: &str) -> String {
+        match self {
+            StatementSubject::Contact(id) => format!("statement-contact-{id}-{to}"),
+            StatementSubject::Account(id) => format!("statement-account-{id:<08}-{to}"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct StatementLine {
+    pub journal_id: i64,
+    pub date: String,
+    pub narrative: String,
+    pub debit: i64,
+    pub credit: i64,
+    /// The balance after this line.
+    pub balance: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Statement {
+    pub subject: StatementSubject,
+    pub name: String,
+    /// The contact the statement is for, if it is for a contact.
+    pub contact: Option<Contact>,
+    pub from: String,
+    pub to: String,
+    pub opening_balance: i64,
+    pub lines: Vec<StatementLine>,
+    pub closing_balance: i64,
+}
+
+impl Statement {
+    /// Whether the statement has no balance and no transactions, so need not be sent.
+    pub fn is_empty(&self) -> bool {
+        self.opening_balance == 0 && self.lines.is_empty()
+    }
+}
+
+/// Build the statement for a contact or account for the period `from` to `to` inclusive.
+pub async fn statement(e: &Pool<Sqlite>, subject: StatementSubject, from: NaiveDate, to: NaiveDate)
+                       -> Result<Statement> {
+    if to < from {
+        bail!(InstructionError("statement ends before it starts".to_string()));
+    }
+    let (contact_id, account_id, name, contact, sign) = match subject {
+        StatementSubject::Contact(contact_id) => {
+            let contact = contacts::contact_detail(e, contact_id).await?;
+            let sign = if contact.kind == ContactKind::Supplier { -1 } else { 1 };
+            (Some(contact_id), None, contact.name.clone(), Some(contact), sign)
+        }
+        StatementSubject::Account(account_id) => {
+            let Some(account_type) = db::account_type_query(e, account_id).await? else {
+                bail!(InstructionError(format!("account {account_id} does not exist")));
+            };
+            let account = ledger::account_detail(e, account_id).await?;
+            let sign = if account_type.is_credit() { -1 } else { 1 };
+            (None, Some(account_id), account.account_name, None, sign)
+        }
+    };
+    let (from, to) = (from.to_string(), to.to_string());
+    let opening_balance = sign * db::statement_opening_query(e, contact_id, account_id, from.as_str()).await?;
+    let mut balance = opening_balance;
+    let lines: Vec<StatementLine> = db::statement_line_query(e, contact_id, account_id, from.as_str(), to.as_str())
+        .await?
+        .into_iter()
+        .map(|r| {
+            balance += sign * r.amount;
+            StatementLine {
+                journal_id: r.journal_id,
+                date: r.date,
+                narrative: r.narrative,
+                debit: r.amount.max(0),
+                credit: -r.amount.min(0),
+                balance,
+            }
+        })
+        .collect();
+    Ok(Statement {
+        subject,
+        name,
+        contact,
+        from,
+        to,
+        opening_balance,
+        lines,
+        closing_balance: balance,
+    })
+}
+
+/// The contacts of a kind, or the client accounts of a type, that statements can be sent to in
+/// bulk. Only accounts marked by [`ledger::account_client_set`] are client accounts, so that
+/// control and tax accounts of the same type are left out.
+pub async fn statement_subjects(e: &Pool<Sqlite>, contact_kind: Option<&ContactKind>,
+                                account_type: Option<&AccountType>) -> Result<Vec<StatementSubject>> {
+    Ok(match (contact_kind, account_type) {
+        (Some(kind), None) => contacts::contact_list(e, Some(kind)).await?
+            .into_iter()
+            .map(|c| StatementSubject::Contact(c.contact_id))
+            .collect(),
+        (None, Some(account_type)) => db::client_account_list_query(e, account_type).await?
+            .into_iter()
+            .map(StatementSubject::Account)
+            .collect(),
+        _ => bail!(InstructionError("give either a contact kind or an account type".to_string())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+    use crate::ledger::{BatchMetadata, Journal, JournalEntry};
+    use crate::users::{Role, User};
+
+    fn admin() -> User {
+        User {
+            user_id: 1,
+            name: "admin".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    /// Move `amount` from cash into a client account on `date`.
+    async fn deposit(e: &Pool<Sqlite>, account_id: i64, amount: i64, date: &str) {
+        let metadata = BatchMetadata {
+            date: Some(date.to_string()),
+            ..Default::default()
+        };
+        let journal = Journal {
+            unstructured_narrative: "Deposit".to_string(),
+            entries: vec![
+                JournalEntry {
+                    account: 100,
+                    amount,
+                    ..Default::default()
+                },
+                JournalEntry {
+                    account: account_id,
+                    amount: -amount,
+                    ..Default::default()
+                },
+            ],
+        };
+        ledger::batch_new(e, &metadata, vec![journal]).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn statement_shows_client_balances_as_held_for_them() {
+        let db = entities::memory_connect().await;
+        let account_id = ledger::account_new(&db, None, &"Client A".to_string(), &AccountType::CurrentLiability, None,
+                                             None, &admin()).await.unwrap();
+        deposit(&db, account_id, 10000, "2024-03-01").await;
+        deposit(&db, account_id, -2500, "2024-04-10").await;
+        deposit(&db, account_id, 1000, "2024-05-01").await;
+
+        let from = NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 4, 30).unwrap();
+        let statement = statement(&db, StatementSubject::Account(account_id), from, to).await.unwrap();
+        assert_eq!(statement.opening_balance, 10000);
+        assert_eq!(statement.lines.len(), 1);
+        let line = &statement.lines[0];
+        assert_eq!((line.debit, line.credit, line.balance), (2500, 0, 7500));
+        assert_eq!(statement.closing_balance, 7500);
+        assert!(is_instruction_error(super::statement(&db, StatementSubject::Account(account_id), to, from).await));
+    }
+
+    #[actix_web::test]
+    async fn statement_subjects_are_only_client_accounts() {
+        let db = entities::memory_connect().await;
+        let account_id = ledger::account_new(&db, None, &"Client A".to_string(), &AccountType::CurrentLiability, None,
+                                             None, &admin()).await.unwrap();
+        let liabilities = Some(&AccountType::CurrentLiability);
+        assert!(statement_subjects(&db, None, liabilities).await.unwrap().is_empty());
+
+        ledger::account_client_set(&db, account_id, true, &admin()).await.unwrap();
+        assert_eq!(statement_subjects(&db, None, liabilities).await.unwrap(),
+                   vec![StatementSubject::Account(account_id)]);
+        assert!(ledger::account_client_set(&db, 100, true, &admin()).await.is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Statutory accounts for filing with Companies House, for micro-entities and dormant companies
+//! reporting under FRS 105.
+//!
+//! The accounts are made up from the ledger to the end of a fiscal year as a single iXBRL document,
+//! tagged with the FRS 105 taxonomy by [`crate::ixbrl`]: a title page, the balance sheet in the
+//! micro-entity format with the statements required on it, the directors' approval, and the notes.
+//! The balance sheet is compared with the previous year end, unless the ledger has nothing before
+//! the year.
+//!
+//! Accounts for a dormant company are refused if anything was posted to the profit and loss
+//! accounts during the year.
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use tera::{Context, Tera};
+
+use crate::{budgets, db, ixbrl, reports};
+use crate::error::Error::InstructionError;
+use crate::ixbrl::{BalanceSheetFormat, Fact, Tagging};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountsVariant {
+    /// Accounts of a micro-entity, exempt from audit as a small company.
+    #[default]
+    Micro,
+    /// Accounts of a dormant company, exempt from audit as a dormant company.
+    Dormant,
+}
+
+/// The details of the accounts that are not in the ledger.
+pub struct Approval {
+    /// The director signing the balance sheet on behalf of the board.
+    pub director: String,
+    /// The date the board approved the accounts.
+    pub date: NaiveDate,
+    /// The average number of persons employed during the year, including directors.
+    pub employees: i64,
+}
+
+/// Check that nothing was posted to the profit and loss accounts during a period.
+async fn dormant_check(e: &Pool<Sqlite>, from: &str, to: &str) -> Result<()> {
+    for a in db::account_balance_query(e, Some(from), Some(to), None).await? {
+        if a.account_type.is_profit_and_loss() && a.balance != 0 {
+            bail!(InstructionError(format!("the company was not dormant: {} has a balance for the year",
+                a.account_name)));
+        }
+    }
+    Ok(())
+}
+
+/// The statements required on the balance sheet of accounts exempt from audit.
+fn statements(tagging: &mut Tagging, variant: AccountsVariant, start: &str, end: &str) -> Vec<Fact> {
+    let exemption = match variant {
+        AccountsVariant::Micro => (
+            "direp:StatementThatCompanyEntitledToExemptionFromAuditUnderSection477CompaniesAct2006RelatingToSmallCompanies",
+            format!("For the year ending {end} the company was entitled to exemption from audit under section 477 \
+                of the Companies Act 2006 relating to small companies."),
+        ),
+        AccountsVariant::Dormant => (
+            "direp:StatementThatCompanyEntitledToExemptionFromAuditUnderSection480CompaniesAct2006RelatingToDormantCompanies",
+            format!("For the year ending {end} the company was entitled to exemption from audit under section 480 \
+                of the Companies Act 2006 relating to dormant companies."),
+        ),
+    };
+    [
+        exemption,
+        ("direp:StatementThatMembersHaveNotRequiredCompanyToObtainAnAudit",
+         "The members have not required the company to obtain an audit of its accounts for the year in question \
+            in accordance with section 476.".to_string()),
+        ("direp:StatementThatDirectorsAcknowledgeTheirResponsibilitiesUnderCompaniesAct",
+         "The directors acknowledge their responsibilities for complying with the requirements of the Act with \
+            respect to accounting records and the preparation of accounts.".to_string()),
+        ("direp:StatementThatAccountsHaveBeenPreparedInAccordanceWithProvisionsSmallCompaniesRegime",
+         "These accounts have been prepared in accordance with the provisions applicable to companies subject to \
+            the small companies regime and in accordance with the micro-entity provisions.".to_string()),
+    ]
+        .iter()
+        .map(|(concept, text)| tagging.text(concept, start, end, None, text))
+        .collect()
+}
+
+/// Render the statutory accounts for the fiscal year ending on `as_at` as an iXBRL document.
+pub async fn accounts_render(e: &Pool<Sqlite>, tmpl: &Tera, as_at: NaiveDate, variant: AccountsVariant,
+                             approval: &Approval) -> Result<String> {
+    let (name, registered_number) = ixbrl::company(e).await?;
+    let Some(next_day) = as_at.succ_opt() else {
+        bail!(InstructionError(format!("{as_at} is out of range")));
+    };
+    if budgets::year_start(e, next_day).await? != next_day {
+        bail!(InstructionError(format!("{as_at} is not the end of a fiscal year")));
+    }
+    if approval.director.trim().is_empty() || approval.director.len() > 140 {
+        bail!(InstructionError("director must be 1-140 chars".to_string()));
+    }
+    if approval.date <= as_at {
+        bail!(InstructionError("accounts must be approved after the end of the year".to_string()));
+    }
+    if approval.employees < 0 {
+        bail!(InstructionError("number of employees must not be negative".to_string()));
+    }
+    let start = budgets::year_start(e, as_at).await?;
+    let (from, to) = (start.to_string(), as_at.to_string());
+    if variant == AccountsVariant::Dormant {
+        dormant_check(e, &from, &to).await?;
+    }
+    let mut comparisons: Vec<NaiveDate> = Vec::new();
+    if let Some(previous) = start.pred_opt() {
+        let balances = db::account_balance_query(e, None, Some(previous.to_string().as_str()), None).await?;
+        if balances.iter().any(|a| a.balance != 0) {
+            comparisons.push(previous);
+        }
+    }
+    let report = reports::balance_sheet(e, as_at, &comparisons).await?;
+
+    let mut tagging = Tagging::default();
+    let mut hidden = ixbrl::company_facts(&mut tagging, &name, &registered_number, &from, &to);
+    let dormant = variant == AccountsVariant::Dormant;
+    hidden.push(tagging.text("bus:EntityDormantTruefalse", &from, &to, None, dormant.to_string().as_str()));
+    let rows = ixbrl::balance_sheet_rows(&mut tagging, &report, BalanceSheetFormat::Micro);
+    let statements = statements(&mut tagging, variant, &from, &to);
+    let approved = tagging.text("core:DateAuthorisationFinancialStatementsForIssue", &from, &to, None,
+                                approval.date.to_string().as_str());
+    let director = tagging.text("bus:NameEntityOfficer", &from, &to, Some((ixbrl::OFFICERS, "bus:Director1")),
+                                approval.director.trim());
+    let employees = tagging.pure("core:AverageNumberEmployeesDuringPeriod", &from, &to, approval.employees);
+
+    let mut ctx = Context::new();
+    ctx.insert("entity_name", &name);
+    ctx.insert("registered_number", &registered_number);
+    ctx.insert("currency", &ixbrl::currency(e).await);
+    ctx.insert("schema", ixbrl::FRS_105_SCHEMA);
+    ctx.insert("dormant", &dormant);
+    ctx.insert("report", &report);
+    ctx.insert("hidden", &hidden);
+    ctx.insert("rows", &rows);
+    ctx.insert("statements", &statements);
+    ctx.insert("approved", &approved);
+    ctx.insert("director", &director);
+    ctx.insert("employees", &employees);
+    ctx.insert("contexts", &tagging.contexts);
+    let html = tmpl.render("accounts.html", &ctx)?;
+    ixbrl::document_check(&html)?;
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{documents, entities, ledger, settings};
+    use crate::error::Error;
+    use crate::ledger::{AccountType, BatchMetadata, Journal, JournalEntry};
+    use crate::users::{Role, User};
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn templates() -> Tera {
+        let mut tera = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/templates/**/*")).unwrap();
+        documents::register_filters(&mut tera);
+        tera
+    }
+
+    fn approval() -> Approval {
+        Approval {
+            director: "A Director".to_string(),
+            date: date("2025-03-01"),
+            employees: 1,
+        }
+    }
+
+    async fn post(e: &Pool<Sqlite>, date: &str, entries: &[(i64, i64)]) {
+        let journal = Journal {
+            unstructured_narrative: "Test".to_string(),
+            entries: entries
+                .iter()
+                .map(|(account, amount)| JournalEntry { account: *account, amount: *amount, ..Default::default() })
+                .collect(),
+        };
+        let metadata = BatchMetadata {
+            date: Some(date.to_string()),
+            ..Default::default()
+        };
+        ledger::batch_new(e, &metadata, vec![journal]).await.unwrap();
+    }
+
+    /// A company which issued its share capital for cash in 2023 and has done nothing since.
+    async fn company() -> Pool<Sqlite> {
+        let db = entities::memory_connect().await;
+        let mut connection = db.acquire().await.unwrap();
+        settings::set_settings_str(&mut connection, "entityName", "Acme Ltd", None).await;
+        settings::set_settings_str(&mut connection, "entityRegisteredNumber", "01234567", None).await;
+        drop(connection);
+        let admin = User { user_id: 1, name: "admin".to_string(), role: Role::Admin };
+        let share_capital = ledger::account_new(&db, None, &"Share Capital".to_string(), &AccountType::Equity,
+                                                None, None, &admin).await.unwrap();
+        post(&db, "2023-06-01", &[(100, 100), (share_capital, -100)]).await;
+        db
+    }
+
+    #[actix_web::test]
+    async fn accounts_render_tags_a_valid_document() {
+        let db = company().await;
+        let html = accounts_render(&db, &templates(), date("2024-12-31"), AccountsVariant::Dormant, &approval())
+            .await
+            .unwrap();
+        ixbrl::document_check(&html).unwrap();
+        assert!(html.contains("section 480"));
+        assert!(html.contains("2023-12-31"));
+
+        let html = accounts_render(&db, &templates(), date("2023-12-31"), AccountsVariant::Micro, &approval())
+            .await
+            .unwrap();
+        ixbrl::document_check(&html).unwrap();
+        assert!(html.contains("section 477"));
+        assert!(!html.contains("2022-12-31"));
+    }
+
+    #[actix_web::test]
+    async fn accounts_render_refuses_dormant_accounts_of_a_trading_company() {
+        let db = company().await;
+        post(&db, "2024-05-01", &[(100, 50), (400, -50)]).await;
+        let tmpl = templates();
+        assert!(is_instruction_error(accounts_render(&db, &tmpl, date("2024-12-31"), AccountsVariant::Dormant,
+                                                     &approval()).await));
+        accounts_render(&db, &tmpl, date("2024-12-31"), AccountsVariant::Micro, &approval()).await.unwrap();
+        accounts_render(&db, &tmpl, date("2025-12-31"), AccountsVariant::Dormant,
+                        &Approval { date: date("2026-03-01"), ..approval() }).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn accounts_render_validates_the_year_end_and_approval() {
+        let db = company().await;
+        let tmpl = templates();
+        let invalid = [
+            ("2024-06-30", approval()),
+            ("2024-12-31", Approval { date: date("2024-12-31"), ..approval() }),
+            ("2024-12-31", Approval { director: " ".to_string(), ..approval() }),
+            ("2024-12-31", Approval { employees: -1, ..approval() }),
+        ];
+        for (as_at, approval) in invalid {
+            assert!(is_instruction_error(accounts_render(&db, &tmpl, date(as_at), AccountsVariant::Micro, &approval)
+                .await), "{as_at}");
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Tax codes and the calculation of VAT.
+//!
+//! Each tax code has a treatment and a rate that may change over time: the rate used for a
+//! supply is the one effective on the date of the invoice, bill or journal. Tax is posted to the
+//! `outputVatAccount` setting for sales and the `inputVatAccount` setting for purchases, with the
+//! tax code recorded on both the net and the tax entries so that a VAT return can be built from
+//! the ledger.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, settings};
+use crate::error::Error::InstructionError;
+use crate::ledger::{Journal, JournalEntry};
+use crate::users::{Role, User};
+
+/// How supplies with a tax code are treated for VAT.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum TaxTreatment {
+    /// Standard, reduced and zero rated supplies. Tax is charged at the rate of the code.
+    Taxable,
+    /// Exempt supplies. No tax is charged.
+    Exempt,
+    /// Transactions outside the scope of VAT, such as wages and transfers.
+    OutsideScope,
+    /// Purchases where the buyer accounts for the tax. Tax at the rate of the code is posted as
+    /// both input and output tax, and sales carry no tax.
+    ReverseCharge,
+}
+
+#[derive(Clone, Serialize)]
+pub struct TaxCode {
+    pub code: String,
+    pub name: String,
+    pub treatment: TaxTreatment,
+    /// The rate in basis points, if one is effective on the date the code was looked up.
+    pub rate: Option<i64>,
+}
+
+impl From<db::TaxCodeResult> for TaxCode {
+    fn from(r: db::TaxCodeResult) -> Self {
+        TaxCode {
+            code: r.code,
+            name: r.name,
+            treatment: r.treatment,
+            rate: r.rate,
+        }
+    }
+}
+
+/// List the tax codes with the rates effective on `date`.
+pub async fn tax_code_list(e: impl SqliteExecutor<'_>, date: NaiveDate) -> Result<Vec<TaxCode>> {
+    Ok(db::tax_code_list_query(e, date.to_string().as_str()).await?
+        .into_iter()
+        .map(TaxCode::from)
+        .collect())
+}
+
+pub async fn tax_code_new(e: &Pool<Sqlite>, code: &str, name: &str, treatment: &TaxTreatment, user: &User)
+                          -> Result<()> {
+    user.require(Role::Admin)?;
+    if code.is_empty() || code.len() > 32 || !code.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+        bail!(InstructionError("tax code must be 1-32 uppercase letters or underscores".to_string()));
+    }
+    if name.is_empty() || name.len() > 140 {
+        bail!(InstructionError("tax code name must be 1-140 chars".to_string()));
+    }
+    if db::tax_code_query(e, code, "9999-12-31").await?.is_some() {
+        bail!(InstructionError(format!("tax code {code} already exists")));
+    }
+    Ok(db::tax_code_new_tx(e, code, name, treatment, user.user_id).await?)
+}
+
+/// Set the rate of a tax code in basis points from a date.
+pub async fn tax_rate_set(e: &Pool<Sqlite>, code: &str, effective_from: NaiveDate, rate: i64, user: &User)
+                          -> Result<()> {
+    user.require(Role::Admin)?;
+    if !(0..=10000).contains(&rate) {
+        bail!(InstructionError("tax rate must be between 0 and 10000 basis points".to_string()));
+    }
+    if db::tax_code_query(e, code, "9999-12-31").await?.is_none() {
+        bail!(InstructionError(format!("unknown tax code {code}")));
+    }
+    Ok(db::tax_rate_set_tx(e, code, effective_from.to_string().as_str(), rate, user.user_id).await?)
+}
+
+/// Look up a tax code and the rate effective on `date`.
+pub async fn tax_code(e: impl SqliteExecutor<'_>, code: &str, date: &str) -> Result<(TaxTreatment, i64)> {
+    let Some(result) = db::tax_code_query(e, code, date).await? else {
+        bail!(InstructionError(format!("unknown tax code {code}")));
+    };
+    let Some(rate) = result.rate else {
+        bail!(InstructionError(format!("tax code {code} has no rate effective on {date}")));
+    };
+    Ok((result.treatment, rate))
+}
+
+/// Calculate tax on a net amount at a rate given in basis points, rounding half away from zero.
+pub fn tax_amount(net_amount: i64, rate: i64) -> i64 {
+    (net_amount as f64 * rate as f64 / 10000.0).round() as i64
+}
+
+/// Add the tax on each journal entry that carries a tax code, dated `date`.
+///
+/// Debits are treated as purchases and credits as sales. Tax is posted per tax code to the input
+/// or output VAT account, and reverse charge purchases post the same tax to both. Entries already
+/// posted to a VAT account are left as they are, so journals are expected to balance only once
+/// tax has been added.
+pub async fn journals_tax(e: &Pool<Sqlite>, journals: &mut [Journal], date: &str) -> Result<()> {
+    let output_vat_account = settings::get_settings_int(e, "outputVatAccount").await.unwrap();
+    let input_vat_account = settings::get_settings_int(e, "inputVatAccount").await.unwrap();
+    for journal in journals {
+        let mut taxes: BTreeMap<(i64, String), i64> = BTreeMap::new();
+        for entry in &journal.entries {
+            let Some(code) = &entry.tax_code else { continue };
+            if entry.account == output_vat_account || entry.account == input_vat_account {
+                continue;
+            }
+            let (treatment, rate) = tax_code(e, code.as_str(), date).await?;
+            let tax = tax_amount(entry.amount, rate);
+            match treatment {
+                TaxTreatment::Taxable if entry.amount > 0 => {
+                    *taxes.entry((input_vat_account, code.clone())).or_default() += tax;
+                }
+                TaxTreatment::Taxable => *taxes.entry((output_vat_account, code.clone())).or_default() += tax,
+                TaxTreatment::ReverseCharge if entry.amount > 0 => {
+                    *taxes.entry((input_vat_account, code.clone())).or_default() += tax;
+                    *taxes.entry((output_vat_account, code.clone())).or_default() -= tax;
+                }
+                TaxTreatment::ReverseCharge | TaxTreatment::Exempt | TaxTreatment::OutsideScope => {}
+            }
+        }
+        for ((account, tax_code), amount) in taxes {
+            if amount != 0 {
+                journal.entries.push(JournalEntry {
+                    account,
+                    amount,
+                    tax_code: Some(tax_code),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::error::Error;
+
+    fn admin() -> User {
+        User {
+            user_id: 1,
+            name: "admin".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    fn is_instruction_error<T>(result: Result<T>) -> bool {
+        matches!(result.err().unwrap().downcast_ref::<Error>(), Some(Error::InstructionError(_)))
+    }
+
+    fn entry(account: i64, amount: i64, tax_code: Option<&str>) -> JournalEntry {
+        JournalEntry {
+            account,
+            amount,
+            tax_code: tax_code.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    /// Add tax to a journal with the given entries, returning the tax entries added.
+    async fn taxes(e: &Pool<Sqlite>, entries: Vec<JournalEntry>) -> Vec<(i64, i64, String)> {
+        let count = entries.len();
+        let mut journals = vec![Journal {
+            unstructured_narrative: "Test".to_string(),
+            entries,
+        }];
+        journals_tax(e, &mut journals, "2024-04-01").await.unwrap();
+        journals.remove(0).entries
+            .into_iter()
+            .skip(count)
+            .map(|e| (e.account, e.amount, e.tax_code.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn tax_amount_rounds_half_away_from_zero() {
+        assert_eq!(tax_amount(1250, 500), 63);
+        assert_eq!(tax_amount(-1250, 500), -63);
+        assert_eq!(tax_amount(1249, 500), 62);
+        assert_eq!(tax_amount(10000, 0), 0);
+    }
+
+    #[actix_web::test]
+    async fn tax_code_uses_the_rate_effective_on_the_date() {
+        let db = entities::memory_connect().await;
+        assert_eq!(tax_code(&db, "STANDARD", "2010-12-31").await.unwrap(), (TaxTreatment::Taxable, 1750));
+        assert_eq!(tax_code(&db, "STANDARD", "2011-01-04").await.unwrap(), (TaxTreatment::Taxable, 2000));
+        assert!(is_instruction_error(tax_code(&db, "UNKNOWN", "2024-04-01").await));
+
+        tax_code_new(&db, "DEFERRED", "Deferred rate", &TaxTreatment::Taxable, &admin()).await.unwrap();
+        tax_rate_set(&db, "DEFERRED", NaiveDate::from_ymd_opt(2024, 7, 1).unwrap(), 1000, &admin()).await.unwrap();
+        assert!(is_instruction_error(tax_code(&db, "DEFERRED", "2024-06-30").await));
+        assert_eq!(tax_code(&db, "DEFERRED", "2024-07-01").await.unwrap(), (TaxTreatment::Taxable, 1000));
+    }
+
+    #[actix_web::test]
+    async fn tax_code_new_and_rate_set_validate() {
+        let db = entities::memory_connect().await;
+        let date = NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+        assert!(is_instruction_error(tax_code_new(&db, "lower", "Lower", &TaxTreatment::Exempt, &admin()).await));
+        assert!(is_instruction_error(tax_code_new(&db, "ZERO", "Zero", &TaxTreatment::Taxable, &admin()).await));
+        assert!(is_instruction_error(tax_rate_set(&db, "ZERO", date, 10001, &admin()).await));
+        assert!(is_instruction_error(tax_rate_set(&db, "UNKNOWN", date, 0, &admin()).await));
+    }
+
+    #[actix_web::test]
+    async fn journals_tax_treats_debits_as_purchases_and_credits_as_sales() {
+        let db = entities::memory_connect().await;
+        assert_eq!(taxes(&db, vec![entry(500, 10000, Some("STANDARD")), entry(100, -12000, None)]).await,
+                   vec![(271, 2000, "STANDARD".to_string())]);
+        assert_eq!(taxes(&db, vec![entry(100, 10500, None), entry(400, -10000, Some("REDUCED"))]).await,
+                   vec![(270, -500, "REDUCED".to_string())]);
+    }
+
+    #[actix_web::test]
+    async fn journals_tax_posts_reverse_charge_purchases_to_both_accounts() {
+        let db = entities::memory_connect().await;
+        assert_eq!(taxes(&db, vec![entry(500, 10000, Some("REVERSE_CHARGE")), entry(100, -10000, None)]).await,
+                   vec![(270, -2000, "REVERSE_CHARGE".to_string()), (271, 2000, "REVERSE_CHARGE".to_string())]);
+        assert!(taxes(&db, vec![entry(100, 10000, None), entry(400, -10000, Some("REVERSE_CHARGE"))]).await
+            .is_empty());
+    }
+
+    #[actix_web::test]
+    async fn journals_tax_leaves_untaxed_and_vat_entries_alone() {
+        let db = entities::memory_connect().await;
+        let entries = vec![entry(500, 10000, Some("EXEMPT")), entry(501, 5000, Some("OUTSIDE_SCOPE")),
+                           entry(502, 3000, Some("ZERO")), entry(271, 400, Some("STANDARD")), entry(100, -18400, None)];
+        assert!(taxes(&db, entries).await.is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+//! Users and API tokens.
+//!
+//! Every call under `/api/v1` must carry an API token as a bearer token in the `Authorization`
+//! header. Tokens are random and only shown when they are created: the database holds their
+//! SHA-256 hash, which is all that is needed to look up the user presenting one.
+//!
+//! When the server starts with no users, [`bootstrap`] creates an `admin` user and prints a token
+//! for it, so that further users can be created through the API.
+//!
+//! Each user has a [`Role`]. The functions that change the books check the role of the user they
+//! are given with [`User::require`], so the same permissions apply whichever front end calls them.
+
+use anyhow::{bail, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::db;
+use crate::error::Error::{InstructionError, PermissionError};
+
+/// The prefix of every API token, to make them easy to recognise, for example by secret scanners.
+const TOKEN_PREFIX: &str = "mb_";
+
+/// What a user may do. Each role may do everything the roles before it may.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "camelCase")]
+pub enum Role {
+    /// Read accounts, documents and reports.
+    Viewer,
+    /// Post journals, and raise and settle invoices and bills.
+    Bookkeeper,
+    /// Approve drafts and payment runs.
+    Approver,
+    /// Create accounts and tax codes, submit VAT returns, which locks periods, and manage users.
+    Admin,
+}
+
+/// A user, as authenticated by an API token.
+#[derive(Clone, Debug, Serialize)]
+pub struct User {
+    pub user_id: i64,
+    pub name: String,
+    pub role: Role,
+}
+
+impl From<db::UserResult> for User {
+    fn from(r: db::UserResult) -> Self {
+        User {
+            user_id: r.user_id,
+            name: r.name,
+            role: r.role,
+        }
+    }
+}
+
+impl User {
+    /// Check that the user has at least the given role.
+    pub fn require(&self, role: Role) -> Result<()> {
+        if self.role < role {
+            bail!(PermissionError(format!("{} has the {:?} role and needs {role:?} or above", self.name, self.role)));
+        }
+        Ok(())
+    }
+}
+
+/// A newly created API token. The token itself cannot be retrieved again.
+pub struct ApiToken {
+    pub token_id: i64,
+    pub user_id: i64,
+    pub token: String,
+}
+
+/// The hash of a token, as stored in the database.
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create a user, returning the user ID.
+pub async fn user_new(e: &Pool<Sqlite>, name: &str, role: Role, user: &User) -> Result<i64> {
+    user.require(Role::Admin)?;
+    if name.is_empty() || name.len() > 64 || !name.chars().all(|c| c.is_ascii_alphanumeric() || "._-@".contains(c)) {
+        bail!(InstructionError("user name must be 1-64 letters, digits or ._-@".to_string()));
+    }
+    if db::user_by_name_query(e, name).await?.is_some() {
+        bail!(InstructionError(format!("user {name} already exists")));
+    }
+    Ok(db::user_new_tx(e, name, role, Some(user.user_id)).await?)
+}
+
+/// Change a user's role. There must always be at least one admin.
+pub async fn user_role_set(e: &Pool<Sqlite>, user_id: i64, role: Role, user: &User) -> Result<User> {
+    user.require(Role::Admin)?;
+    let Some(subject) = db::user_query(e, user_id).await? else {
+        bail!(InstructionError(format!("user {user_id} does not exist")));
+    };
+    if subject.role == Role::Admin && role != Role::Admin && db::user_role_count_query(e, Role::Admin).await? == 1 {
+        bail!(InstructionError(format!("{} is the only admin", subject.name)));
+    }
+    db::user_role_set_tx(e, user_id, role, user.user_id).await?;
+    Ok(User {
+        role,
+        ..User::from(subject)
+    })
+}
+
+/// List the users. Only admins may list users.
+pub async fn user_list(e: impl SqliteExecutor<'_>, user: &User) -> Result<Vec<User>> {
+    user.require(Role::Admin)?;
+    Ok(db::user_list_query(e).await?
+        .into_iter()
+        .map(User::from)
+        .collect())
+}
+
+/// Create an API token for a user. Only admins may create tokens for other users.
+pub async fn token_new(e: &Pool<Sqlite>, user_id: i64, description: Option<&str>, user: &User) -> Result<ApiToken> {
+    if user_id != user.user_id {
+        user.require(Role::Admin)?;
+    }
+    if description.is_some_and(|d| d.len() > 140) {
+        bail!(InstructionError("token description over 140 chars".to_string()));
+    }
+    if db::user_query(e, user_id).await?.is_none() {
+        bail!(InstructionError(format!("user {user_id} does not exist")));
+    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("{TOKEN_PREFIX}{}", hex::encode(bytes));
+    let token_id = db::token_new_tx(e, user_id, token_hash(token.as_str()).as_str(), description, Some(user.user_id)).await?;
+    Ok(ApiToken {
+        token_id,
+        user_id,
+        token,
+    })
+}
+
+/// Revoke an API token so that it can no longer be used. Only admins may revoke other users' tokens.
+pub async fn token_revoke(e: &Pool<Sqlite>, token_id: i64, user: &User) -> Result<()> {
+    if db::token_user_query(e, token_id).await? != Some(user.user_id) {
+        user.require(Role::Admin)?;
+    }
+    match db::token_revoke_tx(e, token_id, user.user_id).await {
+        Err(sqlx::Error::RowNotFound) => bail!(InstructionError(format!("token {token_id} does not exist"))),
+        result => Ok(result?),
+    }
+}
+
+/// Find the user an API token belongs to, if the token is valid and has not been revoked.
+pub async fn authenticate(e: impl SqliteExecutor<'_>, token: &str) -> Result<Option<User>> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+    Ok(db::user_by_token_query(e, token_hash(token).as_str()).await?.map(User::from))
+}
+
+/// Create an `admin` user with a token if there are no users, returning the token.
+pub async fn bootstrap(e: &Pool<Sqlite>) -> Result<Option<String>> {
+    if !db::user_list_query(e).await?.is_empty() {
+        return Ok(None);
+    }
+    let user_id = db::user_new_tx(e, "admin", Role::Admin, None).await?;
+    let admin = User {
+        user_id,
+        name: "admin".to_string(),
+        role: Role::Admin,
+    };
+    Ok(Some(token_new(e, user_id, Some("Created when the server first started"), &admin).await?.token))
+}
@@ -0,0 +1,506 @@
+//! UK VAT returns.
+//!
+//! The nine-box return for a period is built from entries that carry a tax code. Tax posted to
+//! the `outputVatAccount` setting is VAT due (box 1) and tax posted to the `inputVatAccount`
+//! setting is VAT reclaimed (box 4). Other tax-coded entries, except those outside the scope of
+//! VAT, are sales (box 6) if posted to an account that normally has a credit balance, otherwise
+//! purchases (box 7). Boxes 2, 8 and 9 cover trade in goods with the EU and are always zero.
+//!
+//! Under the accrual scheme entries count in the period of their journal date. Under the cash
+//! scheme entries posted by invoices, credit notes and bills count in proportion to the amount of
+//! the document settled by allocations dated in the period, and all other entries count in the
+//! period of their journal date.
+//!
+//! Returns are submitted to a [`VatSubmitter`], normally the HMRC Making Tax Digital API through
+//! [`HmrcSubmitter`]. Submitting a return locks the ledger up to the end of its period: batches
+//! with tax-coded entries, and receipts, payments and allocations, can no longer be dated on or
+//! before the `vatLockDate` setting.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_web::web;
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite, SqliteExecutor};
+
+use crate::{db, settings};
+use crate::error::Error::InstructionError;
+use crate::tax::TaxTreatment;
+use crate::users::{Role, User};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, sqlx::Type, PartialEq, Serialize)]
+#[sqlx(rename_all = "camelCase")]
+pub enum VatScheme {
+    /// VAT is accounted for by the date of invoices and bills.
+    Accrual,
+    /// VAT is accounted for when invoices and bills are paid.
+    Cash,
+}
+
+impl VatScheme {
+    fn from_setting(value: &str) -> Option<Self> {
+        match value {
+            "accrual" => Some(VatScheme::Accrual),
+            "cash" => Some(VatScheme::Cash),
+            _ => None,
+        }
+    }
+}
+
+/// The nine boxes of a VAT return, in minor currency units.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VatBoxes {
+    /// VAT due on sales and other outputs.
+    pub box1: i64,
+    /// VAT due on acquisitions of goods from the EU.
+    pub box2: i64,
+    /// Total VAT due, the sum of boxes 1 and 2.
+    pub box3: i64,
+    /// VAT reclaimed on purchases and other inputs.
+    pub box4: i64,
+    /// Net VAT to pay, box 3 less box 4. Negative if VAT is to be reclaimed.
+    pub box5: i64,
+    /// Total value of sales and other outputs excluding VAT.
+    pub box6: i64,
+    /// Total value of purchases and other inputs excluding VAT.
+    pub box7: i64,
+    /// Total value of supplies of goods to the EU excluding VAT.
+    pub box8: i64,
+    /// Total value of acquisitions of goods from the EU excluding VAT.
+    pub box9: i64,
+}
+
+/// An entry, or the part of an entry, that makes up the value of a box.
+#[derive(Clone, Serialize)]
+pub struct VatTransaction {
+    pub box_number: u8,
+    pub journal_id: i64,
+    pub date: String,
+    pub narrative: String,
+    pub account_id: i64,
+    pub tax_code: String,
+    pub amount: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct VatReturn {
+    pub start: String,
+    pub end: String,
+    pub scheme: VatScheme,
+    pub boxes: VatBoxes,
+    pub transactions: Vec<VatTransaction>,
+}
+
+/// Compute the VAT return for the period `start` to `end` inclusive.
+///
+/// The scheme defaults to the `vatScheme` setting.
+pub async fn vat_return(e: &Pool<Sqlite>, start: NaiveDate, end: NaiveDate, scheme: Option<VatScheme>)
+                        -> Result<VatReturn> {
+    if end < start {
+        bail!(InstructionError("VAT period ends before it starts".to_string()));
+    }
+    let scheme = match scheme {
+        Some(scheme) => scheme,
+        None => settings::get_settings_str(e, "vatScheme").await
+            .and_then(|s| VatScheme::from_setting(s.as_str()))
+            .unwrap_or(VatScheme::Accrual),
+    };
+    let output_vat_account = settings::get_settings_int(e, "outputVatAccount").await.unwrap();
+    let input_vat_account = settings::get_settings_int(e, "inputVatAccount").await.unwrap();
+    let (start, end) = (start.to_string(), end.to_string());
+    let settlements: HashMap<i64, (i64, i64)> = match scheme {
+        VatScheme::Accrual => HashMap::new(),
+        VatScheme::Cash => db::vat_settlement_query(e, start.as_str(), end.as_str()).await?
+            .into_iter()
+            .map(|r| (r.journal_id, (r.settled, r.total)))
+            .collect(),
+    };
+
+    let mut boxes = VatBoxes::default();
+    let mut transactions: Vec<VatTransaction> = Vec::new();
+    for entry in db::vat_entry_query(e, end.as_str()).await? {
+        let amount = match scheme {
+            VatScheme::Cash if entry.is_document => match settlements.get(&entry.journal_id) {
+                Some((settled, total)) if *total != 0 => {
+                    (entry.amount as f64 * *settled as f64 / *total as f64).round() as i64
+                }
+                _ => 0,
+            },
+            _ if entry.date < start => 0,
+            _ => entry.amount,
+        };
+        if amount == 0 {
+            continue;
+        }
+        let (box_number, value) = if entry.account_id == output_vat_account {
+            (1, -amount)
+        } else if entry.account_id == input_vat_account {
+            (4, amount)
+        } else if entry.treatment == TaxTreatment::OutsideScope {
+            continue;
+        } else if entry.account_type.is_credit() {
+            (6, -amount)
+        } else {
+            (7, amount)
+        };
+        match box_number {
+            1 => boxes.box1 += value,
+            4 => boxes.box4 += value,
+            6 => boxes.box6 += value,
+            _ => boxes.box7 += value,
+        }
+        transactions.push(VatTransaction {
+            box_number,
+            journal_id: entry.journal_id,
+            date: entry.date,
+            narrative: entry.narrative,
+            account_id: entry.account_id,
+            tax_code: entry.tax_code,
+            amount: value,
+        });
+    }
+    boxes.box3 = boxes.box1 + boxes.box2;
+    boxes.box5 = boxes.box3 - boxes.box4;
+    Ok(VatReturn {
+        start,
+        end,
+        scheme,
+        boxes,
+        transactions,
+    })
+}
+
+/// A VAT return in the shape of the HMRC Making Tax Digital submission payload. Boxes 1 to 5 are
+/// in pounds and pence and boxes 6 to 9 in whole pounds, with pence dropped. Box 5 is always
+/// positive, whether VAT is to be paid or reclaimed.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MtdVatReturn {
+    pub period_key: String,
+    pub vat_due_sales: f64,
+    pub vat_due_acquisitions: f64,
+    pub total_vat_due: f64,
+    pub vat_reclaimed_curr_period: f64,
+    pub net_vat_due: f64,
+    #[serde(rename = "totalValueSalesExVAT")]
+    pub total_value_sales_ex_vat: i64,
+    #[serde(rename = "totalValuePurchasesExVAT")]
+    pub total_value_purchases_ex_vat: i64,
+    #[serde(rename = "totalValueGoodsSuppliedExVAT")]
+    pub total_value_goods_supplied_ex_vat: i64,
+    #[serde(rename = "totalAcquisitionsExVAT")]
+    pub total_acquisitions_ex_vat: i64,
+    pub finalised: bool,
+}
+
+impl MtdVatReturn {
+    pub fn new(period_key: &str, boxes: &VatBoxes) -> Self {
+        let pounds = |amount: i64| amount as f64 / 100.0;
+        MtdVatReturn {
+            period_key: period_key.to_string(),
+            vat_due_sales: pounds(boxes.box1),
+            vat_due_acquisitions: pounds(boxes.box2),
+            total_vat_due: pounds(boxes.box3),
+            vat_reclaimed_curr_period: pounds(boxes.box4),
+            net_vat_due: pounds(boxes.box5.abs()),
+            total_value_sales_ex_vat: boxes.box6 / 100,
+            total_value_purchases_ex_vat: boxes.box7 / 100,
+            total_value_goods_supplied_ex_vat: boxes.box8 / 100,
+            total_acquisitions_ex_vat: boxes.box9 / 100,
+            finalised: true,
+        }
+    }
+}
+
+/// The receipt returned when a VAT return is accepted.
+#[derive(Clone, Debug, Serialize)]
+pub struct VatReceipt {
+    pub processing_date: String,
+    pub form_bundle_number: String,
+}
+
+/// A service that VAT returns are submitted to.
+pub trait VatSubmitter: Send + Sync {
+    fn submit(&self, vrn: &str, vat_return: &MtdVatReturn) -> Result<VatReceipt>;
+}
+
+/// The HMRC Making Tax Digital VAT API.
+///
+/// The access token must be obtained for the business, with the `write:vat` scope, through the
+/// HMRC OAuth flow. Submitting blocks the calling thread until HMRC responds.
+pub struct HmrcSubmitter {
+    /// The base URL of the API, such as `https://test-api.service.hmrc.gov.uk` for the sandbox.
+    pub api_url: String,
+    pub access_token: String,
+}
+
+/// The receipt in the response to a submission to the HMRC API.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HmrcReceipt {
+    processing_date: String,
+    form_bundle_number: String,
+}
+
+impl VatSubmitter for HmrcSubmitter {
+    fn submit(&self, vrn: &str, vat_return: &MtdVatReturn) -> Result<VatReceipt> {
+        let url = format!("{}/organisations/vat/{vrn}/returns", self.api_url.trim_end_matches('/'));
+        let response = ureq::post(url.as_str())
+            .set("Accept", "application/vnd.hmrc.1.0+json")
+            .set("Authorization", format!("Bearer {}", self.access_token).as_str())
+            .set("Gov-Client-Connection-Method", "BATCH_PROCESS_DIRECT")
+            .set("Gov-Vendor-Product-Name", env!("CARGO_PKG_NAME"))
+            .set("Gov-Vendor-Version", concat!(env!("CARGO_PKG_NAME"), "=", env!("CARGO_PKG_VERSION")))
+            .send_json(vat_return);
+        let receipt: HmrcReceipt = match response {
+            Ok(response) => response.into_json()?,
+            Err(ureq::Error::Status(status, response)) => bail!(InstructionError(format!(
+                "HMRC rejected the return with status {status}: {}", response.into_string().unwrap_or_default()))),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(VatReceipt {
+            processing_date: receipt.processing_date,
+            form_bundle_number: receipt.form_bundle_number,
+        })
+    }
+}
+
+/// A local stand-in for the HMRC VAT service, which applies the same validation rules to the
+/// payload and returns a receipt without sending the return anywhere.
+#[cfg(test)]
+pub struct MockSubmitter;
+
+#[cfg(test)]
+impl VatSubmitter for MockSubmitter {
+    fn submit(&self, _vrn: &str, vat_return: &MtdVatReturn) -> Result<VatReceipt> {
+        let pence = |amount: f64| (amount * 100.0).round() as i64;
+        if pence(vat_return.total_vat_due) != pence(vat_return.vat_due_sales) + pence(vat_return.vat_due_acquisitions) {
+            bail!(InstructionError("totalVatDue is not the sum of vatDueSales and vatDueAcquisitions".to_string()));
+        }
+        if pence(vat_return.net_vat_due)
+            != (pence(vat_return.total_vat_due) - pence(vat_return.vat_reclaimed_curr_period)).abs() {
+            bail!(InstructionError("netVatDue is not the difference of totalVatDue and vatReclaimedCurrPeriod"
+                .to_string()));
+        }
+        if !vat_return.finalised {
+            bail!(InstructionError("return is not finalised".to_string()));
+        }
+        let now = chrono::Utc::now();
+        Ok(VatReceipt {
+            processing_date: now.to_rfc3339(),
+            form_bundle_number: format!("{:012}", now.timestamp_micros() % 1_000_000_000_000),
+        })
+    }
+}
+
+/// Submit the VAT return for a period and lock the ledger up to the end of the period.
+///
+/// Returns are refused if no submitter is configured. The submitter is called on a blocking
+/// thread, so that the worker is free to serve other requests while HMRC responds.
+pub async fn vat_return_submit(e: &Pool<Sqlite>, submitter: Option<Arc<dyn VatSubmitter>>, period_key: &str,
+                               start: NaiveDate, end: NaiveDate, scheme: Option<VatScheme>, user: &User)
+                               -> Result<VatReceipt> {
+    user.require(Role::Admin)?;
+    let Some(submitter) = submitter else {
+        bail!(InstructionError("no VAT submission service is configured".to_string()));
+    };
+    if period_key.len() != 4 || !period_key.chars().all(|c| c.is_ascii_alphanumeric() || c == '#') {
+        bail!(InstructionError(format!("invalid period key {period_key}")));
+    }
+    lock_check(e, start.to_string().as_str()).await?;
+    let Some(vrn) = settings::get_settings_str(e, "entityVatNumber").await else {
+        bail!(InstructionError("the entityVatNumber setting is not set".to_string()));
+    };
+    let vrn: String = vrn.trim_start_matches("GB").chars().filter(|c| !c.is_whitespace()).collect();
+    if vrn.len() != 9 || !vrn.chars().all(|c| c.is_ascii_digit()) {
+        bail!(InstructionError(format!("invalid VAT registration number {vrn}")));
+    }
+    let vat_return = vat_return(e, start, end, scheme).await?;
+    let mtd_vat_return = MtdVatReturn::new(period_key, &vat_return.boxes);
+    let receipt = web::block(move || submitter.submit(vrn.as_str(), &mtd_vat_return)).await??;
+    let boxes = vat_return.boxes;
+    db::vat_return_new_tx(e, &db::VatReturnResult {
+        period_key: period_key.to_string(),
+        start_date: vat_return.start,
+        end_date: vat_return.end,
+        scheme: vat_return.scheme,
+        box1: boxes.box1,
+        box2: boxes.box2,
+        box3: boxes.box3,
+        box4: boxes.box4,
+        box5: boxes.box5,
+        box6: boxes.box6,
+        box7: boxes.box7,
+        box8: boxes.box8,
+        box9: boxes.box9,
+        processing_date: receipt.processing_date.clone(),
+        form_bundle_number: receipt.form_bundle_number.clone(),
+    }, user.user_id).await?;
+    Ok(receipt)
+}
+
+#[derive(Clone, Serialize)]
+pub struct SubmittedVatReturn {
+    pub period_key: String,
+    pub start: String,
+    pub end: String,
+    pub scheme: VatScheme,
+    pub boxes: VatBoxes,
+    pub receipt: VatReceipt,
+}
+
+pub async fn vat_return_list(e: impl SqliteExecutor<'_>) -> Result<Vec<SubmittedVatReturn>> {
+    Ok(db::vat_return_list_query(e).await?
+        .into_iter()
+        .map(|r| SubmittedVatReturn {
+            period_key: r.period_key,
+            start: r.start_date,
+            end: r.end_date,
+            scheme: r.scheme,
+            boxes: VatBoxes {
+                box1: r.box1,
+                box2: r.box2,
+                box3: r.box3,
+                box4: r.box4,
+                box5: r.box5,
+                box6: r.box6,
+                box7: r.box7,
+                box8: r.box8,
+                box9: r.box9,
+            },
+            receipt: VatReceipt {
+                processing_date: r.processing_date,
+                form_bundle_number: r.form_bundle_number,
+            },
+        })
+        .collect())
+}
+
+/// Check that `date` is after the end of the last submitted VAT period.
+pub async fn lock_check(e: impl SqliteExecutor<'_>, date: &str) -> Result<()> {
+    if let Some(lock_date) = settings::get_settings_str(e, "vatLockDate").await {
+        if date <= lock_date.as_str() {
+            bail!(InstructionError(format!("VAT periods up to {lock_date} are locked")));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities;
+    use crate::ledger::{self, AccountType, BatchMetadata, Journal, JournalEntry};
+
+    fn admin() -> User {
+        User {
+            user_id: 1,
+            name: "admin".to_string(),
+            role: Role::Admin,
+        }
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn entry(account: i64, amount: i64, tax_code: Option<&str>) -> JournalEntry {
+        JournalEntry {
+            account,
+            amount,
+            tax_code: tax_code.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    async fn post(e: &Pool<Sqlite>, date: &str, entries: Vec<JournalEntry>) -> Result<()> {
+        let metadata = BatchMetadata {
+            date: Some(date.to_string()),
+            ..Default::default()
+        };
+        ledger::batch_new(e, &metadata, vec![Journal {
+            unstructured_narrative: "Test".to_string(),
+            entries,
+        }]).await?;
+        Ok(())
+    }
+
+    /// A sale of 100.00 plus VAT from the cash account.
+    async fn sale(e: &Pool<Sqlite>, date: &str) {
+        post(e, date, vec![
+            entry(100, 12000, None),
+            entry(400, -10000, Some("STANDARD")),
+            entry(270, -2000, Some("STANDARD")),
+        ]).await.unwrap();
+    }
+
+    /// A purchase of `net` plus VAT from the cash account.
+    async fn purchase(e: &Pool<Sqlite>, date: &str, net: i64) {
+        let expenses = ledger::account_new(e, None, &"Purchases".to_string(), &AccountType::DirectExpense, None, None,
+                                           &admin()).await.unwrap();
+        post(e, date, vec![
+            entry(expenses, net, Some("STANDARD")),
+            entry(271, net / 5, Some("STANDARD")),
+            entry(100, -net * 6 / 5, None),
+        ]).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn vat_return_boxes_for_payment() {
+        let db = entities::memory_connect().await;
+        sale(&db, "2024-04-10").await;
+        purchase(&db, "2024-05-10", 5000).await;
+        sale(&db, "2024-07-10").await;
+        let boxes = vat_return(&db, date("2024-04-01"), date("2024-06-30"), Some(VatScheme::Accrual)).await.unwrap()
+            .boxes;
+        assert_eq!((boxes.box1, boxes.box2, boxes.box3, boxes.box4), (2000, 0, 2000, 1000));
+        assert_eq!(boxes.box5, 1000);
+        assert_eq!((boxes.box6, boxes.box7, boxes.box8, boxes.box9), (10000, 5000, 0, 0));
+    }
+
+    #[actix_web::test]
+    async fn vat_return_box5_is_negative_for_repayment() {
+        let db = entities::memory_connect().await;
+        sale(&db, "2024-04-10").await;
+        purchase(&db, "2024-05-10", 25000).await;
+        let boxes = vat_return(&db, date("2024-04-01"), date("2024-06-30"), Some(VatScheme::Accrual)).await.unwrap()
+            .boxes;
+        assert_eq!((boxes.box3, boxes.box4), (2000, 5000));
+        assert_eq!(boxes.box5, -3000);
+        assert_eq!(MtdVatReturn::new("24A1", &boxes).net_vat_due, 30.0);
+    }
+
+    #[actix_web::test]
+    async fn vat_return_submit_refused_without_submitter() {
+        let db = entities::memory_connect().await;
+        let result = vat_return_submit(&db, None, "24A1", date("2024-04-01"), date("2024-06-30"), None, &admin()).await;
+        assert!(result.is_err());
+        assert_eq!(settings::get_settings_str(&db, "vatLockDate").await, None);
+    }
+
+    #[actix_web::test]
+    async fn vat_return_submit_locks_period() {
+        let db = entities::memory_connect().await;
+        sqlx::query("UPDATE settings SET strValue = 'GB123456789' WHERE name = 'entityVatNumber';")
+            .execute(&db)
+            .await
+            .unwrap();
+        sale(&db, "2024-04-10").await;
+        vat_return_submit(&db, Some(Arc::new(MockSubmitter)), "24A1", date("2024-04-01"), date("2024-06-30"), None, &admin())
+            .await
+            .unwrap();
+
+        assert_eq!(settings::get_settings_str(&db, "vatLockDate").await.as_deref(), Some("2024-06-30"));
+        let submitted = vat_return_list(&db).await.unwrap();
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(submitted[0].boxes.box5, 2000);
+        assert!(post(&db, "2024-06-30", vec![
+            entry(100, 12000, None),
+            entry(400, -10000, Some("STANDARD")),
+            entry(270, -2000, Some("STANDARD")),
+        ]).await.is_err());
+        sale(&db, "2024-07-01").await;
+    }
+}